@@ -75,9 +75,18 @@ struct CommonLeaderArgs {
     /// Enter a code instead of generating one automatically
     #[clap(long, value_name = "CODE")]
     code: Option<String>,
-    /// Length of code (in bytes/words)
-    #[clap(short = 'c', long, value_name = "NUMWORDS", default_value = "2")]
+    /// Length of code (in bytes/words). Each word adds 8 bits of entropy, use more for a higher threat model
+    #[clap(
+        short = 'c',
+        long,
+        value_name = "NUMWORDS",
+        default_value = "2",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
     code_length: usize,
+    /// Give up if nobody used the code within that many minutes
+    #[clap(long, value_name = "MINUTES")]
+    expire_after: Option<u64>,
 }
 
 // receive
@@ -86,6 +95,15 @@ struct CommonReceiverArgs {
     /// Store transferred file or folder in the specified directory. Defaults to $PWD.
     #[clap(long = "out-dir", value_name = "PATH", default_value = ".", value_hint = clap::ValueHint::DirPath)]
     file_path: PathBuf,
+    /// Save received folders as tar archive instead of unpacking them
+    #[clap(long, conflicts_with = "extract")]
+    keep_archive: bool,
+    /// Unpack received tar archives, even if the sender did not offer them as folder
+    #[clap(long)]
+    extract: bool,
+    /// Decompress received compressed files (like `.zst`) while receiving
+    #[clap(long)]
+    decompress: bool,
 }
 
 // receive, connect
@@ -132,6 +150,12 @@ enum ForwardCommand {
         /// List of ports to open up. You can optionally specify a domain/address to forward remote ports
         #[clap(value_name = "[DOMAIN:]PORT", multiple_occurrences = true, value_hint = clap::ValueHint::Hostname)]
         targets: Vec<String>,
+        /// Keep retrying to connect to a target for this many seconds, so that connections survive quick restarts of it
+        #[clap(long, value_name = "SECONDS")]
+        retry_target: Option<u64>,
+        /// Check which targets are reachable and tell the peer
+        #[clap(long)]
+        probe_targets: bool,
         #[clap(flatten)]
         common: CommonArgs,
         #[clap(flatten)]
@@ -150,6 +174,9 @@ enum ForwardCommand {
             value_name = "PORT"
         )]
         ports: Vec<u16>,
+        /// If one of the requested ports is in use or privileged, take the next free one instead of failing
+        #[clap(long)]
+        next_free_port: bool,
         /// Bind to a specific address to accept the forwarding. Depending on your system and firewall, this may make the forwarded ports accessible from the outside.
         #[clap(long = "bind", value_name = "ADDRESS", default_value = "::", value_hint = clap::ValueHint::Other)]
         bind_address: std::net::IpAddr,
@@ -289,11 +316,18 @@ async fn main() -> eyre::Result<()> {
     match app.command {
         WormholeCommand::Send {
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    expire_after,
+                },
             common_send: CommonSenderArgs { file_name, files },
             ..
         } => {
-            let offer = make_send_offer(files, file_name).await?;
+            let Some(offer) = make_send_offer(files, file_name, ctrl_c()).await? else {
+                return Ok(());
+            };
 
             let transit_abilities = parse_transit_args(&common);
             let (wormhole, _code, relay_hints) = match util::cancellable(
@@ -302,6 +336,7 @@ async fn main() -> eyre::Result<()> {
                     common,
                     code,
                     Some(code_length),
+                    expire_after.map(|minutes| Duration::from_secs(minutes * 60)),
                     true,
                     transfer::APP_CONFIG,
                     Some(&sender_print_code),
@@ -329,7 +364,12 @@ async fn main() -> eyre::Result<()> {
             tries,
             timeout,
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    expire_after,
+                },
             common_send: CommonSenderArgs { file_name, files },
             ..
         } => {
@@ -340,6 +380,7 @@ async fn main() -> eyre::Result<()> {
                     common,
                     code,
                     Some(code_length),
+                    expire_after.map(|minutes| Duration::from_secs(minutes * 60)),
                     true,
                     transfer::APP_CONFIG,
                     Some(&sender_print_code),
@@ -370,18 +411,31 @@ async fn main() -> eyre::Result<()> {
             noconfirm,
             common,
             common_follower: CommonFollowerArgs { code },
-            common_receiver: CommonReceiverArgs { file_path },
+            common_receiver:
+                CommonReceiverArgs {
+                    file_path,
+                    keep_archive,
+                    extract,
+                    decompress,
+                },
             ..
         } => {
             let transit_abilities = parse_transit_args(&common);
+            let mut app_config = transfer::APP_CONFIG;
+            if decompress {
+                app_config.app_version = app_config
+                    .app_version
+                    .with_codec(transfer::Compression::Zstd.codec());
+            }
             let (wormhole, _code, relay_hints) = {
                 let connect_fut = Box::pin(parse_and_connect(
                     &mut term,
                     common,
                     code,
                     None,
+                    None,
                     false,
-                    transfer::APP_CONFIG,
+                    app_config,
                     None,
                     clipboard.as_mut(),
                 ));
@@ -396,6 +450,9 @@ async fn main() -> eyre::Result<()> {
                 relay_hints,
                 &file_path,
                 noconfirm,
+                keep_archive,
+                extract,
+                decompress,
                 transit_abilities,
                 ctrl_c,
             ))
@@ -403,8 +460,15 @@ async fn main() -> eyre::Result<()> {
         },
         WormholeCommand::Forward(ForwardCommand::Serve {
             targets,
+            retry_target,
+            probe_targets,
             common,
-            common_leader: CommonLeaderArgs { code, code_length },
+            common_leader:
+                CommonLeaderArgs {
+                    code,
+                    code_length,
+                    expire_after,
+                },
             ..
         }) => {
             // TODO make fancy
@@ -454,6 +518,7 @@ async fn main() -> eyre::Result<()> {
                     common.clone(),
                     code.clone(),
                     Some(code_length),
+                    expire_after.map(|minutes| Duration::from_secs(minutes * 60)),
                     true,
                     app_config,
                     Some(&server_print_code),
@@ -464,17 +529,22 @@ async fn main() -> eyre::Result<()> {
                         Either::Left((result, _)) => result?,
                         Either::Right(((), _)) => break,
                     };
-                async_std::task::spawn(forwarding::serve(
+                let mut options = forwarding::ServeOptions::default();
+                options.target_retry_window = retry_target.map(Duration::from_secs);
+                options.probe_targets = probe_targets;
+                async_std::task::spawn(forwarding::serve_with_options(
                     wormhole,
                     &transit::log_transit_connection,
                     relay_hints,
                     targets.clone(),
+                    options,
                     ctrl_c(),
                 ));
             }
         },
         WormholeCommand::Forward(ForwardCommand::Connect {
             ports,
+            next_free_port,
             noconfirm,
             bind_address,
             common,
@@ -490,6 +560,7 @@ async fn main() -> eyre::Result<()> {
                 common,
                 code,
                 None,
+                None,
                 false,
                 app_config,
                 None,
@@ -497,20 +568,48 @@ async fn main() -> eyre::Result<()> {
             )
             .await?;
 
-            let offer = forwarding::connect(
+            let port_fallback = if next_free_port {
+                forwarding::PortFallback::NextFree
+            } else {
+                forwarding::PortFallback::Error
+            };
+            let mut offer = forwarding::connect_with_selection(
                 wormhole,
                 &transit::log_transit_connection,
                 relay_hints,
                 Some(bind_address),
-                &ports,
+                port_fallback,
+                |targets| {
+                    futures::future::ready(
+                        targets
+                            .into_iter()
+                            .map(|target| target.address)
+                            .zip(ports.iter().copied().chain(std::iter::repeat(0)))
+                            .collect(),
+                    )
+                },
             )
             .await?;
             log::info!("Mapping the following open ports to targets:");
             log::info!("  local port -> remote target (no address = localhost on remote)");
             for (port, target) in &offer.mapping {
-                log::info!("  {} -> {}", port, target);
+                let mut description = String::new();
+                if let Some(service) = offer.service(target) {
+                    description += &format!(" {}", service);
+                }
+                if offer.health(target) == forwarding::TargetHealth::Down {
+                    description += " (not reachable)";
+                }
+                log::info!("  {} -> {}{}", port, target, description);
             }
-            if noconfirm || util::ask_user("Accept forwarded ports?", true).await {
+            if noconfirm
+                || offer
+                    .keep_alive_while(
+                        transit::DEFAULT_KEEPALIVE_INTERVAL,
+                        util::ask_user("Accept forwarded ports?", true),
+                    )
+                    .await?
+            {
                 offer.accept(ctrl_c()).await?;
             } else {
                 offer.reject().await?;
@@ -576,6 +675,7 @@ async fn parse_and_connect(
     common_args: CommonArgs,
     code: Option<String>,
     code_length: Option<usize>,
+    expiry: Option<Duration>,
     is_send: bool,
     mut app_config: magic_wormhole::AppConfig<impl serde::Serialize + Send + Sync + 'static>,
     print_code: Option<&PrintCodeFn>,
@@ -642,14 +742,19 @@ async fn parse_and_connect(
     };
     print_welcome(term, &mailbox_connection.welcome)?;
     let code = mailbox_connection.code.clone();
-    let wormhole = Wormhole::connect(mailbox_connection).await?;
+    let wormhole = match expiry {
+        Some(expiry) => Wormhole::connect_with_expiry(mailbox_connection, expiry).await?,
+        None => Wormhole::connect(mailbox_connection).await?,
+    };
     eyre::Result::<_>::Ok((wormhole, code, relay_hints))
 }
 
+/* If None, the task got cancelled */
 async fn make_send_offer(
     mut files: Vec<PathBuf>,
     file_name: Option<String>,
-) -> eyre::Result<transfer::OfferSend> {
+    cancel: impl Future<Output = ()>,
+) -> eyre::Result<Option<transfer::OfferSend>> {
     for file in &files {
         eyre::ensure!(
             async_std::path::Path::new(&file).exists().await,
@@ -659,11 +764,11 @@ async fn make_send_offer(
     }
     log::trace!("Making send offer in {files:?}, with name {file_name:?}");
 
-    match (files.len(), file_name) {
+    let entries = match (files.len(), file_name) {
         (0, _) => unreachable!("Already checked by CLI parser"),
         (1, Some(file_name)) => {
             let file = files.remove(0);
-            Ok(transfer::OfferSend::new_file_or_folder(file_name, file).await?)
+            vec![(file_name, file)]
         },
         (1, None) => {
             let file = files.remove(0);
@@ -675,11 +780,11 @@ async fn make_send_offer(
                 .to_str()
                 .ok_or_else(|| eyre::format_err!("File path must be a valid UTF-8 string"))?
                 .to_owned();
-            Ok(transfer::OfferSend::new_file_or_folder(file_name, file).await?)
+            vec![(file_name, file)]
+        },
+        (_, Some(_)) => {
+            eyre::bail!("Can't customize file name when sending multiple files")
         },
-        (_, Some(_)) => Err(eyre::format_err!(
-            "Can't customize file name when sending multiple files"
-        )),
         (_, None) => {
             let mut names = std::collections::BTreeMap::new();
             for path in &files {
@@ -690,9 +795,47 @@ async fn make_send_offer(
                     );
                 }
             }
-            Ok(transfer::OfferSend::new_paths(files).await?)
+            files
+                .into_iter()
+                .map(|path| {
+                    let file_name = path
+                        .file_name()
+                        .expect("Already checked above")
+                        .to_str()
+                        .ok_or_else(|| eyre::format_err!("File path must be a valid UTF-8 string"))?
+                        .to_owned();
+                    Ok((file_name, path))
+                })
+                .collect::<eyre::Result<_>>()?
+        },
+    };
+
+    /* Only show a spinner if scanning takes a noticeable amount of time */
+    let start = Instant::now();
+    let mut spinner: Option<ProgressBar> = None;
+    let offer = transfer::OfferSend::scan(
+        entries,
+        |progress| {
+            if let transfer::ScanProgress::File { files, bytes, .. } = progress {
+                if spinner.is_none() && start.elapsed() < Duration::from_millis(200) {
+                    return;
+                }
+                spinner
+                    .get_or_insert_with(ProgressBar::new_spinner)
+                    .set_message(format!(
+                        "Scanning files: {} files, {}",
+                        files,
+                        indicatif::HumanBytes(bytes)
+                    ));
+            }
         },
+        cancel,
+    )
+    .await?;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
     }
+    Ok(offer)
 }
 
 fn create_progress_bar(file_size: u64) -> ProgressBar {
@@ -702,7 +845,7 @@ fn create_progress_bar(file_size: u64) -> ProgressBar {
     pb.set_style(
         ProgressStyle::default_bar()
             // .template("[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .template("[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta})")
+            .template("[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta}) {msg}")
             .unwrap()
             .progress_chars("#>-"),
     );
@@ -720,11 +863,23 @@ fn create_progress_handler(pb: ProgressBar) -> impl FnMut(u64, u64) {
     }
 }
 
+/* Tab completion of the code words. The sender may have chosen any code length */
+struct CodeCompleter(magic_wormhole::CodeCompletion);
+
+impl dialoguer::Completion for CodeCompleter {
+    fn get(&self, input: &str) -> Option<String> {
+        self.0.complete(input)
+    }
+}
+
 fn enter_code() -> eyre::Result<String> {
     use dialoguer::Input;
 
     Input::new()
         .with_prompt("Enter code")
+        .completion_with(&CodeCompleter(magic_wormhole::CodeCompletion::any_length(
+            magic_wormhole::PgpWordlist,
+        )))
         .interact_text()
         .map_err(From::from)
 }
@@ -837,9 +992,12 @@ async fn send_many(
     let time = Instant::now();
 
     /* Special-case the first send with reusing the existing connection */
+    let Some(offer) = make_send_offer(files.clone(), file_name.clone(), ctrl_c()).await? else {
+        return Ok(());
+    };
     send_in_background(
         relay_hints.clone(),
-        make_send_offer(files.clone(), file_name.clone()).await?,
+        offer,
         wormhole,
         term.clone(),
         &mp,
@@ -866,9 +1024,12 @@ async fn send_many(
         )
         .await?;
 
+        let Some(offer) = make_send_offer(files.clone(), file_name.clone(), ctrl_c()).await? else {
+            break;
+        };
         send_in_background(
             relay_hints.clone(),
-            make_send_offer(files.clone(), file_name.clone()).await?,
+            offer,
             wormhole,
             term.clone(),
             &mp,
@@ -927,28 +1088,66 @@ async fn receive(
     relay_hints: Vec<transit::RelayHint>,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    keep_archive: bool,
+    extract: bool,
+    decompress: bool,
     transit_abilities: transit::Abilities,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
-    let req = transfer::request(wormhole, relay_hints, transit_abilities, ctrl_c())
-        .await
-        .context("Could not get an offer")?;
+    /* Only shows up for large offers that take a while */
+    let mut offer_spinner: Option<ProgressBar> = None;
+    let req = transfer::request_with_offer_progress(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        |files, total_size| {
+            offer_spinner
+                .get_or_insert_with(ProgressBar::new_spinner)
+                .set_message(format!(
+                    "Receiving offer: {} files, {}",
+                    files,
+                    indicatif::HumanBytes(total_size)
+                ));
+        },
+        ctrl_c(),
+    )
+    .await
+    .context("Could not get an offer")?;
+    if let Some(spinner) = offer_spinner {
+        spinner.finish_and_clear();
+    }
     /* If None, the task got cancelled */
     match req {
         Some(transfer::ReceiveRequest::V1(req)) => {
-            receive_inner_v1(req, target_dir, noconfirm, ctrl_c).await
+            receive_inner_v1(
+                req,
+                target_dir,
+                noconfirm,
+                keep_archive,
+                extract,
+                decompress,
+                ctrl_c,
+            )
+            .await
         },
         Some(transfer::ReceiveRequest::V2(req)) => {
             receive_inner_v2(req, target_dir, noconfirm, ctrl_c).await
         },
+        Some(req) => eyre::bail!(
+            "The peer uses the unsupported transfer protocol {}",
+            req.protocol_version()
+        ),
         None => Ok(()),
     }
 }
 
 async fn receive_inner_v1(
-    req: transfer::ReceiveRequestV1,
+    mut req: transfer::ReceiveRequestV1,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    keep_archive: bool,
+    extract: bool,
+    decompress: bool,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
     use async_std::fs::OpenOptions;
@@ -962,68 +1161,158 @@ async fn receive_inner_v1(
      */
 
     use number_prefix::NumberPrefix;
+    let prompt = format!(
+        "Receive file '{}' ({})?",
+        req.filename,
+        match NumberPrefix::binary(req.filesize as f64) {
+            NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
+            NumberPrefix::Prefixed(prefix, n) => format!("{:.1} {}B in size", n, prefix.symbol()),
+        },
+    );
     if !(noconfirm
-        || util::ask_user(
-            format!(
-                "Receive file '{}' ({})?",
-                req.filename,
-                match NumberPrefix::binary(req.filesize as f64) {
-                    NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
-                    NumberPrefix::Prefixed(prefix, n) =>
-                        format!("{:.1} {}B in size", n, prefix.symbol()),
-                },
-            ),
-            true,
-        )
-        .await)
+        || req
+            .keep_alive_while(
+                transit::DEFAULT_KEEPALIVE_INTERVAL,
+                util::ask_user(prompt, true),
+            )
+            .await
+            .context("Lost connection while waiting for confirmation")?)
     {
         return req.reject().await.context("Could not reject offer");
     }
 
-    // TODO validate untrusted input here
-    let file_path = std::path::Path::new(target_dir).join(&req.filename);
+    /* Any file may be called `.tar`, so only unpack what got sent as folder unless asked to */
+    if req.is_tar_archive() && (req.is_directory() && !keep_archive || extract) {
+        return receive_extract_v1(req, target_dir, noconfirm, ctrl_c).await;
+    }
+
+    let compression = req.compression().filter(|_| decompress);
+    let file_name = match &compression {
+        Some(compression) => compression.decompressed_filename(&req.filename),
+        None => req.filename.clone(),
+    };
+    let file_path = std::path::Path::new(target_dir).join(transfer::local_filename(
+        &file_name,
+        transfer::FilenamePolicy::Replace,
+    )?);
 
     let pb = create_progress_bar(req.filesize);
 
     /* Then, accept if the file exists */
     if !file_path.exists() || noconfirm {
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .write(true)
             .create_new(true)
             .open(&file_path)
             .await
             .context("Failed to create destination file")?;
-        return req
-            .accept(
-                &transit::log_transit_connection,
-                &mut file,
-                create_progress_handler(pb),
-                ctrl_c(),
-            )
-            .await
-            .context("Receive process failed");
+        return accept_file_v1(req, &file_path, file, compression.is_some(), pb, ctrl_c()).await;
     }
 
     /* If there is a collision, ask whether to overwrite */
-    if !util::ask_user(
-        format!("Override existing file {}?", file_path.display()),
-        false,
-    )
-    .await
+    if !req
+        .keep_alive_while(
+            transit::DEFAULT_KEEPALIVE_INTERVAL,
+            util::ask_user(
+                format!("Override existing file {}?", file_path.display()),
+                false,
+            ),
+        )
+        .await
+        .context("Lost connection while waiting for confirmation")?
     {
         return req.reject().await.context("Could not reject offer");
     }
 
-    let mut file = OpenOptions::new()
+    let file = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
         .open(&file_path)
         .await?;
-    req.accept(
+    accept_file_v1(req, &file_path, file, compression.is_some(), pb, ctrl_c()).await
+}
+
+async fn accept_file_v1(
+    req: transfer::ReceiveRequestV1,
+    file_path: &std::path::Path,
+    file: async_std::fs::File,
+    decompress: bool,
+    pb: ProgressBar,
+    cancel: impl Future<Output = ()>,
+) -> eyre::Result<()> {
+    /* The size after decompressing is not known in advance */
+    if !decompress {
+        transfer::preallocate(file_path, req.filesize)
+            .await
+            .context("Not enough space for receiving the file")?;
+    }
+    /* The sender may delete the file once we confirm, so make sure it's on disk */
+    let file = &mut transfer::SyncOnClose::new(file);
+    if decompress {
+        req.accept_decompress(
+            &transit::log_transit_connection,
+            file,
+            create_progress_handler(pb),
+            cancel,
+        )
+        .await
+    } else {
+        req.accept(
+            &transit::log_transit_connection,
+            file,
+            create_progress_handler(pb),
+            cancel,
+        )
+        .await
+    }
+    .context("Receive process failed")
+}
+
+/** Receive a folder sent as tar archive and unpack it on the fly */
+async fn receive_extract_v1(
+    mut req: transfer::ReceiveRequestV1,
+    target_dir: &std::path::Path,
+    noconfirm: bool,
+    ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    let folder_path = target_dir.join(transfer::local_filename(
+        req.filename.trim_end_matches(".tar"),
+        transfer::FilenamePolicy::Replace,
+    )?);
+
+    /* Unpacking merges into existing folders, ask first */
+    if folder_path.exists()
+        && !noconfirm
+        && !req
+            .keep_alive_while(
+                transit::DEFAULT_KEEPALIVE_INTERVAL,
+                util::ask_user(
+                    format!("Override existing files in {}?", folder_path.display()),
+                    false,
+                ),
+            )
+            .await
+            .context("Lost connection while waiting for confirmation")?
+    {
+        return req.reject().await.context("Could not reject offer");
+    }
+
+    let pb = create_progress_bar(req.filesize);
+    let mut on_bytes = create_progress_handler(pb.clone());
+    let on_progress = move |progress| match progress {
+        transfer::ExtractProgress::Bytes {
+            received, total, ..
+        } => on_bytes(received, total),
+        transfer::ExtractProgress::File { path, .. } => pb.set_message(path.display().to_string()),
+        _ => {},
+    };
+
+    req.accept_extract(
         &transit::log_transit_connection,
-        &mut file,
-        create_progress_handler(pb),
+        target_dir,
+        transfer::FilenamePolicy::Replace,
+        on_progress,
         ctrl_c(),
     )
     .await
@@ -1031,7 +1320,7 @@ async fn receive_inner_v1(
 }
 
 async fn receive_inner_v2(
-    req: transfer::ReceiveRequestV2,
+    mut req: transfer::ReceiveRequestV2,
     target_dir: &std::path::Path,
     noconfirm: bool,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
@@ -1041,20 +1330,22 @@ async fn receive_inner_v2(
     let offer_name = offer.offer_name();
 
     use number_prefix::NumberPrefix;
+    let prompt = format!(
+        "Receive {} ({})?",
+        offer_name,
+        match NumberPrefix::binary(file_size as f64) {
+            NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
+            NumberPrefix::Prefixed(prefix, n) => format!("{:.1} {}B in size", n, prefix.symbol()),
+        },
+    );
     if !(noconfirm
-        || util::ask_user(
-            format!(
-                "Receive {} ({})?",
-                offer_name,
-                match NumberPrefix::binary(file_size as f64) {
-                    NumberPrefix::Standalone(bytes) => format!("{} bytes", bytes),
-                    NumberPrefix::Prefixed(prefix, n) =>
-                        format!("{:.1} {}B in size", n, prefix.symbol()),
-                },
-            ),
-            true,
-        )
-        .await)
+        || req
+            .keep_alive_while(
+                transit::DEFAULT_KEEPALIVE_INTERVAL,
+                util::ask_user(prompt, true),
+            )
+            .await
+            .context("Lost connection while waiting for confirmation")?)
     {
         return req.reject().await.context("Could not reject offer");
     }
@@ -1114,7 +1405,8 @@ async fn receive_inner_v2(
                     tmp_dir.display(),
                 );
             } else {
-                async_std::fs::rename(&path, &target_path).await?;
+                /* Falls back to copying if the temporary directory is on another file system */
+                transfer::move_into_place(&path, &target_path).await?;
             }
             Ok(())
         }})
@@ -0,0 +1,536 @@
+//! Named byte streams to the peer, for protocols of your own
+//!
+//! Applications that need more than a few messages, like an RPC protocol or syncing a game, should not
+//! abuse file transfer or port forwarding for that. With [`connect`], both sides set up a [`transit`]
+//! connection and then open as many [`Channel`]s over it as they like. Each one is a reliable, ordered
+//! byte stream in both directions, with a name to tell them apart. Both sides do the same, there is no
+//! sender or receiver.
+//!
+//! The application brings its own [`AppID`]. The AppConfig's version is not used by this module, so it
+//! is free for the application's own purposes.
+//!
+//! Channels share one connection: a channel nobody reads from stalls the others once its buffer is full.
+//! Read from all channels you opened or accepted, or drop them.
+
+use super::*;
+use futures::{channel::mpsc, AsyncRead, AsyncWrite, Future, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use transit::{TransitConnectError, TransitError};
+
+/* Records buffered for each channel before the other side has to wait */
+const CHANNEL_BUFFER: usize = 16;
+/* The biggest payload of a single record */
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ChannelError {
+    #[error("Something went wrong on the other side: {}", _0)]
+    PeerError(String),
+    /// Some deserialization went wrong, we probably got some garbage
+    #[error("Corrupt JSON message received")]
+    ProtocolJson(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    /// A generic string message for "something went wrong", i.e.
+    /// the peer sent some bullshit message order
+    #[error("Protocol error: {}", _0)]
+    Protocol(Box<str>),
+    /// The connection to the peer is gone, see [`connect`] for when that happens
+    #[error("The connection to the peer is closed")]
+    Closed,
+    #[error("Wormhole connection error")]
+    Wormhole(
+        #[from]
+        #[source]
+        WormholeError,
+    ),
+    #[error("Error while establishing transit connection")]
+    TransitConnect(
+        #[from]
+        #[source]
+        TransitConnectError,
+    ),
+    #[error("Transit error")]
+    Transit(
+        #[from]
+        #[source]
+        TransitError,
+    ),
+    #[error("IO error")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+}
+
+impl ChannelError {
+    fn protocol(message: impl Into<Box<str>>) -> Self {
+        Self::Protocol(message.into())
+    }
+}
+
+/* Sent over the wormhole, to set up the transit connection */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PeerMessage {
+    Transit {
+        abilities: transit::Abilities,
+        hints: transit::Hints,
+        /* The side with the bigger one leads the transit connection */
+        nonce: u64,
+    },
+    Error(String),
+}
+
+/* The records on the transit connection: a tag byte, the channel ID as big endian u64, and the rest */
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Frame {
+    Open { id: u64, name: String },
+    Data { id: u64, payload: Vec<u8> },
+    /* No more data from this side */
+    Close { id: u64 },
+    /* All channels of this side are gone, the session ends */
+    Goodbye,
+}
+
+impl Frame {
+    fn encode(&self) -> Box<[u8]> {
+        let (tag, id, rest): (u8, u64, &[u8]) = match self {
+            Frame::Open { id, name } => (0, *id, name.as_bytes()),
+            Frame::Data { id, payload } => (1, *id, payload),
+            Frame::Close { id } => (2, *id, &[]),
+            Frame::Goodbye => (3, 0, &[]),
+        };
+        let mut record = Vec::with_capacity(9 + rest.len());
+        record.push(tag);
+        record.extend_from_slice(&id.to_be_bytes());
+        record.extend_from_slice(rest);
+        record.into_boxed_slice()
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        let (&tag, record) = record.split_first()?;
+        let id = u64::from_be_bytes(record.get(..8)?.try_into().ok()?);
+        let rest = &record[8..];
+        Some(match tag {
+            0 => Frame::Open {
+                id,
+                name: String::from_utf8(rest.to_vec()).ok()?,
+            },
+            1 => Frame::Data {
+                id,
+                payload: rest.to_vec(),
+            },
+            2 => Frame::Close { id },
+            3 => Frame::Goodbye,
+            _ => return None,
+        })
+    }
+}
+
+/* What channels send to the session. Opening a channel comes with the queue for its incoming data */
+type Outgoing = (Frame, Option<mpsc::Sender<Vec<u8>>>);
+
+/**
+ * Set up a connection for channels to the other side
+ *
+ * Both sides call this on a connected wormhole, which gets closed once the transit connection is there.
+ * This returns the [`Channels`] to open and accept channels with, and the session that carries them. Run
+ * that future alongside, nothing happens otherwise. It finishes once all channels and the [`Channels`] are
+ * dropped on either side, or when the connection breaks.
+ */
+pub async fn connect(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+) -> Result<(Channels, impl Future<Output = Result<(), ChannelError>>), ChannelError> {
+    let abilities = transit::Abilities::ALL_ABILITIES;
+    let connector = transit::init(abilities, None, relay_hints).await?;
+    let nonce: u64 = rand::random();
+    wormhole
+        .send_json(&PeerMessage::Transit {
+            abilities,
+            hints: (**connector.our_hints()).clone(),
+            nonce,
+        })
+        .await?;
+
+    let (their_abilities, their_hints, leader) = match wormhole.receive_json().await?? {
+        PeerMessage::Transit {
+            abilities,
+            hints,
+            nonce: their_nonce,
+        } if their_nonce != nonce => (abilities, hints, nonce > their_nonce),
+        PeerMessage::Transit { .. } => {
+            let error = ChannelError::protocol("Both sides picked the same nonce");
+            let _ = wormhole
+                .send_json(&PeerMessage::Error(error.to_string()))
+                .await;
+            bail!(error)
+        },
+        PeerMessage::Error(err) => bail!(ChannelError::PeerError(err)),
+    };
+
+    let transit_key = wormhole.key().derive_transit_key(wormhole.appid());
+    let their_hints = Arc::new(their_hints);
+    let connected = if leader {
+        connector
+            .leader_connect(transit_key, their_abilities, their_hints)
+            .await
+    } else {
+        connector
+            .follower_connect(transit_key, their_abilities, their_hints)
+            .await
+    };
+    let (transit, info) = match connected {
+        Ok(connected) => connected,
+        Err(error) => {
+            let error = ChannelError::from(error);
+            let _ = wormhole
+                .send_json(&PeerMessage::Error(error.to_string()))
+                .await;
+            return Err(error);
+        },
+    };
+    transit_handler(info);
+    let wormhole = wormhole.close_for_transit().await?;
+
+    let (transit_tx, transit_rx) = transit.split();
+    let (channels, run) = session(leader, transit_tx, transit_rx);
+    let run = async move {
+        run.await?;
+        if let Some(wormhole) = wormhole {
+            wormhole.close().await?;
+        }
+        Ok(())
+    };
+    Ok((channels, run))
+}
+
+/* Everything after the transit connection is there */
+fn session(
+    leader: bool,
+    transit_tx: impl futures::Sink<Box<[u8]>, Error = TransitError>,
+    transit_rx: impl futures::Stream<Item = Result<Box<[u8]>, TransitError>>,
+) -> (Channels, impl Future<Output = Result<(), ChannelError>>) {
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(CHANNEL_BUFFER);
+    let (accept_tx, accept_rx) = mpsc::unbounded();
+    let channels = Channels {
+        outgoing: outgoing_tx,
+        accept: accept_rx,
+        /* Each side has its own IDs, so that opening channels at the same time does not collide */
+        next_id: if leader { 0 } else { 1 },
+    };
+    let run = run(leader, transit_tx, transit_rx, outgoing_rx, accept_tx);
+    (channels, run)
+}
+
+async fn run(
+    leader: bool,
+    transit_tx: impl futures::Sink<Box<[u8]>, Error = TransitError>,
+    transit_rx: impl futures::Stream<Item = Result<Box<[u8]>, TransitError>>,
+    mut outgoing: mpsc::Receiver<Outgoing>,
+    accept: mpsc::UnboundedSender<(u64, String, mpsc::Receiver<Vec<u8>>)>,
+) -> Result<(), ChannelError> {
+    futures::pin_mut!(transit_tx);
+    let transit_rx = transit_rx.fuse();
+    futures::pin_mut!(transit_rx);
+    /* Where the data of each channel goes, until the peer closes it or we drop it */
+    let mut channels: HashMap<u64, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut said_goodbye = false;
+
+    loop {
+        futures::select! {
+            record = transit_rx.next() => {
+                let record = record.ok_or(ChannelError::Closed)??;
+                match Frame::decode(&record) {
+                    Some(Frame::Open { id, name }) => {
+                        if id % 2 == u64::from(!leader) || channels.contains_key(&id) {
+                            bail!(ChannelError::protocol(format!("Invalid channel ID {}", id)));
+                        }
+                        let (incoming_tx, incoming_rx) = mpsc::channel(CHANNEL_BUFFER);
+                        if said_goodbye || accept.unbounded_send((id, name, incoming_rx)).is_err() {
+                            transit_tx.send(Frame::Close { id }.encode()).await?;
+                        } else {
+                            channels.insert(id, incoming_tx);
+                        }
+                    },
+                    Some(Frame::Data { id, payload }) => {
+                        /* Unknown channels got dropped on our side, nobody wants their data */
+                        if let Some(channel) = channels.get_mut(&id) {
+                            if channel.send(payload).await.is_err() {
+                                channels.remove(&id);
+                            }
+                        }
+                    },
+                    Some(Frame::Close { id }) => {
+                        channels.remove(&id);
+                    },
+                    Some(Frame::Goodbye) => {
+                        if !said_goodbye {
+                            transit_tx.send(Frame::Goodbye.encode()).await?;
+                        }
+                        break;
+                    },
+                    None => bail!(ChannelError::protocol("Malformed record")),
+                }
+            },
+            outgoing = outgoing.next() => match outgoing {
+                Some((frame, incoming)) => {
+                    if let (Frame::Open { id, .. }, Some(incoming)) = (&frame, incoming) {
+                        channels.insert(*id, incoming);
+                    }
+                    transit_tx.send(frame.encode()).await?;
+                },
+                /* Everything got dropped on our side. Wait for the peer to acknowledge */
+                None => {
+                    transit_tx.send(Frame::Goodbye.encode()).await?;
+                    said_goodbye = true;
+                },
+            },
+        }
+    }
+    transit_tx.close().await?;
+    Ok(())
+}
+
+/**
+ * Opens and accepts channels, see [`connect`]
+ *
+ * Dropping it does not affect the channels that are already open.
+ */
+#[derive(Debug)]
+pub struct Channels {
+    outgoing: mpsc::Sender<Outgoing>,
+    accept: mpsc::UnboundedReceiver<(u64, String, mpsc::Receiver<Vec<u8>>)>,
+    next_id: u64,
+}
+
+impl Channels {
+    /** Open a new channel. The peer gets it from [`accept`](Self::accept) */
+    pub async fn open(&mut self, name: impl Into<String>) -> Result<Channel, ChannelError> {
+        let id = self.next_id;
+        self.next_id += 2;
+        let name = name.into();
+        let (incoming_tx, incoming_rx) = mpsc::channel(CHANNEL_BUFFER);
+        self.outgoing
+            .send((
+                Frame::Open {
+                    id,
+                    name: name.clone(),
+                },
+                Some(incoming_tx),
+            ))
+            .await
+            .map_err(|_| ChannelError::Closed)?;
+        Ok(Channel::new(id, name, incoming_rx, self.outgoing.clone()))
+    }
+
+    /** The next channel the peer opened, or `None` once the session ended */
+    pub async fn accept(&mut self) -> Option<Channel> {
+        let (id, name, incoming) = self.accept.next().await?;
+        Some(Channel::new(id, name, incoming, self.outgoing.clone()))
+    }
+}
+
+/**
+ * A reliable, ordered byte stream to the peer
+ *
+ * Closing it ends the data towards the peer, which reads an end of file then. Reading still works until
+ * the peer closes it as well. Dropping it closes it in both directions.
+ */
+#[derive(Debug)]
+pub struct Channel {
+    id: u64,
+    name: String,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    /* The current payload, and how much of it got read already */
+    buffer: Vec<u8>,
+    position: usize,
+    outgoing: mpsc::Sender<Outgoing>,
+    closed: bool,
+}
+
+impl Channel {
+    fn new(
+        id: u64,
+        name: String,
+        incoming: mpsc::Receiver<Vec<u8>>,
+        outgoing: mpsc::Sender<Outgoing>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            incoming,
+            buffer: Vec::new(),
+            position: 0,
+            outgoing,
+            closed: false,
+        }
+    }
+
+    /** The name it got opened with */
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn broken_pipe() -> std::io::Error {
+    std::io::ErrorKind::BrokenPipe.into()
+}
+
+impl AsyncRead for Channel {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        while this.position == this.buffer.len() {
+            match futures::ready!(this.incoming.poll_next_unpin(cx)) {
+                Some(payload) => {
+                    this.buffer = payload;
+                    this.position = 0;
+                },
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let length = buf.len().min(this.buffer.len() - this.position);
+        buf[..length].copy_from_slice(&this.buffer[this.position..][..length]);
+        this.position += length;
+        Poll::Ready(Ok(length))
+    }
+}
+
+impl AsyncWrite for Channel {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if this.closed {
+            return Poll::Ready(Err(broken_pipe()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        futures::ready!(this.outgoing.poll_ready(cx)).map_err(|_| broken_pipe())?;
+        let length = buf.len().min(MAX_PAYLOAD_SIZE);
+        this.outgoing
+            .start_send((
+                Frame::Data {
+                    id: this.id,
+                    payload: buf[..length].to_vec(),
+                },
+                None,
+            ))
+            .map_err(|_| broken_pipe())?;
+        Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut()
+            .outgoing
+            .poll_flush_unpin(cx)
+            .map_err(|_| broken_pipe())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.closed {
+            futures::ready!(this.outgoing.poll_ready(cx)).map_err(|_| broken_pipe())?;
+            this.outgoing
+                .start_send((Frame::Close { id: this.id }, None))
+                .map_err(|_| broken_pipe())?;
+            this.closed = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        /* A fresh sender always has room for one message, and it stays behind the data sent before */
+        if !self.closed {
+            let _ = self
+                .outgoing
+                .clone()
+                .try_send((Frame::Close { id: self.id }, None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_frame() {
+        for frame in [
+            Frame::Open {
+                id: 7,
+                name: "chat".into(),
+            },
+            Frame::Data {
+                id: u64::MAX,
+                payload: vec![0, 1, 2],
+            },
+            Frame::Close { id: 0 },
+            Frame::Goodbye,
+        ] {
+            assert_eq!(Frame::decode(&frame.encode()), Some(frame));
+        }
+        assert_eq!(Frame::decode(&[1, 0, 0]), None);
+        assert_eq!(Frame::decode(&[9, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[async_std::test]
+    async fn test_channels() {
+        /* Two sessions wired to each other, as if over transit */
+        let (a_tx, b_rx) = mpsc::unbounded::<Box<[u8]>>();
+        let (b_tx, a_rx) = mpsc::unbounded::<Box<[u8]>>();
+        let sink = |tx: mpsc::UnboundedSender<Box<[u8]>>| {
+            tx.sink_map_err(|_| TransitError::IO(broken_pipe()))
+        };
+        let (mut a, a_run) = session(true, sink(a_tx), a_rx.map(Ok));
+        let (mut b, b_run) = session(false, sink(b_tx), b_rx.map(Ok));
+
+        let a_side = async move {
+            let mut chat = a.open("chat").await.unwrap();
+            chat.write_all(b"hello").await.unwrap();
+            chat.close().await.unwrap();
+            let mut answer = String::new();
+            chat.read_to_string(&mut answer).await.unwrap();
+            assert_eq!(answer, "hi");
+        };
+        let b_side = async move {
+            let mut chat = b.accept().await.unwrap();
+            assert_eq!(chat.name(), "chat");
+            let mut message = String::new();
+            chat.read_to_string(&mut message).await.unwrap();
+            assert_eq!(message, "hello");
+            chat.write_all(b"hi").await.unwrap();
+        };
+
+        let (a_result, b_result, (), ()) = util::timeout(
+            std::time::Duration::from_secs(10),
+            futures::future::join4(a_run, b_run, a_side, b_side),
+        )
+        .await
+        .expect("The session did not end");
+        a_result.unwrap();
+        b_result.unwrap();
+    }
+}
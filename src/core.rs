@@ -1,14 +1,17 @@
+mod claims;
 pub(super) mod key;
 pub mod rendezvous;
+mod seed;
 mod server_messages;
 #[cfg(test)]
 mod test;
-mod wordlist;
+pub(super) mod wordlist;
 
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 
 use self::rendezvous::*;
+pub use self::seed::Seed;
 pub(self) use self::server_messages::EncryptedMessage;
 use log::*;
 
@@ -44,6 +47,18 @@ pub enum WormholeError {
     Crypto,
     #[error("Nameplate is unclaimed: {}", _0)]
     UnclaimedNameplate(Nameplate),
+    #[error("Nobody used the code in time, it has expired")]
+    CodeExpired,
+    /// Connecting got aborted, see [`Wormhole::connect_with_cancel`]
+    #[error("Connecting got cancelled")]
+    Cancelled,
+    /// The peer sent a message that does not match the protocol, see [`Wormhole::set_strict`]
+    #[error("Malformed message received from peer")]
+    Malformed(
+        #[from]
+        #[source]
+        crate::SchemaViolation,
+    ),
 }
 
 impl WormholeError {
@@ -84,7 +99,9 @@ pub struct WormholeWelcome {
  *
  * # Clean shutdown
  *
- * TODO
+ * Call [`Wormhole::close`] once done, which releases the nameplate and closes the mailbox on the server.
+ * A wormhole that gets dropped without that does so in the background, on a best effort basis,
+ * see [`Wormhole::set_release_on_drop`].
  */
 /* TODO
  * Maybe a better way to handle application level protocols is to create a trait for them and then
@@ -99,6 +116,8 @@ pub struct MailboxConnection<V: serde::Serialize + Send + Sync + 'static> {
     server: RendezvousServer,
     /// The welcome message received from the mailbox server
     pub welcome: Option<String>,
+    /// Everything else the mailbox server told when connecting
+    server_welcome: ServerWelcome,
     /// The mailbox id of the created mailbox
     pub mailbox: Mailbox,
     /// The Code which is required to connect to the mailbox.
@@ -111,7 +130,9 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
     /// # Arguments
     ///
     /// * `config`: Application configuration
-    /// * `code_length`: number of words used for the password. The words are taken from the default wordlist.
+    /// * `code_length`: number of words used for the password. The words are taken from the default wordlist,
+    ///   see [`create_with_wordlist`](Self::create_with_wordlist) for using another one. Each word adds 8 bits
+    ///   of entropy, see [`Wordlist::entropy`](crate::Wordlist::entropy) for how many you need.
     ///
     /// # Examples
     ///
@@ -123,11 +144,22 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
     /// # Ok(()) })}
     /// ```
     pub async fn create(config: AppConfig<V>, code_length: usize) -> Result<Self, WormholeError> {
-        Self::create_with_password(
-            config,
-            &wordlist::default_wordlist(code_length).choose_words(),
-        )
-        .await
+        Self::create_with_wordlist(config, &wordlist::PgpWordlist, code_length).await
+    }
+
+    /// Create a connection to a mailbox which is configured with a `Code` starting with the nameplate and by a given number of random words from the given wordlist.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Application configuration
+    /// * `wordlist`: Where the words come from, for example a localized [`CustomWordlist`](crate::CustomWordlist)
+    /// * `code_length`: number of words used for the password
+    pub async fn create_with_wordlist(
+        config: AppConfig<V>,
+        wordlist: &dyn wordlist::Wordlist,
+        code_length: usize,
+    ) -> Result<Self, WormholeError> {
+        Self::create_with_password(config, &wordlist.choose_words(code_length)).await
     }
 
     /// Create a connection to a mailbox which is configured with a `Code` containing the nameplate and the given password.
@@ -150,8 +182,7 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         config: AppConfig<V>,
         password: &str,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
-            RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        let (mut server, server_welcome) = Self::connect_server(&config).await?;
         let (nameplate, mailbox) = server.allocate_claim_open().await?;
         let code = Code::new(&nameplate, password);
 
@@ -160,7 +191,8 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
             server,
             mailbox,
             code,
-            welcome,
+            welcome: server_welcome.motd.clone(),
+            server_welcome,
         })
     }
 
@@ -188,8 +220,7 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         code: Code,
         allocate: bool,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
-            RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        let (mut server, server_welcome) = Self::connect_server(&config).await?;
         let nameplate = code.nameplate();
         if !allocate {
             let nameplates = server.list_nameplates().await?;
@@ -205,10 +236,106 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
             server,
             mailbox,
             code,
-            welcome,
+            welcome: server_welcome.motd.clone(),
+            server_welcome,
         })
     }
 
+    /// Create a connection to the mailbox of two peers that met before, see [`Seed`]
+    ///
+    /// Both sides call this with the same seed, in any order. Instead of a code, the seed determines the
+    /// nameplate and the password, so it gets claimed if it does not exist yet. Get a new seed with
+    /// [`Wormhole::seed`] afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+    /// use magic_wormhole::{transfer::APP_CONFIG, MailboxConnection, Seed, Wormhole};
+    /// let seed: Seed = std::fs::read_to_string("peer.seed")?.parse()?;
+    /// let mailbox_connection = MailboxConnection::connect_with_seed(APP_CONFIG, &seed).await?;
+    /// let wormhole = Wormhole::connect(mailbox_connection).await?;
+    /// std::fs::write("peer.seed", wormhole.seed().to_hex())?;
+    /// # Ok(()) })}
+    /// ```
+    pub async fn connect_with_seed(
+        config: AppConfig<V>,
+        seed: &Seed,
+    ) -> Result<Self, WormholeError> {
+        Self::connect(config, seed.code(), true).await
+    }
+
+    /** Connect to the server of `config`, and set it up accordingly */
+    async fn connect_server(
+        config: &AppConfig<V>,
+    ) -> Result<(RendezvousServer, ServerWelcome), WormholeError> {
+        let connect = Box::pin(RendezvousServer::connect_with_client_version(
+            &config.id,
+            &config.rendezvous_url,
+            config.client_version.clone(),
+        ));
+        let (mut server, server_welcome) = match config.timeouts.server {
+            Some(timeout) => crate::util::timeout(timeout, connect)
+                .await
+                .map_err(|_| RendezvousError::Timeout)??,
+            None => connect.await?,
+        };
+        server.set_reconnect_policy(config.reconnect_policy);
+        server.set_connect_timeout(config.timeouts.server);
+        Ok((server, server_welcome))
+    }
+
+    /**
+     * What the mailbox server told when connecting
+     *
+     * This is available as soon as the connection is established, before waiting for the peer. Its
+     * [`motd`](rendezvous::ServerWelcome::motd) is the same as [`welcome`](Self::welcome).
+     * Servers that do not want this client to connect, for example because it is too old, make
+     * connecting fail with [`RendezvousError::Unwelcome`] instead.
+     */
+    pub fn server_welcome(&self) -> &ServerWelcome {
+        &self.server_welcome
+    }
+
+    /**
+     * How to deal with losing the connection to the rendezvous server, see [`ReconnectPolicy`]
+     *
+     * Defaults to the [`reconnect_policy`](AppConfig::reconnect_policy) of the config. The policy
+     * carries over to the [`Wormhole`] built from this connection.
+     */
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.server.set_reconnect_policy(policy);
+    }
+
+    /**
+     * Watch the attempts to get a lost connection to the rendezvous server back, see [`ReconnectEvent`]
+     *
+     * The stream keeps working for the [`Wormhole`] built from this connection.
+     */
+    pub fn reconnect_events(&mut self) -> impl futures::Stream<Item = ReconnectEvent> {
+        self.server.reconnect_events()
+    }
+
+    /**
+     * Record the claim of the mailbox in `store`, so that it can be cleaned up after a crash
+     *
+     * The claim gets recorded right away, and forgotten once the [`Wormhole`] built from this connection
+     * is closed. See [`ClaimStore`] for cleaning up after a restart.
+     */
+    pub fn set_claim_store(&mut self, store: std::sync::Arc<dyn ClaimStore>) {
+        self.server.set_claim_store(store);
+    }
+
+    /**
+     * Whether to release the mailbox in the background when dropped without shutting it down
+     *
+     * Enabled by default, see [`RendezvousServer::set_release_on_drop`]. The setting carries over to the
+     * [`Wormhole`] built from this connection.
+     */
+    pub fn set_release_on_drop(&mut self, enabled: bool) {
+        self.server.set_release_on_drop(enabled);
+    }
+
     /// Shut down the connection to the mailbox
     ///
     /// # Arguments
@@ -251,7 +378,7 @@ pub struct Wormhole {
      *
      * Not only is that probability low, but they also have only one try per connection and a failed
      * attempts will be noticed by both sides. Nevertheless, comparing the verifier mitigates that
-     * attack vector.
+     * attack vector. See [`Wormhole::connect_unverified`] for making the comparison mandatory.
      */
     pub verifier: Box<secretbox::Key>,
     /**
@@ -264,6 +391,164 @@ pub struct Wormhole {
      * (e.g. by the file transfer API).
      */
     pub peer_version: serde_json::Value,
+    peer_abilities: Vec<String>,
+    peer_dilation_versions: Vec<String>,
+    clock_skew: Option<ClockSkew>,
+    strict: bool,
+    keep_open_during_transit: bool,
+    /* Installed by the protocol that uses this wormhole, applies in strict mode only */
+    validator: Option<crate::schema::Validator>,
+    /* Both sides are debug builds, see [`Trace`] */
+    tracing: bool,
+    our_trace: Option<Trace>,
+    peer_trace: Option<Trace>,
+}
+
+/**
+ * A [`Wormhole`] whose verifier has yet to be compared, see [`Wormhole::connect_unverified`]
+ *
+ * The key exchange is done, but the wormhole is only handed out once the users confirmed that both sides
+ * show the same verifier. If they differ, somebody is in the middle and must not get to see any data.
+ */
+#[derive(Debug)]
+#[must_use = "Either confirm or abort the wormhole"]
+pub struct UnverifiedWormhole {
+    wormhole: Wormhole,
+}
+
+impl UnverifiedWormhole {
+    /** The verifier to compare with the other side, see [`Wormhole::verifier`] */
+    pub fn verifier(&self) -> &[u8] {
+        self.wormhole.verifier.as_slice()
+    }
+
+    /** The same verifier on both sides: go ahead */
+    pub fn confirm(self) -> Wormhole {
+        self.wormhole
+    }
+
+    /**
+     * The verifiers differ: close the mailbox as [`Mood::Scared`] without sending anything
+     *
+     * The peer does not learn about it unless the protocol on top has a message for that, so it may have
+     * to wait for a timeout.
+     */
+    pub async fn abort(self) -> Result<(), WormholeError> {
+        log::debug!("Verifiers did not match, closing the Wormhole");
+        self.wormhole
+            .server
+            .shutdown(Mood::Scared)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/* Wormhole-level ability, advertised by debug builds only */
+const TRACE_ABILITY: &str = "debug-trace-v1";
+
+/**
+ * Trace information attached to the messages of a wormhole
+ *
+ * If both sides are debug builds, they attach a `trace` field to every JSON message they send. It holds
+ * a random ID, the time of sending, and the ID of the last message received from the peer. Logging these
+ * on both sides lets distributed traces be stitched together, which helps finding out who waited for whom
+ * when a session stalls. The field is removed again before the message is handed to the protocol.
+ *
+ * See [`Wormhole::our_trace`] and [`Wormhole::peer_trace`].
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Trace {
+    /// Random ID of the message, in hex
+    pub id: String,
+    /// Time of sending, in milliseconds since the Unix epoch, on the sender's clock
+    pub timestamp: f64,
+    /// The ID of the last message the sender received before, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<String>,
+}
+
+impl Trace {
+    fn new(ack: Option<String>) -> Self {
+        Trace {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            timestamp: crate::util::unix_millis(),
+            ack,
+        }
+    }
+
+    /* Add the trace to a JSON object message. Other messages are passed through unchanged */
+    fn attach(&self, plaintext: Vec<u8>) -> Vec<u8> {
+        match serde_json::from_slice::<serde_json::Value>(&plaintext) {
+            Ok(serde_json::Value::Object(mut message)) => {
+                message.insert("trace".into(), serde_json::to_value(self).unwrap());
+                serde_json::to_vec(&message).unwrap()
+            },
+            _ => plaintext,
+        }
+    }
+
+    /* Remove the trace from a JSON object message, if it has one */
+    fn detach(plaintext: Vec<u8>) -> (Vec<u8>, Option<Self>) {
+        match serde_json::from_slice::<serde_json::Value>(&plaintext) {
+            Ok(serde_json::Value::Object(mut message)) => {
+                let trace = message
+                    .remove("trace")
+                    .and_then(|trace| serde_json::from_value(trace).ok());
+                match trace {
+                    Some(trace) => (serde_json::to_vec(&message).unwrap(), Some(trace)),
+                    None => (plaintext, None),
+                }
+            },
+            _ => (plaintext, None),
+        }
+    }
+}
+
+/**
+ * Estimated difference between the clocks of both sides
+ *
+ * Both sides send their current time along with the version information, at about the same time.
+ * Comparing that with our own clock tells how far the clocks are apart. This is useful for lining up
+ * the logs or events of both sides, for example when debugging stalled transfers.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct ClockSkew {
+    /// How many seconds the clock of the peer is ahead of ours. Negative if it is behind.
+    pub offset: f64,
+    /// The real offset may differ by up to this much, because the network latency is not known
+    pub uncertainty: std::time::Duration,
+}
+
+impl ClockSkew {
+    /* All times in milliseconds since the Unix epoch. We sent at `sent` and got the peer's `timestamp` at `received`. */
+    fn estimate(sent: f64, received: f64, timestamp: f64) -> Self {
+        let round_trip = (received - sent).max(0.0);
+        ClockSkew {
+            offset: (timestamp - (sent + round_trip / 2.0)) / 1000.0,
+            uncertainty: std::time::Duration::from_secs_f64(round_trip / 2000.0),
+        }
+    }
+
+    /** Convert a time of our clock to the corresponding time on the peer's clock */
+    pub fn to_peer_time(&self, time: std::time::SystemTime) -> std::time::SystemTime {
+        shift(time, self.offset)
+    }
+
+    /** Convert a time of the peer's clock (like a timestamp in its logs) to our clock */
+    pub fn from_peer_time(&self, time: std::time::SystemTime) -> std::time::SystemTime {
+        shift(time, -self.offset)
+    }
+}
+
+fn shift(time: std::time::SystemTime, seconds: f64) -> std::time::SystemTime {
+    let offset = std::time::Duration::from_secs_f64(seconds.abs());
+    if seconds >= 0.0 {
+        time + offset
+    } else {
+        time - offset
+    }
 }
 
 impl Wormhole {
@@ -327,9 +612,91 @@ impl Wormhole {
 
     /// Set up a Wormhole which is the client-client part of the connection setup
     ///
-    /// The MailboxConnection already contains a rendezvous server with an opened mailbox.
+    /// The MailboxConnection already contains a rendezvous server with an opened mailbox. If the peer does not
+    /// show up within [`WormholeTimeouts::peer`], this fails like [`connect_with_expiry`](Self::connect_with_expiry).
     pub async fn connect(
         mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+    ) -> Result<Self, WormholeError> {
+        Self::connect_inner(mailbox_connection, None, futures::future::pending()).await
+    }
+
+    /// Like [`connect`](Self::connect), but give up once `cancel` resolves
+    ///
+    /// Use this to abort waiting for the peer, e.g. on CTRL-C. The nameplate gets released and the mailbox
+    /// closed with [`Mood::Lonely`] before returning, so that the code can't be claimed by anybody else later
+    /// on. The call then fails with [`WormholeError::Cancelled`]. Simply dropping the future of
+    /// [`connect`](Self::connect) instead only releases them in the background, see
+    /// [`MailboxConnection::set_release_on_drop`].
+    pub async fn connect_with_cancel(
+        mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+        cancel: impl std::future::Future<Output = ()>,
+    ) -> Result<Self, WormholeError> {
+        Self::connect_inner(mailbox_connection, None, cancel).await
+    }
+
+    /// Like [`connect`](Self::connect), but give up if no peer shows up within `expiry`
+    ///
+    /// This is meant for unattended senders: once the time is up, the nameplate gets released and the
+    /// mailbox closed with [`Mood::Lonely`], so that the code can't be used anymore. The call then
+    /// fails with [`WormholeError::CodeExpired`].
+    pub async fn connect_with_expiry(
+        mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+        expiry: std::time::Duration,
+    ) -> Result<Self, WormholeError> {
+        Self::connect_inner(mailbox_connection, Some(expiry), futures::future::pending()).await
+    }
+
+    /// Like [`connect`](Self::connect), but hold back the wormhole until its verifier got compared
+    ///
+    /// This makes comparing the verifiers a mandatory step, for example in a GUI: show
+    /// [`verifier`](UnverifiedWormhole::verifier) on both sides and let the users
+    /// [`confirm`](UnverifiedWormhole::confirm) or [`abort`](UnverifiedWormhole::abort). Nothing can be
+    /// exchanged with the peer before.
+    pub async fn connect_unverified(
+        mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+    ) -> Result<UnverifiedWormhole, WormholeError> {
+        Self::connect(mailbox_connection)
+            .await
+            .map(|wormhole| UnverifiedWormhole { wormhole })
+    }
+
+    /// Allocate a code and connect with it, starting over with a fresh code if that fails
+    ///
+    /// A connection attempt fails if the key exchange fails (someone mistyped or guessed the code), or if no peer
+    /// showed up within `expiry`. Each failed code gets released before the next one is allocated, and
+    /// `code_handler` is called with every new mailbox so that its code can be shown. After `attempts` failed
+    /// attempts, the last error is returned. Other errors are returned right away.
+    pub async fn connect_with_retries<V: serde::Serialize + Clone + Send + Sync + 'static>(
+        config: AppConfig<V>,
+        code_length: usize,
+        attempts: usize,
+        expiry: Option<std::time::Duration>,
+        mut code_handler: impl FnMut(&MailboxConnection<V>),
+    ) -> Result<Self, WormholeError> {
+        let mut attempt = 1;
+        loop {
+            let mailbox_connection = MailboxConnection::create(config.clone(), code_length).await?;
+            code_handler(&mailbox_connection);
+            match Self::connect_inner(mailbox_connection, expiry, futures::future::pending()).await
+            {
+                Err(WormholeError::PakeFailed | WormholeError::CodeExpired)
+                    if attempt < attempts =>
+                {
+                    log::debug!(
+                        "Connection attempt {} failed, trying again with a new code",
+                        attempt
+                    );
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    pub(crate) async fn connect_inner(
+        mailbox_connection: MailboxConnection<impl serde::Serialize + Send + Sync + 'static>,
+        expiry: Option<std::time::Duration>,
+        cancel: impl std::future::Future<Output = ()>,
     ) -> Result<Self, WormholeError> {
         let MailboxConnection {
             config,
@@ -337,61 +704,156 @@ impl Wormhole {
             mailbox: _mailbox,
             code,
             welcome: _welcome,
+            server_welcome: _server_welcome,
         } = mailbox_connection;
+        let expiry = expiry.or(config.timeouts.peer);
+
+        let handshake = Box::pin(Self::handshake(&mut server, &config, &code, expiry));
+        futures::pin_mut!(cancel);
+        /* None if cancelled. Dropping the handshake gives the server back to us */
+        let result = match futures::future::select(handshake, cancel).await {
+            futures::future::Either::Left((result, _)) => Some(result),
+            futures::future::Either::Right(((), _)) => None,
+        };
+        let (key, versions, clock_skew) = match result {
+            Some(Ok(result)) => result,
+            Some(Err(error)) => {
+                match error {
+                    WormholeError::CodeExpired => server.shutdown(Mood::Lonely).await?,
+                    WormholeError::PakeFailed => {
+                        let _ = server.shutdown(Mood::Scared).await;
+                    },
+                    _ => (),
+                }
+                return Err(error);
+            },
+            None => {
+                log::debug!("Connecting got cancelled, releasing the code");
+                server.shutdown(Mood::Lonely).await?;
+                return Err(WormholeError::Cancelled);
+            },
+        };
 
+        let tracing =
+            cfg!(debug_assertions) && versions.abilities.iter().any(|a| a == TRACE_ABILITY);
+        if tracing {
+            log::debug!("Both sides are debug builds, attaching trace IDs to the messages");
+        }
+
+        log::debug!("Found peer on the rendezvous server.");
+
+        /* We are now fully initialized! Up and running! :tada: */
+        Ok(Self {
+            server,
+            appid: config.id,
+            phase: 0,
+            key: key::Key::new(key.into()),
+            verifier: Box::new(key::derive_verifier(&key)),
+            our_version: Box::new(config.app_version),
+            peer_version: versions.app_versions,
+            peer_abilities: versions.abilities,
+            peer_dilation_versions: versions.can_dilate,
+            clock_skew,
+            strict: false,
+            keep_open_during_transit: false,
+            validator: None,
+            tracing,
+            our_trace: None,
+            peer_trace: None,
+        })
+    }
+
+    /**
+     * The client-client handshake: key exchange, then versions. Returns the key and the peer's versions.
+     *
+     * Shutting down the mailbox on failure is up to the caller.
+     */
+    async fn handshake(
+        server: &mut RendezvousServer,
+        config: &AppConfig<impl serde::Serialize>,
+        code: &Code,
+        expiry: Option<std::time::Duration>,
+    ) -> Result<(secretbox::Key, key::VersionsMessage, Option<ClockSkew>), WormholeError> {
         /* Send PAKE */
         let (pake_state, pake_msg_ser) = key::make_pake(&code.0, &config.id);
         server.send_peer_message(Phase::PAKE, pake_msg_ser).await?;
 
         /* Receive PAKE */
-        let peer_pake = key::extract_pake_msg(&server.next_peer_message_some().await?.body)?;
-        let key = pake_state
-            .finish(&peer_pake)
-            .map_err(|_| WormholeError::PakeFailed)
-            .map(|key| *secretbox::Key::from_slice(&key))?;
+        let peer_pake = match expiry {
+            Some(expiry) => {
+                match crate::util::timeout(expiry, server.next_peer_message_some()).await {
+                    Ok(message) => message?,
+                    Err(_) => {
+                        log::debug!("Nobody claimed the code within {:?}", expiry);
+                        return Err(WormholeError::CodeExpired);
+                    },
+                }
+            },
+            None => server.next_peer_message_some().await?,
+        };
+        let peer_pake = key::extract_pake_msg(&peer_pake.body)?;
+        let key = match pake_state.finish(&peer_pake) {
+            Ok(key) => *secretbox::Key::from_slice(&key),
+            Err(_) => return Err(WormholeError::PakeFailed),
+        };
 
         /* Send versions message */
         let mut versions = key::VersionsMessage::new();
+        if cfg!(debug_assertions) {
+            versions.abilities.push(TRACE_ABILITY.into());
+        }
         versions.set_app_versions(serde_json::to_value(&config.app_version).unwrap());
+        let sent = crate::util::unix_millis();
+        versions.timestamp = Some(sent);
         let (version_phase, version_msg) = key::build_version_msg(server.side(), &key, &versions);
         server.send_peer_message(version_phase, version_msg).await?;
         let peer_version = server.next_peer_message_some().await?;
+        let received = crate::util::unix_millis();
 
         /* Handle received message */
-        let versions: key::VersionsMessage = peer_version
-            .decrypt(&key)
-            .ok_or(WormholeError::PakeFailed)
-            .and_then(|plaintext| {
-                serde_json::from_slice(&plaintext).map_err(WormholeError::ProtocolJson)
-            })?;
-
-        let peer_version = versions.app_versions;
+        let versions: key::VersionsMessage = match peer_version.decrypt(&key) {
+            Some(plaintext) => serde_json::from_slice(&plaintext)?,
+            /* Most likely the peer mistyped the code, or someone guessed wrong */
+            None => return Err(WormholeError::PakeFailed),
+        };
+
+        let clock_skew = versions
+            .timestamp
+            .map(|timestamp| ClockSkew::estimate(sent, received, timestamp));
+        if let Some(clock_skew) = &clock_skew {
+            log::debug!("Estimated clock skew to the peer: {:?}", clock_skew);
+        }
 
         if server.needs_nameplate_release() {
             server.release_nameplate().await?;
         }
 
-        log::info!("Found peer on the rendezvous server.");
-
-        /* We are now fully initialized! Up and running! :tada: */
-        Ok(Self {
-            server,
-            appid: config.id,
-            phase: 0,
-            key: key::Key::new(key.into()),
-            verifier: Box::new(key::derive_verifier(&key)),
-            our_version: Box::new(config.app_version),
-            peer_version,
-        })
+        Ok((key, versions, clock_skew))
     }
 
-    /** TODO */
-    pub async fn connect_with_seed() {
-        todo!()
+    /// Meet a peer again with the [`Seed`] of an earlier wormhole
+    ///
+    /// Shorthand for [`MailboxConnection::connect_with_seed`] followed by [`connect`](Self::connect).
+    pub async fn connect_with_seed(
+        config: AppConfig<impl serde::Serialize + Send + Sync + 'static>,
+        seed: &Seed,
+    ) -> Result<Self, WormholeError> {
+        Self::connect(MailboxConnection::connect_with_seed(config, seed).await?).await
     }
 
     /** Send an encrypted message to peer */
-    pub async fn send(&mut self, plaintext: Vec<u8>) -> Result<(), WormholeError> {
+    pub async fn send(&mut self, mut plaintext: Vec<u8>) -> Result<(), WormholeError> {
+        if self.tracing {
+            let trace = Trace::new(self.peer_trace.as_ref().map(|trace| trace.id.clone()));
+            log::debug!(
+                "Sending message {} with trace {} (acknowledging {:?})",
+                self.phase,
+                trace.id,
+                trace.ack
+            );
+            plaintext = trace.attach(plaintext);
+            self.our_trace = Some(trace);
+        }
         let phase_string = Phase::numeric(self.phase);
         self.phase += 1;
         let data_key = key::derive_phase_key(self.server.side(), &self.key, &phase_string);
@@ -432,9 +894,26 @@ impl Wormhole {
             }
 
             // TODO maybe reorder incoming messages by phase numeral?
-            let decrypted_message = peer_message
+            let mut decrypted_message = peer_message
                 .decrypt(&self.key)
                 .ok_or(WormholeError::Crypto)?;
+            if self.tracing {
+                let (message, trace) = Trace::detach(decrypted_message);
+                decrypted_message = message;
+                if let Some(trace) = trace {
+                    log::debug!(
+                        "Received message {} with trace {} (acknowledging {:?}), sent {:.0}ms ago",
+                        peer_message.phase,
+                        trace.id,
+                        trace.ack,
+                        self.trace_age(&trace),
+                    );
+                    self.peer_trace = Some(trace);
+                }
+            }
+            if let (true, Some(validate)) = (self.strict, self.validator) {
+                validate(&decrypted_message)?;
+            }
 
             // Send to client
             return Ok(decrypted_message);
@@ -463,6 +942,79 @@ impl Wormhole {
         })
     }
 
+    /**
+     * Ping the rendezvous server to keep the connection alive
+     *
+     * Useful while waiting for a user decision: some NATs and firewalls drop
+     * connections that have been idle for a minute or two.
+     */
+    pub async fn ping(&mut self) -> Result<(), WormholeError> {
+        self.server.ping().await.map_err(Into::into)
+    }
+
+    /** How to deal with losing the connection to the rendezvous server, see [`ReconnectPolicy`] */
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.server.set_reconnect_policy(policy);
+    }
+
+    /** Watch the attempts to get a lost connection to the rendezvous server back, see [`ReconnectEvent`] */
+    pub fn reconnect_events(&mut self) -> impl futures::Stream<Item = ReconnectEvent> {
+        self.server.reconnect_events()
+    }
+
+    /** Record the claim of the mailbox until it gets closed, see [`ClaimStore`] */
+    pub fn set_claim_store(&mut self, store: std::sync::Arc<dyn ClaimStore>) {
+        self.server.set_claim_store(store);
+    }
+
+    /** Whether to release the mailbox in the background when dropped without [`close`](Self::close) */
+    pub fn set_release_on_drop(&mut self, enabled: bool) {
+        self.server.set_release_on_drop(enabled);
+    }
+
+    /**
+     * Whether to keep the mailbox open while a transit connection is in use
+     *
+     * Protocols that move on to a transit connection, like the file transfer, the port forwarding and
+     * [`channels`](crate::channels), close the wormhole as soon as that is established. This frees the
+     * connection to the rendezvous server right away, which is the default. Keeping it open instead leaves
+     * a way to reach the peer should the transit connection fail, at the cost of a socket and a claim on
+     * the server. It then gets closed once the transfer is done, or released in the background, see
+     * [`set_release_on_drop`](Self::set_release_on_drop).
+     *
+     * Version 1 of the file transfer always keeps it open until the transfer is done.
+     */
+    pub fn set_keep_open_during_transit(&mut self, enabled: bool) {
+        self.keep_open_during_transit = enabled;
+    }
+
+    /** Whether the mailbox [stays open during transit](Self::set_keep_open_during_transit) */
+    pub fn keeps_open_during_transit(&self) -> bool {
+        self.keep_open_during_transit
+    }
+
+    /* The transit connection is up: close the wormhole, or hand it back to be kept around until the end */
+    #[allow(dead_code)]
+    pub(crate) async fn close_for_transit(self) -> Result<Option<Self>, WormholeError> {
+        if self.keep_open_during_transit {
+            log::debug!("Keeping the Wormhole open during transit");
+            Ok(Some(self))
+        } else {
+            self.close().await?;
+            Ok(None)
+        }
+    }
+
+    /**
+     * Live view on the timing of the rendezvous server
+     *
+     * Tells whether slowness comes from the server or from the peer, see [`MailboxStats`]. Get it
+     * before handing the wormhole to a transfer, for example for [`SessionReporter::watch_mailbox`](crate::transit::SessionReporter::watch_mailbox).
+     */
+    pub fn mailbox_stats_handle(&self) -> MailboxStatsHandle {
+        self.server.stats_handle()
+    }
+
     pub async fn close(self) -> Result<(), WormholeError> {
         log::debug!("Closing Wormhole…");
         self.server.shutdown(Mood::Happy).await.map_err(Into::into)
@@ -483,6 +1035,95 @@ impl Wormhole {
     pub fn key(&self) -> &key::Key<key::WormholeKey> {
         &self.key
     }
+
+    /**
+     * A long-term secret to connect to the same peer again later, see [`Seed`]
+     *
+     * Both sides get the same seed. Store it, and use it with [`Wormhole::connect_with_seed`] instead of a
+     * new code.
+     */
+    pub fn seed(&self) -> Seed {
+        Seed::derive(&self.key)
+    }
+
+    /**
+     * How far the clock of the peer is off from ours
+     *
+     * `None` if the peer did not send its time, which other implementations don't.
+     */
+    pub fn clock_skew(&self) -> Option<ClockSkew> {
+        self.clock_skew
+    }
+
+    /**
+     * The abilities the peer advertised on the wormhole level
+     *
+     * Unlike the [`peer_version`](Self::peer_version), these are independent of the [`AppID`].
+     */
+    pub fn peer_abilities(&self) -> &[String] {
+        &self.peer_abilities
+    }
+
+    /**
+     * The dilation protocol versions the peer supports, if any
+     *
+     * This implementation does not speak dilation (yet), but other implementations do.
+     */
+    pub fn peer_dilation_versions(&self) -> &[String] {
+        &self.peer_dilation_versions
+    }
+
+    /**
+     * Whether both sides attach [`Trace`]s to their messages
+     *
+     * This gets negotiated while connecting, and is only the case if both sides are debug builds of this
+     * implementation.
+     */
+    pub fn is_tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /** The trace attached to the last message we sent, if [tracing](Self::is_tracing) */
+    pub fn our_trace(&self) -> Option<&Trace> {
+        self.our_trace.as_ref()
+    }
+
+    /** The trace attached to the last message we received, if [tracing](Self::is_tracing) */
+    pub fn peer_trace(&self) -> Option<&Trace> {
+        self.peer_trace.as_ref()
+    }
+
+    /* Milliseconds since the peer sent the traced message, corrected by the clock skew if known */
+    fn trace_age(&self, trace: &Trace) -> f64 {
+        let offset = self.clock_skew.map(|skew| skew.offset).unwrap_or(0.0);
+        crate::util::unix_millis() - (trace.timestamp - offset * 1000.0)
+    }
+
+    /**
+     * Reject malformed messages from the peer
+     *
+     * In strict mode, the protocols of this crate check every message from the peer against the schema
+     * of the protocol, before acting on it. This covers the field types, the ranges of numbers, and fields
+     * or variants that the protocol does not know. Offending messages get logged verbatim and turned into a
+     * [`SchemaViolation`](crate::SchemaViolation) error, which also holds the message. This applies to the
+     * messages exchanged over the wormhole as well as those over the transit connection.
+     *
+     * Off by default, as the protocols otherwise tolerate extensions by other implementations.
+     */
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /** Whether [strict mode](Self::set_strict) is on */
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /* Check the messages we receive in strict mode */
+    #[allow(dead_code)]
+    pub(crate) fn set_validator(&mut self, validator: crate::schema::Validator) {
+        self.validator = Some(validator);
+    }
 }
 
 // the serialized forms of these variants are part of the wire protocol, so
@@ -517,6 +1158,12 @@ pub struct AppConfig<V> {
     pub id: AppID,
     pub rendezvous_url: Cow<'static, str>,
     pub app_version: V,
+    /** How to introduce ourselves to the rendezvous server, usually [`ClientVersion::DEFAULT`] */
+    pub client_version: ClientVersion,
+    /** How to deal with losing the connection to the rendezvous server, usually [`ReconnectPolicy::INTERACTIVE`] */
+    pub reconnect_policy: ReconnectPolicy,
+    /** How long to wait for the server and the peer, usually [`WormholeTimeouts::DEFAULT`] */
+    pub timeouts: WormholeTimeouts,
 }
 
 impl<V> AppConfig<V> {
@@ -529,6 +1176,51 @@ impl<V> AppConfig<V> {
         self.rendezvous_url = rendezvous_url;
         self
     }
+
+    pub fn client_version(mut self, client_version: ClientVersion) -> Self {
+        self.client_version = client_version;
+        self
+    }
+
+    pub fn reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: WormholeTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+}
+
+/**
+ * Time limits for setting up a [`Wormhole`], see [`AppConfig::timeouts`]
+ *
+ * Without them, a hung rendezvous server or a peer that never shows up block forever, which does not
+ * work for unattended services. The transit connection and the session on top of it have their own
+ * limits, see [`Timeouts`](crate::transit::Timeouts).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WormholeTimeouts {
+    /// Connecting to the rendezvous server, and each attempt to reconnect. Fails with
+    /// [`RendezvousError::Timeout`]. One minute by default.
+    pub server: Option<std::time::Duration>,
+    /// Waiting for the peer to show up with the code, like [`Wormhole::connect_with_expiry`]. No limit by default.
+    pub peer: Option<std::time::Duration>,
+}
+
+impl WormholeTimeouts {
+    pub const DEFAULT: Self = Self {
+        server: Some(std::time::Duration::from_secs(60)),
+        peer: None,
+    };
+}
+
+impl Default for WormholeTimeouts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 impl<V: serde::Serialize> AppConfig<V> {
@@ -538,6 +1230,38 @@ impl<V: serde::Serialize> AppConfig<V> {
     }
 }
 
+/**
+ * How a client introduces itself to the rendezvous server
+ *
+ * The name of the implementation and its version, sent along when binding to the server. Server
+ * operators use it to see which clients are out there, and to debug version specific issues.
+ * Applications may override it with their own name and version, see [`AppConfig::client_version`].
+ */
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, derive_more::Display)]
+#[display(fmt = "{} {}", _0, _1)]
+pub struct ClientVersion(pub Cow<'static, str>, pub Cow<'static, str>);
+
+impl ClientVersion {
+    /** This library, as `rust` and the crate version */
+    pub const DEFAULT: Self = Self(
+        Cow::Borrowed("rust"),
+        Cow::Borrowed(env!("CARGO_PKG_VERSION")),
+    );
+
+    pub fn new(
+        implementation: impl Into<Cow<'static, str>>,
+        version: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self(implementation.into(), version.into())
+    }
+}
+
+impl Default for ClientVersion {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 /// Newtype wrapper for application IDs
 ///
 /// The application ID is a string that scopes all commands
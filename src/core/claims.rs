@@ -0,0 +1,142 @@
+//! Remember claimed nameplates and open mailboxes across crashes
+//!
+//! A process that dies while holding a nameplate leaves it claimed on the server until it expires, and
+//! the mailbox stays open. With a [`ClaimStore`] set on the connection, see
+//! [`MailboxConnection::set_claim_store`](crate::MailboxConnection::set_claim_store), each claim gets
+//! recorded and forgotten again once the mailbox is closed. After a restart, the
+//! [`leftovers`](ClaimStore::leftovers) can be released with [`RendezvousServer::release_claim`], or
+//! opened again with [`RendezvousServer::reattach`].
+
+use super::{rendezvous::RendezvousServer, AppID, Mailbox, MySide, Nameplate};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// A claimed nameplate and open mailbox, as recorded in a [`ClaimStore`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct ClaimRecord {
+    /// The rendezvous server the claim is on
+    pub relay_url: String,
+    pub appid: AppID,
+    /// The side that holds the claim. Releasing or reattaching binds with it again.
+    pub side: MySide,
+    /// `None` once released, or if the mailbox got opened directly
+    pub nameplate: Option<Nameplate>,
+    pub mailbox: Mailbox,
+}
+
+impl ClaimRecord {
+    pub(super) fn of(server: &RendezvousServer) -> Option<Self> {
+        let (nameplate, mailbox) = server.claim()?;
+        Some(Self {
+            relay_url: server.relay_url().into(),
+            appid: server.appid().clone(),
+            side: server.side().clone(),
+            nameplate: nameplate.cloned(),
+            mailbox: mailbox.clone(),
+        })
+    }
+}
+
+/**
+ * Where to record the claims of a connection
+ *
+ * Records are keyed by their [`side`](ClaimRecord::side). Failing to write one only gets logged, as it
+ * must not fail the connection.
+ */
+pub trait ClaimStore: Send + Sync {
+    /** A mailbox got opened, or the claim changed (like its nameplate got released) */
+    fn record(&self, claim: &ClaimRecord) -> std::io::Result<()>;
+
+    /** The mailbox of this side got closed, forget about it */
+    fn forget(&self, side: &MySide) -> std::io::Result<()>;
+
+    /** All claims that did not get forgotten, for example because the process crashed */
+    fn leftovers(&self) -> std::io::Result<Vec<ClaimRecord>>;
+}
+
+/**
+ * A [`ClaimStore`] backed by a JSON file
+ *
+ * The whole file gets rewritten on every change, which is fine for the handful of claims a process
+ * usually holds at once.
+ */
+#[derive(Debug)]
+pub struct FileClaimStore {
+    path: std::path::PathBuf,
+    /* Serializes read-modify-write cycles within this process */
+    lock: Mutex<()>,
+}
+
+impl FileClaimStore {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> std::io::Result<Vec<ClaimRecord>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Vec<ClaimRecord>)) -> std::io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let mut claims = self.read()?;
+        change(&mut claims);
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&claims)?)
+    }
+}
+
+impl ClaimStore for FileClaimStore {
+    fn record(&self, claim: &ClaimRecord) -> std::io::Result<()> {
+        self.update(|claims| {
+            claims.retain(|other| other.side != claim.side);
+            claims.push(claim.clone());
+        })
+    }
+
+    fn forget(&self, side: &MySide) -> std::io::Result<()> {
+        self.update(|claims| claims.retain(|claim| claim.side != *side))
+    }
+
+    fn leftovers(&self) -> std::io::Result<Vec<ClaimRecord>> {
+        let _lock = self.lock.lock().unwrap();
+        self.read()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_claim_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claims.json");
+        let store = FileClaimStore::new(&path);
+        assert_eq!(store.leftovers().unwrap(), vec![]);
+
+        let side = MySide::generate();
+        let mut claim = ClaimRecord {
+            relay_url: "ws://localhost:4000/v1".into(),
+            appid: AppID::new("example.com/test"),
+            side: side.clone(),
+            nameplate: Some(Nameplate::new("4")),
+            mailbox: Mailbox("mailbox".into()),
+        };
+        store.record(&claim).unwrap();
+        /* Releasing the nameplate replaces the record */
+        claim.nameplate = None;
+        store.record(&claim).unwrap();
+        assert_eq!(store.leftovers().unwrap(), vec![claim]);
+
+        store.forget(&side).unwrap();
+        assert_eq!(store.leftovers().unwrap(), vec![]);
+    }
+}
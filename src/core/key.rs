@@ -108,6 +108,12 @@ pub struct VersionsMessage {
     pub abilities: Vec<String>,
     #[serde(default)]
     pub app_versions: serde_json::Value,
+    /// Dilation protocol versions, sent by implementations that support dilation
+    #[serde(default, rename = "can-dilate", skip_serializing_if = "Vec::is_empty")]
+    pub can_dilate: Vec<String>,
+    /// Time of sending, in milliseconds since the Unix epoch. Only sent by this implementation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<f64>,
     // resume: Option<WormholeResume>,
 }
 
@@ -227,6 +233,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_versions_message() {
+        /* As sent by the Python implementation */
+        let versions: VersionsMessage = serde_json::from_str(
+            r#"{"app_versions": {}, "can-dilate": ["1"], "dilation-abilities": [{"type": "direct-tcp-v1"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(versions.can_dilate, vec!["1".to_string()]);
+        assert!(versions.abilities.is_empty());
+
+        let json = serde_json::to_string(&VersionsMessage::new()).unwrap();
+        assert!(!json.contains("can-dilate"));
+    }
+
     #[test]
     fn test_derive_key() {
         let main = secretbox::Key::from_exact_iter(
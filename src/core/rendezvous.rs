@@ -1,15 +1,28 @@
 //! Implementation of the Client-to-Server part
 //!
 //! Wormhole builds upon this, so you usually don't need to bother.
+//!
+//! Every [`RendezvousServer`] has its own websocket connection, even when a process opens many
+//! wormholes at once. Sharing one connection is not possible: the server binds a connection to a
+//! single `appid` and side, and takes only one `claim`, `release`, `open` and `close` per connection.
+//! This rules out taking turns on one connection as well, as the second wormhole could neither claim
+//! its nameplate nor open its mailbox. Multiplexing would need support from the server protocol first.
 
 #[cfg(not(target_family = "wasm"))]
 use async_tungstenite::tungstenite as ws2;
 use futures::prelude::*;
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+pub use crate::core::claims::{ClaimRecord, ClaimStore, FileClaimStore};
 use crate::core::{
-    server_messages::{InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission},
-    AppID, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
+    server_messages::{
+        InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission, WelcomeMessage,
+    },
+    AppID, ClientVersion, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
 };
 
 /// Some rendezvous server you might use.
@@ -39,6 +52,20 @@ pub enum RendezvousError {
         _0
     )]
     Login(Vec<String>),
+    /// The server does not want us to connect, for example because this client is too old
+    #[error("The server refused the connection: {}", _0)]
+    Unwelcome(Box<str>),
+    /// The connection to the server got lost and could not be established again within the
+    /// [`ReconnectPolicy`]. This is terminal, every further operation fails the same way.
+    #[error(
+        "Lost the connection to the server, gave up after {} attempts to reconnect: {}",
+        attempts,
+        reason
+    )]
+    Failed { attempts: u32, reason: Box<str> },
+    /// Connecting to the server took longer than [`WormholeTimeouts::server`](crate::WormholeTimeouts::server)
+    #[error("The server did not answer in time")]
+    Timeout,
     #[cfg(not(target_family = "wasm"))]
     #[error("Websocket IO error")]
     IO(
@@ -70,6 +97,11 @@ impl RendezvousError {
     pub(self) fn server(error: impl Into<Box<str>>) -> Self {
         Self::Server(error.into())
     }
+
+    /* Whether reconnecting may help */
+    fn is_connection_lost(&self) -> bool {
+        matches!(self, Self::IO(_))
+    }
 }
 
 type MessageQueue = VecDeque<EncryptedMessage>;
@@ -80,7 +112,9 @@ struct NameplateList(Vec<Nameplate>);
 
 #[cfg(not(target_family = "wasm"))]
 struct WsConnection {
-    connection: async_tungstenite::WebSocketStream<async_tungstenite::async_std::ConnectStream>,
+    /* Boxed, since the TLS state makes it large and wormholes get moved around a lot */
+    connection:
+        Box<async_tungstenite::WebSocketStream<async_tungstenite::async_std::ConnectStream>>,
 }
 
 #[cfg(target_family = "wasm")]
@@ -90,6 +124,88 @@ struct WsConnection {
 }
 
 impl WsConnection {
+    /** Open a connection, do the permission negotiation part if required, and bind it */
+    async fn open(
+        appid: &AppID,
+        relay_url: &str,
+        side: &MySide,
+        client_version: ClientVersion,
+    ) -> Result<(Self, ServerWelcome), RendezvousError> {
+        let mut connection;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            #[cfg(feature = "native-certs")]
+            let (stream, _) = async_tungstenite::async_std::connect_async_with_tls_connector(
+                relay_url,
+                Some(crate::tls::connector()),
+            )
+            .await?;
+            #[cfg(not(feature = "native-certs"))]
+            let (stream, _) = async_tungstenite::async_std::connect_async(relay_url).await?;
+            connection = WsConnection {
+                connection: Box::new(stream),
+            };
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
+            connection = WsConnection {
+                meta,
+                connection: stream,
+            };
+        }
+
+        let mut welcome = match connection.receive_message_some().await? {
+            InboundMessage::Welcome { welcome } => welcome,
+            other => {
+                return Err(RendezvousError::protocol(format!(
+                    "First message server sends must be 'welcome', but was '{}'",
+                    other
+                )))
+            },
+        };
+
+        let permission_required = welcome.permission_required.take();
+        let welcome = ServerWelcome::from_message(welcome).map_err(RendezvousError::Unwelcome)?;
+
+        match permission_required {
+            Some(PermissionRequired {
+                hashcash: Some(hashcash),
+                ..
+            }) => {
+                let token = crate::util::hashcash(hashcash.resource, hashcash.bits);
+                connection
+                    .send_message(
+                        &OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
+                            stamp: token.to_string(),
+                        }),
+                        None,
+                    )
+                    .await?;
+            },
+            Some(PermissionRequired { none: true, .. }) => (),
+            Some(PermissionRequired { other, .. }) => {
+                /* We can't actually log in :/ */
+                return Err(RendezvousError::Login(
+                    // TODO use `into_keys` once stable and remove the `cloned`
+                    other.keys().cloned().collect(),
+                ));
+            },
+            None => (),
+        }
+
+        connection
+            .send_message(
+                &OutboundMessage::bind(appid.clone(), side.clone(), client_version),
+                None,
+            )
+            .await?;
+
+        Ok((connection, welcome))
+    }
+
     #[cfg(not(target_family = "wasm"))]
     async fn send_message(
         &mut self,
@@ -202,11 +318,12 @@ impl WsConnection {
 
     #[cfg(not(target_family = "wasm"))]
     async fn receive_message(&mut self) -> Result<Option<InboundMessage>, RendezvousError> {
+        /* The stream ends when the connection got dropped without closing it properly */
         let message = self
             .connection
             .next()
             .await
-            .expect("TODO this should always be Some")?;
+            .unwrap_or(Err(ws2::Error::ConnectionClosed))?;
         match message {
             ws2::Message::Text(message_plain) => {
                 let message = serde_json::from_str(&message_plain)?;
@@ -217,6 +334,8 @@ impl WsConnection {
                         Ok(None)
                     },
                     InboundMessage::Error { error, orig: _ } => Err(RendezvousError::server(error)),
+                    /* Replies to our keep-alive pings carry no information */
+                    InboundMessage::Pong { .. } => Ok(None),
                     message => Ok(Some(message)),
                 }
             },
@@ -239,11 +358,12 @@ impl WsConnection {
 
     #[cfg(target_family = "wasm")]
     async fn receive_message(&mut self) -> Result<Option<InboundMessage>, RendezvousError> {
+        /* The stream ends when the connection got closed */
         let message = self
             .connection
             .next()
             .await
-            .expect("TODO this should always be Some");
+            .ok_or(ws_stream_wasm::WsErr::ConnectionNotOpen)?;
         match message {
             ws_stream_wasm::WsMessage::Text(message_plain) => {
                 let message = serde_json::from_str(&message_plain)?;
@@ -254,6 +374,8 @@ impl WsConnection {
                         Ok(None)
                     },
                     InboundMessage::Error { error, orig: _ } => Err(RendezvousError::server(error)),
+                    /* Replies to our keep-alive pings carry no information */
+                    InboundMessage::Pong { .. } => Ok(None),
                     message => Ok(Some(message)),
                 }
             },
@@ -265,7 +387,7 @@ impl WsConnection {
 
     #[cfg(not(target_family = "wasm"))]
     async fn close(&mut self) -> Result<(), ws2::Error> {
-        self.connection.close(None).await
+        async_tungstenite::WebSocketStream::close(&mut self.connection, None).await
     }
 
     #[cfg(target_family = "wasm")]
@@ -294,6 +416,8 @@ struct MailboxMachine {
     mailbox: Mailbox,
     queue: MessageQueue,
     processed: std::collections::HashSet<Phase>,
+    /** Messages we sent that did not come back as echo yet, to send again after reconnecting */
+    pending: Vec<(Phase, Vec<u8>)>,
 }
 
 impl MailboxMachine {
@@ -307,7 +431,8 @@ impl MailboxMachine {
                 false
             }
         } else {
-            // Echo of ours. Ignore
+            // Echo of ours. The server has it, so no need to send it again
+            self.pending.retain(|(phase, _)| *phase != message.phase);
             false
         }
     }
@@ -317,6 +442,19 @@ pub struct RendezvousServer {
     connection: WsConnection,
     state: Option<MailboxMachine>,
     side: MySide,
+    /* What we need to connect again */
+    appid: AppID,
+    relay_url: Box<str>,
+    client_version: ClientVersion,
+    reconnect: ReconnectPolicy,
+    /* For each attempt to reconnect, see `set_connect_timeout` */
+    connect_timeout: Option<std::time::Duration>,
+    reconnect_events: Option<futures::channel::mpsc::UnboundedSender<ReconnectEvent>>,
+    /* Attempts and reason once reconnecting failed for good */
+    failed: Option<(u32, Box<str>)>,
+    stats: MailboxStatsHandle,
+    claim_store: Option<Arc<dyn ClaimStore>>,
+    release_on_drop: bool,
 }
 
 impl std::fmt::Debug for RendezvousServer {
@@ -324,100 +462,562 @@ impl std::fmt::Debug for RendezvousServer {
         fmt.debug_struct("RendezvousServer")
             .field("state", &self.state)
             .field("side", &self.side)
+            .field("reconnect", &self.reconnect)
             .finish()
     }
 }
 
+/**
+ * What the server tells every client when connecting
+ *
+ * See [`MailboxConnection::server_welcome`](crate::MailboxConnection::server_welcome).
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerWelcome {
+    /** A message from the server (think of "message of the day"). Should be displayed to the user if present. */
+    pub motd: Option<String>,
+    /** The latest release of the Python client. Informative only, this client has its own versioning. */
+    pub current_cli_version: Option<String>,
+}
+
+impl ServerWelcome {
+    /** Refuse to go on if the server signals an error, like the Python client does. Returns that error. */
+    #[allow(deprecated)]
+    pub(crate) fn from_message(welcome: WelcomeMessage) -> Result<Self, Box<str>> {
+        if let Some(error) = welcome.error {
+            return Err(error.into());
+        }
+        Ok(Self {
+            motd: welcome.motd,
+            current_cli_version: welcome.current_cli_version,
+        })
+    }
+}
+
+/**
+ * How hard to try getting the connection to the server back once it got lost
+ *
+ * Reconnecting only happens once a mailbox is open, as until then there is nothing to lose by
+ * simply connecting again. The waiting time between attempts starts at `initial_backoff` and doubles
+ * with every attempt, up to `max_backoff`. With `jitter`, each wait is randomly shortened by up to
+ * half, so that many clients losing their connection at once don't all come back at the same time.
+ * Once `max_attempts` attempts in a row failed, or the next attempt would start after the `deadline`,
+ * the connection fails for good with [`RendezvousError::Failed`].
+ *
+ * The default is [`INTERACTIVE`](Self::INTERACTIVE), see [`AppConfig::reconnect_policy`](crate::AppConfig::reconnect_policy)
+ * or [`RendezvousServer::set_reconnect_policy`] to change it. Attempts can be watched with
+ * [`RendezvousServer::reconnect_events`].
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReconnectPolicy {
+    /// Give up after this many failed attempts in a row. `0` disables reconnecting.
+    pub max_attempts: u32,
+    /// How long to wait before the first attempt
+    pub initial_backoff: std::time::Duration,
+    /// Upper bound for the time between two attempts
+    pub max_backoff: std::time::Duration,
+    /// Randomly shorten each wait by up to half of it
+    pub jitter: bool,
+    /// Give up once reconnecting took this long, counted from losing the connection
+    pub deadline: Option<std::time::Duration>,
+}
+
+impl ReconnectPolicy {
+    /** Fail on the first connection loss, like older versions did */
+    pub const NEVER: Self = Self::new(0, std::time::Duration::ZERO, std::time::Duration::ZERO);
+
+    /** Give up quickly, so that a user waiting in front of the screen learns about it */
+    pub const INTERACTIVE: Self = Self::new(
+        3,
+        std::time::Duration::from_secs(1),
+        std::time::Duration::from_secs(4),
+    )
+    .with_jitter(true);
+
+    /** Keep trying for around half an hour, for unattended use */
+    pub const DAEMON: Self = Self::new(
+        30,
+        std::time::Duration::from_secs(1),
+        std::time::Duration::from_secs(60),
+    )
+    .with_jitter(true)
+    .with_deadline(Some(std::time::Duration::from_secs(30 * 60)));
+
+    pub const fn new(
+        max_attempts: u32,
+        initial_backoff: std::time::Duration,
+        max_backoff: std::time::Duration,
+    ) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff,
+            max_backoff,
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    pub const fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub const fn with_deadline(mut self, deadline: Option<std::time::Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /* How long to wait before the next attempt, given the backoff without jitter */
+    pub(super) fn wait(&self, backoff: std::time::Duration) -> std::time::Duration {
+        if self.jitter {
+            backoff.mul_f64(1.0 - rand::random::<f64>() / 2.0)
+        } else {
+            backoff
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::INTERACTIVE
+    }
+}
+
+/**
+ * Progress of getting the connection to the server back, see [`RendezvousServer::reconnect_events`]
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReconnectEvent {
+    /** The connection got lost, or the previous attempt failed. The next attempt starts after `wait`. */
+    Reconnecting {
+        attempt: u32,
+        wait: std::time::Duration,
+        error: Box<str>,
+    },
+    /** The connection is back after this many attempts */
+    Reconnected { attempts: u32 },
+    /** Out of attempts or time, the connection failed with [`RendezvousError::Failed`] */
+    GaveUp { attempts: u32 },
+}
+
+/**
+ * Timing of the mailbox server, to tell a slow server apart from a peer that is not there
+ *
+ * The server acknowledges every message we send, the time until then is the ack latency. It also
+ * stamps the messages of the peer when they arrive and when it hands them on to us. A long wait in
+ * between means that the message sat in the mailbox until we came along, while a long ack latency
+ * means that the server itself is slow.
+ */
+#[derive(Clone, Debug, Default, PartialEq, serde_derive::Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct MailboxStats {
+    /// Number of our messages the server acknowledged
+    pub acks: u32,
+    /// Average time until the server acknowledged, in milliseconds when serialized
+    #[serde(serialize_with = "as_millis_option")]
+    pub mean_ack_latency: Option<Duration>,
+    /// Longest time until the server acknowledged, in milliseconds when serialized
+    #[serde(serialize_with = "as_millis_option")]
+    pub max_ack_latency: Option<Duration>,
+    /// Number of messages received from the peer
+    pub peer_messages: u32,
+    /// When the latest message of the peer reached the server, in seconds since the epoch by the server's clock
+    pub last_server_rx: Option<f64>,
+    /// When the server sent the latest message of the peer on to us, like `last_server_rx`
+    pub last_server_tx: Option<f64>,
+    /// Longest time a message of the peer waited on the server, in milliseconds when serialized
+    #[serde(serialize_with = "as_millis_option")]
+    pub max_peer_message_wait: Option<Duration>,
+    #[serde(skip)]
+    total_ack_latency: Duration,
+}
+
+/**
+ * Live view on the [`MailboxStats`] of a connection
+ *
+ * It keeps counting while the connection is in use, and stays readable after the [`Wormhole`](crate::Wormhole)
+ * has been handed to a transfer.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct MailboxStatsHandle(Arc<Mutex<MailboxStats>>);
+
+/** Handles are equal if they count for the same connection */
+impl PartialEq for MailboxStatsHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MailboxStatsHandle {}
+
+impl MailboxStatsHandle {
+    pub fn get(&self) -> MailboxStats {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn count_ack(&self, latency: Duration) {
+        let mut stats = self.0.lock().unwrap();
+        stats.acks += 1;
+        stats.total_ack_latency += latency;
+        stats.mean_ack_latency = Some(stats.total_ack_latency / stats.acks);
+        stats.max_ack_latency = stats.max_ack_latency.max(Some(latency));
+    }
+
+    fn count_peer_message(&self, message: &EncryptedMessage) {
+        let mut stats = self.0.lock().unwrap();
+        stats.peer_messages += 1;
+        stats.last_server_rx = message.server_rx;
+        stats.last_server_tx = message.server_tx;
+        /* Both stamps come from the server's clock, so skew does not matter */
+        if let (Some(rx), Some(tx)) = (message.server_rx, message.server_tx) {
+            let wait = tx - rx;
+            if wait.is_finite() && (0.0..1e9).contains(&wait) {
+                stats.max_peer_message_wait = stats
+                    .max_peer_message_wait
+                    .max(Some(Duration::from_secs_f64(wait)));
+            }
+        }
+    }
+}
+
+fn as_millis_option<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => ser.serialize_some(&(duration.as_millis() as u64)),
+        None => ser.serialize_none(),
+    }
+}
+
 impl RendezvousServer {
     /**
      * Connect to the rendezvous server
      *
      * This does the permission negotiation part if required and binds the
-     * connection to the given `appid`.
+     * connection to the given `appid`. Fails with [`RendezvousError::Unwelcome`]
+     * if the server does not want us to connect.
      */
     pub async fn connect(
         appid: &AppID,
         relay_url: &str,
-    ) -> Result<(Self, Option<String>), RendezvousError> {
-        let side = MySide::generate();
-        let mut connection;
-
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let (stream, _) = async_tungstenite::async_std::connect_async(relay_url).await?;
-            connection = WsConnection { connection: stream };
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        {
-            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
-            connection = WsConnection {
-                meta,
-                connection: stream,
-            };
-        }
-
-        let welcome = match connection.receive_message_some().await? {
-            InboundMessage::Welcome { welcome } => welcome,
-            other => {
-                return Err(RendezvousError::protocol(format!(
-                    "First message server sends must be 'welcome', but was '{}'",
-                    other
-                )))
-            },
-        };
+    ) -> Result<(Self, ServerWelcome), RendezvousError> {
+        Self::connect_with_client_version(appid, relay_url, ClientVersion::DEFAULT).await
+    }
 
-        match welcome.permission_required {
-            Some(PermissionRequired {
-                hashcash: Some(hashcash),
-                ..
-            }) => {
-                let token = crate::util::hashcash(hashcash.resource, hashcash.bits);
-                connection
-                    .send_message(
-                        &OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
-                            stamp: token.to_string(),
-                        }),
-                        None,
-                    )
-                    .await?;
-            },
-            Some(PermissionRequired { none: true, .. }) => (),
-            Some(PermissionRequired { other, .. }) => {
-                /* We can't actually log in :/ */
-                return Err(RendezvousError::Login(
-                    // TODO use `into_keys` once stable and remove the `cloned`
-                    other.keys().cloned().collect(),
-                ));
-            },
-            None => (),
-        }
+    /** Like [`connect`](Self::connect), but introduce ourselves as someone else than [`ClientVersion::DEFAULT`] */
+    pub async fn connect_with_client_version(
+        appid: &AppID,
+        relay_url: &str,
+        client_version: ClientVersion,
+    ) -> Result<(Self, ServerWelcome), RendezvousError> {
+        Self::connect_as(appid, relay_url, client_version, MySide::generate()).await
+    }
 
-        connection
-            .send_message(&OutboundMessage::bind(appid.clone(), side.clone()), None)
-            .await?;
+    async fn connect_as(
+        appid: &AppID,
+        relay_url: &str,
+        client_version: ClientVersion,
+        side: MySide,
+    ) -> Result<(Self, ServerWelcome), RendezvousError> {
+        let (connection, welcome) =
+            WsConnection::open(appid, relay_url, &side, client_version.clone()).await?;
 
-        log::info!("Connected to rendezvous server.");
+        log::debug!("Connected to rendezvous server.");
 
         Ok((
             Self {
                 connection,
                 state: None,
                 side,
+                appid: appid.clone(),
+                relay_url: relay_url.into(),
+                client_version,
+                reconnect: ReconnectPolicy::default(),
+                connect_timeout: None,
+                reconnect_events: None,
+                failed: None,
+                stats: Default::default(),
+                claim_store: None,
+                release_on_drop: true,
             },
-            welcome.motd,
+            welcome,
         ))
     }
 
+    /**
+     * Open the mailbox of a claim again, for example one left behind by a crash
+     *
+     * This binds with the side of the claim, claims its nameplate again if it still holds it, and opens
+     * the mailbox. The server then sends all messages in the mailbox once more, including those that got
+     * already processed before.
+     */
+    pub async fn reattach(claim: &ClaimRecord) -> Result<(Self, ServerWelcome), RendezvousError> {
+        let (mut server, welcome) = Self::connect_as(
+            &claim.appid,
+            &claim.relay_url,
+            ClientVersion::DEFAULT,
+            claim.side.clone(),
+        )
+        .await?;
+        if let Some(nameplate) = &claim.nameplate {
+            server
+                .send_message(&OutboundMessage::claim(nameplate.clone()))
+                .await?;
+            match server.receive_reply().await? {
+                RendezvousReply::Claimed(mailbox) if mailbox == claim.mailbox => (),
+                other => return Err(RendezvousError::invalid_message("claimed", other)),
+            };
+        }
+        server
+            .send_message(&OutboundMessage::open(claim.mailbox.clone()))
+            .await?;
+        server.state = Some(MailboxMachine {
+            nameplate: claim.nameplate.clone(),
+            mailbox: claim.mailbox.clone(),
+            queue: Default::default(),
+            processed: Default::default(),
+            pending: Default::default(),
+        });
+        Ok((server, welcome))
+    }
+
+    /**
+     * Release the nameplate of a claim and close its mailbox, for example one left behind by a crash
+     *
+     * Forget it in the [`ClaimStore`] afterwards.
+     */
+    pub async fn release_claim(claim: &ClaimRecord, mood: Mood) -> Result<(), RendezvousError> {
+        let (mut server, _welcome) = Self::connect_as(
+            &claim.appid,
+            &claim.relay_url,
+            ClientVersion::DEFAULT,
+            claim.side.clone(),
+        )
+        .await?;
+        /* A failed release must not spawn another one when this connection gets dropped */
+        server.set_release_on_drop(false);
+        server.state = Some(MailboxMachine {
+            nameplate: claim.nameplate.clone(),
+            mailbox: claim.mailbox.clone(),
+            queue: Default::default(),
+            processed: Default::default(),
+            pending: Default::default(),
+        });
+        server.shutdown(mood).await
+    }
+
+    /**
+     * Record the claim of this connection in `store`, until the mailbox gets closed
+     *
+     * A claim that already exists gets recorded right away.
+     */
+    pub fn set_claim_store(&mut self, store: Arc<dyn ClaimStore>) {
+        self.claim_store = Some(store);
+        self.record_claim();
+    }
+
+    /* The claimed nameplate, if any, and the open mailbox */
+    pub(super) fn claim(&self) -> Option<(Option<&Nameplate>, &Mailbox)> {
+        let state = self.state.as_ref()?;
+        Some((state.nameplate.as_ref(), &state.mailbox))
+    }
+
+    pub(super) fn relay_url(&self) -> &str {
+        &self.relay_url
+    }
+
+    pub(super) fn appid(&self) -> &AppID {
+        &self.appid
+    }
+
+    fn record_claim(&self) {
+        if let (Some(store), Some(claim)) = (&self.claim_store, ClaimRecord::of(self)) {
+            if let Err(err) = store.record(&claim) {
+                log::warn!("Failed to record the claim of {}: {}", claim.mailbox, err);
+            }
+        }
+    }
+
     /** A random unique string for this session */
     pub fn side(&self) -> &MySide {
         &self.side
     }
 
+    /** How to deal with losing the connection to the server once the mailbox is open */
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect = policy;
+    }
+
+    /** Give up on each attempt to connect again after `timeout`, so that a hung server does not block forever */
+    pub fn set_connect_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.connect_timeout = timeout;
+    }
+
+    /**
+     * Watch the attempts to get a lost connection back, instead of only learning about the outcome
+     *
+     * Only the stream of the latest call gets the events.
+     */
+    pub fn reconnect_events(&mut self) -> impl futures::Stream<Item = ReconnectEvent> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.reconnect_events = Some(sender);
+        receiver
+    }
+
+    fn emit(&self, event: ReconnectEvent) {
+        if let Some(events) = &self.reconnect_events {
+            /* Nobody listening any more is fine */
+            let _ = events.unbounded_send(event);
+        }
+    }
+
+    /**
+     * Whether to release the nameplate and close the mailbox when dropped without [`shutdown`](Self::shutdown)
+     *
+     * Enabled by default, so that forgetting to shut down does not keep them claimed on the server until
+     * they expire. Disable it to keep the claim around, for example to [`reattach`](Self::reattach) later.
+     */
+    pub fn set_release_on_drop(&mut self, enabled: bool) {
+        self.release_on_drop = enabled;
+    }
+
+    /* Once reconnecting failed, there is no way back */
+    fn failure(&self) -> Option<RendezvousError> {
+        let (attempts, reason) = self.failed.as_ref()?;
+        Some(RendezvousError::Failed {
+            attempts: *attempts,
+            reason: reason.clone(),
+        })
+    }
+
+    /**
+     * Get the connection back after losing it with `error`, or return that error if it was something else
+     *
+     * This binds again with the same side, claims the nameplate again if we still hold it and opens
+     * the mailbox again. The server then sends us all messages of the mailbox once more: those from the
+     * peer we have already seen get dropped, and those of ours that never came back as echo get sent again.
+     */
+    async fn reconnect(&mut self, error: RendezvousError) -> Result<(), RendezvousError> {
+        if !error.is_connection_lost() || self.state.is_none() || self.reconnect.max_attempts == 0 {
+            return Err(error);
+        }
+
+        let start = instant::Instant::now();
+        let mut last_error = error;
+        let mut backoff = self.reconnect.initial_backoff;
+        let mut attempts = 0;
+        while attempts < self.reconnect.max_attempts {
+            let wait = self.reconnect.wait(backoff);
+            if let Some(deadline) = self.reconnect.deadline {
+                if start.elapsed() + wait > deadline {
+                    log::warn!("Reconnecting to the rendezvous server ran out of time.");
+                    break;
+                }
+            }
+            attempts += 1;
+            log::warn!(
+                "Lost the connection to the rendezvous server ({}), reconnecting in {:?} (attempt {} of {})",
+                last_error,
+                wait,
+                attempts,
+                self.reconnect.max_attempts
+            );
+            self.emit(ReconnectEvent::Reconnecting {
+                attempt: attempts,
+                wait,
+                error: last_error.to_string().into(),
+            });
+            crate::util::sleep(wait).await;
+            match self.reopen().await {
+                Ok(()) => {
+                    log::info!("Reconnected to the rendezvous server.");
+                    self.emit(ReconnectEvent::Reconnected { attempts });
+                    return Ok(());
+                },
+                Err(error) if error.is_connection_lost() => last_error = error,
+                Err(error) => return Err(error),
+            }
+            backoff = std::cmp::min(backoff.saturating_mul(2), self.reconnect.max_backoff);
+        }
+
+        use std::error::Error;
+        let reason = last_error
+            .source()
+            .map_or_else(|| last_error.to_string(), ToString::to_string);
+        self.emit(ReconnectEvent::GaveUp { attempts });
+        self.failed = Some((attempts, reason.into()));
+        Err(self.failure().unwrap())
+    }
+
+    async fn reopen(&mut self) -> Result<(), RendezvousError> {
+        let open = Box::pin(WsConnection::open(
+            &self.appid,
+            &self.relay_url,
+            &self.side,
+            self.client_version.clone(),
+        ));
+        let (connection, _welcome) = match self.connect_timeout {
+            Some(timeout) => crate::util::timeout(timeout, open)
+                .await
+                .map_err(|_| RendezvousError::Timeout)??,
+            None => open.await?,
+        };
+        self.connection = connection;
+
+        let state = self
+            .state
+            .as_mut()
+            .expect("Can only reconnect when having a claimed+open mailbox");
+        if let Some(nameplate) = state.nameplate.clone() {
+            self.connection
+                .send_message(&OutboundMessage::claim(nameplate), Some(&mut state.queue))
+                .await?;
+            match self
+                .connection
+                .receive_reply(Some(&mut state.queue))
+                .await?
+            {
+                RendezvousReply::Claimed(mailbox) if mailbox == state.mailbox => (),
+                other => return Err(RendezvousError::invalid_message("claimed", other)),
+            };
+        }
+        self.connection
+            .send_message(
+                &OutboundMessage::open(state.mailbox.clone()),
+                Some(&mut state.queue),
+            )
+            .await?;
+        for (phase, body) in state.pending.clone() {
+            self.connection
+                .send_message(
+                    &OutboundMessage::Add { body, phase },
+                    Some(&mut state.queue),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /** Live view on the timing of the server, see [`MailboxStats`] */
+    pub fn stats_handle(&self) -> MailboxStatsHandle {
+        self.stats.clone()
+    }
+
     async fn send_message(&mut self, message: &OutboundMessage) -> Result<(), RendezvousError> {
+        if let Some(error) = self.failure() {
+            return Err(error);
+        }
+        let start = instant::Instant::now();
         self.connection
             .send_message(message, self.state.as_mut().map(|state| &mut state.queue))
-            .await
+            .await?;
+        self.stats.count_ack(start.elapsed());
+        Ok(())
     }
 
     async fn receive_reply(&mut self) -> Result<RendezvousReply, RendezvousError> {
@@ -426,13 +1026,62 @@ impl RendezvousServer {
             .await
     }
 
+    /**
+     * Send `message` and wait for the reply, sending it again if the connection got lost in between
+     *
+     * Only for requests that may be repeated, and only works once the mailbox is open, like
+     * reconnecting in general.
+     */
+    async fn request(
+        &mut self,
+        message: &OutboundMessage,
+    ) -> Result<RendezvousReply, RendezvousError> {
+        loop {
+            let reply = match self.send_message(message).await {
+                Ok(()) => self.receive_reply().await,
+                Err(error) => Err(error),
+            };
+            match reply {
+                Err(error) => Box::pin(self.reconnect(error)).await?,
+                reply => return reply,
+            }
+        }
+    }
+
     pub async fn send_peer_message(
         &mut self,
         phase: Phase,
         body: Vec<u8>,
     ) -> Result<(), RendezvousError> {
-        self.send_message(&OutboundMessage::Add { body, phase })
+        if let Some(state) = &mut self.state {
+            state.pending.push((phase.clone(), body.clone()));
+        }
+        match self
+            .send_message(&OutboundMessage::Add { body, phase })
+            .await
+        {
+            /* Reconnecting sends it again, as it did not come back as echo yet */
+            Err(error) => Box::pin(self.reconnect(error)).await,
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /**
+     * Ping the server
+     *
+     * This is an application-level keep-alive, to prevent NATs and firewalls from dropping
+     * an idle connection. The server's pong reply is discarded once it arrives.
+     */
+    pub async fn ping(&mut self) -> Result<(), RendezvousError> {
+        match self
+            .send_message(&OutboundMessage::Ping {
+                ping: rand::random(),
+            })
             .await
+        {
+            Err(error) => Box::pin(self.reconnect(error)).await,
+            Ok(()) => Ok(()),
+        }
     }
 
     pub async fn next_peer_message_some(&mut self) -> Result<EncryptedMessage, RendezvousError> {
@@ -444,20 +1093,32 @@ impl RendezvousServer {
     }
 
     pub async fn next_peer_message(&mut self) -> Result<Option<EncryptedMessage>, RendezvousError> {
+        if let Some(error) = self.failure() {
+            return Err(error);
+        }
         let machine = &mut self
             .state
             .as_mut()
             .expect("Can only receive messages when having a claimed+open mailbox");
         if let Some(message) = machine.queue.pop_front() {
             if machine.receive_message(&message, &self.side) {
+                self.stats.count_peer_message(&message);
                 return Ok(Some(message));
             } else {
                 return Ok(None);
             }
         }
-        match self.connection.receive_message().await? {
+        let message = match self.connection.receive_message().await {
+            Ok(message) => message,
+            Err(error) => {
+                Box::pin(self.reconnect(error)).await?;
+                return Ok(None);
+            },
+        };
+        match message {
             Some(InboundMessage::Message(message)) => {
                 if machine.receive_message(&message, &self.side) {
+                    self.stats.count_peer_message(&message);
                     Ok(Some(message))
                 } else {
                     Ok(None)
@@ -499,7 +1160,9 @@ impl RendezvousServer {
             mailbox: mailbox.clone(),
             queue: Default::default(),
             processed: Default::default(),
+            pending: Default::default(),
         });
+        self.record_claim();
         Ok((nameplate, mailbox))
     }
 
@@ -525,7 +1188,9 @@ impl RendezvousServer {
             mailbox: mailbox.clone(),
             queue: Default::default(),
             processed: Default::default(),
+            pending: Default::default(),
         });
+        self.record_claim();
         Ok(mailbox)
     }
 
@@ -557,13 +1222,15 @@ impl RendezvousServer {
             .expect("Can only release an allocated nameplate, and only once");
 
         use std::ops::Deref;
-        self.send_message(&OutboundMessage::release(nameplate.deref().deref()))
-            .await?;
-        match self.receive_reply().await? {
+        match self
+            .request(&OutboundMessage::release(nameplate.deref().deref()))
+            .await?
+        {
             RendezvousReply::Released => (),
             other => return Err(RendezvousError::invalid_message("released", other)),
         };
         self.state.as_mut().unwrap().nameplate = None;
+        self.record_claim();
         Ok(())
     }
 
@@ -584,38 +1251,75 @@ impl RendezvousServer {
             mailbox,
             queue: Default::default(),
             processed: Default::default(),
+            pending: Default::default(),
         });
+        self.record_claim();
         Ok(())
     }
 
     pub async fn shutdown(mut self, mood: Mood) -> Result<(), RendezvousError> {
-        if let Some(MailboxMachine {
-            nameplate,
-            mailbox,
-            mut queue,
-            ..
-        }) = self.state
-        {
-            if let Some(nameplate) = nameplate {
-                self.connection
-                    .send_message(&OutboundMessage::release(nameplate), Some(&mut queue))
-                    .await?;
-                match self.connection.receive_reply(Some(&mut queue)).await? {
-                    RendezvousReply::Released => (),
-                    other => return Err(RendezvousError::invalid_message("released", other)),
-                };
+        if let Some(error) = self.failure() {
+            return Err(error);
+        }
+        if let Some(state) = &self.state {
+            let mailbox = state.mailbox.clone();
+            if self.needs_nameplate_release() {
+                self.release_nameplate().await?;
             }
 
-            self.connection
-                .send_message(&OutboundMessage::close(mailbox, mood), Some(&mut queue))
-                .await?;
-            match self.connection.receive_reply(Some(&mut queue)).await? {
+            match self.request(&OutboundMessage::close(mailbox, mood)).await? {
                 RendezvousReply::Closed => (),
                 other => return Err(RendezvousError::invalid_message("closed", other)),
             };
+            /* Closed, so there is nothing left to release when dropped */
+            self.state = None;
+            if let Some(store) = &self.claim_store {
+                if let Err(err) = store.forget(&self.side) {
+                    log::warn!("Failed to forget the claim of {}: {}", self.side, err);
+                }
+            }
         }
 
         self.connection.close().await?;
         Ok(())
     }
 }
+
+impl Drop for RendezvousServer {
+    /* A mailbox that is still open here did not get shut down. Release it from a fresh connection in
+     * the background, which is best effort only: it gets lost if the process exits right away. */
+    fn drop(&mut self) {
+        if !self.release_on_drop {
+            return;
+        }
+        let Some(claim) = ClaimRecord::of(self) else {
+            return;
+        };
+
+        #[cfg(not(target_family = "wasm"))]
+        {
+            log::debug!(
+                "Mailbox {} was not closed, releasing it in the background",
+                claim.mailbox
+            );
+            let claim_store = self.claim_store.clone();
+            async_std::task::spawn(async move {
+                match Self::release_claim(&claim, Mood::Errory).await {
+                    Ok(()) => {
+                        if let Some(store) = claim_store {
+                            if let Err(err) = store.forget(&claim.side) {
+                                log::warn!("Failed to forget the claim of {}: {}", claim.side, err);
+                            }
+                        }
+                    },
+                    Err(err) => log::warn!("Failed to release mailbox {}: {}", claim.mailbox, err),
+                }
+            });
+        }
+        #[cfg(target_family = "wasm")]
+        log::warn!(
+            "Mailbox {} was not closed, it stays claimed until it expires on the server",
+            claim.mailbox
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Connect again later without a new code
+//!
+//! After two peers met once, both can derive the same [`Seed`] from the wormhole with
+//! [`Wormhole::seed`](super::Wormhole::seed) and store it. Later on, they meet again with
+//! [`MailboxConnection::connect_with_seed`](super::MailboxConnection::connect_with_seed) instead of a code:
+//! the seed determines a nameplate and a password, which nobody else knows. Both sides claim that
+//! nameplate, so either one may come first.
+
+use super::{key, Code, Nameplate};
+use crypto_secretbox as secretbox;
+use std::fmt;
+
+/**
+ * A long-term secret of two peers that met before
+ *
+ * Keep it as secret as a private key: whoever has it can pose as either side. It is stored as a hex
+ * string, see [`to_hex`](Self::to_hex) and its [`FromStr`](std::str::FromStr) implementation. Every
+ * wormhole connected with a seed yields a new one, so peers may rotate it each time.
+ */
+#[derive(Clone, PartialEq, Eq)]
+pub struct Seed(Box<secretbox::Key>);
+
+impl Seed {
+    pub(super) fn derive(key: &secretbox::Key) -> Self {
+        Self(Box::new(key::derive_key(key, b"wormhole:seed")))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(*self.0)
+    }
+
+    /**
+     * The code to meet with
+     *
+     * The nameplate is a big number, so that it does not collide with allocated ones. The password has
+     * the full entropy of the seed.
+     */
+    pub(super) fn code(&self) -> Code {
+        let nameplate = key::derive_key(&self.0, b"wormhole:seed-nameplate");
+        let nameplate = u64::from_be_bytes(nameplate[..8].try_into().unwrap());
+        let password = key::derive_key(&self.0, b"wormhole:seed-password");
+        Code::new(
+            &Nameplate::new(&nameplate.to_string()),
+            &hex::encode(password),
+        )
+    }
+}
+
+/* Don't leak the seed into logs */
+impl fmt::Debug for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Seed(…)")
+    }
+}
+
+impl std::str::FromStr for Seed {
+    type Err = hex::FromHexError;
+
+    fn from_str(seed: &str) -> Result<Self, Self::Err> {
+        let mut bytes = secretbox::Key::default();
+        hex::decode_to_slice(seed.trim(), &mut bytes)?;
+        Ok(Self(Box::new(bytes)))
+    }
+}
+
+impl serde::Serialize for Seed {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Seed {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let seed = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        seed.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let seed = Seed::derive(&secretbox::Key::default());
+        let hex = seed.to_hex();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<Seed>().unwrap(), seed);
+        assert!(hex[2..].parse::<Seed>().is_err());
+        assert_eq!(
+            serde_json::from_value::<Seed>(serde_json::to_value(&seed).unwrap()).unwrap(),
+            seed
+        );
+        assert_eq!(format!("{:?}", seed), "Seed(…)");
+
+        /* Both sides get the same code, a different seed a different one */
+        let code = seed.code();
+        assert_eq!(code, Seed::derive(&secretbox::Key::default()).code());
+        let (nameplate, password) = code.split();
+        assert!(nameplate.0.parse::<u64>().is_ok());
+        assert_eq!(password.len(), 64);
+        let other = Seed::derive(&secretbox::Key::from([1; 32]));
+        assert_ne!(other.code(), code);
+    }
+}
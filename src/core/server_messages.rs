@@ -1,4 +1,4 @@
-use super::{AppID, Mailbox, Mood, MySide, Nameplate, Phase, TheirSide};
+use super::{AppID, ClientVersion, Mailbox, Mood, MySide, Nameplate, Phase, TheirSide};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -118,6 +118,12 @@ pub struct EncryptedMessage {
     pub phase: Phase,
     #[serde(deserialize_with = "hex::serde::deserialize")]
     pub body: Vec<u8>,
+    /// When the message reached the server, in seconds since the epoch by the server's clock
+    #[serde(default)]
+    pub server_rx: Option<f64>,
+    /// When the server sent it on to us, same as `server_rx`
+    #[serde(default)]
+    pub server_tx: Option<f64>,
 }
 
 impl EncryptedMessage {
@@ -136,10 +142,16 @@ impl EncryptedMessage {
 pub enum OutboundMessage {
     #[display(fmt = "SubmitPermission({})", _0)]
     SubmitPermission(SubmitPermission),
-    #[display(fmt = "Bind {{ appid: {}, side: {} }}", appid, side)]
+    #[display(
+        fmt = "Bind {{ appid: {}, side: {}, client_version: {} }}",
+        appid,
+        side,
+        client_version
+    )]
     Bind {
         appid: AppID,
         side: MySide,
+        client_version: ClientVersion,
     },
     List,
     Allocate,
@@ -177,8 +189,12 @@ pub enum OutboundMessage {
 }
 
 impl OutboundMessage {
-    pub fn bind(appid: AppID, side: MySide) -> Self {
-        OutboundMessage::Bind { appid, side }
+    pub fn bind(appid: AppID, side: MySide, client_version: ClientVersion) -> Self {
+        OutboundMessage::Bind {
+            appid,
+            side,
+            client_version,
+        }
     }
 
     pub fn claim(nameplate: impl Into<String>) -> Self {
@@ -263,13 +279,14 @@ mod test {
         let m1 = OutboundMessage::bind(
             AppID::new("appid"),
             MySide::unchecked_from_string(String::from("side1")),
+            ClientVersion::new("rust", "1.2.3"),
         );
         let s = serde_json::to_string(&m1).unwrap();
         let m2: Value = from_str(&s).unwrap();
         assert_eq!(
             m2,
             json!({"type": "bind", "appid": "appid",
-                   "side": "side1"})
+                   "side": "side1", "client_version": ["rust", "1.2.3"]})
         );
     }
 
@@ -467,8 +484,13 @@ mod test {
                 side: _s,
                 phase: _p,
                 body: _b,
+                server_rx,
+                server_tx,
                 //id: i
-            }) => (),
+            }) => {
+                assert_eq!(server_rx, Some(1523468188.293486));
+                assert_eq!(server_tx, Some(1523498654.753594));
+            },
             _ => panic!(),
         }
     }
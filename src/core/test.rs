@@ -18,6 +18,9 @@ pub const APP_CONFIG: AppConfig<()> = AppConfig::<()> {
     id: TEST_APPID,
     rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
     app_version: (),
+    client_version: crate::ClientVersion::DEFAULT,
+    reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+    timeouts: crate::WormholeTimeouts::DEFAULT,
 };
 
 const TIMEOUT: Duration = Duration::from_secs(60);
@@ -407,12 +410,13 @@ pub async fn test_send_many() -> eyre::Result<()> {
         )
         .await?;
         log::info!("Got key: {}", &wormhole.key);
-        let transfer::ReceiveRequest::V1(req) = crate::transfer::request(
+        /* Boxed, the test future would overflow the stack otherwise */
+        let transfer::ReceiveRequest::V1(req) = Box::pin(crate::transfer::request(
             wormhole,
             default_relay_hints(),
             magic_wormhole::transit::Abilities::ALL_ABILITIES,
             futures::future::pending(),
-        )
+        ))
         .await?
         .unwrap() else {
             panic!("v2 should be disabled for now")
@@ -428,12 +432,12 @@ pub async fn test_send_many() -> eyre::Result<()> {
             .content)(false)
         .await?;
 
-        req.accept(
+        Box::pin(req.accept(
             &transit::log_transit_connection,
             &mut answer,
             |_, _| {},
             futures::future::pending(),
-        )
+        ))
         .await?;
     }
 
@@ -444,6 +448,120 @@ pub async fn test_send_many() -> eyre::Result<()> {
     Ok(())
 }
 
+/** Serve two receivers with [`transfer::send_many`], each with its own code. It then stops by itself */
+#[cfg(feature = "transfer")]
+#[async_std::test]
+pub async fn test_send_many_limits() -> eyre::Result<()> {
+    use futures::{StreamExt, TryStreamExt};
+    init_logger();
+
+    let (code_tx, code_rx) = futures::channel::mpsc::unbounded();
+
+    let sender = async_std::task::spawn(async move {
+        let limits = transfer::SendManyLimits {
+            receivers: Some(2),
+            ..Default::default()
+        };
+        let mut events = 0;
+        let sent = transfer::send_many(
+            transfer::APP_CONFIG.id(TEST_APPID),
+            2,
+            default_relay_hints(),
+            magic_wormhole::transit::Abilities::ALL_ABILITIES,
+            || {
+                transfer::OfferSend::new_file_or_folder(
+                    "example-file.bin".into(),
+                    "tests/example-file.bin",
+                )
+            },
+            limits,
+            |event| {
+                events += 1;
+                if let transfer::SendManyEvent::Code { code, .. } = event {
+                    code_tx.unbounded_send(code).unwrap();
+                }
+            },
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok((sent, events))
+    });
+
+    let codes: Vec<Code> = code_rx
+        .take(2)
+        .then(|code| async move {
+            let wormhole = Wormhole::connect(
+                MailboxConnection::connect(
+                    transfer::APP_CONFIG.id(TEST_APPID),
+                    code.clone(),
+                    false,
+                )
+                .await?,
+            )
+            .await?;
+            let transfer::ReceiveRequest::V1(req) = Box::pin(crate::transfer::request(
+                wormhole,
+                default_relay_hints(),
+                magic_wormhole::transit::Abilities::ALL_ABILITIES,
+                futures::future::pending(),
+            ))
+            .await?
+            .unwrap() else {
+                panic!("v2 should be disabled for now")
+            };
+            Box::pin(req.accept(
+                &transit::log_transit_connection,
+                &mut futures::io::sink(),
+                |_, _| {},
+                futures::future::pending(),
+            ))
+            .await?;
+            eyre::Result::<_>::Ok(code)
+        })
+        .try_collect()
+        .await?;
+
+    let (sent, events) = async_std::future::timeout(TIMEOUT, sender).await??;
+    assert_eq!(sent, 2);
+    assert_eq!(codes.len(), 2);
+    assert_ne!(codes[0], codes[1]);
+    /* Code, connected and sent, for each of them */
+    assert_eq!(events, 6);
+
+    Ok(())
+}
+
+/** Send a text message, as `wormhole send --text` does */
+#[cfg(feature = "transfer")]
+#[async_std::test]
+pub async fn test_text_rust2rust() -> eyre::Result<()> {
+    init_logger();
+
+    let (code_tx, code_rx) = futures::channel::oneshot::channel();
+
+    let sender_task = async_std::task::spawn(async {
+        let mailbox = MailboxConnection::create(transfer::APP_CONFIG.id(TEST_APPID), 2).await?;
+        code_tx.send(mailbox.code.clone()).unwrap();
+        let wormhole = Wormhole::connect(mailbox).await?;
+        transfer::send_text(wormhole, "hello there", futures::future::pending()).await?;
+        eyre::Result::<_>::Ok(())
+    });
+    let receiver_task = async_std::task::spawn(async {
+        let code = code_rx.await?;
+        let mailbox =
+            MailboxConnection::connect(transfer::APP_CONFIG.id(TEST_APPID), code, false).await?;
+        let wormhole = Wormhole::connect(mailbox).await?;
+        let text = transfer::receive_text(wormhole, futures::future::pending()).await?;
+        eyre::Result::<_>::Ok(text)
+    });
+
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    let text = async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    assert_eq!(text.as_deref(), Some("hello there"));
+
+    Ok(())
+}
+
 /// Try to send a file, but use a bad code, and see how it's handled
 #[async_std::test]
 pub async fn test_wrong_code() -> eyre::Result<()> {
@@ -494,6 +612,93 @@ pub async fn test_wrong_code() -> eyre::Result<()> {
     Ok(())
 }
 
+/** Both sides see the same verifier, and only get a wormhole after confirming it */
+#[async_std::test]
+pub async fn test_verifier() -> eyre::Result<()> {
+    init_logger();
+
+    let (code_tx, code_rx) = futures::channel::oneshot::channel();
+
+    let sender_task = async_std::task::spawn(async {
+        let mailbox = MailboxConnection::create(APP_CONFIG, 2).await?;
+        code_tx.send(mailbox.code.clone()).unwrap();
+        let unverified = Wormhole::connect_unverified(mailbox).await?;
+        let verifier = unverified.verifier().to_vec();
+        let mut wormhole = unverified.confirm();
+        wormhole.send(b"verified".to_vec()).await?;
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(verifier)
+    });
+    let receiver_task = async_std::task::spawn(async {
+        let code = code_rx.await?;
+        let mailbox = MailboxConnection::connect(APP_CONFIG, code, false).await?;
+        let unverified = Wormhole::connect_unverified(mailbox).await?;
+        let verifier = unverified.verifier().to_vec();
+        let mut wormhole = unverified.confirm();
+        assert_eq!(wormhole.receive().await?, b"verified");
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(verifier)
+    });
+
+    let sender_verifier = async_std::future::timeout(TIMEOUT, sender_task).await??;
+    let receiver_verifier = async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    assert_eq!(sender_verifier, receiver_verifier);
+
+    Ok(())
+}
+
+/** Both sides derive the same seed, and meet again with it in any order */
+#[async_std::test]
+pub async fn test_seed() -> eyre::Result<()> {
+    init_logger();
+
+    let (code_tx, code_rx) = futures::channel::oneshot::channel();
+
+    let sender_task = async_std::task::spawn(async {
+        let mailbox = MailboxConnection::create(APP_CONFIG, 2).await?;
+        code_tx.send(mailbox.code.clone()).unwrap();
+        let wormhole = Wormhole::connect(mailbox).await?;
+        let seed = wormhole.seed();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(seed)
+    });
+    let receiver_task = async_std::task::spawn(async {
+        let code = code_rx.await?;
+        let mailbox = MailboxConnection::connect(APP_CONFIG, code, false).await?;
+        let wormhole = Wormhole::connect(mailbox).await?;
+        let seed = wormhole.seed();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(seed)
+    });
+
+    let sender_seed = async_std::future::timeout(TIMEOUT, sender_task).await??;
+    let receiver_seed = async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    assert_eq!(sender_seed, receiver_seed);
+
+    /* No code this time */
+    let receiver_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect_with_seed(APP_CONFIG, &receiver_seed).await?;
+        assert_eq!(wormhole.receive().await?, b"again");
+        let seed = wormhole.seed();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(seed)
+    });
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect_with_seed(APP_CONFIG, &sender_seed).await?;
+        wormhole.send(b"again".to_vec()).await?;
+        let seed = wormhole.seed();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok((sender_seed, seed))
+    });
+
+    let (old_seed, sender_seed) = async_std::future::timeout(TIMEOUT, sender_task).await??;
+    let receiver_seed = async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    assert_eq!(sender_seed, receiver_seed);
+    assert_ne!(sender_seed, old_seed);
+
+    Ok(())
+}
+
 /** Connect three people to the party and watch it explode … gracefully */
 #[async_std::test]
 pub async fn test_crowded() -> eyre::Result<()> {
@@ -546,6 +751,168 @@ fn generate_random_code() -> Code {
     Code::new(&nameplate, "guitarist-revenge")
 }
 
+#[test]
+fn test_clock_skew() {
+    /* Sent at 1000, got the answer 200ms later, from a clock that is 5s ahead */
+    let skew = super::ClockSkew::estimate(1000.0, 1200.0, 6100.0);
+    assert_eq!(skew.offset, 5.0);
+    assert_eq!(skew.uncertainty, Duration::from_millis(100));
+
+    let now = std::time::SystemTime::now();
+    assert_eq!(skew.to_peer_time(now), now + Duration::from_secs(5));
+    assert_eq!(skew.from_peer_time(skew.to_peer_time(now)), now);
+}
+
+/*
+ * A mailbox server that drops the first connection when our message arrives, and the second one when
+ * we release the nameplate. Every connection gets all messages in the mailbox, like a real server.
+ */
+async fn flaky_mailbox_server(listener: async_std::net::TcpListener) -> Vec<String> {
+    use async_tungstenite::tungstenite::Message;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+
+    let peer = |phase: &str| {
+        json!({"type": "message", "side": "peer", "phase": phase, "body": "7b7d"}).to_string()
+    };
+    let mut mailbox = vec![peer("pake")];
+    /* What every connection got, as message types */
+    let mut log = Vec::new();
+    for connection in 0..3 {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = async_tungstenite::accept_async(stream).await.unwrap();
+        let mut received = String::new();
+        let mut side = String::new();
+        ws.send(Message::Text(
+            json!({"type": "welcome", "welcome": {}}).to_string(),
+        ))
+        .await
+        .unwrap();
+        while let Some(Ok(Message::Text(message))) = ws.next().await {
+            let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+            let kind = message["type"].as_str().unwrap().to_owned();
+            received += &format!("{} ", kind);
+            match (connection, kind.as_str()) {
+                (0, "add") | (1, "release") => break,
+                _ => (),
+            }
+            ws.send(Message::Text(json!({"type": "ack"}).to_string()))
+                .await
+                .unwrap();
+            let replies = match kind.as_str() {
+                "bind" => {
+                    side = message["side"].as_str().unwrap().to_owned();
+                    vec![]
+                },
+                "claim" => vec![json!({"type": "claimed", "mailbox": "mailbox"}).to_string()],
+                "open" => mailbox.clone(),
+                "add" => {
+                    let echo = json!({"type": "message", "side": side, "phase": message["phase"], "body": message["body"]});
+                    mailbox.push(echo.to_string());
+                    mailbox.push(peer("version"));
+                    mailbox[mailbox.len() - 2..].to_vec()
+                },
+                "release" => vec![json!({"type": "released"}).to_string()],
+                "close" => vec![json!({"type": "closed"}).to_string()],
+                _ => vec![],
+            };
+            for reply in replies {
+                ws.send(Message::Text(reply)).await.unwrap();
+            }
+        }
+        log.push(received.trim_end().to_owned());
+    }
+    log
+}
+
+#[async_std::test]
+async fn test_reconnect_replay() {
+    use crate::rendezvous::{ReconnectEvent, ReconnectPolicy, RendezvousServer};
+    use futures::StreamExt;
+
+    let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+    let server_task = async_std::task::spawn(flaky_mailbox_server(listener));
+
+    let (mut server, _welcome) = RendezvousServer::connect(&TEST_APPID, &url).await.unwrap();
+    server.set_reconnect_policy(ReconnectPolicy::new(
+        3,
+        Duration::from_millis(10),
+        Duration::from_millis(10),
+    ));
+    let mut events = server.reconnect_events();
+    server.claim_open(Nameplate::new("4")).await.unwrap();
+
+    /* Gets lost, and sent again after reconnecting */
+    server
+        .send_peer_message(Phase::PAKE, b"{}".to_vec())
+        .await
+        .unwrap();
+    assert!(matches!(
+        events.next().await,
+        Some(ReconnectEvent::Reconnecting { attempt: 1, .. })
+    ));
+    assert_eq!(
+        events.next().await,
+        Some(ReconnectEvent::Reconnected { attempts: 1 })
+    );
+
+    /* The peer's first message arrived on both connections, but only counts once */
+    assert_eq!(
+        server.next_peer_message_some().await.unwrap().phase,
+        Phase::PAKE
+    );
+    assert_eq!(
+        server.next_peer_message_some().await.unwrap().phase,
+        Phase::VERSION
+    );
+
+    /* Releasing gets repeated, too */
+    server.release_nameplate().await.unwrap();
+    server.shutdown(Mood::Happy).await.unwrap();
+
+    assert_eq!(
+        server_task.await,
+        vec![
+            "bind claim open add",
+            "bind claim open add release",
+            "bind claim open release close",
+        ]
+    );
+}
+
+#[test]
+fn test_reconnect_jitter() {
+    use crate::rendezvous::ReconnectPolicy;
+
+    let backoff = Duration::from_secs(4);
+    assert_eq!(ReconnectPolicy::NEVER.wait(backoff), backoff);
+    for _ in 0..100 {
+        let wait = ReconnectPolicy::INTERACTIVE.wait(backoff);
+        assert!(wait > backoff / 2 && wait <= backoff, "{:?}", wait);
+    }
+}
+
+#[test]
+fn test_trace() {
+    let trace = super::Trace::new(Some("0123456789abcdef".into()));
+    assert_eq!(trace.id.len(), 16);
+
+    let message = trace.attach(br#"{"offer":{"message":"hello"}}"#.to_vec());
+    let (message, detached) = super::Trace::detach(message);
+    assert_eq!(message, br#"{"offer":{"message":"hello"}}"#);
+    assert_eq!(detached, Some(trace.clone()));
+
+    /* Messages that are no JSON objects are left alone */
+    assert_eq!(trace.attach(b"[1,2]".to_vec()), b"[1,2]");
+    assert_eq!(
+        super::Trace::detach(b"not json".to_vec()),
+        (b"not json".to_vec(), None)
+    );
+}
+
 #[test]
 fn test_phase() {
     let p = Phase::PAKE;
@@ -580,3 +947,114 @@ fn test_mood() {
         serde_json::to_string(&Mood::Unwelcome).unwrap()
     );
 }
+
+#[test]
+fn test_server_welcome() {
+    use super::{rendezvous::ServerWelcome, server_messages::WelcomeMessage};
+
+    let welcome: WelcomeMessage =
+        serde_json::from_str(r#"{"motd": "hello world", "current_cli_version": "0.13.0"}"#)
+            .unwrap();
+    let welcome = ServerWelcome::from_message(welcome).unwrap();
+    assert_eq!(welcome.motd.as_deref(), Some("hello world"));
+    assert_eq!(welcome.current_cli_version.as_deref(), Some("0.13.0"));
+
+    let unwelcome: WelcomeMessage =
+        serde_json::from_str(r#"{"motd": "hello world", "error": "Please upgrade"}"#).unwrap();
+    assert_eq!(
+        ServerWelcome::from_message(unwelcome),
+        Err("Please upgrade".into())
+    );
+}
+
+/* A mailbox server where the peer never shows up. Returns the message types it got, and the mood */
+async fn lonely_mailbox_server(listener: async_std::net::TcpListener) -> (String, String) {
+    use async_tungstenite::tungstenite::Message;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::json;
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut ws = async_tungstenite::accept_async(stream).await.unwrap();
+    let mut received = String::new();
+    let mut mood = String::new();
+    ws.send(Message::Text(
+        json!({"type": "welcome", "welcome": {}}).to_string(),
+    ))
+    .await
+    .unwrap();
+    while let Some(Ok(Message::Text(message))) = ws.next().await {
+        let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+        let kind = message["type"].as_str().unwrap().to_owned();
+        received += &format!("{} ", kind);
+        ws.send(Message::Text(json!({"type": "ack"}).to_string()))
+            .await
+            .unwrap();
+        let reply = match kind.as_str() {
+            "claim" => json!({"type": "claimed", "mailbox": "mailbox"}),
+            "release" => json!({"type": "released"}),
+            "close" => {
+                mood = message["mood"].as_str().unwrap().to_owned();
+                json!({"type": "closed"})
+            },
+            _ => continue,
+        };
+        ws.send(Message::Text(reply.to_string())).await.unwrap();
+    }
+    (received.trim_end().to_owned(), mood)
+}
+
+/** Cancelling while waiting for the peer releases the code */
+#[async_std::test]
+async fn test_connect_cancel() {
+    let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+    let server_task = async_std::task::spawn(lonely_mailbox_server(listener));
+
+    let mailbox_connection = MailboxConnection::connect(
+        APP_CONFIG.rendezvous_url(url.into()),
+        Code("4-purple-sausages".into()),
+        true,
+    )
+    .await
+    .unwrap();
+    let result = Wormhole::connect_with_cancel(
+        mailbox_connection,
+        async_std::task::sleep(Duration::from_millis(100)),
+    )
+    .await;
+    assert!(matches!(result, Err(WormholeError::Cancelled)));
+
+    assert_eq!(
+        server_task.await,
+        ("bind claim open add release close".into(), "lonely".into())
+    );
+}
+
+/** A server that accepts the connection but never answers must not block forever */
+#[async_std::test]
+async fn test_server_timeout() {
+    let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let url = format!("ws://{}/v1", listener.local_addr().unwrap());
+    let _server_task = async_std::task::spawn(async move {
+        let (_stream, _) = listener.accept().await.unwrap();
+        futures::future::pending::<()>().await
+    });
+
+    let timeouts = crate::WormholeTimeouts {
+        server: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let result =
+        MailboxConnection::create(APP_CONFIG.rendezvous_url(url.into()).timeouts(timeouts), 2)
+            .await;
+    assert!(matches!(
+        result,
+        Err(WormholeError::ServerError(
+            crate::rendezvous::RendezvousError::Timeout
+        ))
+    ));
+}
@@ -1,73 +1,297 @@
+//! The words of generated codes
+//!
+//! Codes are made of the numeric nameplate, followed by words from a [`Wordlist`] joined with dashes, like
+//! `15-purple-sausages`. The default is the PGP wordlist with its even and odd words. Applications may bring
+//! their own, for example a localized one, with [`CustomWordlist`]. Both sides need not agree on the
+//! wordlist, only the code matters.
+
 use rand::{rngs::OsRng, seq::SliceRandom};
 use serde_json::{self, Value};
-use std::fmt;
+use std::{fmt, sync::OnceLock};
+
+/**
+ * Where the words of a code come from
+ *
+ * A wordlist has a list of words for each position of the code, after the nameplate. Implementors only
+ * need to provide those, choosing and completing codes is done by the provided methods.
+ */
+pub trait Wordlist: Send + Sync {
+    /**
+     * The words to choose from for the word at `position`, starting at zero
+     *
+     * Must not be empty for any position. No word may contain a dash or whitespace.
+     */
+    fn words(&self, position: usize) -> &[String];
+
+    /**
+     * How many bits of entropy a password of `num_words` words has
+     *
+     * With the [`PgpWordlist`], that's 8 bits per word. An attacker who knows the nameplate gets one guess
+     * per connection, so the default of two words gives them one chance in 65536. The rendezvous server
+     * makes guessing even harder, as a wrong guess ends the connection for both sides. If that does not
+     * hold for your server, or your threat model is higher, use more words.
+     */
+    fn entropy(&self, num_words: usize) -> f64 {
+        (0..num_words)
+            .map(|position| (self.words(position).len() as f64).log2())
+            .sum()
+    }
+
+    /** Randomly choose `num_words` words, joined with dashes. This is the password part of a code */
+    fn choose_words(&self, num_words: usize) -> String {
+        let mut rng = OsRng;
+        let components: Vec<&str> = (0..num_words)
+            .map(|position| {
+                self.words(position)
+                    .choose(&mut rng)
+                    .expect("Wordlist must not be empty")
+                    .as_str()
+            })
+            .collect();
+        components.join("-")
+    }
+
+    /**
+     * All passwords of `num_words` words that `prefix` may be the beginning of, up to the next word
+     *
+     * The prefix is the password part of the code only, without the nameplate. Completions of anything but
+     * the last word end with a dash.
+     */
+    fn get_completions(&self, prefix: &str, num_words: usize) -> Vec<String> {
+        let position = prefix.matches('-').count();
+        let last_partial_word = prefix.rsplit('-').next().unwrap_or_default();
+        let completed = &prefix[..prefix.len() - last_partial_word.len()];
+
+        let mut completions: Vec<String> = self
+            .words(position)
+            .iter()
+            .filter(|word| word.starts_with(last_partial_word))
+            .map(|word| {
+                let mut completion = format!("{}{}", completed, word);
+                if position + 1 < num_words {
+                    completion.push('-');
+                }
+                completion
+            })
+            .collect();
+        completions.sort();
+        completions
+    }
+}
+
+/**
+ * The PGP wordlist, which is the default
+ *
+ * Even positions take words of the "even" list (two syllables), odd positions of the "odd" list (three
+ * syllables), which makes codes easy to tell apart when read out loud. Compatible with the Python
+ * implementation.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PgpWordlist;
+
+impl Wordlist for PgpWordlist {
+    fn words(&self, position: usize) -> &[String] {
+        static WORDS: OnceLock<Vec<Vec<String>>> = OnceLock::new();
+        let words = WORDS.get_or_init(load_pgpwords);
+        &words[position % words.len()]
+    }
+}
 
-#[derive(PartialEq)]
-pub struct Wordlist {
-    pub num_words: usize,
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum WordlistError {
+    /// A wordlist needs at least one list, and no list may be empty
+    #[error("The wordlist is empty")]
+    Empty,
+    /// Words must not be empty, nor contain dashes or whitespace, as that would break splitting the code
+    #[error("Invalid word in the wordlist: '{}'", _0)]
+    InvalidWord(String),
+}
+
+/**
+ * A wordlist brought by the application, for example a localized one
+ *
+ * Like for the [`PgpWordlist`], the lists are used in turn: with two lists, the first one is used for even
+ * positions and the second one for odd positions.
+ */
+#[derive(Clone, PartialEq, Eq)]
+pub struct CustomWordlist {
     words: Vec<Vec<String>>,
 }
 
-impl fmt::Debug for Wordlist {
+impl fmt::Debug for CustomWordlist {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Wordlist ( {}, lots of words...)", self.num_words)
+        write!(
+            f,
+            "CustomWordlist ( {} lists, lots of words...)",
+            self.words.len()
+        )
     }
 }
 
-impl Wordlist {
-    #[cfg(test)]
-    pub fn new(num_words: usize, words: Vec<Vec<String>>) -> Wordlist {
-        Wordlist { num_words, words }
+impl CustomWordlist {
+    pub fn new(words: Vec<Vec<String>>) -> Result<Self, WordlistError> {
+        if words.is_empty() || words.iter().any(Vec::is_empty) {
+            return Err(WordlistError::Empty);
+        }
+        if let Some(word) = words
+            .iter()
+            .flatten()
+            .find(|word| word.is_empty() || word.contains(|c: char| c == '-' || c.is_whitespace()))
+        {
+            return Err(WordlistError::InvalidWord(word.clone()));
+        }
+        Ok(Self { words })
     }
 
-    #[allow(dead_code)] // TODO make this API public one day
-    pub fn get_completions(&self, prefix: &str) -> Vec<String> {
-        let count_dashes = prefix.matches('-').count();
-        let mut completions = Vec::new();
-        let words = &self.words[count_dashes % self.words.len()];
+    /** A single list used for all positions */
+    pub fn from_words(
+        words: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, WordlistError> {
+        Self::new(vec![words.into_iter().map(Into::into).collect()])
+    }
+}
 
-        let last_partial_word = prefix.split('-').last();
-        let lp = if let Some(w) = last_partial_word {
-            w.len()
-        } else {
-            0
-        };
+impl Wordlist for CustomWordlist {
+    fn words(&self, position: usize) -> &[String] {
+        &self.words[position % self.words.len()]
+    }
+}
 
-        for word in words {
-            let mut suffix: String = prefix.to_owned();
-            if word.starts_with(last_partial_word.unwrap()) {
-                if lp == 0 {
-                    suffix.push_str(word);
-                } else {
-                    let p = prefix.len() - lp;
-                    suffix.truncate(p);
-                    suffix.push_str(word);
-                }
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CodeError {
+    /// Codes start with a numeric nameplate, followed by a dash
+    #[error("The code must start with a number followed by a dash, like '7-'")]
+    InvalidNameplate,
+    #[error("The code has {} words instead of {}", actual, expected)]
+    WrongLength { expected: usize, actual: usize },
+    #[error("'{}' is not in the wordlist", _0)]
+    UnknownWord(String),
+}
 
-                if count_dashes + 1 < self.num_words {
-                    suffix.push('-');
-                }
+/**
+ * Completion and validation of codes as they get entered, for interactive clients
+ *
+ * Unlike [`Wordlist::get_completions`], this works on whole codes including the nameplate, like `7-crosso`.
+ * Nameplates themselves are not completed, as only the rendezvous server knows them.
+ */
+pub struct CodeCompletion {
+    wordlist: Box<dyn Wordlist>,
+    /* `None` for codes of any length */
+    num_words: Option<usize>,
+}
 
-                completions.push(suffix);
-            }
+impl fmt::Debug for CodeCompletion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CodeCompletion ( {:?} words )", self.num_words)
+    }
+}
+
+impl CodeCompletion {
+    /** Complete codes of `num_words` words from the default [`PgpWordlist`] */
+    pub fn new(num_words: usize) -> Self {
+        Self::with_wordlist(PgpWordlist, num_words)
+    }
+
+    pub fn with_wordlist(wordlist: impl Wordlist + 'static, num_words: usize) -> Self {
+        Self {
+            wordlist: Box::new(wordlist),
+            num_words: Some(num_words),
         }
-        completions.sort();
-        completions
     }
 
-    pub fn choose_words(&self) -> String {
-        let mut rng = OsRng;
-        let components: Vec<String> = self
-            .words
+    /**
+     * Complete codes with any number of words
+     *
+     * For the receiving side, which does not know how many words the other side chose. Completions never
+     * end with a dash then, as every word may be the last one.
+     */
+    pub fn any_length(wordlist: impl Wordlist + 'static) -> Self {
+        Self {
+            wordlist: Box::new(wordlist),
+            num_words: None,
+        }
+    }
+
+    /** All codes that `partial_code` may be the beginning of, up to the next word */
+    pub fn completions(&self, partial_code: &str) -> Vec<String> {
+        let Some((nameplate, password)) = partial_code.split_once('-') else {
+            return Vec::new();
+        };
+        let position = password.matches('-').count();
+        if !is_nameplate(nameplate)
+            || self
+                .num_words
+                .is_some_and(|num_words| position >= num_words)
+        {
+            return Vec::new();
+        }
+        /* Without a length, the word being entered is the last one as far as we know */
+        let num_words = self.num_words.unwrap_or(position + 1);
+        self.wordlist
+            .get_completions(password, num_words)
+            .into_iter()
+            .map(|password| format!("{}-{}", nameplate, password))
+            .collect()
+    }
+
+    /**
+     * What `partial_code` can be extended to without ambiguity, for tab completion
+     *
+     * This is the longest common beginning of all [`completions`](Self::completions), if there are any.
+     */
+    pub fn complete(&self, partial_code: &str) -> Option<String> {
+        let completions = self.completions(partial_code);
+        let (first, rest) = completions.split_first()?;
+        let common = rest.iter().fold(first.as_str(), |common, completion| {
+            let length = common
+                .char_indices()
+                .zip(completion.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len(), |((index, _), _)| index);
+            &common[..length]
+        });
+        Some(common.to_owned())
+    }
+
+    /**
+     * Check that `code` is made of a nameplate and the right number of words from the wordlist
+     *
+     * Any number of words is fine for [`any_length`](Self::any_length).
+     *
+     * Codes with arbitrary passwords are perfectly fine to connect with, so use this for hinting at typos
+     * only.
+     */
+    pub fn validate(&self, code: &str) -> Result<crate::Code, CodeError> {
+        let code = code.trim();
+        let (_, password) = code
+            .split_once('-')
+            .filter(|(nameplate, _)| is_nameplate(nameplate))
+            .ok_or(CodeError::InvalidNameplate)?;
+        let words: Vec<&str> = password.split('-').collect();
+        if let Some(num_words) = self.num_words.filter(|num_words| *num_words != words.len()) {
+            return Err(CodeError::WrongLength {
+                expected: num_words,
+                actual: words.len(),
+            });
+        }
+        if let Some(word) = words
             .iter()
-            .cycle()
-            .take(self.num_words)
-            .map(|words| words.choose(&mut rng).unwrap().to_string())
-            .collect();
-        components.join("-")
+            .enumerate()
+            .find(|(position, word)| !self.wordlist.words(*position).iter().any(|w| w == *word))
+            .map(|(_, word)| word)
+        {
+            return Err(CodeError::UnknownWord(word.to_string()));
+        }
+        Ok(crate::Code(code.to_owned()))
     }
 }
 
+fn is_nameplate(nameplate: &str) -> bool {
+    !nameplate.is_empty() && nameplate.bytes().all(|b| b.is_ascii_digit())
+}
+
 fn load_pgpwords() -> Vec<Vec<String>> {
     let raw_words_value: Value = serde_json::from_str(include_str!("pgpwords.json")).unwrap();
     let raw_words = raw_words_value.as_object().unwrap();
@@ -96,13 +320,6 @@ fn load_pgpwords() -> Vec<Vec<String>> {
     vec![even_words, odd_words]
 }
 
-pub fn default_wordlist(num_words: usize) -> Wordlist {
-    Wordlist {
-        num_words,
-        words: load_pgpwords(),
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -119,12 +336,11 @@ mod test {
 
     #[test]
     fn test_default_wordlist() {
-        let d = default_wordlist(2);
-        assert_eq!(d.words.len(), 2);
-        assert_eq!(d.words[0][0], "adroitness");
-        assert_eq!(d.words[1][0], "aardvark");
-        assert_eq!(d.words[0][255], "yucatan");
-        assert_eq!(d.words[1][255], "zulu");
+        let d = PgpWordlist;
+        assert_eq!(d.words(0)[0], "adroitness");
+        assert_eq!(d.words(1)[0], "aardvark");
+        assert_eq!(d.words(2)[255], "yucatan");
+        assert_eq!(d.words(3)[255], "zulu");
     }
 
     fn vecstrings(all: &str) -> Vec<String> {
@@ -146,23 +362,24 @@ mod test {
             vecstrings("sausages seltzer snobol"),
         ];
 
-        let w = Wordlist::new(2, words);
-        assert_eq!(w.get_completions(""), vec!["green-", "purple-", "yellow-"]);
-        assert_eq!(w.get_completions("pur"), vec!["purple-"]);
-        assert_eq!(w.get_completions("blu"), Vec::<String>::new());
-        assert_eq!(w.get_completions("purple-sa"), vec!["purple-sausages"]);
+        let w = CustomWordlist::new(words).unwrap();
+        assert_eq!(
+            w.get_completions("", 2),
+            vec!["green-", "purple-", "yellow-"]
+        );
+        assert_eq!(w.get_completions("pur", 2), vec!["purple-"]);
+        assert_eq!(w.get_completions("blu", 2), Vec::<String>::new());
+        assert_eq!(w.get_completions("purple-sa", 2), vec!["purple-sausages"]);
     }
 
     #[test]
     fn test_choose_words() {
         let few_words: Vec<Vec<String>> = vec![vecstrings("purple"), vecstrings("sausages")];
 
-        let w = Wordlist::new(2, few_words.clone());
-        assert_eq!(w.choose_words(), "purple-sausages");
-        let w = Wordlist::new(3, few_words.clone());
-        assert_eq!(w.choose_words(), "purple-sausages-purple");
-        let w = Wordlist::new(4, few_words);
-        assert_eq!(w.choose_words(), "purple-sausages-purple-sausages");
+        let w = CustomWordlist::new(few_words).unwrap();
+        assert_eq!(w.choose_words(2), "purple-sausages");
+        assert_eq!(w.choose_words(3), "purple-sausages-purple");
+        assert_eq!(w.choose_words(4), "purple-sausages-purple-sausages");
     }
 
     #[test]
@@ -181,33 +398,117 @@ mod test {
         .map(|s| s.to_string())
         .collect();
 
-        let w = Wordlist::new(2, more_words.clone());
+        let w = CustomWordlist::new(more_words).unwrap();
         for _ in 0..20 {
-            assert!(expected2.contains(&w.choose_words()));
+            assert!(expected2.contains(&w.choose_words(2)));
         }
-
-        let w = Wordlist::new(3, more_words);
         for _ in 0..20 {
-            assert!(expected3.contains(&w.choose_words()));
+            assert!(expected3.contains(&w.choose_words(3)));
         }
     }
 
+    #[test]
+    fn test_custom_wordlist() {
+        assert!(matches!(
+            CustomWordlist::new(vec![]),
+            Err(WordlistError::Empty)
+        ));
+        assert!(matches!(
+            CustomWordlist::new(vec![vecstrings("purple"), vec![]]),
+            Err(WordlistError::Empty)
+        ));
+        assert!(matches!(
+            CustomWordlist::from_words(["lila", "grün-gelb"]),
+            Err(WordlistError::InvalidWord(word)) if word == "grün-gelb"
+        ));
+
+        /* Localized words work the same */
+        let w = CustomWordlist::from_words(["grün", "gelb"]).unwrap();
+        assert_eq!(w.get_completions("gr", 2), vec!["grün-"]);
+        assert_eq!(w.get_completions("grün-ge", 2), vec!["grün-gelb"]);
+        assert!(["grün", "gelb"].contains(&w.choose_words(1).as_str()));
+    }
+
+    #[test]
+    fn test_code_completion() {
+        let c = CodeCompletion::new(2);
+        assert_eq!(
+            c.completions("7-armistice-ba"),
+            vec![
+                "7-armistice-baboon",
+                "7-armistice-backfield",
+                "7-armistice-backward",
+                "7-armistice-banjo",
+            ]
+        );
+        assert_eq!(
+            c.complete("7-armistice-bac"),
+            Some("7-armistice-back".into())
+        );
+        assert_eq!(c.complete("7-armis"), Some("7-armistice-".into()));
+        assert_eq!(c.complete("7-xyz"), None);
+
+        /* Nothing to complete without a nameplate, or with too many words */
+        assert!(c.completions("7").is_empty());
+        assert!(c.completions("x-armis").is_empty());
+        assert!(c.completions("7-armistice-baboon-").is_empty());
+
+        assert_eq!(
+            *c.validate("7-armistice-baboon\n").unwrap(),
+            "7-armistice-baboon"
+        );
+        assert!(matches!(
+            c.validate("armistice-baboon"),
+            Err(CodeError::InvalidNameplate)
+        ));
+        assert!(matches!(
+            c.validate("7-armistice"),
+            Err(CodeError::WrongLength {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        /* "baboon" is an odd word, "armistice" an even one */
+        assert!(matches!(
+            c.validate("7-baboon-armistice"),
+            Err(CodeError::UnknownWord(word)) if word == "baboon"
+        ));
+
+        /* The receiving side does not know how long the code is */
+        let c = CodeCompletion::any_length(PgpWordlist);
+        assert_eq!(c.complete("7-armis"), Some("7-armistice".into()));
+        assert_eq!(
+            c.complete("7-armistice-baboon-armis"),
+            Some("7-armistice-baboon-armistice".into())
+        );
+        assert!(c.validate("7-armistice").is_ok());
+        assert!(c.validate("7-armistice-baboon-armistice-baboon").is_ok());
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(PgpWordlist.entropy(2), 16.0);
+        assert_eq!(PgpWordlist.entropy(5), 40.0);
+        let w = CustomWordlist::from_words(["purple", "yellow"]).unwrap();
+        assert_eq!(w.entropy(3), 3.0);
+    }
+
     #[test]
     fn test_default_completions() {
-        let w = default_wordlist(2);
-        let c = w.get_completions("ar");
+        let w = PgpWordlist;
+        let c = w.get_completions("ar", 2);
         assert_eq!(c.len(), 2);
         assert!(c.contains(&String::from("article-")));
         assert!(c.contains(&String::from("armistice-")));
 
-        let c = w.get_completions("armis");
+        let c = w.get_completions("armis", 2);
         assert_eq!(c.len(), 1);
         assert!(c.contains(&String::from("armistice-")));
 
-        let c = w.get_completions("armistice-");
+        let c = w.get_completions("armistice-", 2);
         assert_eq!(c.len(), 256);
 
-        let c = w.get_completions("armistice-ba");
+        let c = w.get_completions("armistice-ba", 2);
         assert_eq!(
             c,
             vec![
@@ -218,8 +519,7 @@ mod test {
             ]
         );
 
-        let w = default_wordlist(3);
-        let c = w.get_completions("armistice-ba");
+        let c = w.get_completions("armistice-ba", 3);
         assert_eq!(
             c,
             vec![
@@ -230,8 +530,7 @@ mod test {
             ]
         );
 
-        let w = default_wordlist(4);
-        let c = w.get_completions("armistice-baboon");
+        let c = w.get_completions("armistice-baboon", 4);
         assert_eq!(c, vec!["armistice-baboon-"]);
     }
 }
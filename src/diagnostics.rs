@@ -0,0 +1,410 @@
+//! Find out why connections fail
+//!
+//! When a transfer hangs, the cause is usually somewhere between the two peers: an unreachable
+//! rendezvous server, a blocked relay port, or a NAT that makes direct connections impossible.
+//! [`doctor`] checks all of these and returns a [`DiagnosticsReport`], which serializes to JSON for
+//! attaching it to bug reports:
+//!
+//! ```no_run
+//! # async fn doc() {
+//! use magic_wormhole::{diagnostics, rendezvous, transit};
+//!
+//! let relay_hints =
+//!     vec![transit::RelayHint::from_urls(None, [transit::DEFAULT_RELAY_SERVER.parse().unwrap()]).unwrap()];
+//! let report = diagnostics::doctor(rendezvous::DEFAULT_RENDEZVOUS_SERVER, &relay_hints).await;
+//! println!("{}", serde_json::to_string_pretty(&report).unwrap());
+//! # }
+//! ```
+
+use crate::{rendezvous::RendezvousServer, transit, util, AppID};
+use serde_derive::Serialize;
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+/// The App ID used for checking the rendezvous server. We never claim a nameplate with it.
+const APPID: AppID = AppID(Cow::Borrowed("piegames.de/wormhole/diagnostics"));
+
+/// Settings for [`doctor_with_options`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DoctorOptions {
+    /// Give up on each individual check after this long
+    pub timeout: Duration,
+    /**
+     * STUN servers to estimate the NAT type with, as `host:port`
+     *
+     * At least two different servers are needed to tell apart the NAT types. Only the first two
+     * are used.
+     */
+    pub stun_servers: Vec<String>,
+}
+
+impl Default for DoctorOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            stun_servers: vec![
+                transit::PUBLIC_STUN_SERVER.to_owned(),
+                "stun.stunprotocol.org:3478".to_owned(),
+            ],
+        }
+    }
+}
+
+/// The result of [`doctor`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DiagnosticsReport {
+    /// Whether the rendezvous server could be reached
+    pub rendezvous: RendezvousCheck,
+    /// Whether the relay servers could be reached, one entry per endpoint
+    pub relays: Vec<RelayCheck>,
+    /// How our NAT maps ports, which determines if direct connections can work
+    pub nat: NatCheck,
+}
+
+impl DiagnosticsReport {
+    /**
+     * Whether transfers are expected to work at all
+     *
+     * This requires the rendezvous server and at least one relay endpoint to be reachable.
+     * Direct connections are not required, since the relay can always step in.
+     */
+    pub fn is_healthy(&self) -> bool {
+        self.rendezvous.error.is_none() && self.relays.iter().any(|relay| relay.error.is_none())
+    }
+}
+
+/// See [`DiagnosticsReport::rendezvous`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct RendezvousCheck {
+    pub url: String,
+    /// Until the server welcomed us, in seconds when serialized
+    #[serde(serialize_with = "as_secs_option")]
+    pub latency: Option<Duration>,
+    /// The message of the day sent by the server, if any
+    pub motd: Option<String>,
+    pub error: Option<String>,
+}
+
+/// See [`DiagnosticsReport::relays`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct RelayCheck {
+    /// The name of the relay hint this endpoint belongs to
+    pub name: Option<String>,
+    /// `tcp://host:port` or the WebSocket URL
+    pub endpoint: String,
+    /// Until a TCP connection was established, in seconds when serialized
+    #[serde(serialize_with = "as_secs_option")]
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+}
+
+/// See [`DiagnosticsReport::nat`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct NatCheck {
+    pub nat_type: NatType,
+    /// The address our STUN queries went out from
+    pub local_address: Option<SocketAddr>,
+    /// Our address as seen by each STUN server
+    pub external_addresses: Vec<SocketAddr>,
+    /// Errors of the STUN queries, if any
+    pub errors: Vec<String>,
+}
+
+/// An estimation of how our NAT behaves, see [`NatCheck`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, derive_more::Display)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum NatType {
+    /// We have a public address, direct connections should work
+    #[display(fmt = "no NAT")]
+    None,
+    /**
+     * The NAT maps our local port to the same external port for all destinations
+     *
+     * Also known as "cone NAT". Direct connections may work, especially if the peer has no NAT.
+     */
+    #[display(fmt = "endpoint-independent")]
+    EndpointIndependent,
+    /**
+     * The NAT maps our local port to a different external port for each destination
+     *
+     * Direct connections to peers behind a NAT will most likely fail, transfers go over the relay.
+     */
+    #[display(fmt = "symmetric")]
+    Symmetric,
+    /// Not enough STUN queries succeeded
+    #[display(fmt = "unknown")]
+    Unknown,
+}
+
+impl NatType {
+    /** Classify the results of two STUN queries that went out from the same local address */
+    fn estimate(local: SocketAddr, first: SocketAddr, second: Option<SocketAddr>) -> Self {
+        if canonical(first) == canonical(local) {
+            return NatType::None;
+        }
+        match second {
+            Some(second) if canonical(second) == canonical(first) => NatType::EndpointIndependent,
+            Some(_) => NatType::Symmetric,
+            None => NatType::Unknown,
+        }
+    }
+}
+
+/** Strip IPv4-mapped IPv6 addresses to plain IPv4 ones, so that they compare equal */
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+fn as_secs_option<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/** Run all checks with the default [`DoctorOptions`] */
+pub async fn doctor(rendezvous_url: &str, relay_hints: &[transit::RelayHint]) -> DiagnosticsReport {
+    doctor_with_options(rendezvous_url, relay_hints, &DoctorOptions::default()).await
+}
+
+/**
+ * Check the reachability of the rendezvous server and each relay endpoint, and estimate the NAT type
+ *
+ * The checks run concurrently. None of them fails the whole call; failures are recorded in the
+ * respective part of the report instead.
+ */
+pub async fn doctor_with_options(
+    rendezvous_url: &str,
+    relay_hints: &[transit::RelayHint],
+    options: &DoctorOptions,
+) -> DiagnosticsReport {
+    let relays = futures::future::join_all(
+        relay_hints
+            .iter()
+            .flat_map(|hint| {
+                let tcp = hint.tcp.iter().map(|tcp| {
+                    (
+                        format!("tcp://{}:{}", tcp.hostname, tcp.port),
+                        tcp.hostname.clone(),
+                        tcp.port,
+                    )
+                });
+                let ws = hint.ws.iter().filter_map(|url| {
+                    Some((
+                        url.to_string(),
+                        url.host_str()?.to_owned(),
+                        url.port_or_known_default()?,
+                    ))
+                });
+                tcp.chain(ws)
+                    .map(move |(endpoint, host, port)| (hint.name.clone(), endpoint, host, port))
+            })
+            .map(|(name, endpoint, host, port)| {
+                check_relay(name, endpoint, host, port, options.timeout)
+            }),
+    );
+
+    let (rendezvous, relays, nat) = futures::join!(
+        check_rendezvous(rendezvous_url, options.timeout),
+        relays,
+        check_nat(&options.stun_servers, options.timeout),
+    );
+
+    DiagnosticsReport {
+        rendezvous,
+        relays,
+        nat,
+    }
+}
+
+async fn check_rendezvous(url: &str, timeout: Duration) -> RendezvousCheck {
+    let start = instant::Instant::now();
+    let mut check = RendezvousCheck {
+        url: url.to_owned(),
+        latency: None,
+        motd: None,
+        error: None,
+    };
+    match util::timeout(timeout, RendezvousServer::connect(&APPID, url)).await {
+        Ok(Ok((server, welcome))) => {
+            check.latency = Some(start.elapsed());
+            check.motd = welcome.motd;
+            if let Err(error) = server.shutdown(crate::Mood::Happy).await {
+                log::debug!("Failed to close the rendezvous connection: {}", error);
+            }
+        },
+        Ok(Err(error)) => check.error = Some(error.to_string()),
+        Err(_) => check.error = Some("Connection timed out".into()),
+    }
+    check
+}
+
+async fn check_relay(
+    name: Option<String>,
+    endpoint: String,
+    host: String,
+    port: u16,
+    timeout: Duration,
+) -> RelayCheck {
+    let start = instant::Instant::now();
+    let (latency, error) = match util::timeout(
+        timeout,
+        async_std::net::TcpStream::connect((host.as_str(), port)),
+    )
+    .await
+    {
+        Ok(Ok(_)) => (Some(start.elapsed()), None),
+        Ok(Err(error)) => (None, Some(error.to_string())),
+        Err(_) => (None, Some("Connection timed out".into())),
+    };
+    RelayCheck {
+        name,
+        endpoint,
+        latency,
+        error,
+    }
+}
+
+async fn check_nat(stun_servers: &[String], timeout: Duration) -> NatCheck {
+    let mut check = NatCheck {
+        nat_type: NatType::Unknown,
+        local_address: None,
+        external_addresses: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut servers = stun_servers.iter();
+
+    /* The first query picks a local port, the second one reuses it */
+    let (local, first) = match servers.next() {
+        Some(server) => {
+            match transit::stun_query(server, "[::]:0".parse().unwrap(), timeout).await {
+                Ok(result) => result,
+                Err(error) => {
+                    check.errors.push(format!("{}: {}", server, error));
+                    return check;
+                },
+            }
+        },
+        None => {
+            check.errors.push("No STUN servers configured".into());
+            return check;
+        },
+    };
+    check.local_address = Some(canonical(local));
+    check.external_addresses.push(canonical(first));
+
+    let second = match servers.next() {
+        Some(server) => {
+            let local = SocketAddr::new("::".parse().unwrap(), local.port());
+            match transit::stun_query(server, local, timeout).await {
+                Ok((_, second)) => {
+                    check.external_addresses.push(canonical(second));
+                    Some(second)
+                },
+                Err(error) => {
+                    check.errors.push(format!("{}: {}", server, error));
+                    None
+                },
+            }
+        },
+        None => None,
+    };
+
+    check.nat_type = NatType::estimate(local, first, second);
+    check
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nat_estimate() {
+        let local: SocketAddr = "[::ffff:192.168.1.2]:4000".parse().unwrap();
+        let first: SocketAddr = "203.0.113.1:4000".parse().unwrap();
+
+        assert_eq!(
+            NatType::estimate(local, "192.168.1.2:4000".parse().unwrap(), None),
+            NatType::None
+        );
+        assert_eq!(
+            NatType::estimate(local, first, Some(first)),
+            NatType::EndpointIndependent
+        );
+        assert_eq!(
+            NatType::estimate(local, first, Some("203.0.113.1:4001".parse().unwrap())),
+            NatType::Symmetric
+        );
+        assert_eq!(NatType::estimate(local, first, None), NatType::Unknown);
+    }
+
+    #[test]
+    fn test_report_serialization() {
+        let report = DiagnosticsReport {
+            rendezvous: RendezvousCheck {
+                url: "ws://localhost:4000/v1".into(),
+                latency: Some(Duration::from_millis(250)),
+                motd: None,
+                error: None,
+            },
+            relays: vec![RelayCheck {
+                name: None,
+                endpoint: "tcp://localhost:4001".into(),
+                latency: None,
+                error: Some("Connection refused".into()),
+            }],
+            nat: NatCheck {
+                nat_type: NatType::EndpointIndependent,
+                local_address: None,
+                external_addresses: vec![],
+                errors: vec![],
+            },
+        };
+        assert!(!report.is_healthy());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "rendezvous": {
+                    "url": "ws://localhost:4000/v1",
+                    "latency": 0.25,
+                    "motd": null,
+                    "error": null,
+                },
+                "relays": [{
+                    "name": null,
+                    "endpoint": "tcp://localhost:4001",
+                    "latency": null,
+                    "error": "Connection refused",
+                }],
+                "nat": {
+                    "nat-type": "endpoint-independent",
+                    "local-address": null,
+                    "external-addresses": [],
+                    "errors": [],
+                },
+            })
+        );
+    }
+}
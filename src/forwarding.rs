@@ -14,8 +14,9 @@
 //! that need buffering already do it on their side, and those who don't, don't.)
 
 use super::*;
+use crate::schema::{self, Field, Schema, UnknownFields, UnknownVariants};
 use async_std::net::{TcpListener, TcpStream};
-use futures::{AsyncReadExt, AsyncWriteExt, Future, SinkExt, StreamExt, TryStreamExt};
+use futures::{AsyncReadExt, AsyncWriteExt, Future, FutureExt, SinkExt, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
@@ -39,16 +40,49 @@ pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersi
     rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
     app_version: AppVersion {
         transit_abilities: transit::Abilities::ALL_ABILITIES,
+        flow_control_window: Some(DEFAULT_FLOW_CONTROL_WINDOW),
+        keep_alive: true,
+        resume: true,
         other: serde_json::Value::Null,
     },
+    client_version: crate::ClientVersion::DEFAULT,
+    reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+    timeouts: crate::WormholeTimeouts::DEFAULT,
 };
 
+/// Default for [`AppVersion::flow_control_window`]
+pub const DEFAULT_FLOW_CONTROL_WINDOW: u64 = 1024 * 1024;
+
 /**
  * The application specific version information for this protocol.
  */
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct AppVersion {
     pub transit_abilities: transit::Abilities,
+    /**
+     * How many bytes of each forwarded connection may be on their way to us, unacknowledged
+     *
+     * If both sides set this, each side only reads as much from its local connections as the peer's
+     * window allows, and waits for the peer to acknowledge the written data before reading more.
+     * Otherwise, a slow transit connection makes the data pile up in memory.
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control_window: Option<u64>,
+    /**
+     * Whether we take keep-alive messages from the peer while it waits for the user to accept the offer
+     *
+     * If the forwarder does not set this, [`ConnectOffer::keep_alive_while`] pings the rendezvous server instead,
+     * as long as the connection to it is kept open.
+     */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_alive: bool,
+    /**
+     * Whether we can meet the peer again after the transit connection broke
+     *
+     * Only if both sides set this, [`ServeOptions::resume_window`] takes effect.
+     */
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resume: bool,
     #[serde(flatten)]
     other: serde_json::Value,
 }
@@ -85,29 +119,70 @@ pub enum ForwardingError {
     )]
     ProtocolUnexpectedMessage(Box<str>, Box<dyn std::fmt::Debug + Send + Sync>),
     #[error("Wormhole connection error")]
-    Wormhole(
-        #[from]
-        #[source]
-        WormholeError,
-    ),
+    Wormhole(#[source] Box<WormholeError>),
     #[error("Error while establishing transit connection")]
-    TransitConnect(
-        #[from]
-        #[source]
-        TransitConnectError,
-    ),
+    TransitConnect(#[source] TransitConnectError),
     #[error("Transit error")]
-    Transit(
-        #[from]
-        #[source]
-        TransitError,
-    ),
+    Transit(#[source] TransitError),
     #[error("IO error")]
     IO(
         #[from]
         #[source]
         std::io::Error,
     ),
+    /// The peer did not answer the offer within [`Timeouts::offer`](transit::Timeouts::offer)
+    #[error("The peer did not answer the offer in time")]
+    OfferTimeout,
+    /// The transit connection was not established within [`Timeouts::transit`](transit::Timeouts::transit)
+    #[error("Timed out while establishing the transit connection")]
+    TransitHandshakeTimeout,
+    /// The peer went silent for longer than [`Timeouts::idle_peer`](transit::Timeouts::idle_peer)
+    #[error("The peer did not send anything for too long")]
+    IdlePeerTimeout,
+    /// The peer sent more than allowed by the [`ServeOptions`]
+    #[error("Peer exceeded the limits: {}", _0)]
+    LimitExceeded(Box<str>),
+    /// The certificates or keys given for TLS are not usable
+    #[error("Invalid TLS configuration")]
+    Tls(#[source] Box<rustls::Error>),
+    /// What was going on when the error in `source` happened, like which connection was affected.
+    /// See [`root`](Self::root) for the original error.
+    #[error("{}", context)]
+    Context {
+        context: Box<str>,
+        #[source]
+        source: Box<ForwardingError>,
+    },
+}
+
+impl From<WormholeError> for ForwardingError {
+    fn from(error: WormholeError) -> Self {
+        Self::Wormhole(Box::new(error))
+    }
+}
+
+impl From<rustls::Error> for ForwardingError {
+    fn from(error: rustls::Error) -> Self {
+        Self::Tls(Box::new(error))
+    }
+}
+
+impl From<TransitConnectError> for ForwardingError {
+    fn from(error: TransitConnectError) -> Self {
+        match error {
+            TransitConnectError::Timeout => Self::TransitHandshakeTimeout,
+            error => Self::TransitConnect(error),
+        }
+    }
+}
+
+impl From<TransitError> for ForwardingError {
+    fn from(error: TransitError) -> Self {
+        match error {
+            TransitError::IdleTimeout => Self::IdlePeerTimeout,
+            error => Self::Transit(error),
+        }
+    }
 }
 
 impl ForwardingError {
@@ -121,6 +196,462 @@ impl ForwardingError {
     ) -> Self {
         Self::ProtocolUnexpectedMessage(expected.into(), Box::new(got))
     }
+
+    /** Add what was going on when this error happened, see [`ForwardingError::Context`] */
+    pub(self) fn context(self, context: impl Into<Box<str>>) -> Self {
+        Self::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /** The original error, without the [`Context`](Self::Context) that got added to it */
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+}
+
+/**
+ * Settings of a serving side, see [`serve_with_options`]
+ *
+ * A peer exceeding the limits gets an error message, and the forwarding session is closed.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServeOptions {
+    /// Maximum number of bytes in a single forwarded payload
+    pub max_payload_size: usize,
+    /// Maximum number of messages per second, across all connections
+    pub max_messages_per_second: u32,
+    /**
+     * Keep retrying to connect to a local target for this long before giving up
+     *
+     * This lets forwarded connections survive quick restarts of the target, like a development
+     * server reloading. Other connections are paused while retrying. Disabled by default.
+     */
+    pub target_retry_window: Option<std::time::Duration>,
+    /// Check which targets accept connections before offering them, see [`ConnectOffer::health`]
+    pub probe_targets: bool,
+    /// Names and protocols of the targets to show to the peer, see [`ConnectOffer::service`]
+    pub services: HashMap<(Option<url::Host>, u16), ServiceInfo>,
+    /// Targets that only speak TLS, like internal `https://` services
+    pub tls_targets: HashMap<(Option<url::Host>, u16), TlsOrigination>,
+    /**
+     * Targets served by a command instead of a listening socket, inetd-style
+     *
+     * Every connection to such a target spawns the command, with its standard input and output as the
+     * connection. The target is offered in addition to the others, its port is only the name the peer
+     * knows it by. Nothing needs to listen there, and it is not probed.
+     */
+    pub command_targets: HashMap<(Option<url::Host>, u16), TargetCommand>,
+    /**
+     * Try to resume the session for this long after the transit connection got lost
+     *
+     * Both sides keep their local connections open meanwhile, and meet again on a relay server with a key
+     * derived from the wormhole key. Connections that lost data on the way get closed, the others carry
+     * on as if nothing happened. Only works if both sides allow relayed connections, and if the peer
+     * supports it, see [`AppVersion::resume`]. Disabled by default.
+     */
+    pub resume_window: Option<std::time::Duration>,
+    /**
+     * Give up on the peer after these time budgets
+     *
+     * The transit budget bounds establishing the connection, and the idle one how long the peer may
+     * stay silent during the session. The offer timeout does not apply, as the peer accepts without
+     * answering. No idle timeout by default.
+     */
+    pub timeouts: transit::Timeouts,
+    /// Close the session once it forwarded more than this many bytes, in both directions together
+    pub max_bytes: Option<u64>,
+    /// Application specific data to send along with the offer, see [`ConnectOffer::metadata`]
+    pub metadata: Option<crate::OfferMetadata>,
+    /// Where the connection workers run, on the async-std executor by default
+    pub spawner: Spawner,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            max_payload_size: 64 * 1024,
+            max_messages_per_second: 10_000,
+            target_retry_window: None,
+            probe_targets: false,
+            services: HashMap::new(),
+            tls_targets: HashMap::new(),
+            command_targets: HashMap::new(),
+            resume_window: None,
+            timeouts: transit::Timeouts::default(),
+            max_bytes: None,
+            metadata: None,
+            spawner: Spawner::default(),
+        }
+    }
+}
+
+/**
+ * Runs the worker tasks of a forwarding session
+ *
+ * Every forwarded connection gets a worker that reads from its local socket. By default, they get
+ * spawned onto the async-std executor. Embedders with an executor of their own plug it in with
+ * [`Spawner::new`], see [`ServeOptions::spawner`] and [`ConnectOffer::set_spawner`]. The workers are
+ * not `Send`, so it must be able to run them on the current thread.
+ */
+#[derive(Clone)]
+pub struct Spawner(Arc<dyn futures::task::LocalSpawn + Send + Sync>);
+
+impl Spawner {
+    pub fn new(spawner: impl futures::task::LocalSpawn + Send + Sync + 'static) -> Self {
+        Self(Arc::new(spawner))
+    }
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self::new(AsyncStdSpawner)
+    }
+}
+
+impl std::fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Spawner")
+    }
+}
+
+/* Only the very same spawner compares equal, for `ServeOptions` */
+impl PartialEq for Spawner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Spawner {}
+
+struct AsyncStdSpawner;
+
+impl futures::task::LocalSpawn for AsyncStdSpawner {
+    fn spawn_local_obj(
+        &self,
+        future: futures::task::LocalFutureObj<'static, ()>,
+    ) -> Result<(), futures::task::SpawnError> {
+        async_std::task::spawn_local(future);
+        Ok(())
+    }
+}
+
+/**
+ * Connect to a target over TLS, see [`ServeOptions::tls_targets`]
+ *
+ * The peer still gets the plain traffic, so that it can offer the target on its side without a
+ * certificate. Use [`ConnectOffer::terminate_tls`] there to encrypt it again.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TlsOrigination {
+    /// The name to verify the certificate against. Defaults to the host of the target, or `localhost`
+    pub server_name: Option<String>,
+    /**
+     * DER encoded certificates to trust instead of the public web PKI
+     *
+     * Internal services tend to have a certificate of a private CA, or a self-signed one. If empty, the
+     * platform's trust store is used as well with the `native-certs` feature.
+     */
+    pub root_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsOrigination {
+    fn connector(&self) -> Result<async_tls::TlsConnector, rustls::Error> {
+        if self.root_certificates.is_empty() {
+            #[cfg(feature = "native-certs")]
+            return Ok(crate::tls::connector());
+            #[cfg(not(feature = "native-certs"))]
+            return Ok(async_tls::TlsConnector::new());
+        }
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in &self.root_certificates {
+            roots.add(&rustls::Certificate(certificate.clone()))?;
+        }
+        Ok(rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+            .into())
+    }
+}
+
+/**
+ * A command to spawn for every connection to a target, see [`ServeOptions::command_targets`]
+ *
+ * The command gets the connection as its standard input and output, like with inetd. It gets killed when
+ * the connection is closed, and closing its standard output closes the connection. Standard error is not
+ * forwarded, it goes to ours.
+ *
+ * **Security warning:** the peer can run this command as often as it likes. Think of `sftp-server` or a
+ * shell: it gets whatever access the command grants.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TargetCommand {
+    pub program: std::ffi::OsString,
+    pub args: Vec<std::ffi::OsString>,
+    /// The working directory, ours by default
+    pub current_dir: Option<std::path::PathBuf>,
+}
+
+impl TargetCommand {
+    pub fn new(
+        program: impl Into<std::ffi::OsString>,
+        args: impl IntoIterator<Item = impl Into<std::ffi::OsString>>,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            current_dir: None,
+        }
+    }
+
+    fn spawn(&self) -> std::io::Result<CommandConnection> {
+        use async_std::process::{Command, Stdio};
+
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        let mut child = command.spawn()?;
+        Ok(CommandConnection {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: child.stdout.take().expect("stdout is piped"),
+            _child: child,
+        })
+    }
+}
+
+/* A spawned `TargetCommand`, its standard input and output make the connection. Dropping it kills it */
+struct CommandConnection {
+    stdin: async_std::process::ChildStdin,
+    stdout: async_std::process::ChildStdout,
+    _child: async_std::process::Child,
+}
+
+impl futures::AsyncRead for CommandConnection {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        std::pin::Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl futures::AsyncWrite for CommandConnection {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        std::pin::Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.stdin).poll_close(cx)
+    }
+}
+
+/** How long a local client may take for the TLS handshake, see [`ConnectOffer::terminate_tls`] */
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/* A forwarded connection, either plain TCP or wrapped in TLS */
+trait Socket: futures::AsyncRead + futures::AsyncWrite + Unpin + Send {}
+
+impl<T: futures::AsyncRead + futures::AsyncWrite + Unpin + Send> Socket for T {}
+
+/**
+ * Human readable information about a forwarded target
+ *
+ * **Security warning:** when received from the peer, this is untrusted input.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceInfo {
+    /// A name for the service, like "Grafana"
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The protocol spoken by the service, like "http" or "ssh"
+    #[serde(default)]
+    pub protocol: Option<String>,
+}
+
+impl std::fmt::Display for ServiceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.label, &self.protocol) {
+            (Some(label), Some(protocol)) => write!(f, "{} ({})", label, protocol),
+            (Some(label), None) => write!(f, "{}", label),
+            (None, Some(protocol)) => write!(f, "({})", protocol),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Whether a forwarded target accepted a connection when the offer was made
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum TargetHealth {
+    Up,
+    Down,
+    #[serde(other)]
+    Unknown,
+}
+
+/** How long to wait for a target to accept the probe connection */
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/** Try to connect to all targets concurrently */
+async fn probe_targets(
+    targets: &HashMap<String, (Option<url::Host>, u16)>,
+) -> HashMap<String, TargetHealth> {
+    futures::future::join_all(targets.iter().map(|(address, (host, port))| async move {
+        let target = match host {
+            Some(_) => address.clone(),
+            None => format!("[::1]:{}", port),
+        };
+        let health = match util::timeout(PROBE_TIMEOUT, TcpStream::connect(&target)).await {
+            Ok(Ok(_)) => TargetHealth::Up,
+            Ok(Err(err)) => {
+                log::warn!("Target {} is not reachable: {}", target, err);
+                TargetHealth::Down
+            },
+            Err(_) => {
+                log::warn!("Target {} is not reachable: timed out", target);
+                TargetHealth::Down
+            },
+        };
+        (address.clone(), health)
+    }))
+    .await
+    .into_iter()
+    .collect()
+}
+
+/** Connect to `target`, retrying with exponential backoff for up to `retry_window` */
+async fn connect_with_retry(
+    target: &str,
+    retry_window: Option<std::time::Duration>,
+) -> std::io::Result<TcpStream> {
+    let start = instant::Instant::now();
+    let mut backoff = std::time::Duration::from_millis(100);
+    loop {
+        match TcpStream::connect(target).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => match retry_window {
+                Some(window) if start.elapsed() + backoff < window => {
+                    log::debug!(
+                        "Cannot connect to {}: {}. Retrying in {:?}",
+                        target,
+                        err,
+                        backoff
+                    );
+                    util::sleep(backoff).await;
+                    backoff = (backoff * 2).min(std::time::Duration::from_secs(2));
+                },
+                _ => return Err(err),
+            },
+        }
+    }
+}
+
+/** Count the peer's messages in one second windows */
+struct RateLimiter {
+    limit: u32,
+    window_start: instant::Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            window_start: instant::Instant::now(),
+            count: 0,
+        }
+    }
+
+    /** Register a message, returns `false` if that's one too many */
+    fn check(&mut self) -> bool {
+        if self.window_start.elapsed() >= std::time::Duration::from_secs(1) {
+            self.window_start = instant::Instant::now();
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        self.count <= self.limit
+    }
+}
+
+/**
+ * Take turns between messages from the peer and from our connections
+ *
+ * `select!` picks randomly among the ready branches, which under sustained load in both directions may
+ * still keep one of them waiting for a long stretch. While both have messages ready, this strictly
+ * alternates instead, so that for example an SCP session through the tunnel keeps flowing both ways.
+ */
+#[derive(Default)]
+struct Alternate {
+    right_first: bool,
+}
+
+impl Alternate {
+    fn next<'a, L, R>(
+        &'a mut self,
+        left: &'a mut L,
+        right: &'a mut R,
+    ) -> impl futures::future::FusedFuture<
+        Output = futures::future::Either<Option<L::Item>, Option<R::Item>>,
+    > + 'a
+    where
+        L: futures::Stream + Unpin,
+        R: futures::Stream + Unpin,
+    {
+        use futures::future::Either;
+        use std::task::Poll;
+
+        futures::future::poll_fn(move |cx| {
+            if self.right_first {
+                if let Poll::Ready(item) = right.poll_next_unpin(cx) {
+                    self.right_first = false;
+                    return Poll::Ready(Either::Right(item));
+                }
+            }
+            if let Poll::Ready(item) = left.poll_next_unpin(cx) {
+                self.right_first = true;
+                return Poll::Ready(Either::Left(item));
+            }
+            if !self.right_first {
+                if let Poll::Ready(item) = right.poll_next_unpin(cx) {
+                    return Poll::Ready(Either::Right(item));
+                }
+            }
+            Poll::Pending
+        })
+        .fuse()
+    }
 }
 
 /// Offer to forward some ports
@@ -134,23 +665,112 @@ impl ForwardingError {
 /// handling. If you want the forward to never (successfully) stop, pass [`futures::future::pending()`]
 /// as the value.
 pub async fn serve(
+    wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    targets: Vec<(Option<url::Host>, u16)>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), ForwardingError> {
+    serve_with_options(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        targets,
+        ServeOptions::default(),
+        cancel,
+    )
+    .await
+}
+
+/// Like [`serve`], but with custom [`ServeOptions`]
+pub async fn serve_with_options(
+    wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    targets: Vec<(Option<url::Host>, u16)>,
+    options: ServeOptions,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), ForwardingError> {
+    serve_inner(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        targets,
+        options,
+        Events::default(),
+        cancel,
+    )
+    .await
+}
+
+/// Like [`serve_with_options`], but also get a stream of [`ForwardingEvent`]s to watch the connections
+///
+/// The stream ends once the returned future is done. Drop it if you are not interested anymore.
+pub fn serve_with_events(
+    wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    targets: Vec<(Option<url::Host>, u16)>,
+    options: ServeOptions,
+    cancel: impl Future<Output = ()>,
+) -> (
+    impl Future<Output = Result<(), ForwardingError>>,
+    impl futures::Stream<Item = ForwardingEvent>,
+) {
+    let (events, receiver) = Events::channel();
+    let serve = serve_inner(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        targets,
+        options,
+        events,
+        cancel,
+    );
+    (serve, receiver)
+}
+
+async fn serve_inner(
     mut wormhole: Wormhole,
     transit_handler: impl FnOnce(transit::TransitInfo),
     relay_hints: Vec<transit::RelayHint>,
     targets: Vec<(Option<url::Host>, u16)>,
+    options: ServeOptions,
+    events: Events,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), ForwardingError> {
+    let tls_connectors = options
+        .tls_targets
+        .iter()
+        .map(|(target, tls)| {
+            let server_name = match (&tls.server_name, &target.0) {
+                (Some(name), _) => name.clone(),
+                (None, Some(host)) => host.to_string(),
+                (None, None) => "localhost".into(),
+            };
+            Ok((target.clone(), (server_name, tls.connector()?)))
+        })
+        .collect::<Result<HashMap<_, _>, rustls::Error>>()?;
+
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
     let our_version: &AppVersion = wormhole
         .our_version
         .downcast_ref()
         .expect("You may only use a Wormhole instance with the correct AppVersion type!");
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
-    let connector = transit::init(
+    let abilities = (
+        our_version.transit_abilities,
+        peer_version.transit_abilities,
+    );
+    let resume = our_version.resume && peer_version.resume;
+    let flow = FlowControl::new(our_version, &peer_version);
+    let mut connector = transit::init(
         our_version.transit_abilities,
         Some(peer_version.transit_abilities),
-        relay_hints,
+        relay_hints.clone(),
     )
     .await?;
+    connector.set_timeouts(options.timeouts.transit);
 
     /* Send our transit hints */
     wormhole
@@ -161,6 +781,7 @@ pub async fn serve(
 
     let targets: HashMap<String, (Option<url::Host>, u16)> = targets
         .into_iter()
+        .chain(options.command_targets.keys().cloned())
         .map(|(host, port)| match host {
             Some(host) => {
                 if port == 80 || port == 443 || port == 8000 || port == 8080 {
@@ -172,103 +793,708 @@ pub async fn serve(
         })
         .collect();
 
-    /* Receive their transit hints */
-    let their_hints: transit::Hints = match wormhole.receive_json().await?? {
-        PeerMessage::Transit { hints } => {
-            log::debug!("Received transit message: {:?}", hints);
-            hints
-        },
-        PeerMessage::Error(err) => {
-            bail!(ForwardingError::PeerError(err));
-        },
-        other => {
-            let error = ForwardingError::unexpected_message("transit", other);
-            let _ = wormhole
-                .send_json(&PeerMessage::Error(format!("{}", error)))
-                .await;
-            bail!(error)
-        },
-    };
+    /* Receive their transit hints */
+    let their_hints: transit::Hints = match wormhole.receive_json().await?? {
+        PeerMessage::Transit { hints } => {
+            log::debug!("Received transit message: {:?}", hints);
+            hints
+        },
+        PeerMessage::Error(err) => {
+            bail!(ForwardingError::PeerError(err));
+        },
+        other => {
+            let error = ForwardingError::unexpected_message("transit", other);
+            let _ = wormhole
+                .send_json(&PeerMessage::Error(format!("{}", error)))
+                .await;
+            bail!(error)
+        },
+    };
+    let resumption = options.resume_window.and_then(|window| {
+        Resumption::new(
+            &wormhole,
+            window,
+            true,
+            resume,
+            abilities,
+            relay_hints,
+            &their_hints,
+        )
+    });
+
+    let (mut transit, info) = match connector
+        .leader_connect(
+            wormhole.key().derive_transit_key(wormhole.appid()),
+            peer_version.transit_abilities,
+            Arc::new(their_hints),
+        )
+        .await
+    {
+        Ok(transit) => transit,
+        Err(error) => {
+            let error = ForwardingError::from(error);
+            let _ = wormhole
+                .send_json(&PeerMessage::Error(format!("{}", error)))
+                .await;
+            return Err(error);
+        },
+    };
+    if wormhole.is_strict() {
+        transit.set_validator(Some(|record| PEER_MESSAGE_SCHEMA.validate_msgpack(record)));
+    }
+    transit_handler(info);
+
+    /* We got a transit, now close the Wormhole */
+    let wormhole = wormhole.close_for_transit().await?;
+
+    let health = if options.probe_targets {
+        let probed = targets
+            .iter()
+            .filter(|(_, target)| !options.command_targets.contains_key(target))
+            .map(|(address, target)| (address.clone(), target.clone()))
+            .collect();
+        probe_targets(&probed).await
+    } else {
+        HashMap::new()
+    };
+    let services = targets
+        .iter()
+        .filter_map(|(address, target)| {
+            options
+                .services
+                .get(target)
+                .map(|service| (address.clone(), service.clone()))
+        })
+        .collect();
+    transit
+        .send_record(
+            &PeerMessage::Offer {
+                addresses: targets.keys().cloned().collect(),
+                health,
+                services,
+                resume_window: resumption
+                    .as_ref()
+                    .map(|resumption| resumption.window.as_secs()),
+                metadata: options.metadata.clone(),
+            }
+            .ser_msgpack(),
+        )
+        .await?;
+
+    let (backchannel_tx, backchannel_rx) =
+        futures::channel::mpsc::channel::<(u64, Option<Vec<u8>>)>(20);
+
+    /* Main processing loop. Errors other than a PeerError (i.e. coming from the other side) get sent to the peer. */
+    let idle_timeout = options.timeouts.idle_peer;
+    run_session(
+        &mut ForwardingServe {
+            targets,
+            tls_connectors,
+            connections: HashMap::new(),
+            transferred: HashMap::new(),
+            historic_connections: HashSet::new(),
+            backchannel_tx,
+            backchannel_rx,
+            flow,
+            forwarded_bytes: 0,
+            options,
+            events,
+        },
+        transit,
+        resumption,
+        idle_timeout,
+        cancel,
+    )
+    .await?;
+    if let Some(wormhole) = wormhole {
+        wormhole.close().await?;
+    }
+    Ok(())
+}
+
+/// What happens on the connections of a forwarding session
+///
+/// See [`serve_with_events`] and [`ConnectOffer::accept_with_events`]. Byte counts are payload bytes
+/// of that connection so far, `sent` to the peer and `received` from it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ForwardingEvent {
+    /// A connection to the target got established
+    ConnectionOpened { connection_id: u64, target: String },
+    /// Some data got forwarded over the connection
+    BytesTransferred {
+        connection_id: u64,
+        sent: u64,
+        received: u64,
+    },
+    /// Forwarding the connection failed because its worker panicked. It gets closed right after.
+    ConnectionFailed { connection_id: u64, error: String },
+    /// The connection got closed, by either side
+    ConnectionClosed {
+        connection_id: u64,
+        sent: u64,
+        received: u64,
+    },
+}
+
+/* Where the events of a session go, if anywhere */
+#[derive(Clone, Default)]
+struct Events(Option<futures::channel::mpsc::UnboundedSender<ForwardingEvent>>);
+
+impl Events {
+    fn channel() -> (
+        Self,
+        futures::channel::mpsc::UnboundedReceiver<ForwardingEvent>,
+    ) {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        (Self(Some(sender)), receiver)
+    }
+
+    fn emit(&self, event: ForwardingEvent) {
+        if let Some(sender) = &self.0 {
+            /* Nobody listens anymore */
+            let _ = sender.unbounded_send(event);
+        }
+    }
+
+    fn transferred(&self, connection_id: u64, (sent, received): (u64, u64)) {
+        self.emit(ForwardingEvent::BytesTransferred {
+            connection_id,
+            sent,
+            received,
+        });
+    }
+
+    fn closed(&self, connection_id: u64, (sent, received): (u64, u64)) {
+        self.emit(ForwardingEvent::ConnectionClosed {
+            connection_id,
+            sent,
+            received,
+        });
+    }
+}
+
+/// What happens to the sessions of [`serve_many`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ServeEvent {
+    /// The transit connection to the peer of the session is established
+    Connected {
+        session: String,
+        info: transit::TransitInfo,
+    },
+    /// The session is over, either successfully or with an error
+    Finished {
+        session: String,
+        result: Result<(), ForwardingError>,
+    },
+    /// The session was turned away, because `max_sessions` were already running
+    Rejected { session: String },
+}
+
+/**
+ * Offer the same targets to several peers at once
+ *
+ * Every item of `sessions` is a connected wormhole together with a name for the session, like the
+ * user it got handed out to. Each one gets served as with [`serve_with_options`], concurrently and
+ * independently of each other. The limits of the [`ServeOptions`], like [`max_bytes`](ServeOptions::max_bytes),
+ * apply to each session on its own. Once `max_sessions` are running, further ones get rejected until
+ * one of them finished.
+ *
+ * This runs until `sessions` ended and all sessions finished, or until `cancel` resolves. The latter
+ * closes all running sessions gracefully.
+ */
+pub async fn serve_many(
+    sessions: impl futures::Stream<Item = (String, Wormhole)>,
+    relay_hints: Vec<transit::RelayHint>,
+    targets: Vec<(Option<url::Host>, u16)>,
+    options: ServeOptions,
+    max_sessions: usize,
+    event_handler: impl FnMut(ServeEvent),
+    cancel: impl Future<Output = ()>,
+) -> Result<(), ForwardingError> {
+    let event_handler = std::cell::RefCell::new(event_handler);
+    /* Dropping the sender cancels all sessions */
+    let (cancel_tx, cancel_rx) = futures::channel::oneshot::channel::<()>();
+    let cancel_rx = cancel_rx.shared();
+
+    let serve_session = |session: String, wormhole: Wormhole| {
+        let cancel = cancel_rx.clone().map(|_| ());
+        let event_handler = &event_handler;
+        let serve = serve_with_options(
+            wormhole,
+            {
+                let session = session.clone();
+                move |info| event_handler.borrow_mut()(ServeEvent::Connected { session, info })
+            },
+            relay_hints.clone(),
+            targets.clone(),
+            options.clone(),
+            cancel,
+        );
+        async move { (session, serve.await) }
+    };
+
+    let sessions = sessions.fuse();
+    futures::pin_mut!(sessions);
+    let cancel = cancel.fuse();
+    futures::pin_mut!(cancel);
+    let mut running = futures::stream::FuturesUnordered::new();
+    loop {
+        futures::select! {
+            next = sessions.next() => {
+                let Some((session, mut wormhole)) = next else {
+                    continue;
+                };
+                if running.len() >= max_sessions {
+                    log::info!("Rejecting session '{}', {} are running already", session, max_sessions);
+                    let _ = wormhole
+                        .send_json(&PeerMessage::Error("Too many sessions".into()))
+                        .await;
+                    let _ = wormhole.close().await;
+                    event_handler.borrow_mut()(ServeEvent::Rejected { session });
+                    continue;
+                }
+                log::debug!("Starting session '{}'", session);
+                running.push(serve_session(session, wormhole));
+            },
+            (session, result) = running.select_next_some() => {
+                log::debug!("Session '{}' finished", session);
+                event_handler.borrow_mut()(ServeEvent::Finished { session, result });
+            },
+            () = cancel => break,
+            complete => return Ok(()),
+        }
+    }
+
+    /* Wind down the running sessions */
+    drop(cancel_tx);
+    while let Some((session, result)) = running.next().await {
+        event_handler.borrow_mut()(ServeEvent::Finished { session, result });
+    }
+    Ok(())
+}
+
+/** The peer may ask to wait at most this long for resuming */
+const MAX_RESUME_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/** What it takes to meet the peer again, see [`ServeOptions::resume_window`] */
+struct Resumption {
+    key: Key<GenericKey>,
+    /* Number of resumptions so far. Every one needs a fresh transit key. */
+    generation: u64,
+    window: std::time::Duration,
+    leader: bool,
+    relay_hints: Vec<transit::RelayHint>,
+    their_hints: Arc<transit::Hints>,
+}
+
+impl Resumption {
+    /**
+     * `None` if not both sides advertise [`AppVersion::resume`], or if the abilities or hints don't allow
+     * meeting on a relay server
+     */
+    fn new(
+        wormhole: &Wormhole,
+        window: std::time::Duration,
+        leader: bool,
+        resume: bool,
+        abilities: (transit::Abilities, transit::Abilities),
+        relay_hints: Vec<transit::RelayHint>,
+        their_hints: &transit::Hints,
+    ) -> Option<Self> {
+        if !resume {
+            return None;
+        }
+        if !abilities.0.can_relay() || !abilities.1.can_relay() {
+            return None;
+        }
+        if relay_hints.is_empty() && their_hints.relay.is_empty() {
+            return None;
+        }
+        Some(Self {
+            key: wormhole
+                .key()
+                .derive_subkey_from_purpose(&format!("{}/resume-key", wormhole.appid())),
+            generation: 0,
+            window,
+            leader,
+            relay_hints,
+            their_hints: Arc::new(transit::Hints::new([], their_hints.relay.clone())),
+        })
+    }
+
+    /** Connect to the peer again, retrying until the window is over */
+    async fn reconnect(&mut self) -> Result<transit::Transit, ForwardingError> {
+        let start = instant::Instant::now();
+        let mut backoff = std::time::Duration::from_secs(1);
+        loop {
+            let key = self
+                .key
+                .derive_subkey_from_purpose(&format!("resume/{}", self.generation));
+            let attempt = async {
+                let connector = transit::init(
+                    transit::Abilities::FORCE_RELAY,
+                    Some(transit::Abilities::FORCE_RELAY),
+                    self.relay_hints.clone(),
+                )
+                .await?;
+                if self.leader {
+                    connector
+                        .leader_connect(
+                            key,
+                            transit::Abilities::FORCE_RELAY,
+                            self.their_hints.clone(),
+                        )
+                        .await
+                } else {
+                    connector
+                        .follower_connect(
+                            key,
+                            transit::Abilities::FORCE_RELAY,
+                            self.their_hints.clone(),
+                        )
+                        .await
+                }
+            };
+            let remaining = self.window.saturating_sub(start.elapsed());
+            let error = match util::timeout(remaining, attempt).await {
+                Ok(Ok((transit, _info))) => {
+                    self.generation += 1;
+                    return Ok(transit);
+                },
+                Ok(Err(error)) => error,
+                Err(_) => TransitConnectError::Timeout,
+            };
+            if start.elapsed() + backoff >= self.window {
+                return Err(error.into());
+            }
+            log::debug!("Cannot resume yet: {}. Retrying in {:?}", error, backoff);
+            util::sleep(backoff).await;
+            backoff = (backoff * 2).min(std::time::Duration::from_secs(10));
+        }
+    }
+}
+
+/* The parts of both sides that are needed for running and resuming a session */
+trait Session {
+    async fn run(
+        &mut self,
+        transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+        transit_rx: &mut (impl futures::stream::FusedStream<Item = Result<Box<[u8]>, TransitError>>
+                  + Unpin),
+        cancel: &mut (impl futures::future::FusedFuture<Output = ()> + Unpin),
+    ) -> Result<(), ForwardingError>;
+
+    async fn shutdown(&mut self);
+
+    /* Bytes forwarded to us so far, by connection */
+    fn received(&self) -> HashMap<u64, u64>;
+
+    /* Close the connections that the peer doesn't know or that lost data */
+    async fn resume(
+        &mut self,
+        transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+        peer_received: HashMap<u64, u64>,
+    ) -> Result<(), ForwardingError>;
+}
+
+/**
+ * Run the session until it is done, resuming it whenever the transit connection gets lost
+ *
+ * On errors, tell the peer if possible. A peer silent for longer than `idle_timeout` counts as lost.
+ */
+async fn run_session(
+    session: &mut impl Session,
+    mut transit: transit::Transit,
+    mut resumption: Option<Resumption>,
+    idle_timeout: Option<std::time::Duration>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), ForwardingError> {
+    use futures::FutureExt;
+    let cancel = cancel.fuse();
+    futures::pin_mut!(cancel);
+    let mut resuming = false;
+    let validator = transit.validator();
+    loop {
+        transit.set_idle_timeout(idle_timeout);
+        transit.set_validator(validator);
+        let (transit_tx, transit_rx) = transit.split();
+        let transit_rx = transit_rx.fuse();
+        futures::pin_mut!(transit_tx);
+        futures::pin_mut!(transit_rx);
+
+        let run = async {
+            if resuming {
+                /* Tell each other which data arrived */
+                transit_tx
+                    .send(
+                        PeerMessage::Resume {
+                            received: session.received(),
+                        }
+                        .ser_msgpack()
+                        .into_boxed_slice(),
+                    )
+                    .await?;
+                let message = transit_rx.next().await.unwrap_or_else(|| {
+                    Err(TransitError::IO(std::io::ErrorKind::UnexpectedEof.into()))
+                })?;
+                match PeerMessage::de_msgpack(&message)? {
+                    PeerMessage::Resume { received } => {
+                        session.resume(&mut transit_tx, received).await?
+                    },
+                    PeerMessage::Error(err) => bail!(ForwardingError::PeerError(err)),
+                    other => bail!(ForwardingError::unexpected_message("resume", other)),
+                }
+                log::debug!("Resumed the session");
+            }
+            session
+                .run(&mut transit_tx, &mut transit_rx, &mut cancel)
+                .await
+        };
+        /* Losing the connection to the peer can be resumed from, returns why it got lost */
+        let resumable = |error: &ForwardingError| match error.root() {
+            ForwardingError::Transit(TransitError::IO(error)) => Some(error.to_string()),
+            error @ ForwardingError::IdlePeerTimeout => Some(error.to_string()),
+            _ => None,
+        };
+        let error = match run.await {
+            Ok(()) => return Ok(()),
+            Err(error) if matches!(error.root(), ForwardingError::PeerError(_)) => {
+                return Err(error)
+            },
+            Err(error) => match (&resumption, resumable(&error)) {
+                (Some(_), Some(reason)) => reason,
+                _ => {
+                    let _ = transit_tx
+                        .send(
+                            PeerMessage::Error(format!("{}", error.root()))
+                                .ser_msgpack()
+                                .into_boxed_slice(),
+                        )
+                        .await;
+                    return Err(error);
+                },
+            },
+        };
+
+        log::warn!(
+            "Lost the connection to the peer: {}. Trying to resume the session.",
+            error
+        );
+        let reconnect = resumption.as_mut().unwrap().reconnect().fuse();
+        futures::pin_mut!(reconnect);
+        transit = futures::select! {
+            transit = reconnect => transit?,
+            () = &mut cancel => {
+                session.shutdown().await;
+                return Ok(());
+            },
+        };
+        resuming = true;
+    }
+}
+
+/**
+ * A spawned connection worker that gets cancelled when dropped
+ *
+ * Spawned tasks get detached, so dropping a forwarding session would leave its workers (and their
+ * sockets) around until the runtime exits. Use [`cancel`](Self::cancel) to wait until the worker
+ * is actually gone.
+ */
+struct WorkerHandle {
+    /* Resolves once the task dropped the worker */
+    done: Option<futures::channel::oneshot::Receiver<()>>,
+    abort: futures::future::AbortHandle,
+}
+
+impl WorkerHandle {
+    fn spawn(spawner: &Spawner, worker: impl Future<Output = ()> + 'static) -> Self {
+        use futures::task::LocalSpawnExt;
+
+        let (abort, registration) = futures::future::AbortHandle::new_pair();
+        let (done_tx, done) = futures::channel::oneshot::channel::<()>();
+        let task = async move {
+            let _ = futures::future::Abortable::new(worker, registration).await;
+            drop(done_tx);
+        };
+        if let Err(err) = spawner.0.spawn_local(task) {
+            log::warn!("Could not spawn a connection worker: {}", err);
+        }
+        Self {
+            done: Some(done),
+            abort,
+        }
+    }
+
+    async fn cancel(mut self) {
+        self.abort.abort();
+        if let Some(done) = self.done.take() {
+            let _ = done.await;
+        }
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        /* The task notices on its next poll and drops the worker, closing the connection */
+        self.abort.abort();
+    }
+}
+
+/**
+ * Per-connection flow control, see [`AppVersion::flow_control_window`]
+ *
+ * Each connection worker waits for acknowledgements once it has a window worth of data in flight.
+ * In the other direction, we acknowledge the written data every half window.
+ */
+#[derive(Default)]
+struct FlowControl {
+    /* (peer's window, our window), `None` if not both sides support it */
+    windows: Option<(u64, u64)>,
+    /* Tell the workers how many bytes the peer acknowledged */
+    acks: HashMap<u64, futures::channel::mpsc::UnboundedSender<u64>>,
+    /* Bytes we acknowledged so far, by connection */
+    acked: HashMap<u64, u64>,
+}
 
-    let (mut transit, info) = match connector
-        .leader_connect(
-            wormhole.key().derive_transit_key(wormhole.appid()),
-            peer_version.transit_abilities,
-            Arc::new(their_hints),
-        )
-        .await
-    {
-        Ok(transit) => transit,
-        Err(error) => {
-            let error = ForwardingError::TransitConnect(error);
-            let _ = wormhole
-                .send_json(&PeerMessage::Error(format!("{}", error)))
-                .await;
-            return Err(error);
-        },
-    };
-    transit_handler(info);
+impl FlowControl {
+    fn new(ours: &AppVersion, peer: &AppVersion) -> Self {
+        let windows = ours.flow_control_window.zip(peer.flow_control_window);
+        if windows.is_none() {
+            log::debug!("Not using flow control, the peer does not support it");
+        }
+        Self {
+            windows,
+            ..Self::default()
+        }
+    }
 
-    /* We got a transit, now close the Wormhole */
-    wormhole.close().await?;
+    /** Register a new connection, and get what its worker needs for waiting for acknowledgements */
+    fn add(
+        &mut self,
+        connection_id: u64,
+    ) -> Option<(u64, futures::channel::mpsc::UnboundedReceiver<u64>)> {
+        let (window, _) = self.windows?;
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        self.acks.insert(connection_id, tx);
+        self.acked.insert(connection_id, 0);
+        Some((window, rx))
+    }
 
-    transit
-        .send_record(
-            &PeerMessage::Offer {
-                addresses: targets.keys().cloned().collect(),
-            }
-            .ser_msgpack(),
-        )
-        .await?;
+    fn remove(&mut self, connection_id: u64) {
+        self.acks.remove(&connection_id);
+        self.acked.remove(&connection_id);
+    }
 
-    let (backchannel_tx, backchannel_rx) =
-        futures::channel::mpsc::channel::<(u64, Option<Vec<u8>>)>(20);
+    fn clear(&mut self) {
+        self.acks.clear();
+        self.acked.clear();
+    }
 
-    let (transit_tx, transit_rx) = transit.split();
-    let transit_rx = transit_rx.fuse();
-    use futures::future::FutureExt;
-    let cancel = cancel.fuse();
-    futures::pin_mut!(transit_tx);
-    futures::pin_mut!(transit_rx);
-    futures::pin_mut!(cancel);
+    /** The peer wrote `bytes` of the connection in total */
+    fn acknowledged(&mut self, connection_id: u64, bytes: u64) {
+        if let Some(acks) = self.acks.get(&connection_id) {
+            /* The worker may be gone already */
+            let _ = acks.unbounded_send(bytes);
+        }
+    }
 
-    /* Main processing loop. Catch errors */
-    let result = ForwardingServe {
-        targets,
-        connections: HashMap::new(),
-        historic_connections: HashSet::new(),
-        backchannel_tx,
-        backchannel_rx,
-    }
-    .run(&mut transit_tx, &mut transit_rx, &mut cancel)
-    .await;
-    /* If the error is not a PeerError (i.e. coming from the other side), try notifying the other side before quitting. */
-    match result {
-        Ok(()) => Ok(()),
-        Err(error @ ForwardingError::PeerError(_)) => Err(error),
-        Err(error) => {
-            let _ = transit_tx
-                .send(
-                    PeerMessage::Error(format!("{}", error))
-                        .ser_msgpack()
-                        .into_boxed_slice(),
-                )
-                .await;
-            Err(error)
-        },
+    /** We wrote `received` bytes of the connection in total. Returns the acknowledgement to send, if due. */
+    fn ack(&mut self, connection_id: u64, received: u64) -> Option<PeerMessage> {
+        let (_, window) = self.windows?;
+        let acked = self.acked.get_mut(&connection_id)?;
+        if received - *acked < (window / 2).max(1) {
+            return None;
+        }
+        *acked = received;
+        Some(PeerMessage::Ack {
+            connection_id,
+            bytes: received,
+        })
     }
 }
 
+/**
+ * Read from a local connection and hand the data to the session, until the connection closes
+ *
+ * With flow control, wait for the peer whenever it has a window worth of data unacknowledged.
+ * Should the worker panic, the connection gets closed like on any other error, and the panic
+ * reported as [`ForwardingEvent::ConnectionFailed`]. The other connections are not affected.
+ */
+fn spawn_worker(
+    spawner: &Spawner,
+    connection_id: u64,
+    mut connection_rd: futures::io::ReadHalf<Box<dyn Socket>>,
+    mut backchannel_tx: futures::channel::mpsc::Sender<(u64, Option<Vec<u8>>)>,
+    mut flow: Option<(u64, futures::channel::mpsc::UnboundedReceiver<u64>)>,
+    events: Events,
+) -> WorkerHandle {
+    let mut close_tx = backchannel_tx.clone();
+    let worker = async move {
+        let mut buffer = vec![0; 4096];
+        let (mut sent, mut acked) = (0u64, 0u64);
+        /* Ignore errors */
+        macro_rules! break_on_err {
+            ($expr:expr) => {
+                match $expr {
+                    Ok(val) => val,
+                    Err(_) => break,
+                }
+            };
+        }
+        'read: loop {
+            if let Some((window, acks)) = &mut flow {
+                while sent - acked >= *window {
+                    match acks.next().await {
+                        /* The peer cannot have written more than we sent */
+                        Some(bytes) => acked = acked.max(bytes.min(sent)),
+                        None => break 'read,
+                    }
+                }
+            }
+            let read = break_on_err!(connection_rd.read(&mut buffer).await);
+            if read == 0 {
+                break;
+            }
+            sent += read as u64;
+            let buffer = &buffer[..read];
+            break_on_err!(
+                backchannel_tx
+                    .send((connection_id, Some(buffer.to_vec())))
+                    .await
+            );
+        }
+        /* Close connection (maybe or not because of error) */
+        let _ = backchannel_tx.send((connection_id, None)).await;
+        backchannel_tx.disconnect();
+    };
+    WorkerHandle::spawn(spawner, async move {
+        if let Err(panic) = std::panic::AssertUnwindSafe(worker).catch_unwind().await {
+            let error = crate::util::panic_message(&*panic).to_owned();
+            log::error!(
+                "The worker of connection {} panicked: {}",
+                connection_id,
+                error
+            );
+            events.emit(ForwardingEvent::ConnectionFailed {
+                connection_id,
+                error,
+            });
+            let _ = close_tx.send((connection_id, None)).await;
+        }
+        close_tx.disconnect();
+    })
+}
+
 struct ForwardingServe {
     targets: HashMap<String, (Option<url::Host>, u16)>,
+    /* Server name and connector for the targets from `ServeOptions::tls_targets` */
+    tls_connectors: HashMap<(Option<url::Host>, u16), (String, async_tls::TlsConnector)>,
     /* self => remote */
-    connections: HashMap<
-        u64,
-        (
-            async_std::task::JoinHandle<()>,
-            futures::io::WriteHalf<TcpStream>,
-        ),
-    >,
+    connections: HashMap<u64, (WorkerHandle, futures::io::WriteHalf<Box<dyn Socket>>)>,
+    /* Bytes (sent, received) for each connection, for resuming */
+    transferred: HashMap<u64, (u64, u64)>,
     /* Track old connection IDs that won't be reused again. This is to distinguish race hazards where
      * one side closes a connection while the other one accesses it simultaneously. Despite the name, the
      * set also includes connections that are currently live.
@@ -277,6 +1503,11 @@ struct ForwardingServe {
     /* remote => self. (connection_id, Some=payload or None=close) */
     backchannel_tx: futures::channel::mpsc::Sender<(u64, Option<Vec<u8>>)>,
     backchannel_rx: futures::channel::mpsc::Receiver<(u64, Option<Vec<u8>>)>,
+    flow: FlowControl,
+    /* Payload bytes in both directions, for `ServeOptions::max_bytes` */
+    forwarded_bytes: u64,
+    options: ServeOptions,
+    events: Events,
 }
 
 //futures::pin_mut!(backchannel_rx);
@@ -295,6 +1526,14 @@ impl ForwardingServe {
                     log::warn!("Forwarding to #{} failed: {}", connection_id, e);
                     self.remove_connection(transit_tx, connection_id, true)
                         .await?;
+                } else if let Some(transferred) = self.transferred.get_mut(&connection_id) {
+                    transferred.1 += payload.len() as u64;
+                    self.events.transferred(connection_id, *transferred);
+                    if let Some(ack) = self.flow.ack(connection_id, transferred.1) {
+                        transit_tx
+                            .send(ack.ser_msgpack().into_boxed_slice())
+                            .await?;
+                    }
                 }
             },
             None if !self.historic_connections.contains(&connection_id) => {
@@ -324,6 +1563,10 @@ impl ForwardingServe {
                 )
                 .await?;
         }
+        if let Some(transferred) = self.transferred.remove(&connection_id) {
+            self.events.closed(connection_id, transferred);
+        }
+        self.flow.remove(connection_id);
         match self.connections.remove(&connection_id) {
             Some((worker, _connection)) => {
                 worker.cancel().await;
@@ -339,9 +1582,17 @@ impl ForwardingServe {
         Ok(())
     }
 
+    /**
+     * Open a connection to `target` for the peer.
+     *
+     * Name resolution and connection attempts (including their retries) give up as soon as
+     * `cancel` resolves, so that a terminating session does not have to wait for OS timeouts.
+     * Check `cancel` for termination afterwards.
+     */
     async fn spawn_connection(
         &mut self,
         transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+        cancel: &mut (impl futures::future::FusedFuture<Output = ()> + Unpin),
         mut target: String,
         connection_id: u64,
     ) -> Result<(), ForwardingError> {
@@ -358,11 +1609,38 @@ impl ForwardingServe {
             },
         };
 
-        let (host, port) = self.targets.get(&target).unwrap();
-        if host.is_none() {
-            target = format!("[::1]:{}", port);
+        let key = self.targets.get(&target).unwrap();
+        let address = target.clone();
+        if key.0.is_none() {
+            target = format!("[::1]:{}", key.1);
+        }
+        let tls = self.tls_connectors.get(key);
+        let command = self.options.command_targets.get(key);
+        if let Some(command) = command {
+            target = format!("{:?}", command.program);
+        }
+        let stream = async {
+            if let Some(command) = command {
+                return Ok::<Box<dyn Socket>, std::io::Error>(Box::new(command.spawn()?));
+            }
+            let stream = connect_with_retry(&target, self.options.target_retry_window).await?;
+            Ok::<Box<dyn Socket>, std::io::Error>(match tls {
+                Some((server_name, connector)) => {
+                    Box::new(connector.connect(server_name, stream).await?)
+                },
+                None => Box::new(stream),
+            })
         }
-        let stream = match TcpStream::connect(&target).await {
+        .fuse();
+        futures::pin_mut!(stream);
+        let stream = futures::select! {
+            stream = stream => stream,
+            () = &mut *cancel => {
+                log::debug!("Cancelled connecting to {}", target);
+                return Ok(());
+            },
+        };
+        let stream = match stream {
             Ok(stream) => stream,
             Err(err) => {
                 log::warn!(
@@ -380,126 +1658,213 @@ impl ForwardingServe {
                 return Ok(());
             },
         };
-        let (mut connection_rd, connection_wr) = stream.split();
-        let mut backchannel_tx = self.backchannel_tx.clone();
-        let worker = async_std::task::spawn_local(async move {
-            let mut buffer = vec![0; 4096];
-            /* Ignore errors */
-            macro_rules! break_on_err {
-                ($expr:expr) => {
-                    match $expr {
-                        Ok(val) => val,
-                        Err(_) => break,
-                    }
-                };
-            }
-            #[allow(clippy::while_let_loop)]
-            loop {
-                let read = break_on_err!(connection_rd.read(&mut buffer).await);
-                if read == 0 {
-                    break;
-                }
-                let buffer = &buffer[..read];
-                break_on_err!(
-                    backchannel_tx
-                        .send((connection_id, Some(buffer.to_vec())))
-                        .await
-                );
-            }
-            /* Close connection (maybe or not because of error) */
-            let _ = backchannel_tx.send((connection_id, None)).await;
-            backchannel_tx.disconnect();
-        });
+        let (connection_rd, connection_wr) = stream.split();
+        let worker = spawn_worker(
+            &self.options.spawner,
+            connection_id,
+            connection_rd,
+            self.backchannel_tx.clone(),
+            self.flow.add(connection_id),
+            self.events.clone(),
+        );
         entry.insert((worker, connection_wr));
+        self.transferred.insert(connection_id, (0, 0));
+        self.events.emit(ForwardingEvent::ConnectionOpened {
+            connection_id,
+            target: address,
+        });
         Ok(())
     }
 
-    async fn shutdown(self) {
-        log::debug!("Shutting down everything");
-        for (worker, _connection) in self.connections.into_values() {
-            worker.cancel().await;
-        }
+    /** Gracefully end the session on our side */
+    async fn close(
+        &mut self,
+        transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+    ) -> Result<(), ForwardingError> {
+        log::debug!("Closing connection");
+        transit_tx
+            .send(PeerMessage::Close.ser_msgpack().into_boxed_slice())
+            .await?;
+        transit_tx.close().await?;
+        self.shutdown().await;
+        Ok(())
     }
+}
 
+impl Session for ForwardingServe {
     async fn run(
-        mut self,
+        &mut self,
         transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
         transit_rx: &mut (impl futures::stream::FusedStream<Item = Result<Box<[u8]>, TransitError>>
                   + Unpin),
         cancel: &mut (impl futures::future::FusedFuture<Output = ()> + Unpin),
     ) -> Result<(), ForwardingError> {
+        let options = self.options.clone();
+        let mut rate_limiter = RateLimiter::new(options.max_messages_per_second);
+        let mut alternate = Alternate::default();
         /* Event processing loop */
         log::debug!("Entered processing loop");
         let ret = loop {
+            if let Some(max_bytes) = options.max_bytes {
+                if self.forwarded_bytes > max_bytes {
+                    self.shutdown().await;
+                    bail!(ForwardingError::LimitExceeded(
+                        format!("more than {} bytes forwarded", max_bytes).into()
+                    ));
+                }
+            }
             futures::select! {
-                message = transit_rx.next() => {
-                    match PeerMessage::de_msgpack(&message.unwrap()?)? {
-                        PeerMessage::Forward { connection_id, payload } => {
-                            self.forward(transit_tx, connection_id, &payload).await?
-                        },
-                        PeerMessage::Connect { target, connection_id } => {
-                            /* No matter what happens, as soon as we receive the "connect" command that ID is burned. */
-                            self.historic_connections.insert(connection_id);
-                            ensure!(
-                                self.targets.contains_key(&target),
-                                ForwardingError::protocol(format!("We don't know forwarding target '{}'", target)),
-                            );
-
-                            self.spawn_connection(transit_tx, target, connection_id).await?;
-                        },
-                        PeerMessage::Disconnect { connection_id } => {
-                            self.remove_connection(transit_tx, connection_id, false).await?;
-                        },
-                        PeerMessage::Close => {
-                            log::info!("Peer gracefully closed connection");
-                            self.shutdown().await;
-                            break Ok(());
-                        },
-                        PeerMessage::Error(err) => {
-                            self.shutdown().await;
-                            bail!(ForwardingError::PeerError(err));
-                        },
-                        other => {
+                message = alternate.next(transit_rx, &mut self.backchannel_rx) => match message {
+                    futures::future::Either::Left(message) => {
+                        if !rate_limiter.check() {
                             self.shutdown().await;
-                            bail!(ForwardingError::unexpected_message("connect' or 'disconnect' or 'forward' or 'close", other));
-                        },
-                    }
-                },
-                message = self.backchannel_rx.next() => {
-                    /* This channel will never run dry, since we always have at least one sender active */
-                    match message.unwrap() {
-                        (connection_id, Some(payload)) => {
-                            transit_tx.send(
-                                PeerMessage::Forward {
-                                    connection_id,
-                                    payload
+                            bail!(ForwardingError::LimitExceeded(format!(
+                                "more than {} messages per second",
+                                options.max_messages_per_second
+                            ).into()));
+                        }
+                        match PeerMessage::de_msgpack(&message.unwrap_or_else(|| Err(TransitError::IO(std::io::ErrorKind::UnexpectedEof.into())))?)? {
+                            PeerMessage::Forward { connection_id, payload } => {
+                                if payload.len() > options.max_payload_size {
+                                    self.shutdown().await;
+                                    bail!(ForwardingError::LimitExceeded(format!(
+                                        "payload of {} bytes for connection '{}' is larger than {} bytes",
+                                        payload.len(),
+                                        connection_id,
+                                        options.max_payload_size
+                                    ).into()));
                                 }
-                                .ser_msgpack()
-                                .into_boxed_slice()
-                            ).await?;
-                        },
-                        (connection_id, None) => {
-                            self.remove_connection(transit_tx, connection_id, true).await?;
-                        },
-                    }
+                                self.forwarded_bytes += payload.len() as u64;
+                                self.forward(transit_tx, connection_id, &payload).await.map_err(|error| {
+                                    error.context(format!("Forwarding data to connection #{}", connection_id))
+                                })?
+                            },
+                            PeerMessage::Connect { target, connection_id } => {
+                                /* No matter what happens, as soon as we receive the "connect" command that ID is burned. */
+                                self.historic_connections.insert(connection_id);
+                                ensure!(
+                                    self.targets.contains_key(&target),
+                                    ForwardingError::protocol(format!("We don't know forwarding target '{}'", target)),
+                                );
+
+                                self.spawn_connection(transit_tx, cancel, target.clone(), connection_id).await.map_err(|error| {
+                                    error.context(format!("Opening connection #{} to '{}'", connection_id, target))
+                                })?;
+                                if cancel.is_terminated() {
+                                    break self.close(transit_tx).await;
+                                }
+                            },
+                            PeerMessage::Disconnect { connection_id } => {
+                                self.remove_connection(transit_tx, connection_id, false).await.map_err(|error| {
+                                    error.context(format!("Closing connection #{}", connection_id))
+                                })?;
+                            },
+                            PeerMessage::Ack { connection_id, bytes } => {
+                                self.flow.acknowledged(connection_id, bytes);
+                            },
+                            PeerMessage::KeepAlive => {},
+                            PeerMessage::Close => {
+                                log::debug!("Peer gracefully closed connection");
+                                self.shutdown().await;
+                                break Ok(());
+                            },
+                            PeerMessage::Error(err) => {
+                                self.shutdown().await;
+                                bail!(ForwardingError::PeerError(err));
+                            },
+                            other => {
+                                self.shutdown().await;
+                                bail!(ForwardingError::unexpected_message("connect' or 'disconnect' or 'forward' or 'close", other));
+                            },
+                        }
+                    },
+                    futures::future::Either::Right(message) => {
+                        /* This channel will never run dry, since we always have at least one sender active */
+                        match message.unwrap() {
+                            (connection_id, Some(payload)) => {
+                                self.forwarded_bytes += payload.len() as u64;
+                                if let Some(transferred) = self.transferred.get_mut(&connection_id) {
+                                    transferred.0 += payload.len() as u64;
+                                    self.events.transferred(connection_id, *transferred);
+                                }
+                                transit_tx.send(
+                                    PeerMessage::Forward {
+                                        connection_id,
+                                        payload
+                                    }
+                                    .ser_msgpack()
+                                    .into_boxed_slice()
+                                ).await.map_err(|error| {
+                                    ForwardingError::from(error).context(format!("Sending data of connection #{} to the peer", connection_id))
+                                })?;
+                            },
+                            (connection_id, None) => {
+                                self.remove_connection(transit_tx, connection_id, true).await.map_err(|error| {
+                                    error.context(format!("Closing connection #{}", connection_id))
+                                })?;
+                            },
+                        }
+                    },
                 },
                 /* We are done */
-                () = &mut *cancel => {
-                    log::info!("Closing connection");
-                    transit_tx.send(
-                        PeerMessage::Close.ser_msgpack()
-                        .into_boxed_slice()
-                    )
-                    .await?;
-                    transit_tx.close().await?;
-                    self.shutdown().await;
-                    break Ok(());
-                },
+                () = &mut *cancel => break self.close(transit_tx).await,
             }
         };
         log::debug!("Exited processing loop");
         ret
     }
+
+    async fn shutdown(&mut self) {
+        log::debug!("Shutting down everything");
+        for (worker, _connection) in self.connections.drain().map(|(_, value)| value) {
+            worker.cancel().await;
+        }
+        for (connection_id, transferred) in self.transferred.drain() {
+            self.events.closed(connection_id, transferred);
+        }
+        self.flow.clear();
+    }
+
+    fn received(&self) -> HashMap<u64, u64> {
+        self.transferred
+            .iter()
+            .map(|(connection_id, (_sent, received))| (*connection_id, *received))
+            .collect()
+    }
+
+    async fn resume(
+        &mut self,
+        transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+        peer_received: HashMap<u64, u64>,
+    ) -> Result<(), ForwardingError> {
+        let connection_ids = self.connections.keys().copied().collect::<Vec<_>>();
+        for connection_id in connection_ids {
+            let sent = self
+                .transferred
+                .get(&connection_id)
+                .map_or(0, |(sent, _)| *sent);
+            match peer_received.get(&connection_id) {
+                /* Acknowledgements may have been lost on the way */
+                Some(received) if *received == sent => {
+                    self.flow.acknowledged(connection_id, *received)
+                },
+                /* The peer closed it meanwhile, or never heard of it */
+                None => {
+                    self.remove_connection(transit_tx, connection_id, false)
+                        .await?
+                },
+                Some(_) => {
+                    log::warn!(
+                        "Connection #{} lost data while the session was interrupted",
+                        connection_id
+                    );
+                    self.remove_connection(transit_tx, connection_id, true)
+                        .await?
+                },
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Request a port forwarding offer from the other side
@@ -517,21 +1882,195 @@ impl ForwardingServe {
 /// This method already binds to all the necessary ports up-front. To limit abuse potential
 /// no more than 1024 ports may be forwarded at once.
 pub async fn connect(
-    mut wormhole: Wormhole,
+    wormhole: Wormhole,
     transit_handler: impl FnOnce(transit::TransitInfo),
     relay_hints: Vec<transit::RelayHint>,
     bind_address: Option<std::net::IpAddr>,
     custom_ports: &[u16],
 ) -> Result<ConnectOffer, ForwardingError> {
+    connect_with_selection(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        bind_address,
+        PortFallback::Error,
+        |targets| {
+            futures::future::ready(
+                targets
+                    .into_iter()
+                    .map(|target| target.address)
+                    .zip(custom_ports.iter().copied().chain(std::iter::repeat(0)))
+                    .collect(),
+            )
+        },
+    )
+    .await
+}
+
+/// Request a port forwarding offer from the other side, and forward it on listeners that are already bound
+///
+/// Like [`connect`], but instead of binding ports, the targets get mapped to the given `listeners`, in
+/// order. Targets beyond the number of listeners are not forwarded. See [`ForwardingRequest::accept_listeners`].
+pub async fn connect_with_listeners(
+    wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    listeners: Vec<std::net::TcpListener>,
+) -> Result<ConnectOffer, ForwardingError> {
+    let request = request(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        None,
+        PortFallback::Error,
+    )
+    .await?;
+    if listeners.len() > request.targets().len() {
+        log::warn!(
+            "Got {} listeners, but only {} targets are offered",
+            listeners.len(),
+            request.targets().len()
+        );
+    }
+    let listeners = request
+        .targets()
+        .iter()
+        .map(|target| target.address.clone())
+        .zip(listeners)
+        .collect();
+    request.accept_listeners(listeners).await
+}
+
+/// What to do if a requested local port can't be bound, see [`connect_with_selection`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PortFallback {
+    /// Fail the whole connection
+    #[default]
+    Error,
+    /// Take the next higher port that is free
+    NextFree,
+    /// Let the operating system choose a free port
+    Random,
+}
+
+/** How many ports to try with [`PortFallback::NextFree`] before giving up */
+const MAX_NEXT_FREE_ATTEMPTS: u16 = 100;
+
+/** Bind to `port`, or to another one if it is in use or privileged */
+async fn bind_with_fallback(
+    bind_address: std::net::IpAddr,
+    port: u16,
+    fallback: PortFallback,
+) -> std::io::Result<TcpListener> {
+    let error = match TcpListener::bind((bind_address, port)).await {
+        Ok(listener) => return Ok(listener),
+        Err(error) if port == 0 => return Err(error),
+        Err(error) => error,
+    };
+    if !matches!(
+        error.kind(),
+        std::io::ErrorKind::AddrInUse | std::io::ErrorKind::PermissionDenied
+    ) {
+        return Err(error);
+    }
+    let listener = match fallback {
+        PortFallback::Error => return Err(error),
+        PortFallback::NextFree => {
+            let mut candidates =
+                (port.saturating_add(1)..=u16::MAX).take(MAX_NEXT_FREE_ATTEMPTS as usize);
+            loop {
+                let Some(candidate) = candidates.next() else {
+                    return Err(error);
+                };
+                if let Ok(listener) = TcpListener::bind((bind_address, candidate)).await {
+                    break listener;
+                }
+            }
+        },
+        PortFallback::Random => TcpListener::bind((bind_address, 0)).await?,
+    };
+    log::warn!(
+        "Cannot bind to port {}: {}. Using port {} instead.",
+        port,
+        error,
+        listener.local_addr()?.port()
+    );
+    Ok(listener)
+}
+
+/// A target offered by the other side, see [`connect_with_selection`]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OfferedTarget {
+    /// **Security warning:** this is untrusted and unverified input
+    pub address: String,
+    pub health: TargetHealth,
+    /// **Security warning:** this is untrusted and unverified input
+    pub service: Option<ServiceInfo>,
+}
+
+/// Request a port forwarding offer from the other side, and choose which targets to forward
+///
+/// Like [`connect`], but instead of binding all offered targets, `select` gets called
+/// with the offer and returns the addresses that should be made available, each with the
+/// local port to bind to (`0` for an arbitrary one). Addresses that weren't offered are ignored.
+///
+/// If a port is in use or privileged, `port_fallback` decides whether to fail or to take another
+/// port. The [`mapping`](ConnectOffer::mapping) of the result contains the ports actually used.
+pub async fn connect_with_selection<F>(
+    wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    bind_address: Option<std::net::IpAddr>,
+    port_fallback: PortFallback,
+    select: impl FnOnce(Vec<OfferedTarget>) -> F,
+) -> Result<ConnectOffer, ForwardingError>
+where
+    F: Future<Output = Vec<(String, u16)>>,
+{
+    let request = request(
+        wormhole,
+        transit_handler,
+        relay_hints,
+        bind_address,
+        port_fallback,
+    )
+    .await?;
+    let selected = select(request.targets().to_vec()).await;
+    request.accept(selected).await
+}
+
+/// Request a port forwarding offer from the other side, without binding any ports yet
+///
+/// The returned [`ForwardingRequest`] shows what the peer offers. Use it to ask the user which
+/// targets to forward to which local ports, then [`accept`](ForwardingRequest::accept) or
+/// [`reject`](ForwardingRequest::reject) it. `bind_address` and `port_fallback` work like
+/// for [`connect_with_selection`].
+pub async fn request(
+    mut wormhole: Wormhole,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    relay_hints: Vec<transit::RelayHint>,
+    bind_address: Option<std::net::IpAddr>,
+    port_fallback: PortFallback,
+) -> Result<ForwardingRequest, ForwardingError> {
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
     let our_version: &AppVersion = wormhole
         .our_version
         .downcast_ref()
         .expect("You may only use a Wormhole instance with the correct AppVersion type!");
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
+    let abilities = (
+        our_version.transit_abilities,
+        peer_version.transit_abilities,
+    );
+    let resume = our_version.resume && peer_version.resume;
+    let flow = FlowControl::new(our_version, &peer_version);
+    let keep_alive = our_version.keep_alive && peer_version.keep_alive;
     let connector = transit::init(
         our_version.transit_abilities,
         Some(peer_version.transit_abilities),
-        relay_hints,
+        relay_hints.clone(),
     )
     .await?;
     let bind_address = bind_address.unwrap_or_else(|| std::net::IpAddr::V6("::".parse().unwrap()));
@@ -560,6 +2099,16 @@ pub async fn connect(
             bail!(error)
         },
     };
+    /* The window is only known once the offer is in */
+    let resumption = Resumption::new(
+        &wormhole,
+        std::time::Duration::ZERO,
+        false,
+        resume,
+        abilities,
+        relay_hints,
+        &their_hints,
+    );
 
     let (mut transit, info) = match connector
         .follower_connect(
@@ -571,73 +2120,259 @@ pub async fn connect(
     {
         Ok(transit) => transit,
         Err(error) => {
-            let error = ForwardingError::TransitConnect(error);
+            let error = ForwardingError::from(error);
             let _ = wormhole
                 .send_json(&PeerMessage::Error(format!("{}", error)))
                 .await;
             return Err(error);
         },
     };
+    if wormhole.is_strict() {
+        transit.set_validator(Some(|record| PEER_MESSAGE_SCHEMA.validate_msgpack(record)));
+    }
     transit_handler(info);
 
     /* We got a transit, now close the Wormhole */
-    wormhole.close().await?;
+    let wormhole = wormhole.close_for_transit().await?;
 
     let run = async {
-        /* Receive offer and ask user */
+        /* Receive the offer, the user gets asked via the `ForwardingRequest` */
 
-        let addresses = match PeerMessage::de_msgpack(&transit.receive_record().await?)? {
-            PeerMessage::Offer { addresses } => addresses,
-            PeerMessage::Error(err) => {
-                bail!(ForwardingError::PeerError(err));
-            },
-            other => {
-                bail!(ForwardingError::unexpected_message("offer", other))
-            },
-        };
+        let (addresses, health, services, resume_window, metadata) =
+            match PeerMessage::de_msgpack(&transit.receive_record().await?)? {
+                PeerMessage::Offer {
+                    addresses,
+                    health,
+                    services,
+                    resume_window,
+                    metadata,
+                } => (addresses, health, services, resume_window, metadata),
+                PeerMessage::Error(err) => {
+                    bail!(ForwardingError::PeerError(err));
+                },
+                other => {
+                    bail!(ForwardingError::unexpected_message("offer", other))
+                },
+            };
 
         /* Sanity check on untrusted input */
         if addresses.len() > 1024 {
             return Err(ForwardingError::protocol("Too many forwarded ports"));
         }
+        let too_long = |text: &Option<String>| text.as_ref().map_or(0, String::len) > 256;
+        if services
+            .values()
+            .any(|service| too_long(&service.label) || too_long(&service.protocol))
+        {
+            return Err(ForwardingError::protocol("Service description too long"));
+        }
+
+        let targets = addresses
+            .iter()
+            .map(|address| OfferedTarget {
+                address: address.clone(),
+                health: health
+                    .get(address)
+                    .copied()
+                    .unwrap_or(TargetHealth::Unknown),
+                service: services.get(address).cloned(),
+            })
+            .collect();
+        Ok((targets, health, services, resume_window, metadata))
+    };
+
+    match run.await {
+        Ok((targets, health, services, resume_window, metadata)) => Ok(ForwardingRequest {
+            targets,
+            transit,
+            wormhole,
+            bind_address,
+            port_fallback,
+            health,
+            services,
+            metadata,
+            resumption: resume_window.and_then(|window| {
+                let mut resumption = resumption?;
+                resumption.window = std::time::Duration::from_secs(window).min(MAX_RESUME_WINDOW);
+                Some(resumption)
+            }),
+            flow,
+            keep_alive,
+        }),
+        Err(error) if matches!(error.root(), ForwardingError::PeerError(_)) => Err(error),
+        Err(error) => {
+            let _ = transit
+                .send_record(&PeerMessage::Error(format!("{}", error.root())).ser_msgpack())
+                .await;
+            Err(error)
+        },
+    }
+}
+
+/// What the other side offers to forward, before any local ports are bound
+///
+/// Returned by [`request`]. You *should* consume this object, either by calling
+/// [`accept`](ForwardingRequest::accept) or [`reject`](ForwardingRequest::reject).
+#[must_use]
+pub struct ForwardingRequest {
+    targets: Vec<OfferedTarget>,
+    transit: transit::Transit,
+    /* Only if it is kept open during transit, see [`Wormhole::set_keep_open_during_transit`] */
+    wormhole: Option<Wormhole>,
+    bind_address: std::net::IpAddr,
+    port_fallback: PortFallback,
+    health: HashMap<String, TargetHealth>,
+    services: HashMap<String, ServiceInfo>,
+    metadata: Option<crate::OfferMetadata>,
+    resumption: Option<Resumption>,
+    flow: FlowControl,
+    /* Whether the peer takes [`PeerMessage::KeepAlive`], see [`AppVersion::keep_alive`] */
+    keep_alive: bool,
+}
+
+impl ForwardingRequest {
+    /// The targets the peer offers, in the order it sent them
+    pub fn targets(&self) -> &[OfferedTarget] {
+        &self.targets
+    }
+
+    /// The application specific data the peer attached to the offer, see [`ServeOptions::metadata`]
+    ///
+    /// **Security warning:** this is untrusted input.
+    pub fn metadata(&self) -> Option<&crate::OfferMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Bind the local ports and get the [`ConnectOffer`] to start forwarding with
+    ///
+    /// `port_mapping` contains the addresses of the targets to forward, each with the local port
+    /// to bind to (`0` for an arbitrary one). Targets that weren't offered are ignored. If binding
+    /// fails, the peer gets told so.
+    pub async fn accept(
+        self,
+        port_mapping: Vec<(String, u16)>,
+    ) -> Result<ConnectOffer, ForwardingError> {
+        let port_mapping = self.offered_only(port_mapping);
 
         /* self => remote
          *                  (address, connection)
          * Vec<Stream<Item = (String, TcpStream)>>
          */
-        let listeners: Vec<(
-            async_std::net::TcpListener,
-            u16,
-            std::rc::Rc<std::string::String>,
-        )> = futures::stream::iter(
-            addresses
+        let (bind_address, port_fallback) = (self.bind_address, self.port_fallback);
+        let listeners = futures::stream::iter(
+            port_mapping
                 .into_iter()
-                .map(Rc::new)
-                .zip(custom_ports.iter().copied().chain(std::iter::repeat(0))),
+                .map(|(address, port)| (Rc::new(address), port)),
         )
         .then(|(address, port)| async move {
-            let connection = TcpListener::bind((bind_address, port)).await?;
-            let port = connection.local_addr()?.port();
-            Result::<_, std::io::Error>::Ok((connection, port, address))
+            let connection = bind_with_fallback(bind_address, port, port_fallback).await?;
+            let local_address = connection.local_addr()?;
+            Result::<_, std::io::Error>::Ok((connection, local_address, address))
         })
         .try_collect()
-        .await?;
-        Ok(listeners)
-    };
+        .await;
+        self.into_offer(listeners).await
+    }
 
-    match run.await {
-        Ok(listeners) => Ok(ConnectOffer {
-            transit,
-            mapping: listeners.iter().map(|(_, b, c)| (*b, c.clone())).collect(),
+    /// Like [`accept`](Self::accept), but with listening sockets that are already bound
+    ///
+    /// This allows running under a service manager that binds the (possibly privileged) ports, like
+    /// systemd with socket activation, or to use sockets inherited from a parent process. Each listener
+    /// is mapped to the address of an offered target. Targets that weren't offered are ignored.
+    pub async fn accept_listeners(
+        self,
+        listeners: Vec<(String, std::net::TcpListener)>,
+    ) -> Result<ConnectOffer, ForwardingError> {
+        let listeners = self
+            .offered_only(listeners)
+            .into_iter()
+            .map(|(address, listener)| {
+                let listener = TcpListener::from(listener);
+                let local_address = listener.local_addr()?;
+                Ok((listener, local_address, Rc::new(address)))
+            })
+            .collect();
+        self.into_offer(listeners).await
+    }
+
+    /** Drop the selected targets that weren't offered */
+    fn offered_only<T>(&self, mut selected: Vec<(String, T)>) -> Vec<(String, T)> {
+        selected.retain(|(address, _)| {
+            let offered = self.targets.iter().any(|target| &target.address == address);
+            if !offered {
+                log::warn!("Ignoring selected target '{}', it wasn't offered", address);
+            }
+            offered
+        });
+        selected
+    }
+
+    /** Tell the peer if the listeners could not be set up */
+    #[allow(clippy::type_complexity)]
+    async fn into_offer(
+        mut self,
+        listeners: Result<Vec<(TcpListener, std::net::SocketAddr, Rc<String>)>, std::io::Error>,
+    ) -> Result<ConnectOffer, ForwardingError> {
+        let listeners = match listeners {
+            Ok(listeners) => listeners,
+            Err(error) => {
+                let error = ForwardingError::from(error);
+                let _ = self
+                    .transit
+                    .send_record(&PeerMessage::Error(format!("{}", error)).ser_msgpack())
+                    .await;
+                return Err(error);
+            },
+        };
+
+        Ok(ConnectOffer {
+            mapping: listeners
+                .iter()
+                .map(|(_, b, c)| (b.port(), c.clone()))
+                .collect(),
+            transit: self.transit,
+            wormhole: self.wormhole,
             listeners,
-        }),
-        Err(error @ ForwardingError::PeerError(_)) => Err(error),
-        Err(error) => {
-            let _ = transit
-                .send_record(&PeerMessage::Error(format!("{}", error)).ser_msgpack())
-                .await;
-            Err(error)
-        },
+            health: self.health,
+            services: self.services,
+            metadata: self.metadata,
+            tls: HashMap::new(),
+            resumption: self.resumption,
+            flow: self.flow,
+            keep_alive: self.keep_alive,
+            idle_timeout: None,
+            spawner: Spawner::default(),
+        })
+    }
+
+    /// Keep the connection alive while waiting for `future`, see [`ConnectOffer::keep_alive_while`]
+    pub async fn keep_alive_while<T>(
+        &mut self,
+        interval: std::time::Duration,
+        future: impl Future<Output = T>,
+    ) -> Result<T, ForwardingError> {
+        keep_alive_while(
+            &mut self.transit,
+            self.wormhole.as_mut(),
+            self.keep_alive,
+            interval,
+            future,
+        )
+        .await
+    }
+
+    /// Reject the offer
+    ///
+    /// This will send an error message to the other side so that it knows the transfer failed.
+    pub async fn reject(mut self) -> Result<(), ForwardingError> {
+        self.transit
+            .send_record(&PeerMessage::Error("transfer rejected".into()).ser_msgpack())
+            .await?;
+        if let Some(wormhole) = self.wormhole {
+            wormhole.close().await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -648,14 +2383,100 @@ pub async fn connect(
 pub struct ConnectOffer {
     pub mapping: Vec<(u16, Rc<String>)>,
     transit: transit::Transit,
+    wormhole: Option<Wormhole>,
     listeners: Vec<(
         async_std::net::TcpListener,
-        u16,
+        std::net::SocketAddr,
         std::rc::Rc<std::string::String>,
     )>,
+    health: HashMap<String, TargetHealth>,
+    services: HashMap<String, ServiceInfo>,
+    metadata: Option<crate::OfferMetadata>,
+    tls: HashMap<String, async_tls::TlsAcceptor>,
+    resumption: Option<Resumption>,
+    flow: FlowControl,
+    keep_alive: bool,
+    idle_timeout: Option<std::time::Duration>,
+    spawner: Spawner,
 }
 
 impl ConnectOffer {
+    /// The local addresses that are bound for forwarding, and their remote targets
+    ///
+    /// Unlike [`mapping`](Self::mapping), this contains the full socket address, for example
+    /// to tell other components where to connect.
+    pub fn local_addresses(&self) -> impl Iterator<Item = (std::net::SocketAddr, &str)> + '_ {
+        self.listeners
+            .iter()
+            .map(|(_, local_address, target)| (*local_address, target.as_str()))
+    }
+
+    /// Whether the peer could reach the target when making the offer
+    ///
+    /// This is only known if the peer probed its targets, see [`ServeOptions::probe_targets`].
+    pub fn health(&self, target: &str) -> TargetHealth {
+        self.health
+            .get(target)
+            .copied()
+            .unwrap_or(TargetHealth::Unknown)
+    }
+
+    /// The name and protocol of the target, if the peer provided them
+    ///
+    /// **Security warning:** this is untrusted and unverified input
+    pub fn service(&self, target: &str) -> Option<&ServiceInfo> {
+        self.services.get(target)
+    }
+
+    /// The application specific data the peer attached to the offer, see [`ServeOptions::metadata`]
+    ///
+    /// **Security warning:** this is untrusted input.
+    pub fn metadata(&self) -> Option<&crate::OfferMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Present a TLS listener for `target` instead of a plain one
+    ///
+    /// This lets local clients like browsers talk HTTPS to the mapped port. `certificate_chain` holds the
+    /// DER encoded certificates, starting with the one for this listener, and `private_key` its DER encoded
+    /// PKCS#8, PKCS#1 or SEC1 key. The traffic is decrypted before it goes to the peer, which sees no
+    /// difference. Local clients that fail the handshake get disconnected without bothering the peer.
+    pub fn terminate_tls(
+        &mut self,
+        target: &str,
+        certificate_chain: Vec<Vec<u8>>,
+        private_key: Vec<u8>,
+    ) -> Result<(), ForwardingError> {
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                certificate_chain
+                    .into_iter()
+                    .map(rustls::Certificate)
+                    .collect(),
+                rustls::PrivateKey(private_key),
+            )?;
+        if !self.mapping.iter().any(|(_, address)| **address == target) {
+            log::warn!("Ignoring TLS for target '{}', it isn't forwarded", target);
+        }
+        self.tls.insert(target.to_owned(), config.into());
+        Ok(())
+    }
+
+    /// Give up on the peer if it stays silent for longer than [`Timeouts::idle_peer`](transit::Timeouts::idle_peer)
+    ///
+    /// The transit connection already exists at this point, and there is no answer to wait for, so
+    /// the other time budgets have no effect here.
+    pub fn set_timeouts(&mut self, timeouts: transit::Timeouts) {
+        self.idle_timeout = timeouts.idle_peer;
+    }
+
+    /// Run the connection workers on another executor than async-std, see [`Spawner`]
+    pub fn set_spawner(&mut self, spawner: Spawner) {
+        self.spawner = spawner;
+    }
+
     /// Accept the offer and start the forwarding
     ///
     /// The method will run until an error occurs, the peer terminates the connection
@@ -663,51 +2484,88 @@ impl ConnectOffer {
     /// handling. If you want the forward to never (successfully) stop, pass [`futures::future::pending()`]
     /// as the value.
     pub async fn accept(self, cancel: impl Future<Output = ()>) -> Result<(), ForwardingError> {
-        let (transit_tx, transit_rx) = self.transit.split();
-        let transit_rx = transit_rx.fuse();
-        use futures::FutureExt;
-        let cancel = cancel.fuse();
-        futures::pin_mut!(transit_tx);
-        futures::pin_mut!(transit_rx);
-        futures::pin_mut!(cancel);
+        self.accept_inner(Events::default(), cancel).await
+    }
 
-        /* Error handling catcher (see below) */
-        let run = async {
-            let (backchannel_tx, backchannel_rx) =
-                futures::channel::mpsc::channel::<(u64, Option<Vec<u8>>)>(20);
+    /// Like [`accept`](Self::accept), but also get a stream of [`ForwardingEvent`]s to watch the connections
+    ///
+    /// The stream ends once the returned future is done. Drop it if you are not interested anymore.
+    pub fn accept_with_events(
+        self,
+        cancel: impl Future<Output = ()>,
+    ) -> (
+        impl Future<Output = Result<(), ForwardingError>>,
+        impl futures::Stream<Item = ForwardingEvent>,
+    ) {
+        let (events, receiver) = Events::channel();
+        (self.accept_inner(events, cancel), receiver)
+    }
+
+    async fn accept_inner(
+        self,
+        events: Events,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(), ForwardingError> {
+        let (backchannel_tx, backchannel_rx) =
+            futures::channel::mpsc::channel::<(u64, Option<Vec<u8>>)>(20);
 
-            ForwardConnect {
+        let tls = self.tls;
+        /* Errors other than a PeerError (i.e. coming from the other side) get sent to the peer */
+        run_session(
+            &mut ForwardConnect {
                 incoming: futures::stream::select_all(self.listeners.into_iter().map(
                     |(connection, _, address)| {
+                        let acceptor = tls.get(&*address).cloned();
                         connection
                             .into_incoming()
-                            .map_ok(move |stream| (address.clone(), stream))
+                            .and_then(move |stream| {
+                                accept_local(address.clone(), acceptor.clone(), stream)
+                            })
+                            .try_filter_map(futures::future::ok)
                             .boxed_local()
                     },
                 )),
                 connection_counter: 0,
                 connections: HashMap::new(),
+                transferred: HashMap::new(),
                 backchannel_tx,
                 backchannel_rx,
-            }
-            .run(&mut transit_tx, &mut transit_rx, &mut cancel)
-            .await
-        };
-
-        match run.await {
-            Ok(()) => Ok(()),
-            Err(error @ ForwardingError::PeerError(_)) => Err(error),
-            Err(error) => {
-                let _ = transit_tx
-                    .send(
-                        PeerMessage::Error(format!("{}", error))
-                            .ser_msgpack()
-                            .into_boxed_slice(),
-                    )
-                    .await;
-                Err(error)
+                flow: self.flow,
+                events,
+                spawner: self.spawner,
             },
+            self.transit,
+            self.resumption,
+            self.idle_timeout,
+            cancel,
+        )
+        .await?;
+        if let Some(wormhole) = self.wormhole {
+            wormhole.close().await?;
         }
+        Ok(())
+    }
+
+    /// Keep the connection alive while waiting for `future`
+    ///
+    /// Use this while asking the user whether to accept the offer, so that NATs and firewalls
+    /// don't drop the connection in the meantime. Every `interval`, a no-op message is sent to the peer.
+    /// If the peer does not support that (see [`AppVersion::keep_alive`]), the rendezvous server gets pinged
+    /// instead if the connection to it is kept open, and nothing is sent otherwise.
+    /// [`transit::DEFAULT_KEEPALIVE_INTERVAL`] is a sensible value.
+    pub async fn keep_alive_while<T>(
+        &mut self,
+        interval: std::time::Duration,
+        future: impl Future<Output = T>,
+    ) -> Result<T, ForwardingError> {
+        keep_alive_while(
+            &mut self.transit,
+            self.wormhole.as_mut(),
+            self.keep_alive,
+            interval,
+            future,
+        )
+        .await
     }
 
     /// Reject the offer
@@ -717,11 +2575,71 @@ impl ConnectOffer {
         self.transit
             .send_record(&PeerMessage::Error("transfer rejected".into()).ser_msgpack())
             .await?;
+        if let Some(wormhole) = self.wormhole {
+            wormhole.close().await?;
+        }
 
         Ok(())
     }
 }
 
+/**
+ * Send no-op messages to the peer every `interval` until `future` is done
+ *
+ * Older peers fail on them, so without `peer_keep_alive` this pings the rendezvous server instead, if there is
+ * still a connection to it.
+ */
+async fn keep_alive_while<T>(
+    transit: &mut transit::Transit,
+    wormhole: Option<&mut Wormhole>,
+    peer_keep_alive: bool,
+    interval: std::time::Duration,
+    future: impl Future<Output = T>,
+) -> Result<T, ForwardingError> {
+    match wormhole {
+        _ if peer_keep_alive => {
+            crate::util::keep_alive_while(transit, interval, future, |transit| {
+                Box::pin(async move {
+                    transit
+                        .send_record(&PeerMessage::KeepAlive.ser_msgpack())
+                        .await?;
+                    Ok(())
+                })
+            })
+            .await
+        },
+        Some(wormhole) => {
+            crate::util::keep_alive_while(wormhole, interval, future, |wormhole| {
+                Box::pin(async move { Ok(wormhole.ping().await?) })
+            })
+            .await
+        },
+        None => Ok(future.await),
+    }
+}
+
+/** Do the TLS handshake with a local client if needed. `None` if it failed. */
+async fn accept_local(
+    address: Rc<String>,
+    acceptor: Option<async_tls::TlsAcceptor>,
+    stream: TcpStream,
+) -> std::io::Result<Option<(Rc<String>, Box<dyn Socket>)>> {
+    let Some(acceptor) = acceptor else {
+        return Ok(Some((address, Box::new(stream))));
+    };
+    match util::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => Ok(Some((address, Box::new(stream)))),
+        Ok(Err(err)) => {
+            log::warn!("TLS handshake for {} failed: {}", address, err);
+            Ok(None)
+        },
+        Err(_) => {
+            log::warn!("TLS handshake for {} failed: timed out", address);
+            Ok(None)
+        },
+    }
+}
+
 #[allow(clippy::type_complexity)]
 struct ForwardConnect {
     //transit: &'a mut transit::Transit,
@@ -729,21 +2647,20 @@ struct ForwardConnect {
     incoming: futures::stream::SelectAll<
         futures::stream::LocalBoxStream<
             'static,
-            Result<(Rc<String>, async_std::net::TcpStream), std::io::Error>,
+            Result<(Rc<String>, Box<dyn Socket>), std::io::Error>,
         >,
     >,
     /* Our next unique connection_id */
     connection_counter: u64,
-    connections: HashMap<
-        u64,
-        (
-            async_std::task::JoinHandle<()>,
-            futures::io::WriteHalf<TcpStream>,
-        ),
-    >,
+    connections: HashMap<u64, (WorkerHandle, futures::io::WriteHalf<Box<dyn Socket>>)>,
+    /* Bytes (sent, received) for each connection, for resuming */
+    transferred: HashMap<u64, (u64, u64)>,
     /* application => self. (connection_id, Some=payload or None=close) */
     backchannel_tx: futures::channel::mpsc::Sender<(u64, Option<Vec<u8>>)>,
     backchannel_rx: futures::channel::mpsc::Receiver<(u64, Option<Vec<u8>>)>,
+    flow: FlowControl,
+    events: Events,
+    spawner: Spawner,
 }
 
 impl ForwardConnect {
@@ -761,6 +2678,14 @@ impl ForwardConnect {
                     log::warn!("Forwarding to #{} failed: {}", connection_id, e);
                     self.remove_connection(transit_tx, connection_id, true)
                         .await?;
+                } else if let Some(transferred) = self.transferred.get_mut(&connection_id) {
+                    transferred.1 += payload.len() as u64;
+                    self.events.transferred(connection_id, *transferred);
+                    if let Some(ack) = self.flow.ack(connection_id, transferred.1) {
+                        transit_tx
+                            .send(ack.ser_msgpack().into_boxed_slice())
+                            .await?;
+                    }
                 }
             },
             None if self.connection_counter <= connection_id => {
@@ -790,6 +2715,10 @@ impl ForwardConnect {
                 )
                 .await?;
         }
+        if let Some(transferred) = self.transferred.remove(&connection_id) {
+            self.events.closed(connection_id, transferred);
+        }
+        self.flow.remove(connection_id);
         match self.connections.remove(&connection_id) {
             Some((worker, _connection)) => {
                 worker.cancel().await;
@@ -809,12 +2738,11 @@ impl ForwardConnect {
         &mut self,
         transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
         target: Rc<String>,
-        connection: TcpStream,
+        connection: Box<dyn Socket>,
     ) -> Result<(), ForwardingError> {
         let connection_id = self.connection_counter;
         self.connection_counter += 1;
-        let (mut connection_rd, connection_wr) = connection.split();
-        let mut backchannel_tx = self.backchannel_tx.clone();
+        let (connection_rd, connection_wr) = connection.split();
         log::debug!("Creating new connection: #{} -> {}", connection_id, target);
 
         transit_tx
@@ -828,108 +2756,107 @@ impl ForwardConnect {
             )
             .await?;
 
-        let worker = async_std::task::spawn_local(async move {
-            let mut buffer = vec![0; 4096];
-            /* Ignore errors */
-            macro_rules! break_on_err {
-                ($expr:expr) => {
-                    match $expr {
-                        Ok(val) => val,
-                        Err(_) => break,
-                    }
-                };
-            }
-            #[allow(clippy::while_let_loop)]
-            loop {
-                let read = break_on_err!(connection_rd.read(&mut buffer).await);
-                if read == 0 {
-                    break;
-                }
-                let buffer = &buffer[..read];
-                break_on_err!(
-                    backchannel_tx
-                        .send((connection_id, Some(buffer.to_vec())))
-                        .await
-                );
-            }
-            /* Close connection (maybe or not because of error) */
-            let _ = backchannel_tx.send((connection_id, None)).await;
-            backchannel_tx.disconnect();
-        });
+        let worker = spawn_worker(
+            &self.spawner,
+            connection_id,
+            connection_rd,
+            self.backchannel_tx.clone(),
+            self.flow.add(connection_id),
+            self.events.clone(),
+        );
 
         self.connections
             .insert(connection_id, (worker, connection_wr));
+        self.transferred.insert(connection_id, (0, 0));
+        self.events.emit(ForwardingEvent::ConnectionOpened {
+            connection_id,
+            target: (*target).clone(),
+        });
         Ok(())
     }
+}
 
-    async fn shutdown(self) {
-        log::debug!("Shutting down everything");
-        for (worker, _connection) in self.connections.into_values() {
-            worker.cancel().await;
-        }
-    }
-
+impl Session for ForwardConnect {
     async fn run(
-        mut self,
+        &mut self,
         transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
         transit_rx: &mut (impl futures::stream::FusedStream<Item = Result<Box<[u8]>, TransitError>>
                   + Unpin),
         cancel: &mut (impl futures::future::FusedFuture<Output = ()> + Unpin),
     ) -> Result<(), ForwardingError> {
+        let mut alternate = Alternate::default();
         /* Event processing loop */
         log::debug!("Entered processing loop");
         let ret = loop {
             futures::select! {
-                message = transit_rx.next() => {
-                    match PeerMessage::de_msgpack(&message.unwrap()?)? {
-                        PeerMessage::Forward { connection_id, payload } => {
-                            self.forward(transit_tx, connection_id, &payload).await?;
-                        },
-                        PeerMessage::Disconnect { connection_id } => {
-                            self.remove_connection(transit_tx, connection_id, false).await?;
-                        },
-                        PeerMessage::Close => {
-                            log::info!("Peer gracefully closed connection");
-                            self.shutdown().await;
-                            break Ok(())
-                        },
-                        PeerMessage::Error(err) => {
-                            for (worker, _connection) in self.connections.into_values() {
-                                worker.cancel().await;
-                            }
-                            bail!(ForwardingError::PeerError(err));
-                        },
-                        other => {
-                            self.shutdown().await;
-                            bail!(ForwardingError::unexpected_message("connect' or 'disconnect' or 'forward' or 'close", other));
-                        },
-                    }
-                },
-                message = self.backchannel_rx.next() => {
-                    /* This channel will never run dry, since we always have at least one sender active */
-                    match message.unwrap() {
-                        (connection_id, Some(payload)) => {
-                            transit_tx.send(
-                                PeerMessage::Forward {
-                                    connection_id,
-                                    payload
-                                }.ser_msgpack()
-                                .into_boxed_slice()
-                            )
-                            .await?;
-                        },
-                        (connection_id, None) => {
-                            self.remove_connection(transit_tx, connection_id, true).await?;
-                        },
-                    }
+                message = alternate.next(transit_rx, &mut self.backchannel_rx) => match message {
+                    futures::future::Either::Left(message) => {
+                        match PeerMessage::de_msgpack(&message.unwrap_or_else(|| Err(TransitError::IO(std::io::ErrorKind::UnexpectedEof.into())))?)? {
+                            PeerMessage::Forward { connection_id, payload } => {
+                                self.forward(transit_tx, connection_id, &payload).await.map_err(|error| {
+                                    error.context(format!("Forwarding data to connection #{}", connection_id))
+                                })?;
+                            },
+                            PeerMessage::Disconnect { connection_id } => {
+                                self.remove_connection(transit_tx, connection_id, false).await.map_err(|error| {
+                                    error.context(format!("Closing connection #{}", connection_id))
+                                })?;
+                            },
+                            PeerMessage::Ack { connection_id, bytes } => {
+                                self.flow.acknowledged(connection_id, bytes);
+                            },
+                            PeerMessage::Close => {
+                                log::debug!("Peer gracefully closed connection");
+                                self.shutdown().await;
+                                break Ok(())
+                            },
+                            PeerMessage::Error(err) => {
+                                self.shutdown().await;
+                                bail!(ForwardingError::PeerError(err));
+                            },
+                            other => {
+                                self.shutdown().await;
+                                bail!(ForwardingError::unexpected_message("connect' or 'disconnect' or 'forward' or 'close", other));
+                            },
+                        }
+                    },
+                    futures::future::Either::Right(message) => {
+                        /* This channel will never run dry, since we always have at least one sender active */
+                        match message.unwrap() {
+                            (connection_id, Some(payload)) => {
+                                if let Some(transferred) = self.transferred.get_mut(&connection_id) {
+                                    transferred.0 += payload.len() as u64;
+                                    self.events.transferred(connection_id, *transferred);
+                                }
+                                transit_tx.send(
+                                    PeerMessage::Forward {
+                                        connection_id,
+                                        payload
+                                    }.ser_msgpack()
+                                    .into_boxed_slice()
+                                )
+                                .await
+                                .map_err(|error| {
+                                    ForwardingError::from(error).context(format!("Sending data of connection #{} to the peer", connection_id))
+                                })?;
+                            },
+                            (connection_id, None) => {
+                                self.remove_connection(transit_tx, connection_id, true).await.map_err(|error| {
+                                    error.context(format!("Closing connection #{}", connection_id))
+                                })?;
+                            },
+                        }
+                    },
                 },
                 connection = self.incoming.next() => {
-                    let (target, connection): (Rc<String>, TcpStream) = connection.unwrap()?;
-                    self.spawn_connection(transit_tx, target, connection).await?;
+                    let (target, connection): (Rc<String>, Box<dyn Socket>) = connection.unwrap()?;
+                    self.spawn_connection(transit_tx, target.clone(), connection).await.map_err(|error| {
+                        error.context(format!("Opening a connection to '{}'", target))
+                    })?;
                 },
                 /* We are done */
                 () = &mut *cancel => {
-                    log::info!("Closing connection");
+                    log::debug!("Closing connection");
                     transit_tx.send(
                         PeerMessage::Close.ser_msgpack()
                         .into_boxed_slice()
@@ -944,6 +2871,58 @@ impl ForwardConnect {
         log::debug!("Exited processing loop");
         ret
     }
+
+    async fn shutdown(&mut self) {
+        log::debug!("Shutting down everything");
+        for (worker, _connection) in self.connections.drain().map(|(_, value)| value) {
+            worker.cancel().await;
+        }
+        for (connection_id, transferred) in self.transferred.drain() {
+            self.events.closed(connection_id, transferred);
+        }
+        self.flow.clear();
+    }
+
+    fn received(&self) -> HashMap<u64, u64> {
+        self.transferred
+            .iter()
+            .map(|(connection_id, (_sent, received))| (*connection_id, *received))
+            .collect()
+    }
+
+    async fn resume(
+        &mut self,
+        transit_tx: &mut (impl futures::sink::Sink<Box<[u8]>, Error = TransitError> + Unpin),
+        peer_received: HashMap<u64, u64>,
+    ) -> Result<(), ForwardingError> {
+        let connection_ids = self.connections.keys().copied().collect::<Vec<_>>();
+        for connection_id in connection_ids {
+            let sent = self
+                .transferred
+                .get(&connection_id)
+                .map_or(0, |(sent, _)| *sent);
+            match peer_received.get(&connection_id) {
+                /* Acknowledgements may have been lost on the way */
+                Some(received) if *received == sent => {
+                    self.flow.acknowledged(connection_id, *received)
+                },
+                /* The peer closed it meanwhile, or never heard of it */
+                None => {
+                    self.remove_connection(transit_tx, connection_id, false)
+                        .await?
+                },
+                Some(_) => {
+                    log::warn!(
+                        "Connection #{} lost data while the session was interrupted",
+                        connection_id
+                    );
+                    self.remove_connection(transit_tx, connection_id, true)
+                        .await?
+                },
+            }
+        }
+        Ok(())
+    }
 }
 
 /** Serialization struct for this protocol */
@@ -954,7 +2933,21 @@ enum PeerMessage {
     /** Offer some destinations to be forwarded to.
      * forwarder -> forwardee only
      */
-    Offer { addresses: Vec<String> },
+    Offer {
+        addresses: Vec<String>,
+        /** Only present if the forwarder probed its targets */
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        health: HashMap<String, TargetHealth>,
+        /** Optional descriptions of the addresses */
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        services: HashMap<String, ServiceInfo>,
+        /** Seconds to try resuming a lost session. Only present if the forwarder supports it */
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_window: Option<u64>,
+        /** Application specific data, see [`ServeOptions::metadata`] */
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<crate::OfferMetadata>,
+    },
     /** Forward a new connection.
      * forwardee -> forwarder only
      */
@@ -969,16 +2962,135 @@ enum PeerMessage {
         connection_id: u64,
         payload: Vec<u8>,
     },
+    /** Total number of bytes of a connection that got written to the local socket.
+     * Any direction, only if both sides use flow control. See [`AppVersion::flow_control_window`]
+     */
+    Ack { connection_id: u64, bytes: u64 },
     /** Close the whole session */
     Close,
     /** Tell the other side you got an error */
     Error(String),
     /** Used to set up a transit channel */
     Transit { hints: transit::Hints },
+    /** First message after resuming a session.
+     * Any direction. The number of forwarded bytes that arrived, for every open connection
+     */
+    Resume { received: HashMap<u64, u64> },
+    /** No-op, sent to keep the connection from going idle.
+     * forwardee -> forwarder only
+     */
+    KeepAlive,
     #[serde(other)]
     Unknown,
 }
 
+static PEER_MESSAGE_SCHEMA: Schema = Schema::Enum {
+    variants: &[
+        (
+            "offer",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required(
+                        "addresses",
+                        &Schema::Array {
+                            items: &schema::STRING,
+                            min_len: 0,
+                            max_len: 1024,
+                        },
+                    ),
+                    Field::optional(
+                        "health",
+                        &Schema::Map {
+                            keys: &schema::STRING,
+                            values: &Schema::Enum {
+                                variants: &[("up", None), ("down", None)],
+                                unknown_variants: UnknownVariants::Ignore,
+                            },
+                        },
+                    ),
+                    Field::optional(
+                        "services",
+                        &Schema::Map {
+                            keys: &schema::STRING,
+                            values: &Schema::Object {
+                                fields: &[
+                                    Field::optional("label", &schema::NULLABLE_STRING),
+                                    Field::optional("protocol", &schema::NULLABLE_STRING),
+                                ],
+                                unknown_fields: UnknownFields::Reject,
+                            },
+                        },
+                    ),
+                    Field::optional("resume_window", &schema::U64),
+                    Field::optional("metadata", &crate::metadata::OFFER_METADATA_SCHEMA),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "connect",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("target", &schema::STRING),
+                    Field::required("connection_id", &schema::U64),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "disconnect",
+            Some(&Schema::Object {
+                fields: &[Field::required("connection_id", &schema::U64)],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "forward",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("connection_id", &schema::U64),
+                    Field::required("payload", &schema::BYTES),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "ack",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("connection_id", &schema::U64),
+                    Field::required("bytes", &schema::U64),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        ("close", None),
+        ("error", Some(&schema::STRING)),
+        (
+            "transit",
+            Some(&Schema::Object {
+                fields: &[Field::required("hints", &transit::HINTS_SCHEMA)],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "resume",
+            Some(&Schema::Object {
+                fields: &[Field::required(
+                    "received",
+                    &Schema::Map {
+                        keys: &schema::U64,
+                        values: &schema::U64,
+                    },
+                )],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        ("keep-alive", None),
+    ],
+    unknown_variants: UnknownVariants::Reject,
+};
+
 impl PeerMessage {
     #[allow(dead_code)]
     pub fn ser_msgpack(&self) -> Vec<u8> {
@@ -995,3 +3107,289 @@ impl PeerMessage {
         rmp_serde::from_read(&mut &*data)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::Either;
+
+    /* Reading from it panics */
+    struct PanickingSocket;
+
+    impl futures::AsyncRead for PanickingSocket {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            panic!("broken socket");
+        }
+    }
+
+    impl futures::AsyncWrite for PanickingSocket {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_std::test]
+    async fn test_worker_panic() {
+        let (backchannel_tx, mut backchannel_rx) = futures::channel::mpsc::channel(20);
+        let (events, mut events_rx) = Events::channel();
+        let (connection_rd, _connection_wr) =
+            (Box::new(PanickingSocket) as Box<dyn Socket>).split();
+        let _worker = spawn_worker(
+            &Spawner::default(),
+            7,
+            connection_rd,
+            backchannel_tx,
+            None,
+            events,
+        );
+
+        /* The session learns about it like about any other closed connection */
+        assert_eq!(backchannel_rx.next().await, Some((7, None)));
+        assert_eq!(
+            events_rx.next().await,
+            Some(ForwardingEvent::ConnectionFailed {
+                connection_id: 7,
+                error: "broken socket".into(),
+            })
+        );
+    }
+
+    /* Acknowledging more than got sent must not make the worker fail */
+    #[async_std::test]
+    async fn test_worker_oversized_ack() {
+        let (backchannel_tx, mut backchannel_rx) = futures::channel::mpsc::channel(20);
+        let (events, mut events_rx) = Events::channel();
+        let (acks_tx, acks_rx) = futures::channel::mpsc::unbounded();
+        let (connection_rd, _connection_wr) =
+            (Box::new(futures::io::Cursor::new(vec![1u8; 16])) as Box<dyn Socket>).split();
+        let _worker = spawn_worker(
+            &Spawner::default(),
+            7,
+            connection_rd,
+            backchannel_tx,
+            Some((8, acks_rx)),
+            events,
+        );
+
+        assert_eq!(backchannel_rx.next().await, Some((7, Some(vec![1u8; 16]))));
+        acks_tx.unbounded_send(1000).unwrap();
+        assert_eq!(backchannel_rx.next().await, Some((7, None)));
+        assert_eq!(events_rx.next().await, None);
+    }
+
+    /* Terminating the session must not wait for a connection attempt to give up */
+    #[async_std::test]
+    async fn test_cancel_connecting() {
+        /* Nothing listens there, so connecting retries until the window closes */
+        let port = std::net::TcpListener::bind("[::1]:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (backchannel_tx, backchannel_rx) = futures::channel::mpsc::channel(20);
+        let mut session = ForwardingServe {
+            targets: [(format!("{}", port), (None, port))].into(),
+            tls_connectors: HashMap::new(),
+            connections: HashMap::new(),
+            transferred: HashMap::new(),
+            historic_connections: HashSet::new(),
+            backchannel_tx,
+            backchannel_rx,
+            flow: FlowControl::default(),
+            forwarded_bytes: 0,
+            options: ServeOptions {
+                target_retry_window: Some(std::time::Duration::from_secs(600)),
+                ..ServeOptions::default()
+            },
+            events: Events::default(),
+        };
+
+        let (transit_tx, mut sent) = futures::channel::mpsc::unbounded::<Box<[u8]>>();
+        let mut transit_tx =
+            transit_tx.sink_map_err(|_| TransitError::IO(std::io::ErrorKind::BrokenPipe.into()));
+        let connect = PeerMessage::Connect {
+            target: format!("{}", port),
+            connection_id: 1,
+        };
+        let mut transit_rx = futures::stream::iter([Ok(connect.ser_msgpack().into_boxed_slice())])
+            .chain(futures::stream::pending())
+            .fuse();
+        let mut cancel = Box::pin(util::sleep(std::time::Duration::from_millis(200))).fuse();
+
+        util::timeout(
+            std::time::Duration::from_secs(10),
+            session.run(&mut transit_tx, &mut transit_rx, &mut cancel),
+        )
+        .await
+        .expect("Connecting did not get cancelled")
+        .unwrap();
+        let close = sent.next().await.unwrap();
+        assert!(matches!(
+            PeerMessage::de_msgpack(&close).unwrap(),
+            PeerMessage::Close
+        ));
+        assert!(session.connections.is_empty());
+    }
+
+    /* A command target talks over its standard input and output */
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_command_target() {
+        let (backchannel_tx, backchannel_rx) = futures::channel::mpsc::channel(20);
+        let mut session = ForwardingServe {
+            targets: [("22".into(), (None, 22))].into(),
+            tls_connectors: HashMap::new(),
+            connections: HashMap::new(),
+            transferred: HashMap::new(),
+            historic_connections: HashSet::new(),
+            backchannel_tx,
+            backchannel_rx,
+            flow: FlowControl::default(),
+            forwarded_bytes: 0,
+            options: ServeOptions {
+                command_targets: [((None, 22), TargetCommand::new("cat", Vec::<String>::new()))]
+                    .into(),
+                ..ServeOptions::default()
+            },
+            events: Events::default(),
+        };
+
+        let (transit_tx, mut sent) = futures::channel::mpsc::unbounded::<Box<[u8]>>();
+        let mut transit_tx =
+            transit_tx.sink_map_err(|_| TransitError::IO(std::io::ErrorKind::BrokenPipe.into()));
+        let messages = [
+            PeerMessage::Connect {
+                target: "22".into(),
+                connection_id: 1,
+            },
+            PeerMessage::Forward {
+                connection_id: 1,
+                payload: b"hello".to_vec(),
+            },
+        ];
+        let mut transit_rx = futures::stream::iter(
+            messages.map(|message| Ok(message.ser_msgpack().into_boxed_slice())),
+        )
+        .chain(futures::stream::pending())
+        .fuse();
+        let (cancel_tx, cancel_rx) = futures::channel::oneshot::channel::<()>();
+        let mut cancel = cancel_rx.map(|_| ()).fuse();
+
+        let echo = async {
+            while let Some(message) = sent.next().await {
+                if let PeerMessage::Forward {
+                    connection_id,
+                    payload,
+                } = PeerMessage::de_msgpack(&message).unwrap()
+                {
+                    cancel_tx.send(()).unwrap();
+                    return (connection_id, payload);
+                }
+            }
+            panic!("Nothing came back");
+        };
+        let (result, echo) = util::timeout(
+            std::time::Duration::from_secs(10),
+            futures::future::join(
+                session.run(&mut transit_tx, &mut transit_rx, &mut cancel),
+                echo,
+            ),
+        )
+        .await
+        .expect("The command did not answer");
+        result.unwrap();
+        assert_eq!(echo, (1, b"hello".to_vec()));
+    }
+
+    /* Older versions fail on keep-alive messages, and don't advertise taking them */
+    #[test]
+    fn test_keep_alive_version() {
+        let ours = serde_json::to_value(&APP_CONFIG.app_version).unwrap();
+        assert_eq!(ours["keep_alive"], true);
+        let mut older = ours.clone();
+        older.as_object_mut().unwrap().remove("keep_alive");
+        let older: AppVersion = serde_json::from_value(older).unwrap();
+        assert!(!older.keep_alive);
+    }
+
+    /* Older versions don't know how to resume, and don't advertise it */
+    #[test]
+    fn test_resume_version() {
+        let ours = serde_json::to_value(&APP_CONFIG.app_version).unwrap();
+        assert_eq!(ours["resume"], true);
+        let mut older = ours.clone();
+        older.as_object_mut().unwrap().remove("resume");
+        let older: AppVersion = serde_json::from_value(older).unwrap();
+        assert!(!older.resume);
+    }
+
+    /* Both directions saturated: they must take turns */
+    #[async_std::test]
+    async fn test_alternate_saturated() {
+        let mut alternate = Alternate::default();
+        let mut left = futures::stream::repeat('l');
+        let mut right = futures::stream::repeat('r');
+        let mut order = String::new();
+        for _ in 0..6 {
+            match alternate.next(&mut left, &mut right).await {
+                Either::Left(item) | Either::Right(item) => order.push(item.unwrap()),
+            }
+        }
+        assert_eq!(order, "lrlrlr");
+    }
+
+    /* One direction saturated: the other one gets served as soon as it has something */
+    #[async_std::test]
+    async fn test_alternate_one_sided() {
+        let mut alternate = Alternate::default();
+        let (mut right_tx, mut right) = futures::channel::mpsc::unbounded();
+        let mut left = futures::stream::repeat('l');
+        for _ in 0..3 {
+            assert!(matches!(
+                alternate.next(&mut left, &mut right).await,
+                Either::Left(_)
+            ));
+        }
+        right_tx.send('r').await.unwrap();
+        assert!(matches!(
+            alternate.next(&mut left, &mut right).await,
+            Either::Right(Some('r'))
+        ));
+        assert!(matches!(
+            alternate.next(&mut left, &mut right).await,
+            Either::Left(_)
+        ));
+
+        /* And the other way around */
+        let mut left = futures::stream::pending::<char>();
+        let mut right = futures::stream::repeat('r');
+        for _ in 0..3 {
+            assert!(matches!(
+                alternate.next(&mut left, &mut right).await,
+                Either::Right(_)
+            ));
+        }
+    }
+}
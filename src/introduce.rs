@@ -0,0 +1,185 @@
+//! Use the wormhole only to introduce two applications to each other
+//!
+//! Some applications bring their own transport and only need a way to pair two devices: a WireGuard
+//! tunnel needs both public keys and endpoints, a syncthing pairing needs both device IDs. [`introduce`]
+//! does that on a connected [`Wormhole`]: both sides tell each other where they can be reached, get a key
+//! only the two of them know, and the wormhole is closed afterwards.
+//!
+//! The application brings its own [`AppID`], so that its codes don't mix with other applications. Both
+//! sides do the same, there is no sender or receiver. The endpoints are opaque to this crate, they may be
+//! addresses, URLs, public keys or anything else the application understands. Anything else the other side
+//! needs to know can go into the details of [`introduce_with_details`], like the [`wireguard`](crate::wireguard)
+//! module does.
+
+use super::*;
+use serde::{Deserialize, Serialize};
+
+/// The purpose of the key handed out by [`introduce`], see [`Introduction::key`]
+#[derive(Debug)]
+pub struct IntroductionKey;
+impl KeyPurpose for IntroductionKey {}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum IntroductionError {
+    #[error("Something went wrong on the other side: {}", _0)]
+    PeerError(String),
+    /// Some deserialization went wrong, we probably got some garbage
+    #[error("Corrupt JSON message received")]
+    ProtocolJson(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("Wormhole connection error")]
+    Wormhole(
+        #[from]
+        #[source]
+        WormholeError,
+    ),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PeerMessage {
+    Introduction {
+        endpoints: Vec<String>,
+        #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+        details: serde_json::Value,
+    },
+    Error(String),
+}
+
+/**
+ * What both sides know about each other after [`introduce`]
+ */
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Introduction {
+    /**
+     * A key that only the two sides know
+     *
+     * It is derived from the wormhole's key with the `"{appid}/introduction-key"` purpose, so both sides got
+     * the same one if and only if the PAKE succeeded. Use it to authenticate the application's own
+     * connection, or derive further keys from it.
+     */
+    pub key: Key<IntroductionKey>,
+    /** The wormhole's verifier, for the users to compare. See [`Wormhole::verifier`](Wormhole#structfield.verifier) */
+    pub verifier: Box<crypto_secretbox::Key>,
+    /** Where the other side can be reached, as it told us */
+    pub peer_endpoints: Vec<String>,
+    /** What else the other side told us, see [`introduce_with_details`]. `Null` if nothing */
+    pub peer_details: serde_json::Value,
+    /** The application version information the other side sent, see [`Wormhole::peer_version`](Wormhole#structfield.peer_version) */
+    pub peer_version: serde_json::Value,
+}
+
+/**
+ * Exchange endpoints with the other side, then close the wormhole
+ *
+ * Both sides call this with their own endpoints, which may be empty if they don't have any. The wormhole
+ * gets closed afterwards, also in case of an error.
+ */
+pub async fn introduce(
+    wormhole: Wormhole,
+    endpoints: Vec<String>,
+) -> Result<Introduction, IntroductionError> {
+    introduce_with_details(wormhole, endpoints, serde_json::Value::Null).await
+}
+
+/**
+ * Like [`introduce`], but also tell the other side some application specific details
+ *
+ * They arrive as [`Introduction::peer_details`] on the other side.
+ */
+pub async fn introduce_with_details(
+    mut wormhole: Wormhole,
+    endpoints: Vec<String>,
+    details: serde_json::Value,
+) -> Result<Introduction, IntroductionError> {
+    let (peer_endpoints, peer_details) = match exchange(&mut wormhole, endpoints, details).await {
+        Ok(peer) => peer,
+        Err(err) => {
+            /* Tell the other side, unless the error came from it. This fails if the connection is broken */
+            if !matches!(err, IntroductionError::PeerError(_)) {
+                let _ = wormhole
+                    .send_json(&PeerMessage::Error(err.to_string()))
+                    .await;
+            }
+            let _ = wormhole.close().await;
+            return Err(err);
+        },
+    };
+
+    let introduction = Introduction {
+        key: derive_key(wormhole.key(), wormhole.appid()),
+        verifier: wormhole.verifier.clone(),
+        peer_endpoints,
+        peer_details,
+        peer_version: wormhole.peer_version.clone(),
+    };
+    wormhole.close().await?;
+    Ok(introduction)
+}
+
+async fn exchange(
+    wormhole: &mut Wormhole,
+    endpoints: Vec<String>,
+    details: serde_json::Value,
+) -> Result<(Vec<String>, serde_json::Value), IntroductionError> {
+    wormhole
+        .send_json(&PeerMessage::Introduction { endpoints, details })
+        .await?;
+    match wormhole.receive_json::<PeerMessage>().await?? {
+        PeerMessage::Introduction { endpoints, details } => Ok((endpoints, details)),
+        PeerMessage::Error(err) => Err(IntroductionError::PeerError(err)),
+    }
+}
+
+fn derive_key(key: &Key<WormholeKey>, appid: &AppID) -> Key<IntroductionKey> {
+    key.derive_subkey_from_purpose(&format!("{}/introduction-key", appid))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_introduction_message() {
+        assert_eq!(
+            serde_json::to_value(PeerMessage::Introduction {
+                endpoints: vec!["192.0.2.1:51820".into()],
+                details: serde_json::Value::Null,
+            })
+            .unwrap(),
+            serde_json::json!({"introduction": {"endpoints": ["192.0.2.1:51820"]}})
+        );
+        assert_eq!(
+            serde_json::from_value::<PeerMessage>(serde_json::json!({"introduction": {
+                "endpoints": [],
+                "details": {"name": "laptop"},
+            }}))
+            .unwrap(),
+            PeerMessage::Introduction {
+                endpoints: vec![],
+                details: serde_json::json!({"name": "laptop"}),
+            }
+        );
+        assert_eq!(
+            serde_json::from_value::<PeerMessage>(serde_json::json!({"error": "nope"})).unwrap(),
+            PeerMessage::Error("nope".into())
+        );
+
+        /* The key is bound to the application */
+        let key = Key::<WormholeKey>::new(Box::default());
+        let appid = AppID::new("example.com/introduce");
+        assert_eq!(
+            derive_key(&key, &appid).to_hex(),
+            derive_key(&key, &appid).to_hex()
+        );
+        assert_ne!(
+            derive_key(&key, &appid).to_hex(),
+            derive_key(&key, &AppID::new("example.com/other")).to_hex()
+        );
+    }
+}
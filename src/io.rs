@@ -0,0 +1,7 @@
+//! Compatibility with the I/O API of earlier versions
+//!
+//! The API of this crate used to come in a blocking flavor. It is kept around as a thin layer over the
+//! asynchronous one, so that code written against it still works. New code should use [`Wormhole`](crate::Wormhole)
+//! and [`MailboxConnection`](crate::MailboxConnection) directly.
+
+pub mod blocking;
@@ -0,0 +1,231 @@
+//! A blocking wormhole, as in earlier versions of this crate
+//!
+//! Each call blocks the current thread until the asynchronous operation behind it is done, so don't use this
+//! from within an async runtime. The peer is awaited lazily: [`set_code`](Wormhole::set_code) or
+//! [`allocate_code`](Wormhole::allocate_code) only claim the mailbox, the first call that needs the other side
+//! does the key exchange. Errors come boxed, as [`WormholeError`] is rather large.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> {
+//! use magic_wormhole::io::blocking::Wormhole;
+//! let mut wormhole = Wormhole::new("lothar.com/example", magic_wormhole::rendezvous::DEFAULT_RENDEZVOUS_SERVER);
+//! wormhole.set_code("4-purple-sausages")?;
+//! println!("Verifier: {}", hex::encode(wormhole.get_verifier()?));
+//! wormhole.send(b"hello".to_vec())?;
+//! let reply = wormhole.receive()?;
+//! wormhole.close()?;
+//! # Ok(()) }
+//! ```
+//!
+//! Use [`into_async`](Wormhole::into_async) to move over to the asynchronous API one step at a time.
+
+use crate::{AppConfig, AppID, Code, MailboxConnection, Mood, WormholeError};
+use async_std::task::block_on;
+use std::borrow::Cow;
+
+enum State {
+    /* No code yet, or connecting with the last one failed */
+    New,
+    Mailbox(MailboxConnection<serde_json::Value>),
+    Connected(crate::Wormhole),
+}
+
+/**
+ * A wormhole that blocks on every call
+ *
+ * Get a code with [`allocate_code`](Self::allocate_code) or enter one with [`set_code`](Self::set_code) first.
+ * If connecting to the peer fails, the code is gone and a new one has to be set.
+ */
+pub struct Wormhole {
+    config: AppConfig<serde_json::Value>,
+    code: Option<Code>,
+    state: State,
+}
+
+impl Wormhole {
+    /** A wormhole for `appid` on the rendezvous server at `rendezvous_url`. Nothing gets connected yet */
+    pub fn new(appid: &str, rendezvous_url: &str) -> Self {
+        Self::with_config(AppConfig {
+            id: AppID::new(appid.to_owned()),
+            rendezvous_url: Cow::Owned(rendezvous_url.to_owned()),
+            app_version: serde_json::Value::Object(Default::default()),
+            client_version: crate::ClientVersion::DEFAULT,
+            reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+            timeouts: crate::WormholeTimeouts::DEFAULT,
+        })
+    }
+
+    /** Like [`new`](Self::new), with the whole configuration of the asynchronous API */
+    pub fn with_config(config: AppConfig<impl serde::Serialize>) -> Self {
+        let app_version = serde_json::to_value(&config.app_version).unwrap();
+        Self {
+            config: AppConfig {
+                id: config.id,
+                rendezvous_url: config.rendezvous_url,
+                app_version,
+                client_version: config.client_version,
+                reconnect_policy: config.reconnect_policy,
+                timeouts: config.timeouts,
+            },
+            code: None,
+            state: State::New,
+        }
+    }
+
+    /**
+     * Allocate a nameplate and generate a code with `code_length` random words
+     *
+     * See [`MailboxConnection::create`].
+     */
+    pub fn allocate_code(&mut self, code_length: usize) -> Result<Code, Box<WormholeError>> {
+        let mailbox = block_on(MailboxConnection::create(self.config.clone(), code_length))?;
+        Ok(self.set_mailbox(mailbox))
+    }
+
+    /**
+     * Use a code that was entered by the user
+     *
+     * The nameplate gets claimed even if nobody allocated it, so both sides may set the same code.
+     */
+    pub fn set_code(&mut self, code: &str) -> Result<(), Box<WormholeError>> {
+        let code = Code(code.trim().to_owned());
+        let mailbox = block_on(MailboxConnection::connect(self.config.clone(), code, true))?;
+        self.set_mailbox(mailbox);
+        Ok(())
+    }
+
+    fn set_mailbox(&mut self, mailbox: MailboxConnection<serde_json::Value>) -> Code {
+        let code = mailbox.code.clone();
+        self.code = Some(code.clone());
+        self.state = State::Mailbox(mailbox);
+        code
+    }
+
+    /** The code that got allocated or set, if any */
+    pub fn get_code(&self) -> Option<&Code> {
+        self.code.as_ref()
+    }
+
+    /* Wait for the peer on first use */
+    fn wormhole(&mut self) -> Result<&mut crate::Wormhole, Box<WormholeError>> {
+        if let State::Mailbox(_) = self.state {
+            let State::Mailbox(mailbox) = std::mem::replace(&mut self.state, State::New) else {
+                unreachable!()
+            };
+            self.state = State::Connected(block_on(crate::Wormhole::connect(mailbox))?);
+        }
+        match &mut self.state {
+            State::Connected(wormhole) => Ok(wormhole),
+            _ => panic!("No code set, call `allocate_code` or `set_code` first"),
+        }
+    }
+
+    /**
+     * The verifier to compare with the other side, see [`Wormhole::verifier`](crate::Wormhole::verifier)
+     *
+     * # Panics
+     *
+     * This and the other methods that talk to the peer panic if no code is set.
+     */
+    pub fn get_verifier(&mut self) -> Result<Vec<u8>, Box<WormholeError>> {
+        Ok(self.wormhole()?.verifier.to_vec())
+    }
+
+    /** The app versions the other side sent */
+    pub fn get_versions(&mut self) -> Result<serde_json::Value, Box<WormholeError>> {
+        Ok(self.wormhole()?.peer_version.clone())
+    }
+
+    /** Send an encrypted message to the peer */
+    pub fn send(&mut self, message: Vec<u8>) -> Result<(), Box<WormholeError>> {
+        let wormhole = self.wormhole()?;
+        Ok(block_on(wormhole.send(message))?)
+    }
+
+    /** Receive the next encrypted message from the peer */
+    pub fn receive(&mut self) -> Result<Vec<u8>, Box<WormholeError>> {
+        let wormhole = self.wormhole()?;
+        Ok(block_on(wormhole.receive())?)
+    }
+
+    #[deprecated(since = "0.7.0", note = "please use 'send' instead")]
+    pub fn send_message(&mut self, message: &[u8]) -> Result<(), Box<WormholeError>> {
+        self.send(message.to_vec())
+    }
+
+    #[deprecated(since = "0.7.0", note = "please use 'receive' instead")]
+    pub fn get_message(&mut self) -> Result<Vec<u8>, Box<WormholeError>> {
+        self.receive()
+    }
+
+    /**
+     * Wait for the peer and hand out the wormhole of the asynchronous API
+     *
+     * # Panics
+     *
+     * If no code is set.
+     */
+    pub fn into_async(mut self) -> Result<crate::Wormhole, Box<WormholeError>> {
+        self.wormhole()?;
+        match self.state {
+            State::Connected(wormhole) => Ok(wormhole),
+            _ => unreachable!(),
+        }
+    }
+
+    /**
+     * Release the nameplate and close the mailbox
+     *
+     * If the peer never showed up, the mailbox gets closed as [`Mood::Lonely`].
+     */
+    pub fn close(self) -> Result<(), Box<WormholeError>> {
+        match self.state {
+            State::New => Ok(()),
+            State::Mailbox(mailbox) => Ok(block_on(mailbox.shutdown(Mood::Lonely))?),
+            State::Connected(wormhole) => Ok(block_on(wormhole.close())?),
+        }
+    }
+}
+
+impl std::fmt::Debug for Wormhole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wormhole")
+            .field("appid", &self.config.id)
+            .field("code", &self.code)
+            .field("connected", &matches!(self.state, State::Connected(_)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let wormhole = Wormhole::new("lothar.com/example", "ws://localhost:4000/v1");
+        assert_eq!(&*wormhole.config.id, "lothar.com/example");
+        assert_eq!(wormhole.config.rendezvous_url, "ws://localhost:4000/v1");
+        assert_eq!(wormhole.config.app_version, serde_json::json!({}));
+        assert!(wormhole.get_code().is_none());
+
+        let wormhole = Wormhole::with_config(AppConfig {
+            id: AppID::new("lothar.com/example"),
+            rendezvous_url: crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER.into(),
+            app_version: vec!["v1"],
+            client_version: crate::ClientVersion::DEFAULT,
+            reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+            timeouts: crate::WormholeTimeouts::DEFAULT,
+        });
+        assert_eq!(wormhole.config.app_version, serde_json::json!(["v1"]));
+        /* Nothing to close */
+        wormhole.close().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "No code set")]
+    fn test_no_code() {
+        let mut wormhole = Wormhole::new("lothar.com/example", "ws://localhost:4000/v1");
+        let _ = wormhole.get_verifier();
+    }
+}
@@ -13,11 +13,23 @@
 //! protocol and thus requires a [`Wormhole`].
 //!
 //! As an alternative to file transfer, there is the [`forwarding`] module, which allows to forward arbitrary TCP connections over the Wormhole/Transit tunnel.
+//! The [`ssh_invite`] module hands over SSH public keys, like `wormhole ssh invite` and `wormhole ssh accept` do.
+//! Applications that bring their own transport can use [`introduce`] to pair two devices and exchange their endpoints,
+//! which the [`wireguard`] module does for setting up a VPN. For protocols of their own, the [`channels`] module gives them
+//! named byte streams to the peer.
 //!
 //! Transferring large amounts of data should not be done over the rendezvous server. Instead, you have to set up a [`transit`]
 //! connection. A transit is little more than an encrypted TcpConnection. If a direct connection between both clients is not possible,
 //! a relay server will transparently connect them together. Transit is used by the file transfer for example, but any other AppID protocol
 //! might make use of it as well.
+//!
+//! ## Logging
+//!
+//! This crate uses the [`log`] crate for diagnostics only, at the `debug` level and below. Nothing in
+//! there is meant to be shown to users. Everything an application may want to tell its users about
+//! comes through the return values and the handler callbacks, like the code handler of
+//! [`Wormhole::connect_with_retries`] or the transit and progress handlers of the [`transfer`] functions.
+//! Warnings and errors are logged when something goes wrong that is not reported otherwise.
 
 #![forbid(unsafe_code)]
 #![allow(clippy::upper_case_acronyms)]
@@ -26,18 +38,39 @@
 
 #[macro_use]
 mod util;
+#[cfg(all(feature = "channels", not(target_family = "wasm")))]
+pub mod channels;
 mod core;
+#[cfg(all(feature = "transit", not(target_family = "wasm")))]
+pub mod diagnostics;
 #[cfg(feature = "forwarding")]
 pub mod forwarding;
+pub mod introduce;
+#[cfg(all(feature = "blocking", not(target_family = "wasm")))]
+pub mod io;
+#[cfg(feature = "transit")]
+mod metadata;
+mod schema;
+#[cfg(feature = "ssh-invite")]
+pub mod ssh_invite;
+#[cfg(all(feature = "native-certs", not(target_family = "wasm")))]
+mod tls;
 #[cfg(feature = "transfer")]
 pub mod transfer;
 #[cfg(feature = "transit")]
 pub mod transit;
 #[cfg(feature = "transfer")]
 pub mod uri;
+#[cfg(feature = "wireguard")]
+pub mod wireguard;
 
 pub use crate::core::{
     key::{GenericKey, Key, KeyPurpose, WormholeKey},
-    rendezvous, AppConfig, AppID, Code, MailboxConnection, Mood, Nameplate, Wormhole,
-    WormholeError,
+    rendezvous,
+    wordlist::{CodeCompletion, CodeError, CustomWordlist, PgpWordlist, Wordlist, WordlistError},
+    AppConfig, AppID, ClientVersion, ClockSkew, Code, MailboxConnection, Mood, Nameplate, Seed,
+    Trace, UnverifiedWormhole, Wormhole, WormholeError, WormholeTimeouts,
 };
+#[cfg(feature = "transit")]
+pub use crate::metadata::OfferMetadata;
+pub use crate::schema::SchemaViolation;
@@ -0,0 +1,120 @@
+//! Application specific data that travels along with an offer
+//!
+//! Both file transfer offers ([`Offer::set_metadata`](crate::transfer::Offer::set_metadata)) and port
+//! forwarding offers ([`ServeOptions::metadata`](crate::forwarding::ServeOptions::metadata)) may carry
+//! an [`OfferMetadata`], like a ticket ID or a description to show to the receiver. The protocol does
+//! not look into it, it is up to the applications on both sides to agree on its meaning.
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+use crate::schema::{Field, Schema, UnknownFields};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/** Upper bound for the serialized data, so that offers stay small */
+const MAX_DATA_LEN: usize = 64 * 1024;
+
+/**
+ * Some application specific data attached to an offer
+ *
+ * The data is JSON, serialized from a type of the application. `kind` tells which type it is, and
+ * `version` which revision of it, so that applications can evolve their format without confusing
+ * older peers.
+ *
+ * **Security warning:** on the receiving side, this is untrusted input.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct OfferMetadata {
+    /// What the data is, chosen by the application, like `"com.example.ticket"`
+    pub kind: String,
+    /// The revision of the format of the data
+    pub version: u32,
+    /// The data itself, as JSON
+    pub data: String,
+}
+
+impl OfferMetadata {
+    /** Serialize `data` as metadata of the given kind and version */
+    pub fn new<T: Serialize>(
+        kind: impl Into<String>,
+        version: u32,
+        data: &T,
+    ) -> Result<Self, serde_json::Error> {
+        let data = serde_json::to_string(data)?;
+        if data.len() > MAX_DATA_LEN {
+            return Err(serde::ser::Error::custom(format!(
+                "Metadata must not be larger than {} bytes",
+                MAX_DATA_LEN
+            )));
+        }
+        Ok(Self {
+            kind: kind.into(),
+            version,
+            data,
+        })
+    }
+
+    /**
+     * Get the data back, if it is of the given kind and at most `max_version`
+     *
+     * Returns `None` for metadata this application does not know how to read, including data that
+     * does not deserialize into `T`.
+     */
+    pub fn decode<T: DeserializeOwned>(&self, kind: &str, max_version: u32) -> Option<T> {
+        if self.kind != kind || self.version > max_version {
+            return None;
+        }
+        serde_json::from_str(&self.data)
+            .map_err(|err| log::debug!("Ignoring malformed '{}' metadata: {}", kind, err))
+            .ok()
+    }
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static OFFER_METADATA_SCHEMA: Schema = Schema::Object {
+    fields: &[
+        Field::required("kind", &Schema::String { max_len: 256 }),
+        Field::required(
+            "version",
+            &Schema::Integer {
+                min: 0,
+                max: u32::MAX as i128,
+            },
+        ),
+        Field::required(
+            "data",
+            &Schema::String {
+                max_len: MAX_DATA_LEN,
+            },
+        ),
+    ],
+    unknown_fields: UnknownFields::Reject,
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ticket {
+        id: u64,
+    }
+
+    #[test]
+    fn test_offer_metadata() {
+        let metadata = OfferMetadata::new("com.example.ticket", 2, &Ticket { id: 42 }).unwrap();
+        assert_eq!(
+            metadata.decode::<Ticket>("com.example.ticket", 2),
+            Some(Ticket { id: 42 })
+        );
+        /* Too new, or something else entirely */
+        assert_eq!(metadata.decode::<Ticket>("com.example.ticket", 1), None);
+        assert_eq!(metadata.decode::<Ticket>("com.example.other", 2), None);
+        assert_eq!(metadata.decode::<String>("com.example.ticket", 2), None);
+
+        #[cfg(any(feature = "transfer", feature = "forwarding"))]
+        let json = serde_json::to_vec(&metadata).unwrap();
+        #[cfg(any(feature = "transfer", feature = "forwarding"))]
+        assert!(OFFER_METADATA_SCHEMA.validate_json(&json).is_ok());
+        assert!(OfferMetadata::new("big", 1, &"x".repeat(MAX_DATA_LEN)).is_err());
+    }
+}
@@ -0,0 +1,646 @@
+/* Strict mode: check peer messages against a schema before acting on them. See `Wormhole::set_strict` */
+/* Only the protocols have schemas, the rest of the crate just passes their validators around */
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+use std::fmt;
+
+/** Checks a received message, as installed on a [`Wormhole`](crate::Wormhole) or `Transit` by a protocol */
+pub(crate) type Validator = fn(&[u8]) -> Result<(), SchemaViolation>;
+
+/**
+ * A peer message that does not match the schema of its protocol
+ *
+ * Only returned in strict mode, see [`Wormhole::set_strict`](crate::Wormhole::set_strict).
+ */
+#[derive(Debug, thiserror::Error)]
+#[error("Malformed message from peer at {}: {}", path, reason)]
+#[non_exhaustive]
+pub struct SchemaViolation {
+    /// Where the message went wrong, like `$.offer.content["notes.txt"].size`
+    pub path: String,
+    /// What is wrong with it
+    pub reason: String,
+    /// The rejected message, verbatim
+    pub message: Vec<u8>,
+}
+
+/** Upper bound for all strings in peer messages */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+const MAX_STRING_LEN: usize = 1024 * 1024;
+
+/** What a (part of a) message must look like */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+#[derive(Debug)]
+pub(crate) enum Schema {
+    Bool,
+    Integer {
+        min: i128,
+        max: i128,
+    },
+    /** Integers are fine as well */
+    Float,
+    String {
+        max_len: usize,
+    },
+    Array {
+        items: &'static Schema,
+        min_len: usize,
+        max_len: usize,
+    },
+    Map {
+        keys: &'static Schema,
+        values: &'static Schema,
+    },
+    /** A struct */
+    Object {
+        fields: &'static [Field],
+        unknown_fields: UnknownFields,
+    },
+    Nullable(&'static Schema),
+    /**
+     * Serde's default enum representation: unit variants are a string with their name,
+     * the others a map with their name as only key. `None` marks unit variants.
+     */
+    Enum {
+        variants: &'static [(&'static str, Option<&'static Schema>)],
+        unknown_variants: UnknownVariants,
+    },
+    /** An enum with the variant name in a field, like `#[serde(tag = "type")]`. The variants must be objects. */
+    Tagged {
+        tag: &'static str,
+        variants: &'static [(&'static str, &'static Schema)],
+        unknown_variants: UnknownVariants,
+    },
+}
+
+/** What to do with fields a [`Schema::Object`] does not list */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum UnknownFields {
+    Reject,
+    /** For fields that other implementations are known to send */
+    Ignore,
+}
+
+/** What to do with unit variants that are not listed, like `#[serde(other)]` */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum UnknownVariants {
+    Reject,
+    /** For extension points of the protocol that are meant to be skipped */
+    Ignore,
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+#[derive(Debug)]
+pub(crate) struct Field {
+    name: &'static str,
+    schema: &'static Schema,
+    required: bool,
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl Field {
+    pub(crate) const fn required(name: &'static str, schema: &'static Schema) -> Self {
+        Self {
+            name,
+            schema,
+            required: true,
+        }
+    }
+
+    /** May be missing */
+    pub(crate) const fn optional(name: &'static str, schema: &'static Schema) -> Self {
+        Self {
+            name,
+            schema,
+            required: false,
+        }
+    }
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static BOOL: Schema = Schema::Bool;
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static U16: Schema = Schema::Integer {
+    min: 0,
+    max: u16::MAX as i128,
+};
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static U64: Schema = Schema::Integer {
+    min: 0,
+    max: u64::MAX as i128,
+};
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static FLOAT: Schema = Schema::Float;
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static STRING: Schema = Schema::String {
+    max_len: MAX_STRING_LEN,
+};
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static NULLABLE_STRING: Schema = Schema::Nullable(&STRING);
+/** A path of file names */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static PATH: Schema = Schema::Array {
+    items: &STRING,
+    min_len: 1,
+    max_len: 4096,
+};
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static BYTES: Schema = Schema::Array {
+    items: &Schema::Integer {
+        min: 0,
+        max: u8::MAX as i128,
+    },
+    min_len: 0,
+    max_len: usize::MAX,
+};
+/** An empty struct, like `struct FileEnd {}` */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static EMPTY: Schema = Schema::Object {
+    fields: &[],
+    unknown_fields: UnknownFields::Reject,
+};
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl Schema {
+    /** Check a JSON encoded message, logging it if it does not match */
+    pub(crate) fn validate_json(&self, message: &[u8]) -> Result<(), SchemaViolation> {
+        serde_json::from_slice::<Value>(message)
+            .map_err(|error| Mismatch::new(error.to_string()))
+            .and_then(|value| self.check(&value))
+            .map_err(|mismatch| {
+                let violation = mismatch.into_violation(message);
+                log::error!(
+                    "{}. The message was: '{}'",
+                    violation,
+                    String::from_utf8_lossy(message)
+                );
+                violation
+            })
+    }
+
+    /** Check a msgpack encoded message, logging it if it does not match */
+    #[cfg(feature = "rmp-serde")]
+    pub(crate) fn validate_msgpack(&self, message: &[u8]) -> Result<(), SchemaViolation> {
+        rmp_serde::from_slice::<Value>(message)
+            .map_err(|error| Mismatch::new(error.to_string()))
+            .and_then(|value| self.check(&value))
+            .map_err(|mismatch| {
+                let violation = mismatch.into_violation(message);
+                log::error!(
+                    "{}. The message was (hex): {}",
+                    violation,
+                    hex::encode(message)
+                );
+                violation
+            })
+    }
+
+    fn check(&self, value: &Value) -> Result<(), Mismatch> {
+        match (self, value) {
+            (Schema::Bool, Value::Bool) => Ok(()),
+            (Schema::Integer { min, max }, Value::Integer(integer)) => {
+                if (min..=max).contains(&integer) {
+                    Ok(())
+                } else {
+                    Err(Mismatch::new(format!(
+                        "{} is out of range {}..={}",
+                        integer, min, max
+                    )))
+                }
+            },
+            (Schema::Float, Value::Float | Value::Integer(_)) => Ok(()),
+            (Schema::String { max_len }, Value::String(string)) => {
+                if string.len() <= *max_len {
+                    Ok(())
+                } else {
+                    Err(Mismatch::new(format!(
+                        "string is longer than {} bytes",
+                        max_len
+                    )))
+                }
+            },
+            (
+                Schema::Array {
+                    items,
+                    min_len,
+                    max_len,
+                },
+                Value::Array(values),
+            ) => {
+                if !(min_len..=max_len).contains(&&values.len()) {
+                    return Err(Mismatch::new(format!(
+                        "expected {}..={} elements, found {}",
+                        min_len,
+                        max_len,
+                        values.len()
+                    )));
+                }
+                for (index, value) in values.iter().enumerate() {
+                    items
+                        .check(value)
+                        .map_err(|mismatch| mismatch.at(format!("[{}]", index)))?;
+                }
+                Ok(())
+            },
+            (Schema::Map { keys, values }, Value::Map(entries)) => {
+                for (key, value) in entries {
+                    keys.check(key)
+                        .map_err(|mismatch| mismatch.at(format!("[{}]", key)))?;
+                    values
+                        .check(value)
+                        .map_err(|mismatch| mismatch.at(format!("[{}]", key)))?;
+                }
+                Ok(())
+            },
+            (
+                Schema::Object {
+                    fields,
+                    unknown_fields,
+                },
+                Value::Map(entries),
+            ) => check_fields(fields, *unknown_fields, entries, None),
+            (Schema::Nullable(_), Value::Null) => Ok(()),
+            (Schema::Nullable(schema), value) => schema.check(value),
+            (
+                Schema::Enum {
+                    variants,
+                    unknown_variants,
+                },
+                Value::String(name),
+            ) => match variants.iter().find(|(variant, _)| variant == name) {
+                Some((_, None)) => Ok(()),
+                Some((_, Some(_))) => {
+                    Err(Mismatch::new(format!("variant '{}' needs content", name)))
+                },
+                None => unknown_variant(name, *unknown_variants),
+            },
+            (Schema::Enum { variants, .. }, Value::Map(entries)) => {
+                let [(Value::String(name), content)] = &entries[..] else {
+                    return Err(Mismatch::new(
+                        "expected a map with a variant name as single key",
+                    ));
+                };
+                match variants.iter().find(|(variant, _)| variant == name) {
+                    Some((_, Some(schema))) => schema
+                        .check(content)
+                        .map_err(|mismatch| mismatch.at(format!(".{}", name))),
+                    Some((_, None)) => Err(Mismatch::new(format!(
+                        "variant '{}' must not have content",
+                        name
+                    ))),
+                    None => Err(Mismatch::new(format!("unknown variant '{}'", name))),
+                }
+            },
+            (
+                Schema::Tagged {
+                    tag,
+                    variants,
+                    unknown_variants,
+                },
+                Value::Map(entries),
+            ) => {
+                let name = match entries.iter().find(|(key, _)| key.is_str(tag)) {
+                    Some((_, Value::String(name))) => name,
+                    Some(_) => {
+                        return Err(Mismatch::new("expected a string").at(format!(".{}", tag)))
+                    },
+                    None => return Err(Mismatch::new(format!("missing field '{}'", tag))),
+                };
+                match variants.iter().find(|(variant, _)| variant == name) {
+                    Some((
+                        _,
+                        Schema::Object {
+                            fields,
+                            unknown_fields,
+                        },
+                    )) => check_fields(fields, *unknown_fields, entries, Some(tag)),
+                    Some((_, schema)) => {
+                        unreachable!("Tagged variants must be objects: {:?}", schema)
+                    },
+                    None => unknown_variant(name, *unknown_variants),
+                }
+            },
+            (schema, value) => Err(Mismatch::new(format!(
+                "expected {}, found {}",
+                schema.describe(),
+                value.describe()
+            ))),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Schema::Bool => "a boolean",
+            Schema::Integer { .. } => "an integer",
+            Schema::Float => "a number",
+            Schema::String { .. } => "a string",
+            Schema::Array { .. } => "a list",
+            Schema::Map { .. } | Schema::Object { .. } | Schema::Tagged { .. } => "a map",
+            Schema::Nullable(schema) => schema.describe(),
+            Schema::Enum { .. } => "a variant",
+        }
+    }
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+fn check_fields(
+    fields: &[Field],
+    unknown_fields: UnknownFields,
+    entries: &[(Value, Value)],
+    tag: Option<&str>,
+) -> Result<(), Mismatch> {
+    let mut seen = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        let Value::String(name) = key else {
+            return Err(Mismatch::new(format!(
+                "expected field names, found {}",
+                key.describe()
+            )));
+        };
+        if seen.contains(&name) {
+            return Err(Mismatch::new(format!("duplicate field '{}'", name)));
+        }
+        seen.push(name);
+        if Some(name.as_str()) == tag {
+            continue;
+        }
+        match fields.iter().find(|field| field.name == name) {
+            Some(field) => field
+                .schema
+                .check(value)
+                .map_err(|mismatch| mismatch.at(format!(".{}", name)))?,
+            None => match unknown_fields {
+                UnknownFields::Reject => {
+                    return Err(Mismatch::new(format!("unknown field '{}'", name)))
+                },
+                UnknownFields::Ignore => {},
+            },
+        }
+    }
+    match fields
+        .iter()
+        .find(|field| field.required && !seen.iter().any(|name| *name == field.name))
+    {
+        Some(field) => Err(Mismatch::new(format!("missing field '{}'", field.name))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+fn unknown_variant(name: &str, unknown_variants: UnknownVariants) -> Result<(), Mismatch> {
+    match unknown_variants {
+        UnknownVariants::Reject => Err(Mismatch::new(format!("unknown variant '{}'", name))),
+        UnknownVariants::Ignore => Ok(()),
+    }
+}
+
+/** A [`SchemaViolation`] on its way up, collecting the path in reverse */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+struct Mismatch {
+    path: Vec<String>,
+    reason: String,
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl Mismatch {
+    fn new(reason: impl Into<String>) -> Self {
+        Self {
+            path: Vec::new(),
+            reason: reason.into(),
+        }
+    }
+
+    fn at(mut self, segment: String) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    fn into_violation(self, message: &[u8]) -> SchemaViolation {
+        SchemaViolation {
+            path: std::iter::once("$".to_owned())
+                .chain(self.path.into_iter().rev())
+                .collect(),
+            reason: self.reason,
+            message: message.to_vec(),
+        }
+    }
+}
+
+/**
+ * A message of any self-describing format, without losing information like map keys that are not strings
+ *
+ * Only keeps what the schemas can check.
+ */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+#[derive(Debug)]
+enum Value {
+    Null,
+    Bool,
+    Integer(i128),
+    Float,
+    String(String),
+    Bytes,
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl Value {
+    fn is_str(&self, string: &str) -> bool {
+        matches!(self, Value::String(value) if value == string)
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool => "a boolean",
+            Value::Integer(_) => "an integer",
+            Value::Float => "a float",
+            Value::String(_) => "a string",
+            Value::Bytes => "bytes",
+            Value::Array(_) => "a list",
+            Value::Map(_) => "a map",
+        }
+    }
+}
+
+/* For map keys in paths */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(string) => write!(f, "{:?}", string),
+            Value::Integer(integer) => write!(f, "{}", integer),
+            other => f.write_str(other.describe()),
+        }
+    }
+}
+
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+impl<'de> serde::Deserialize<'de> for Value {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("any value")
+            }
+
+            fn visit_bool<E>(self, _value: bool) -> Result<Value, E> {
+                Ok(Value::Bool)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+                Ok(Value::Integer(value.into()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+                Ok(Value::Integer(value.into()))
+            }
+
+            fn visit_f64<E>(self, _value: f64) -> Result<Value, E> {
+                Ok(Value::Float)
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+                Ok(Value::String(value.to_owned()))
+            }
+
+            fn visit_string<E>(self, value: String) -> Result<Value, E> {
+                Ok(Value::String(value))
+            }
+
+            fn visit_bytes<E>(self, _value: &[u8]) -> Result<Value, E> {
+                Ok(Value::Bytes)
+            }
+
+            fn visit_unit<E>(self) -> Result<Value, E> {
+                Ok(Value::Null)
+            }
+
+            fn visit_none<E>(self) -> Result<Value, E> {
+                Ok(Value::Null)
+            }
+
+            fn visit_some<D>(self, de: D) -> Result<Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                serde::Deserialize::deserialize(de)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(value) = seq.next_element()? {
+                    values.push(value);
+                }
+                Ok(Value::Array(values))
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0).min(4096));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Value::Map(entries))
+            }
+        }
+
+        de.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(all(test, any(feature = "transfer", feature = "forwarding")))]
+mod test {
+    use super::*;
+
+    static POINT: Schema = Schema::Object {
+        fields: &[
+            Field::required("x", &U16),
+            Field::optional("label", &NULLABLE_STRING),
+        ],
+        unknown_fields: UnknownFields::Reject,
+    };
+    static SHAPE: Schema = Schema::Enum {
+        variants: &[
+            ("point", Some(&POINT)),
+            (
+                "line",
+                Some(&Schema::Array {
+                    items: &POINT,
+                    min_len: 2,
+                    max_len: 2,
+                }),
+            ),
+            ("nothing", None),
+        ],
+        unknown_variants: UnknownVariants::Reject,
+    };
+
+    fn violation(message: &str) -> String {
+        SHAPE
+            .validate_json(message.as_bytes())
+            .map(|()| String::new())
+            .unwrap_or_else(|violation| violation.to_string())
+    }
+
+    #[test]
+    fn test_valid() {
+        for message in [
+            r#""nothing""#,
+            r#"{"point": {"x": 1}}"#,
+            r#"{"point": {"x": 1, "label": null}}"#,
+            r#"{"line": [{"x": 1}, {"x": 65535, "label": "end"}]}"#,
+        ] {
+            assert_eq!(violation(message), "", "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            violation(r#"{"point": {"x": 70000}}"#),
+            "Malformed message from peer at $.point.x: 70000 is out of range 0..=65535"
+        );
+        assert_eq!(
+            violation(r#"{"point": {"x": 1, "y": 2}}"#),
+            "Malformed message from peer at $.point: unknown field 'y'"
+        );
+        assert_eq!(
+            violation(r#"{"point": {"label": "a"}}"#),
+            "Malformed message from peer at $.point: missing field 'x'"
+        );
+        assert_eq!(
+            violation(r#"{"line": [{"x": 1}, {"x": "2"}]}"#),
+            "Malformed message from peer at $.line[1].x: expected an integer, found a string"
+        );
+        assert_eq!(
+            violation(r#""circle""#),
+            "Malformed message from peer at $: unknown variant 'circle'"
+        );
+        assert_eq!(
+            violation(r#"{"point": {"x": 1, "x": 2}}"#),
+            "Malformed message from peer at $.point: duplicate field 'x'"
+        );
+    }
+
+    #[test]
+    fn test_verbatim() {
+        let message = br#"{"point": {"x": -1}}"#;
+        let violation = SHAPE.validate_json(message).unwrap_err();
+        assert_eq!(violation.message, message);
+    }
+}
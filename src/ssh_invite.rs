@@ -0,0 +1,361 @@
+//! Client-to-Client protocol to hand over an SSH public key
+//!
+//! This is what `wormhole ssh invite` and `wormhole ssh accept` of the Python implementation do, and it is
+//! compatible with them. The side that wants to grant access *invites*: it allocates a code and waits with
+//! [`receive_key`]. The other side *accepts* the invitation by entering that code and sending one of its
+//! public keys with [`send_key`]. The inviting side then appends the key to its `authorized_keys` with
+//! [`install_key`].
+//!
+//! It is bound to an [`APPID`](APPID), which is distinct to the one used for file transfer. Therefore, the
+//! codes used for this are in an independent namespace than those for sending files.
+//!
+//! **Security warning:** whoever sends a key this way can log in afterwards. Only the received key is
+//! installed, with any `authorized_keys` options stripped, but check it before doing so nevertheless.
+
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+
+const APPID_RAW: &str = "lothar.com/wormhole/ssh";
+
+/// The App ID associated with this protocol.
+pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
+
+/// An [`crate::AppConfig`] with sane defaults for this protocol.
+///
+/// You **must not** change `id` and `rendezvous_url` to be interoperable.
+pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersion> {
+    id: AppID(Cow::Borrowed(APPID_RAW)),
+    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    app_version: AppVersion {},
+    client_version: crate::ClientVersion::DEFAULT,
+    reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+    timeouts: crate::WormholeTimeouts::DEFAULT,
+};
+
+/**
+ * The application specific version information for this protocol.
+ *
+ * There is nothing to negotiate (yet), this is always empty.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct AppVersion {}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum SshInviteError {
+    #[error("Something went wrong on the other side: {}", _0)]
+    PeerError(String),
+    /// Some deserialization went wrong, we probably got some garbage
+    #[error("Corrupt JSON message received")]
+    ProtocolJson(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    /// A generic string message for "something went wrong", i.e.
+    /// the peer sent some bullshit message order
+    #[error("Protocol error: {}", _0)]
+    Protocol(Box<str>),
+    /// What we were given or got sent is not an SSH public key
+    #[error("Invalid SSH public key: {}", _0)]
+    InvalidKey(Box<str>),
+    #[error("Wormhole connection error")]
+    Wormhole(
+        #[from]
+        #[source]
+        WormholeError,
+    ),
+    #[error("IO error")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+}
+
+impl SshInviteError {
+    fn invalid_key(reason: impl Into<Box<str>>) -> Self {
+        Self::InvalidKey(reason.into())
+    }
+}
+
+/* The messages of Python's `xfer_util`, which only carry a text message */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PeerMessage {
+    Offer { message: String },
+    Answer { message_ack: String },
+    Error(String),
+}
+
+/**
+ * An OpenSSH public key, as found in a `.pub` file
+ *
+ * Parsing checks that the key data matches its type, and rejects anything more than a single key
+ * (like `authorized_keys` options or further lines). [`Display`](std::fmt::Display) gives the line to
+ * put into `authorized_keys`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicKey {
+    kind: String,
+    data: String,
+    comment: Option<String>,
+}
+
+impl PublicKey {
+    /** The key type, like `ssh-ed25519` */
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /** The comment at the end, usually telling whose key it is */
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /** Whether both are the same key, regardless of the comment */
+    pub fn same_key(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.data == other.data
+    }
+}
+
+impl std::str::FromStr for PublicKey {
+    type Err = SshInviteError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        use base64::Engine;
+
+        let line = line.trim();
+        ensure!(
+            !line.contains(['\n', '\r', '\0']),
+            SshInviteError::invalid_key("more than one line")
+        );
+        let mut parts = line.split_whitespace();
+        let (Some(kind), Some(data)) = (parts.next(), parts.next()) else {
+            bail!(SshInviteError::invalid_key(
+                "expected the key type and data"
+            ));
+        };
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|_| SshInviteError::invalid_key("the key data is not base64"))?;
+        /* The key data starts with its type again, as a length prefixed string */
+        let blob_kind = blob
+            .get(..4)
+            .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+            .and_then(|len| blob.get(4..4 + len));
+        ensure!(
+            blob_kind == Some(kind.as_bytes()),
+            SshInviteError::invalid_key(format!("the key data does not match type '{}'", kind))
+        );
+        let comment = parts.collect::<Vec<_>>().join(" ");
+
+        Ok(Self {
+            kind: kind.into(),
+            data: data.into(),
+            comment: (!comment.is_empty()).then_some(comment),
+        })
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.kind, self.data)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The public key files in `ssh_dir`, usually `~/.ssh`
+ *
+ * These are the `*.pub` files, sorted by name. When there are several, let the user choose. Parse the
+ * contents of the file as [`PublicKey`].
+ */
+pub fn find_public_keys(ssh_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut keys = std::fs::read_dir(ssh_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|extension| extension == "pub") && path.is_file()
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    keys.sort();
+    Ok(keys)
+}
+
+/**
+ * Append `key` to the `authorized_keys` in `ssh_dir`, usually `~/.ssh`
+ *
+ * Both get created if missing, with permissions only for the owner like OpenSSH wants them. A key that is
+ * already authorized does not get added again. Returns the path of the `authorized_keys` file.
+ */
+pub fn install_key(ssh_dir: &Path, key: &PublicKey) -> std::io::Result<PathBuf> {
+    use std::io::Write;
+
+    let mut dir = std::fs::DirBuilder::new();
+    dir.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+    dir.create(ssh_dir)?;
+
+    let path = ssh_dir.join("authorized_keys");
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let authorized = existing
+        .lines()
+        .filter_map(|line| line.parse::<PublicKey>().ok())
+        .any(|other| other.same_key(key));
+    if authorized {
+        log::debug!("Key is already in {}", path.display());
+        return Ok(path);
+    }
+
+    let mut file = std::fs::OpenOptions::new();
+    file.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    let mut file = file.open(&path)?;
+    /* Don't glue our key to the end of a last line without a line break */
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", key)?;
+    Ok(path)
+}
+
+/**
+ * Accept an invitation: send our public key to the inviting side
+ *
+ * The wormhole must be connected with the code the inviting side has shown, using [`APP_CONFIG`]. It gets
+ * closed afterwards.
+ */
+pub async fn send_key(mut wormhole: Wormhole, key: &PublicKey) -> Result<(), SshInviteError> {
+    wormhole
+        .send_json(&PeerMessage::Offer {
+            message: key.to_string(),
+        })
+        .await?;
+    match wormhole.receive_json::<PeerMessage>().await?? {
+        PeerMessage::Answer { message_ack } if message_ack == "ok" => (),
+        PeerMessage::Error(err) => bail!(SshInviteError::PeerError(err)),
+        other => bail!(SshInviteError::Protocol(
+            format!("Unexpected answer: {:?}", other).into()
+        )),
+    }
+    wormhole.close().await?;
+    Ok(())
+}
+
+/**
+ * Invite someone: wait for their public key
+ *
+ * The wormhole must be connected with [`APP_CONFIG`], and its code shown to the other side. It gets closed
+ * afterwards. Pass the key to [`install_key`] to grant access.
+ */
+pub async fn receive_key(mut wormhole: Wormhole) -> Result<PublicKey, SshInviteError> {
+    let key = match wormhole.receive_json::<PeerMessage>().await?? {
+        PeerMessage::Offer { message } => message.parse::<PublicKey>(),
+        PeerMessage::Error(err) => bail!(SshInviteError::PeerError(err)),
+        other => Err(SshInviteError::Protocol(
+            format!("Unexpected offer: {:?}", other).into(),
+        )),
+    };
+    match key {
+        Ok(key) => {
+            wormhole
+                .send_json(&PeerMessage::Answer {
+                    message_ack: "ok".into(),
+                })
+                .await?;
+            wormhole.close().await?;
+            Ok(key)
+        },
+        Err(err) => {
+            let _ = wormhole
+                .send_json(&PeerMessage::Error(err.to_string()))
+                .await;
+            let _ = wormhole.close().await;
+            Err(err)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHe3hsSnEgvnK/0lc0JHRAU6RKIdTCoU/Yklk/IsgNKq alice@example";
+
+    #[test]
+    fn test_public_key() {
+        let key: PublicKey = format!("{}\n", KEY).parse().unwrap();
+        assert_eq!(key.kind(), "ssh-ed25519");
+        assert_eq!(key.comment(), Some("alice@example"));
+        assert_eq!(key.to_string(), KEY);
+
+        /* Smuggling in options or a second key */
+        assert!(format!("command=\"true\" {}", KEY)
+            .parse::<PublicKey>()
+            .is_err());
+        assert!(format!("{}\n{}", KEY, KEY).parse::<PublicKey>().is_err());
+        assert!(KEY
+            .replacen("ssh-ed25519", "ssh-rsa", 1)
+            .parse::<PublicKey>()
+            .is_err());
+
+        /* The same as Python's `xfer_util` */
+        assert_eq!(
+            serde_json::to_value(PeerMessage::Offer {
+                message: KEY.into()
+            })
+            .unwrap(),
+            serde_json::json!({"offer": {"message": KEY}})
+        );
+        assert_eq!(
+            serde_json::to_value(PeerMessage::Answer {
+                message_ack: "ok".into()
+            })
+            .unwrap(),
+            serde_json::json!({"answer": {"message_ack": "ok"}})
+        );
+    }
+
+    #[test]
+    fn test_install_key() {
+        let home = tempfile::tempdir().unwrap();
+        let dir = home.path().join(".ssh");
+        let key: PublicKey = KEY.parse().unwrap();
+        let path = install_key(&dir, &key).unwrap();
+        install_key(&dir, &key).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", KEY)
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(dir.join("id_ed25519.pub"), KEY).unwrap();
+        assert_eq!(
+            find_public_keys(&dir).unwrap(),
+            vec![dir.join("id_ed25519.pub")]
+        );
+        let read = std::fs::read_to_string(dir.join("id_ed25519.pub")).unwrap();
+        assert_eq!(read.parse::<PublicKey>().unwrap(), key);
+    }
+}
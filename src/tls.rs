@@ -0,0 +1,114 @@
+//! The platform's trust store, for TLS connections to servers
+//!
+//! By default, `wss://` rendezvous servers and relays are only checked against the bundled web PKI roots.
+//! That breaks in environments where a proxy intercepts TLS with a certificate of a company CA, which is
+//! only known to the operating system. With the `native-certs` feature, the certificates of the platform's
+//! trust store are trusted as well.
+//!
+//! The trust store is read from the PEM bundle of the distribution, or from the files given in the
+//! `SSL_CERT_FILE` and `SSL_CERT_DIR` environment variables, like OpenSSL does. Platforms that keep their
+//! certificates elsewhere (Windows, the macOS keychain) need those variables.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/* Where distributions put their CA bundle, the same list openssl-probe uses */
+const CERTIFICATE_FILES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/pki/tls/cacert.pem",
+    "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+    "/usr/local/share/certs/ca-root-nss.crt",
+];
+
+/** A connector trusting both the bundled roots and the platform's. The trust store is read once only */
+pub(crate) fn connector() -> async_tls::TlsConnector {
+    static CONNECTOR: OnceLock<async_tls::TlsConnector> = OnceLock::new();
+    CONNECTOR
+        .get_or_init(|| {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+            let (added, ignored) = roots.add_parsable_certificates(&platform_certificates());
+            log::debug!(
+                "Trusting {} certificates of the platform ({} unusable ones ignored)",
+                added,
+                ignored
+            );
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+                .into()
+        })
+        .clone()
+}
+
+/** The DER encoded certificates of the platform's trust store */
+fn platform_certificates() -> Vec<Vec<u8>> {
+    let file = std::env::var_os("SSL_CERT_FILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            CERTIFICATE_FILES
+                .iter()
+                .map(Path::new)
+                .find(|path| path.is_file())
+                .map(Path::to_owned)
+        });
+    let mut certificates = file
+        .map(|file| read_certificates(&file))
+        .unwrap_or_default();
+
+    for dir in std::env::var_os("SSL_CERT_DIR")
+        .iter()
+        .flat_map(std::env::split_paths)
+    {
+        match std::fs::read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    if entry.path().is_file() {
+                        certificates.extend(read_certificates(&entry.path()));
+                    }
+                }
+            },
+            Err(err) => log::warn!("Cannot read certificates from {}: {}", dir.display(), err),
+        }
+    }
+    certificates
+}
+
+/** All certificates of a PEM file. Broken files are skipped with a warning, like OpenSSL does */
+fn read_certificates(path: &Path) -> Vec<Vec<u8>> {
+    std::fs::File::open(path)
+        .and_then(|file| rustls_pemfile::certs(&mut std::io::BufReader::new(file)))
+        .unwrap_or_else(|err| {
+            log::warn!("Cannot read certificates from {}: {}", path.display(), err);
+            Vec::new()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_certificates() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/example-certificate.pem");
+        let certificates = read_certificates(&path);
+        assert_eq!(certificates.len(), 1);
+        let mut roots = rustls::RootCertStore::empty();
+        assert_eq!(roots.add_parsable_certificates(&certificates), (1, 0));
+
+        /* Missing files don't break anything */
+        assert!(read_certificates(&path.with_extension("missing")).is_empty());
+    }
+}
@@ -7,6 +7,13 @@
 //!
 //! At its core, "peer messages" are exchanged over an established wormhole connection with the other side.
 //! They are used to set up a [transit] portal and to exchange a file offer/accept. Then, the file is transmitted over the transit relay.
+//!
+//! ## Protocol versions
+//!
+//! The protocol exists in multiple versions, each in its own module: [`v1`] and [`v2`]. The functions in this module,
+//! like [`send`] and [`request`], negotiate the version with the peer and dispatch to it, so they keep working when
+//! new versions get added. Only reach into the versioned modules for what is specific to one version, after matching
+//! on the [`ReceiveRequest`]. See [`ProtocolVersion`] for which one is in use.
 
 use futures::{AsyncRead, AsyncSeek, AsyncWrite};
 use serde_derive::{Deserialize, Serialize};
@@ -17,7 +24,10 @@ use std::sync::Arc;
 use super::{core::WormholeError, transit, AppID, Wormhole};
 use futures::Future;
 use log::*;
-use std::{borrow::Cow, collections::BTreeMap};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+};
 
 #[cfg(not(target_family = "wasm"))]
 use std::path::{Path, PathBuf};
@@ -26,15 +36,79 @@ use transit::{
     Abilities as TransitAbilities, Transit, TransitConnectError, TransitConnector, TransitError,
 };
 
+mod abort;
 mod cancel;
-mod v1;
-mod v2;
-
+mod capabilities;
+mod checkpoint;
+#[cfg(not(target_family = "wasm"))]
+mod compression;
+#[cfg(not(target_family = "wasm"))]
+mod durable;
+#[cfg(not(target_family = "wasm"))]
+mod extract;
+mod filename;
+mod hash;
+mod manifest;
+mod many;
+mod mime;
+#[cfg(not(target_family = "wasm"))]
+mod persist;
+#[cfg(not(target_family = "wasm"))]
+mod policy;
+#[cfg(not(target_family = "wasm"))]
+mod preallocate;
+mod preset;
+mod read_ahead;
+mod scanner;
+mod tee;
+mod text;
+pub mod v1;
+pub mod v2;
+mod withdraw;
+
+pub use abort::AbortSummary;
+pub use capabilities::PeerCapabilities;
+pub use checkpoint::{Checkpoint, CheckpointError, FileProgress};
+#[cfg(not(target_family = "wasm"))]
+pub use compression::{Codec, Compression};
+#[cfg(not(target_family = "wasm"))]
+pub use durable::SyncOnClose;
+#[cfg(not(target_family = "wasm"))]
+pub use extract::ExtractProgress;
+pub use filename::{local_filename, normalize_filename, FilenamePolicy, InvalidFilename};
+pub use hash::HashAlgorithm;
+pub use manifest::{Manifest, ManifestEntry, ManifestRecorder};
+pub use many::{send_many, SendManyEvent, SendManyLimits};
+pub use mime::sniff_mime_type;
+#[cfg(not(target_family = "wasm"))]
+pub use persist::move_into_place;
+#[cfg(not(target_family = "wasm"))]
+pub use policy::{OverwritePolicy, PolicyDecision, PolicyRejection, ReceivePolicy};
+#[cfg(not(target_family = "wasm"))]
+pub use preallocate::preallocate;
+pub use preset::Preset;
+pub use scanner::ContentScanner;
+pub use tee::Tee;
+pub use text::{receive_text, send_text};
+/** Same as [`v1::ReceiveRequest`] */
 pub use v1::ReceiveRequest as ReceiveRequestV1;
+/** Same as [`v2::ReceiveRequest`] */
 pub use v2::ReceiveRequest as ReceiveRequestV2;
+pub use withdraw::WithdrawReason;
 
 const APPID_RAW: &str = "lothar.com/wormhole/text-or-file-xfer";
 
+/* Advertised in the `abilities`, see [`AppVersion::supports_written_ack`] */
+const WRITTEN_ACK_ABILITY: &str = "written-ack-v1";
+/* Advertised in the `abilities`, see [`AbortSummary`] */
+const ABORT_SUMMARY_ABILITY: &str = "abort-summary-v1";
+/* Advertised in the `abilities`, see [`WithdrawReason`] */
+const OFFER_WITHDRAWAL_ABILITY: &str = "offer-withdrawal-v1";
+/* Advertised in the `abilities`, see [`ReceiveRequest::keep_alive_while`] */
+const KEEP_ALIVE_ABILITY: &str = "keep-alive-v1";
+/* Advertised in the `abilities`: the v2 offer may come in several `offer-manifest` messages */
+const OFFER_MANIFEST_ABILITY: &str = "offer-manifest-v1";
+
 /// The App ID associated with this protocol.
 pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
 
@@ -46,6 +120,9 @@ pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersi
     id: AppID(Cow::Borrowed(APPID_RAW)),
     rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
     app_version: AppVersion::new(),
+    client_version: crate::ClientVersion::DEFAULT,
+    reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+    timeouts: crate::WormholeTimeouts::DEFAULT,
 };
 
 // TODO be more extensible on the JSON enum types (i.e. recognize unknown variants)
@@ -66,6 +143,12 @@ pub enum TransferError {
     UnsupportedOffer,
     #[error("Something went wrong on the other side: {}", _0)]
     PeerError(String),
+    #[error("Received an invalid file name")]
+    Filename(
+        #[from]
+        #[source]
+        InvalidFilename,
+    ),
 
     /// Some deserialization went wrong, we probably got some garbage
     #[error("Corrupt JSON message received")]
@@ -97,23 +180,65 @@ pub enum TransferError {
         WormholeError,
     ),
     #[error("Error while establishing transit connection")]
-    TransitConnect(
-        #[from]
-        #[source]
-        TransitConnectError,
-    ),
+    TransitConnect(#[source] TransitConnectError),
     #[error("Transit error")]
-    Transit(
-        #[from]
-        #[source]
-        TransitError,
-    ),
+    Transit(#[source] TransitError),
     #[error("IO error")]
     IO(
         #[from]
         #[source]
         std::io::Error,
     ),
+    /// The peer did not answer the offer within [`Timeouts::offer`](transit::Timeouts::offer)
+    #[error("The peer did not answer the offer in time")]
+    OfferTimeout,
+    /// The transit connection was not established within [`Timeouts::transit`](transit::Timeouts::transit)
+    #[error("Timed out while establishing the transit connection")]
+    TransitHandshakeTimeout,
+    /// The peer went silent for longer than [`Timeouts::idle_peer`](transit::Timeouts::idle_peer)
+    #[error("The peer did not send anything for too long")]
+    IdlePeerTimeout,
+    /// A [`ContentScanner`] of the receiving side rejected the content
+    #[error("The received content was rejected: {}", _0)]
+    ContentRejected(String),
+    /// The peer received everything, but failed to write it, see [`AppVersion::supports_written_ack`]
+    #[error("The peer failed to write the received content: {}", _0)]
+    NotWritten(String),
+    /// The peer aborted the transfer, and told how far it got
+    #[error("The other side aborted the transfer: {}", reason)]
+    PeerAborted {
+        reason: String,
+        summary: AbortSummary,
+    },
+    /// The sender took back its offer before it got accepted
+    #[error("The other side withdrew the offer ({})", _0)]
+    OfferWithdrawn(WithdrawReason),
+    /// What was going on when the error in `source` happened, like which file was being sent.
+    /// See [`root`](Self::root) for the original error.
+    #[error("{}", context)]
+    Context {
+        context: Box<str>,
+        #[source]
+        source: Box<TransferError>,
+    },
+}
+
+impl From<TransitConnectError> for TransferError {
+    fn from(error: TransitConnectError) -> Self {
+        match error {
+            TransitConnectError::Timeout => Self::TransitHandshakeTimeout,
+            error => Self::TransitConnect(error),
+        }
+    }
+}
+
+impl From<TransitError> for TransferError {
+    fn from(error: TransitError) -> Self {
+        match error {
+            TransitError::IdleTimeout => Self::IdlePeerTimeout,
+            error => Self::Transit(error),
+        }
+    }
 }
 
 impl TransferError {
@@ -123,6 +248,30 @@ impl TransferError {
     ) -> Self {
         Self::ProtocolUnexpectedMessage(expected.into(), got.to_string().into())
     }
+
+    /** Add what was going on when this error happened, see [`TransferError::Context`] */
+    pub(crate) fn context(self, context: impl Into<Box<str>>) -> Self {
+        Self::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /** The original error, without the [`Context`](Self::Context) that got added to it */
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /* The peer stopped the transfer, so there is no point in telling it about the error */
+    fn is_from_peer(&self) -> bool {
+        matches!(
+            self.root(),
+            Self::PeerError(_) | Self::PeerAborted { .. } | Self::OfferWithdrawn(_)
+        )
+    }
 }
 
 /**
@@ -135,6 +284,12 @@ pub struct AppVersion {
     abilities: Cow<'static, [Cow<'static, str>]>,
     #[serde(default)]
     transfer_v2: Option<AppVersionTransferV2Hint>,
+    /* Names of the supported [`HashAlgorithm`]s */
+    #[serde(default)]
+    hash_algorithms: Cow<'static, [Cow<'static, str>]>,
+    /* Compressed formats that v1 offers get decompressed from, see `with_codec` */
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    decompress_formats: Cow<'static, [Cow<'static, str>]>,
 }
 
 // TODO check invariants during deserialization
@@ -144,15 +299,113 @@ impl AppVersion {
             // Dont advertize v2 for now
             abilities: Cow::Borrowed(&[
                 Cow::Borrowed("transfer-v1"), /* Cow::Borrowed("transfer-v2") */
+                Cow::Borrowed(WRITTEN_ACK_ABILITY),
+                Cow::Borrowed(ABORT_SUMMARY_ABILITY),
+                Cow::Borrowed(OFFER_WITHDRAWAL_ABILITY),
+                Cow::Borrowed(KEEP_ALIVE_ABILITY),
+                Cow::Borrowed(OFFER_MANIFEST_ABILITY),
             ]),
             transfer_v2: Some(AppVersionTransferV2Hint::new()),
+            hash_algorithms: Cow::Borrowed(&[
+                Cow::Borrowed("blake3"),
+                Cow::Borrowed("blake2b"),
+                Cow::Borrowed("sha256"),
+            ]),
+            decompress_formats: Cow::Borrowed(&[]),
+        }
+    }
+
+    /** Only advertise the given hash algorithms. SHA-256 is used as fallback even if it is missing here. */
+    pub fn with_hash_algorithms(mut self, algorithms: &[HashAlgorithm]) -> Self {
+        self.hash_algorithms = algorithms
+            .iter()
+            .map(|algorithm| Cow::Borrowed(algorithm.name()))
+            .collect();
+        self
+    }
+
+    /**
+     * Advertise that we can receive files compressed with `codec`, as a single file and as tar archive
+     *
+     * Only do so if such offers get received with [`accept_decompress`](ReceiveRequestV1::accept_decompress)
+     * or [`accept_extract`](ReceiveRequestV1::accept_extract), which only exist in the v1 protocol. For the
+     * built-in Zstandard, pass [`Compression::Zstd.codec()`](Compression::codec). See
+     * [`AppVersion::supports_format`] for the peer's side.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn with_codec(mut self, codec: &dyn Codec) -> Self {
+        let formats = self.decompress_formats.to_mut();
+        for format in [codec.name().to_owned(), format!("tar.{}", codec.name())] {
+            if !formats.iter().any(|supported| *supported == format) {
+                formats.push(Cow::Owned(format));
+            }
         }
+        self
+    }
+
+    /**
+     * The hash algorithm to use with a peer
+     *
+     * This is the fastest one that both sides support, so that both come to the same result.
+     */
+    pub fn hash_algorithm(&self, peer: &AppVersion) -> HashAlgorithm {
+        let supports = |version: &AppVersion, algorithm: &HashAlgorithm| {
+            version
+                .hash_algorithms
+                .iter()
+                .any(|name| name == algorithm.name())
+        };
+        HashAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| supports(self, algorithm) && supports(peer, algorithm))
+            .unwrap_or_default()
+    }
+
+    /**
+     * Whether the peer confirms that received data has been written, not just received
+     *
+     * If both sides support this, the receiver acknowledges a transfer in two stages: once all bytes
+     * arrived, and again after its content handler got closed. Sending only completes after the second
+     * one, so that deleting the source right away does not race the receiver's disk. Content handlers
+     * that should be durable need to sync on close, like [`SyncOnClose`] does.
+     */
+    pub fn supports_written_ack(&self) -> bool {
+        self.abilities.contains(&WRITTEN_ACK_ABILITY.into())
     }
 
     #[allow(dead_code)]
     fn supports_v2(&self) -> bool {
         self.abilities.contains(&"transfer-v2".into())
     }
+
+    /** The protocol version to talk to this peer */
+    fn protocol_version(&self) -> ProtocolVersion {
+        if self.supports_v2() {
+            ProtocolVersion::V2
+        } else {
+            ProtocolVersion::V1
+        }
+    }
+
+    /**
+     * Whether the peer can receive files in the given format, like `"tar.zst"`
+     *
+     * Parse the [`peer_version`](crate::Wormhole::peer_version) into an `AppVersion` to find out
+     * before sending something compressed. Compressed formats only apply to the v1 protocol.
+     */
+    pub fn supports_format(&self, format: &str) -> bool {
+        self.formats().any(|supported| supported == format)
+    }
+
+    /* The formats of the v2 hint, and the compressed ones on top */
+    fn formats(&self) -> impl Iterator<Item = &str> {
+        self.transfer_v2
+            .iter()
+            .flat_map(|hint| hint.supported_formats.iter())
+            .chain(self.decompress_formats.iter())
+            .map(|format| &**format)
+    }
 }
 
 impl Default for AppVersion {
@@ -161,6 +414,22 @@ impl Default for AppVersion {
     }
 }
 
+/**
+ * A version of the file transfer protocol, see the [module documentation](self#protocol-versions)
+ *
+ * More versions may be added in the future.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, derive_more::Display)]
+#[non_exhaustive]
+pub enum ProtocolVersion {
+    /** The [`v1`] protocol, as spoken by the original Python implementation */
+    #[display(fmt = "v1")]
+    V1,
+    /** The [`v2`] protocol */
+    #[display(fmt = "v2")]
+    V2,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppVersionTransferV2Hint {
@@ -212,21 +481,50 @@ pub enum PeerMessage {
     /** Tell the other side you got an error */
     #[display(fmt = "error")]
     Error(String),
+    /** Like `Error`, but with an [`AbortSummary`]. Only sent to peers that support it */
+    #[display(fmt = "abort")]
+    Abort(abort::AbortMessage),
+    /** Instead of `Error`, if the offer is not answered yet. Only sent to peers that support it */
+    #[display(fmt = "offer-withdrawn")]
+    OfferWithdrawn(WithdrawReason),
     #[display(fmt = "unknown")]
     #[serde(other)]
     Unknown,
 }
 
+static PEER_MESSAGE_SCHEMA: crate::schema::Schema = crate::schema::Schema::Enum {
+    variants: &[
+        ("transit", Some(&v1::TRANSIT_SCHEMA)),
+        ("offer", Some(&v1::OFFER_SCHEMA)),
+        ("answer", Some(&v1::ANSWER_SCHEMA)),
+        ("transit-v2", Some(&v2::TRANSIT_SCHEMA)),
+        ("error", Some(&crate::schema::STRING)),
+        ("abort", Some(&abort::ABORT_SCHEMA)),
+        ("offer-withdrawn", Some(&withdraw::WITHDRAW_REASON_SCHEMA)),
+    ],
+    unknown_variants: crate::schema::UnknownVariants::Reject,
+};
+
 impl PeerMessage {
-    #[allow(unused)]
     fn offer_message_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Offer(v1::OfferMessage::Message(msg.into()))
     }
 
-    fn offer_file_v1(name: impl Into<String>, size: u64) -> Self {
+    fn offer_file_v1(
+        name: impl Into<String>,
+        size: u64,
+        mime_type: Option<String>,
+        metadata: Option<crate::OfferMetadata>,
+        valid_for: Option<u64>,
+        directory: bool,
+    ) -> Self {
         PeerMessage::Offer(v1::OfferMessage::File {
             filename: name.into(),
             filesize: size,
+            mime_type,
+            metadata,
+            valid_for,
+            directory,
         })
     }
 
@@ -247,7 +545,6 @@ impl PeerMessage {
         })
     }
 
-    #[allow(dead_code)]
     fn message_ack_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Answer(v1::AnswerMessage::MessageAck(msg.into()))
     }
@@ -274,6 +571,8 @@ impl PeerMessage {
     fn check_err(&self) -> Result<Self, TransferError> {
         match self {
             Self::Error(err) => Err(TransferError::PeerError(err.clone())),
+            Self::Abort(abort) => Err(abort.clone().into()),
+            Self::OfferWithdrawn(reason) => Err(TransferError::OfferWithdrawn(*reason)),
             other => Ok(other.clone()),
         }
     }
@@ -290,6 +589,31 @@ pub type OfferSend = Offer<OfferContent>;
 #[serde(bound(deserialize = "T: Default"))]
 pub struct Offer<T = ()> {
     content: BTreeMap<String, OfferEntry<T>>,
+    /* By file path, joined with slashes. See [`Offer::mime_type`] */
+    #[serde(
+        default,
+        rename = "mime-types",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    mime_types: BTreeMap<String, String>,
+    /* See [`Offer::metadata`] */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<crate::OfferMetadata>,
+}
+
+/// Progress events while scanning the file system for an offer, see [`OfferSend::scan`]
+#[cfg(not(target_family = "wasm"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ScanProgress {
+    /// Entered a directory
+    Directory { path: PathBuf },
+    /// Found a file. `files` and `bytes` are the totals so far, including this file.
+    File {
+        path: PathBuf,
+        files: u64,
+        bytes: u64,
+    },
 }
 
 impl OfferSend {
@@ -304,17 +628,15 @@ impl OfferSend {
             "OfferSend::new_file_or_folder: {offer_name}, {}",
             path.display()
         );
-        let mut content = BTreeMap::new();
-        content.insert(offer_name, OfferSendEntry::new(path).await?);
-        Ok(Self { content })
+        Self::walk([(offer_name, path.to_owned())], &mut |_, _| {}).await
     }
 
     /// Offer list of paths (files and folders)
     /// Panics if any of the paths does not have a name (like `/`).
-    /// Panics if any two or more of the paths have the same name.
+    /// Fails if any two or more of the paths have the same name.
     #[cfg(not(target_family = "wasm"))]
     pub async fn new_paths(paths: impl IntoIterator<Item = PathBuf>) -> std::io::Result<Self> {
-        let mut content = BTreeMap::new();
+        let mut entries = Vec::new();
         for path in paths {
             let offer_name = path.file_name().expect("Path must have a name");
             let offer_name = offer_name
@@ -329,10 +651,68 @@ impl OfferSend {
                     )
                 })?
                 .to_owned();
-            let old = content.insert(offer_name, OfferSendEntry::new(path).await?);
-            assert!(old.is_none(), "Duplicate names found");
+            entries.push((offer_name, path));
+        }
+        Self::walk(entries, &mut |_, _| {}).await
+    }
+
+    /**
+     * Offer a list of paths under the given names, reporting progress while walking the directories
+     *
+     * Large directory trees can take a while to scan. The progress events tell how many files
+     * and bytes were found so far, which allows the user to abort before accidentally sending something
+     * enormous. The `cancel` future stops the scan, in which case `Ok(None)` is returned.
+     *
+     * Fails if any two or more of the entries have the same name, after [normalization](normalize_filename).
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn scan(
+        entries: impl IntoIterator<Item = (String, PathBuf)>,
+        mut progress_handler: impl FnMut(ScanProgress) + Send,
+        cancel: impl Future<Output = ()>,
+    ) -> std::io::Result<Option<Self>> {
+        let entries: Vec<_> = entries.into_iter().collect();
+        let (mut files, mut bytes) = (0, 0);
+        let mut on_entry = |path: &Path, size: Option<u64>| {
+            let path = path.to_owned();
+            progress_handler(match size {
+                Some(size) => {
+                    files += 1;
+                    bytes += size;
+                    ScanProgress::File { path, files, bytes }
+                },
+                None => ScanProgress::Directory { path },
+            })
+        };
+        let run = Box::pin(Self::walk(entries, &mut on_entry));
+        match cancel::cancellable(run, cancel).await {
+            Ok(result) => result.map(Some),
+            Err(cancel::Cancelled) => Ok(None),
+        }
+    }
+
+    /* Walk the entries, calling `on_entry` with each directory and (file, size) found */
+    #[cfg(not(target_family = "wasm"))]
+    async fn walk(
+        entries: impl IntoIterator<Item = (String, PathBuf)>,
+        on_entry: &mut (dyn FnMut(&Path, Option<u64>) + Send),
+    ) -> std::io::Result<Self> {
+        let mut content = BTreeMap::new();
+        let mut paths = BTreeMap::<_, PathBuf>::new();
+        for (offer_name, path) in entries {
+            let name = normalize_filename(&offer_name);
+            if let Some(other) = paths.get(&name) {
+                return Err(name_collision(other.display(), path.display()));
+            }
+            let entry = OfferSendEntry::new(&path, on_entry).await?;
+            content.insert(name.clone(), entry);
+            paths.insert(name, path);
         }
-        Ok(Self { content })
+        Ok(Self {
+            content,
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        })
     }
 
     /// Offer a single file with custom content
@@ -341,8 +721,73 @@ impl OfferSend {
     /// as advertized in file_size.
     pub fn new_file_custom(offer_name: String, size: u64, content: OfferContent) -> Self {
         let mut content_ = BTreeMap::new();
-        content_.insert(offer_name, OfferSendEntry::RegularFile { size, content });
-        Self { content: content_ }
+        content_.insert(
+            normalize_filename(&offer_name),
+            OfferSendEntry::RegularFile { size, content },
+        );
+        Self {
+            content: content_,
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        }
+    }
+
+    /**
+     * Offer several independent files or directories under the given names
+     *
+     * With the v2 protocol, the receiver sees each of them and may accept only some, see
+     * [`Offer::retain_files`]. Fails if two of them have the same name, after [normalization](normalize_filename).
+     */
+    pub fn from_entries(
+        entries: impl IntoIterator<Item = (String, OfferSendEntry)>,
+    ) -> std::io::Result<Self> {
+        let mut content = BTreeMap::new();
+        let mut offer_names = BTreeMap::new();
+        for (offer_name, entry) in entries {
+            let name = normalize_filename(&offer_name);
+            if let Some(other) = offer_names.get(&name) {
+                return Err(name_collision(other, offer_name));
+            }
+            content.insert(name.clone(), entry);
+            offer_names.insert(name, offer_name);
+        }
+        Ok(Self {
+            content,
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        })
+    }
+
+    /**
+     * Guess the MIME types of all files without one from their first bytes, see [`sniff_mime_type`]
+     *
+     * This opens every file of the offer, which may take a while for large directories.
+     */
+    pub async fn sniff_mime_types(&mut self) -> std::io::Result<()> {
+        use futures::AsyncReadExt;
+
+        let mut sniffed = Vec::new();
+        for (path, content, _size) in self.iter_files() {
+            if self.mime_type(&path).is_some() {
+                continue;
+            }
+            let mut reader = content().await?;
+            let mut buffer = vec![0; mime::SNIFF_LEN];
+            let mut len = 0;
+            while len < buffer.len() {
+                match reader.read(&mut buffer[len..]).await? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if let Some(mime_type) = sniff_mime_type(&buffer[..len]) {
+                sniffed.push((path, mime_type));
+            }
+        }
+        for (path, mime_type) in sniffed {
+            self.set_mime_type(&path, mime_type);
+        }
+        Ok(())
     }
 }
 
@@ -388,32 +833,107 @@ impl<T> Offer<T> {
         self.iter_files().map(|v| v.2).sum()
     }
 
+    /**
+     * The MIME type of a file, if the sender provided one
+     *
+     * **Security warning:** on the receiving side, this is untrusted input. It may not match the actual content.
+     */
+    pub fn mime_type(&self, path: &[String]) -> Option<&str> {
+        self.mime_types.get(&path.join("/")).map(String::as_str)
+    }
+
+    /** Tell the receiver the MIME type of a file, see [`OfferSend::sniff_mime_types`] for guessing it */
+    pub fn set_mime_type(&mut self, path: &[String], mime_type: impl Into<String>) {
+        self.mime_types.insert(path.join("/"), mime_type.into());
+    }
+
+    /**
+     * The application specific data the sender attached, if any
+     *
+     * **Security warning:** on the receiving side, this is untrusted input.
+     */
+    pub fn metadata(&self) -> Option<&crate::OfferMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /** Attach application specific data for the receiver, like a ticket ID */
+    pub fn set_metadata(&mut self, metadata: crate::OfferMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    /**
+     * Accept all files, saving them into `target_dir`
+     *
+     * File names that can't be used on this platform get replaced.
+     */
     #[cfg(not(target_family = "wasm"))]
     pub fn accept_all(&self, target_dir: &Path) -> OfferAccept {
-        self.set_content(|path| {
-            let full_path: PathBuf = target_dir.join(path.join("/"));
+        self.accept_all_with_policy(target_dir, FilenamePolicy::Replace)
+            .expect("Replacing invalid file names never fails")
+    }
+
+    /** Like [`accept_all`](Self::accept_all), but `policy` decides about invalid file names */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn accept_all_with_policy(
+        &self,
+        target_dir: &Path,
+        policy: FilenamePolicy,
+    ) -> Result<OfferAccept, InvalidFilename> {
+        let mut error = None;
+        let mut sizes = self.iter_files().map(|(_, _, size)| size);
+        let accept = self.set_content(|path| {
+            let full_path = filename::local_path(target_dir, path, policy).unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                PathBuf::new()
+            });
+            /* Both iterate the files in the same order */
+            let size = sizes.next().unwrap_or(0);
             let content = new_accept_content(move |append| {
                 let full_path = full_path.clone();
-                async_std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(append)
-                    .truncate(!append)
-                    .open(full_path)
+                async move {
+                    let file = async_std::fs::OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .append(append)
+                        .truncate(!append)
+                        .open(&full_path)
+                        .await?;
+                    preallocate(&full_path, size).await?;
+                    Ok(SyncOnClose::new(file))
+                }
             });
             AcceptInner {
                 content: Box::new(content) as _,
                 offset: 0,
                 sha256: None,
             }
-        })
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(accept),
+        }
     }
 
+    /** Create all directories of the offer, replacing file names that can't be used on this platform */
     #[cfg(not(target_family = "wasm"))]
     pub async fn create_directories(&self, target_path: &Path) -> std::io::Result<()> {
+        self.create_directories_with_policy(target_path, FilenamePolicy::Replace)
+            .await
+    }
+
+    /** Like [`create_directories`](Self::create_directories), but `policy` decides about invalid file names */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn create_directories_with_policy(
+        &self,
+        target_path: &Path,
+        policy: FilenamePolicy,
+    ) -> std::io::Result<()> {
         // TODO this could be made more efficient by passing around just one buffer
         for (name, file) in &self.content {
-            file.create_directories(&target_path.join(name)).await?;
+            let name = local_filename(name, policy)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            file.create_directories(&target_path.join(name), policy)
+                .await?;
         }
         Ok(())
     }
@@ -446,6 +966,43 @@ impl<T> Offer<T> {
         self.content.len() > 1
     }
 
+    /**
+     * Keep only the files for which `keep` returns `true`
+     *
+     * Use this on the answer to accept only some of the offered files, like
+     * `answer.retain_files(|path| wanted.contains(path))`. The sender then only sends those. Directories
+     * that end up empty get removed as well, while the ones that were empty already stay. Only the v2
+     * protocol supports this, v1 offers consist of a single file.
+     */
+    pub fn retain_files(&mut self, mut keep: impl FnMut(&[String]) -> bool) {
+        fn retain<T>(
+            content: &mut BTreeMap<String, OfferEntry<T>>,
+            path: &mut Vec<String>,
+            keep: &mut impl FnMut(&[String]) -> bool,
+            removed: &mut HashSet<String>,
+        ) {
+            content.retain(|name, entry| {
+                path.push(name.clone());
+                let retained = match entry {
+                    OfferEntry::RegularFile { .. } => keep(path),
+                    OfferEntry::Directory { content } => {
+                        let was_empty = content.is_empty();
+                        retain(content, path, keep, removed);
+                        was_empty || !content.is_empty()
+                    },
+                };
+                if !retained {
+                    removed.insert(path.join("/"));
+                }
+                path.pop();
+                retained
+            });
+        }
+        let mut removed = HashSet::new();
+        retain(&mut self.content, &mut Vec::new(), &mut keep, &mut removed);
+        self.mime_types.retain(|path, _| !removed.contains(path));
+    }
+
     pub fn is_directory(&self) -> bool {
         self.is_multiple()
             || self
@@ -461,6 +1018,24 @@ impl<T> Offer<T> {
                 .iter()
                 .map(|(k, v)| (k.clone(), v.set_content(&mut vec![k.clone()], &mut f)))
                 .collect(),
+            mime_types: self.mime_types.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /** Like [`set_content`](Self::set_content), but consuming the old content */
+    fn map_content<U>(self, mut f: impl FnMut(&[String], u64, T) -> U) -> Offer<U> {
+        Offer {
+            content: self
+                .content
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = v.map_content(&mut vec![k.clone()], &mut f);
+                    (k, v)
+                })
+                .collect(),
+            mime_types: self.mime_types,
+            metadata: self.metadata,
         }
     }
 }
@@ -532,15 +1107,28 @@ pub enum OfferEntry<T = ()> {
     // },
 }
 
+/* Two entries of an offer that end up with the same name */
+fn name_collision(first: impl std::fmt::Display, second: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{} and {} have the same name in the offer", first, second),
+    )
+}
+
 impl OfferSendEntry {
+    /** Walk `path`, calling `on_entry` with the size of each file (or `None` for directories) */
     #[cfg(not(target_family = "wasm"))]
-    async fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+    async fn new(
+        path: impl AsRef<Path>,
+        on_entry: &mut (dyn FnMut(&Path, Option<u64>) + Send),
+    ) -> std::io::Result<Self> {
         // Workaround for https://github.com/rust-lang/rust/issues/78649
         #[inline(always)]
         fn new_recurse<'a>(
             path: impl AsRef<Path> + 'a + Send,
+            on_entry: &'a mut (dyn FnMut(&Path, Option<u64>) + Send),
         ) -> futures::future::BoxFuture<'a, std::io::Result<OfferSendEntry>> {
-            Box::pin(OfferSendEntry::new(path))
+            Box::pin(OfferSendEntry::new(path, on_entry))
         }
 
         let path = path.as_ref();
@@ -552,6 +1140,7 @@ impl OfferSendEntry {
         //     .as_secs();
         if metadata.is_file() {
             log::trace!("OfferSendEntry::new {path:?} is file");
+            on_entry(path, Some(metadata.len()));
             let path = path.to_owned();
             Ok(Self::RegularFile {
                 size: metadata.len(),
@@ -577,13 +1166,15 @@ impl OfferSendEntry {
         } else if metadata.is_dir() {
             use futures::TryStreamExt;
             log::trace!("OfferSendEntry::new {path:?} is directory");
-
-            let content: BTreeMap<String, Self> = async_std::fs::read_dir(path)
-                .await?
-                .and_then(|file| async move {
-                    let path = file.path();
-                    let name = path
-                        .file_name()
+            on_entry(path, None);
+
+            let mut content = BTreeMap::new();
+            let mut paths = BTreeMap::<_, async_std::path::PathBuf>::new();
+            let mut files = async_std::fs::read_dir(path).await?;
+            while let Some(file) = files.try_next().await? {
+                let path = file.path();
+                let name = normalize_filename(
+                    path.file_name()
                         .expect("Internal error: non-root paths should always have a name")
                         .to_str()
                         .ok_or_else(|| {
@@ -591,13 +1182,15 @@ impl OfferSendEntry {
                                 std::io::ErrorKind::Other,
                                 format!("{} is not UTF-8 encoded", path.display()),
                             )
-                        })?
-                        .to_owned();
-                    let offer = new_recurse(path).await?;
-                    Ok((name, offer))
-                })
-                .try_collect()
-                .await?;
+                        })?,
+                );
+                if let Some(other) = paths.get(&name) {
+                    return Err(name_collision(other.display(), path.display()));
+                }
+                let offer = new_recurse(&path, &mut *on_entry).await?;
+                content.insert(name.clone(), offer);
+                paths.insert(name, path);
+            }
             Ok(Self::Directory { content })
         } else {
             unreachable!()
@@ -655,19 +1248,26 @@ impl<T> OfferEntry<T> {
     }
 
     #[cfg(not(target_family = "wasm"))]
-    async fn create_directories(&self, target_path: &Path) -> std::io::Result<()> {
+    async fn create_directories(
+        &self,
+        target_path: &Path,
+        policy: FilenamePolicy,
+    ) -> std::io::Result<()> {
         #[inline(always)]
         fn recurse<'a, T>(
             this: &'a OfferEntry<T>,
             path: &'a Path,
+            policy: FilenamePolicy,
         ) -> futures::future::LocalBoxFuture<'a, std::io::Result<()>> {
-            Box::pin(OfferEntry::create_directories(this, path))
+            Box::pin(OfferEntry::create_directories(this, path, policy))
         }
         match self {
             Self::Directory { content, .. } => {
                 async_std::fs::create_dir(target_path).await?;
                 for (name, file) in content {
-                    recurse(file, &target_path.join(name)).await?;
+                    let name = local_filename(name, policy)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                    recurse(file, &target_path.join(name), policy).await?;
                 }
                 Ok(())
             },
@@ -724,6 +1324,30 @@ impl<T> OfferEntry<T> {
             // },
         }
     }
+
+    fn map_content<U>(
+        self,
+        base_path: &mut Vec<String>,
+        f: &mut impl FnMut(&[String], u64, T) -> U,
+    ) -> OfferEntry<U> {
+        match self {
+            OfferEntry::RegularFile { size, content } => OfferEntry::RegularFile {
+                size,
+                content: f(base_path, size, content),
+            },
+            OfferEntry::Directory { content } => OfferEntry::Directory {
+                content: content
+                    .into_iter()
+                    .map(|(k, v)| {
+                        base_path.push(k.clone());
+                        let v = v.map_content(base_path, f);
+                        base_path.pop();
+                        (k, v)
+                    })
+                    .collect(),
+            },
+        }
+    }
 }
 
 impl<T: 'static + Send> OfferEntry<T> {
@@ -806,33 +1430,218 @@ pub async fn send(
     progress_handler: impl FnMut(u64, u64) + 'static,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
+    send_inner(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        Preset::default().record_size(),
+        offer,
+        transit::Timeouts::default(),
+        transit_handler,
+        progress_handler,
+        cancel,
+    )
+    .await
+}
+
+/**
+ * Offer several independent files or directories under the given names, see [`OfferSend::from_entries`]
+ *
+ * If the peer supports the v2 protocol, it may accept only some of them. Otherwise they get sent together
+ * as a single tar archive.
+ */
+pub async fn send_files(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    files: Vec<(String, OfferSendEntry)>,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    send(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        OfferSend::from_entries(files)?,
+        transit_handler,
+        progress_handler,
+        cancel,
+    )
+    .await
+}
+
+/**
+ * Like [`send`], but with the transit abilities, timeouts, strictness and tuning taken from a [`Preset`]
+ */
+pub async fn send_with_preset(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    preset: Preset,
+    offer: OfferSend,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    if preset.strict() {
+        wormhole.set_strict(true);
+    }
+    send_inner(
+        wormhole,
+        relay_hints,
+        preset.transit_abilities(),
+        preset.record_size(),
+        offer,
+        preset.timeouts(),
+        transit_handler,
+        progress_handler,
+        cancel,
+    )
+    .await
+}
+
+/**
+ * Like [`send`], but with custom [`Timeouts`](transit::Timeouts)
+ *
+ * Use this to bound how long to wait for the peer to answer the offer, how long to
+ * try establishing the transit connection and how long the peer may stay silent.
+ */
+pub async fn send_with_timeouts(
+    wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    offer: OfferSend,
+    timeouts: transit::Timeouts,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    send_inner(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        Preset::default().record_size(),
+        offer,
+        timeouts,
+        transit_handler,
+        progress_handler,
+        cancel,
+    )
+    .await
+}
+
+async fn send_inner(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    record_size: usize,
+    offer: OfferSend,
+    timeouts: transit::Timeouts,
+    transit_handler: impl FnOnce(transit::TransitInfo),
+    progress_handler: impl FnMut(u64, u64) + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
-    if peer_version.supports_v2() {
-        v2::send(
-            wormhole,
-            relay_hints,
-            transit_abilities,
-            offer,
-            progress_handler,
-            peer_version,
-            cancel,
-        )
-        .await
-    } else {
-        v1::send(
-            wormhole,
-            relay_hints,
-            transit_abilities,
-            offer,
-            progress_handler,
-            transit_handler,
-            peer_version,
-            cancel,
-        )
-        .await
+    match peer_version.protocol_version() {
+        ProtocolVersion::V2 => {
+            v2::send(
+                wormhole,
+                relay_hints,
+                transit_abilities,
+                record_size,
+                offer,
+                timeouts,
+                progress_handler,
+                peer_version,
+                cancel,
+            )
+            .await
+        },
+        ProtocolVersion::V1 => {
+            v1::send(
+                wormhole,
+                relay_hints,
+                transit_abilities,
+                record_size,
+                timeouts,
+                offer,
+                progress_handler,
+                transit_handler,
+                peer_version,
+                cancel,
+            )
+            .await
+        },
     }
 }
 
+/* The hash algorithm both sides agreed on, see [`AppVersion::hash_algorithm`] */
+fn hash_algorithm(wormhole: &Wormhole) -> HashAlgorithm {
+    let Some(our_version) = wormhole.our_version.downcast_ref::<AppVersion>() else {
+        return HashAlgorithm::default();
+    };
+    serde_json::from_value::<AppVersion>(wormhole.peer_version.clone())
+        .map(|peer_version| our_version.hash_algorithm(&peer_version))
+        .unwrap_or_default()
+}
+
+/* Whether both sides advertise the given ability */
+fn both_support(wormhole: &Wormhole, ability: &str) -> bool {
+    let supports = |version: &AppVersion| version.abilities.iter().any(|a| a == ability);
+    let Some(our_version) = wormhole.our_version.downcast_ref::<AppVersion>() else {
+        return false;
+    };
+    supports(our_version)
+        && serde_json::from_value::<AppVersion>(wormhole.peer_version.clone())
+            .is_ok_and(|peer_version| supports(&peer_version))
+}
+
+/* Whether both sides confirm written data, see [`AppVersion::supports_written_ack`] */
+fn written_ack(wormhole: &Wormhole) -> bool {
+    both_support(wormhole, WRITTEN_ACK_ABILITY)
+}
+
+/* Whether the peer ignores keep-alive messages on the transit connection, instead of failing on them */
+fn keep_alive(wormhole: &Wormhole) -> bool {
+    both_support(wormhole, KEEP_ALIVE_ABILITY)
+}
+
+/* Whether the v2 offer may be sent in chunks */
+fn offer_manifest(wormhole: &Wormhole) -> bool {
+    both_support(wormhole, OFFER_MANIFEST_ABILITY)
+}
+
+/* Tracks the progress for an [`AbortSummary`] if both sides support it */
+fn abort_tracker(wormhole: &Wormhole) -> abort::AbortTracker {
+    abort::AbortTracker::new(
+        both_support(wormhole, ABORT_SUMMARY_ABILITY),
+        both_support(wormhole, OFFER_WITHDRAWAL_ABILITY),
+    )
+}
+
+/* Wait for the peer's answer to our offer, giving up after `timeout` if there is one. Meanwhile, stopping withdraws the offer. */
+async fn answer_within<T>(
+    timeout: Option<std::time::Duration>,
+    abort: &abort::AbortTracker,
+    answer: impl Future<Output = Result<T, TransferError>>,
+) -> Result<T, TransferError> {
+    abort.offer_pending();
+    let result = match timeout {
+        Some(timeout) => crate::util::timeout(timeout, answer)
+            .await
+            .unwrap_or(Err(TransferError::OfferTimeout)),
+        None => answer.await,
+    };
+    match &result {
+        Err(TransferError::OfferTimeout) => abort.offer_expired(),
+        /* Stop withdrawing only on an actual answer, errors may have been caused by us */
+        Ok(_) => abort.offer_answered(),
+        Err(_) => {},
+    }
+    result
+}
+
 /**
  * Wait for a file offer from the other side
  *
@@ -847,21 +1656,66 @@ pub async fn request(
     transit_abilities: transit::Abilities,
     cancel: impl Future<Output = ()>,
 ) -> Result<Option<ReceiveRequest>, TransferError> {
+    request_with_offer_progress(
+        wormhole,
+        relay_hints,
+        transit_abilities,
+        |_files, _total_size| {},
+        cancel,
+    )
+    .await
+}
+
+/**
+ * Like [`request`], but with the transit abilities, timeouts and strictness taken from a [`Preset`]
+ *
+ * The timeouts get [set](ReceiveRequest::set_timeouts) on the returned request.
+ */
+pub async fn request_with_preset(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    preset: Preset,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<ReceiveRequest>, TransferError> {
+    if preset.strict() {
+        wormhole.set_strict(true);
+    }
+    let mut request = request(wormhole, relay_hints, preset.transit_abilities(), cancel).await?;
+    if let Some(request) = &mut request {
+        request.set_timeouts(preset.timeouts());
+    }
+    Ok(request)
+}
+
+/**
+ * Like [`request`], but report progress while receiving the offer
+ *
+ * Offers of large directories are transferred in multiple parts. `offer_progress` gets called
+ * after each part with the number of files and their total size announced so far.
+ */
+pub async fn request_with_offer_progress(
+    mut wormhole: Wormhole,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    offer_progress: impl FnMut(u64, u64),
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<ReceiveRequest>, TransferError> {
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
-    if peer_version.supports_v2() {
-        v2::request(
+    match peer_version.protocol_version() {
+        ProtocolVersion::V2 => v2::request(
             wormhole,
             relay_hints,
             peer_version,
             transit_abilities,
+            offer_progress,
             cancel,
         )
         .await
-        .map(|req| req.map(ReceiveRequest::V2))
-    } else {
-        v1::request(wormhole, relay_hints, transit_abilities, cancel)
+        .map(|req| req.map(ReceiveRequest::V2)),
+        ProtocolVersion::V1 => v1::request(wormhole, relay_hints, transit_abilities, cancel)
             .await
-            .map(|req| req.map(ReceiveRequest::V1))
+            .map(|req| req.map(ReceiveRequest::V1)),
     }
 }
 
@@ -869,11 +1723,100 @@ pub async fn request(
  * A pending files send offer from the other side
  *
  * You *should* consume this object, by matching on the protocol version and then calling either `accept` or `reject`.
+ * More versions may be added in the future, so be prepared to reject the ones you don't know.
  */
 #[must_use]
+#[non_exhaustive]
 pub enum ReceiveRequest {
-    V1(ReceiveRequestV1),
-    V2(ReceiveRequestV2),
+    V1(v1::ReceiveRequest),
+    V2(v2::ReceiveRequest),
+}
+
+impl ReceiveRequest {
+    /** The protocol version the peer offered with */
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        match self {
+            ReceiveRequest::V1(_) => ProtocolVersion::V1,
+            ReceiveRequest::V2(_) => ProtocolVersion::V2,
+        }
+    }
+
+    /**
+     * The application specific data the sender attached to the offer, see [`Offer::set_metadata`]
+     *
+     * **Security warning:** this is untrusted input.
+     */
+    pub fn metadata(&self) -> Option<&crate::OfferMetadata> {
+        match self {
+            ReceiveRequest::V1(request) => request.metadata.as_ref(),
+            ReceiveRequest::V2(request) => request.metadata(),
+        }
+    }
+
+    /**
+     * Bound the remaining steps of the transfer, see [`transit::Timeouts`]
+     *
+     * Call this before accepting the offer. With the v1 protocol, the transit connection
+     * is only established upon acceptance, so [`Timeouts::transit`](transit::Timeouts::transit)
+     * applies to it. With v2 the connection already exists at this point, and only
+     * [`Timeouts::idle_peer`](transit::Timeouts::idle_peer) has an effect.
+     * The offer timeout only concerns the sending side.
+     */
+    pub fn set_timeouts(&mut self, timeouts: transit::Timeouts) {
+        match self {
+            ReceiveRequest::V1(request) => request.set_timeouts(timeouts),
+            ReceiveRequest::V2(request) => request.set_timeouts(timeouts),
+        }
+    }
+
+    /**
+     * Check the received content before acknowledging it, see [`ContentScanner`]
+     *
+     * With the v1 protocol, the sender learns about a rejection of the complete content as
+     * [`TransferError::ContentRejected`] as well; rejecting while the data is still coming in aborts the
+     * transfer with a [`TransferError::PeerError`] on its side. The v2 protocol has no acknowledgment, so
+     * the sender may already consider the transfer done and only gets an error if it is still listening.
+     */
+    pub fn set_scanner(&mut self, scanner: impl ContentScanner + 'static) {
+        match self {
+            ReceiveRequest::V1(request) => request.set_scanner(scanner),
+            ReceiveRequest::V2(request) => request.set_scanner(scanner),
+        }
+    }
+
+    /**
+     * Keep the connection to the peer alive while waiting for `future`
+     *
+     * Use this while prompting the user whether to accept the offer: humans can take
+     * their time, and NATs or firewalls may drop the connection in the meantime.
+     * Every `interval`, a no-op message is sent so that the connection never looks idle.
+     * [`transit::DEFAULT_KEEPALIVE_INTERVAL`] is a sensible value.
+     *
+     * Fails with [`TransferError::OfferWithdrawn`] if the sender withdraws the offer or it
+     * [expires](Self::expires_at) in the meantime, so that the prompt can be dismissed.
+     */
+    pub async fn keep_alive_while<T>(
+        &mut self,
+        interval: std::time::Duration,
+        future: impl Future<Output = T>,
+    ) -> Result<T, TransferError> {
+        match self {
+            ReceiveRequest::V1(request) => request.keep_alive_while(interval, future).await,
+            ReceiveRequest::V2(request) => request.keep_alive_while(interval, future).await,
+        }
+    }
+
+    /**
+     * When the sender stops waiting for an answer
+     *
+     * `None` if the sender did not tell, because it waits forever or is an older implementation.
+     */
+    pub fn expires_at(&self) -> Option<instant::Instant> {
+        match self {
+            ReceiveRequest::V1(request) => request.expires_at,
+            ReceiveRequest::V2(request) => request.expires_at(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -881,9 +1824,72 @@ mod test {
     use super::*;
     use transit::{Abilities, DirectHint, RelayHint};
 
+    #[test]
+    fn test_retain_files() {
+        let file = |size| OfferEntry::RegularFile { size, content: () };
+        let mut offer = Offer {
+            content: BTreeMap::from([
+                ("a.txt".to_owned(), file(1)),
+                (
+                    "photos".to_owned(),
+                    OfferEntry::Directory {
+                        content: BTreeMap::from([
+                            ("b.jpg".to_owned(), file(2)),
+                            (
+                                "empty".to_owned(),
+                                OfferEntry::Directory {
+                                    content: BTreeMap::new(),
+                                },
+                            ),
+                        ]),
+                    },
+                ),
+                (
+                    "videos".to_owned(),
+                    OfferEntry::Directory {
+                        content: BTreeMap::from([("c.mp4".to_owned(), file(4))]),
+                    },
+                ),
+            ]),
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        };
+        offer.set_mime_type(&["a.txt".into()], "text/plain");
+        offer.set_mime_type(&["photos".into(), "b.jpg".into()], "image/jpeg");
+
+        offer.retain_files(|path| path[0] == "a.txt");
+        assert_eq!(offer.total_size(), 1);
+        assert!(offer.get(&["videos".into()]).is_none());
+        /* Only emptied directories get removed */
+        assert!(offer.get(&["photos".into(), "empty".into()]).is_some());
+        assert_eq!(offer.mime_type(&["a.txt".into()]), Some("text/plain"));
+        assert_eq!(offer.mime_types.len(), 1);
+    }
+
+    #[test]
+    fn test_name_collision() {
+        let empty = || OfferSendEntry::Directory {
+            content: BTreeMap::new(),
+        };
+        let error = OfferSend::from_entries([
+            ("caf\u{e9}".to_owned(), empty()),
+            ("cafe\u{301}".to_owned(), empty()),
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("cafe\u{301}"));
+        assert!(
+            OfferSend::from_entries([("a".to_owned(), empty()), ("b".to_owned(), empty())]).is_ok()
+        );
+    }
+
     #[test]
     fn test_transit() {
-        let abilities = Abilities::ALL_ABILITIES;
+        let abilities = Abilities {
+            direct_quic_v1: false,
+            ..Abilities::ALL_ABILITIES
+        };
         let hints = transit::Hints::new(
             [DirectHint::new("192.168.1.8", 46295)],
             [RelayHint::new(
@@ -896,7 +1902,7 @@ mod test {
             serde_json::json!(crate::transfer::PeerMessage::transit_v1(abilities, hints)),
             serde_json::json!({
                 "transit": {
-                    "abilities-v1": [{"type":"direct-tcp-v1"},{"type":"relay-v1"}],
+                    "abilities-v1": [{"type":"direct-tcp-v1"},{"type":"relay-v1"},{"type":"relay-v2"},{"type":"record-v2"},{"type":"noise-crypto-v1"}],
                     "hints-v1": [
                         {"hostname":"192.168.1.8","port":46295,"type":"direct-tcp-v1"},
                         {
@@ -912,6 +1918,39 @@ mod test {
         );
     }
 
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn test_scan() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_owned();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), b"world!").unwrap();
+
+        async_std::task::block_on(async {
+            let mut last = None;
+            let offer = OfferSend::scan(
+                [("folder".to_owned(), dir.clone())],
+                |progress| {
+                    if let ScanProgress::File { files, bytes, .. } = progress {
+                        last = Some((files, bytes));
+                    }
+                },
+                futures::future::pending(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            assert_eq!(last, Some((2, 11)));
+            assert_eq!(offer.total_size(), 11);
+
+            let cancelled = OfferSend::scan([("folder".to_owned(), dir.clone())], |_| {}, async {})
+                .await
+                .unwrap();
+            assert!(cancelled.is_none());
+        });
+    }
+
     #[test]
     fn test_message() {
         let m1 = PeerMessage::offer_message_v1("hello from rust");
@@ -923,7 +1962,7 @@ mod test {
 
     #[test]
     fn test_offer_file() {
-        let f1 = PeerMessage::offer_file_v1("somefile.txt", 34556);
+        let f1 = PeerMessage::offer_file_v1("somefile.txt", 34556, None, None, None, false);
         assert_eq!(
             serde_json::json!(f1).to_string(),
             "{\"offer\":{\"file\":{\"filename\":\"somefile.txt\",\"filesize\":34556}}}"
@@ -956,4 +1995,127 @@ mod test {
             "{\"answer\":{\"file_ack\":\"ok\"}}"
         );
     }
+
+    #[test]
+    fn test_hash_algorithm() {
+        let ours = AppVersion::new();
+        assert_eq!(ours.hash_algorithm(&ours), HashAlgorithm::Blake3);
+        /* Other implementations don't advertise anything */
+        let python: AppVersion = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(ours.hash_algorithm(&python), HashAlgorithm::Sha256);
+        assert_eq!(python.hash_algorithm(&ours), HashAlgorithm::Sha256);
+        let sha256_only = AppVersion::new().with_hash_algorithms(&[HashAlgorithm::Sha256]);
+        assert_eq!(ours.hash_algorithm(&sha256_only), HashAlgorithm::Sha256);
+        /* Older versions of this crate only know BLAKE2b */
+        let blake2b = AppVersion::new()
+            .with_hash_algorithms(&[HashAlgorithm::Blake2b, HashAlgorithm::Sha256]);
+        assert_eq!(ours.hash_algorithm(&blake2b), HashAlgorithm::Blake2b);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let error = TransferError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            .context("Sending 'file.txt'")
+            .context("Sending the folder");
+        assert!(matches!(error.root(), TransferError::IO(_)));
+        assert_eq!(error.to_string(), "Sending the folder");
+
+        use std::error::Error;
+        let mut chain = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(error) = source {
+            chain.push(error.to_string());
+            source = error.source();
+        }
+        assert_eq!(
+            chain,
+            [
+                "Sending the folder",
+                "Sending 'file.txt'",
+                "IO error",
+                "broken pipe"
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_timeout_errors() {
+        assert!(matches!(
+            TransferError::from(TransitConnectError::Timeout),
+            TransferError::TransitHandshakeTimeout
+        ));
+        assert!(matches!(
+            TransferError::from(TransitError::IdleTimeout),
+            TransferError::IdlePeerTimeout
+        ));
+        let abort = abort::AbortTracker::new(false, true);
+        let never = answer_within(
+            Some(std::time::Duration::from_millis(10)),
+            &abort,
+            futures::future::pending::<Result<(), TransferError>>(),
+        );
+        assert!(matches!(never.await, Err(TransferError::OfferTimeout)));
+        assert_eq!(abort.withdrawal(), Some(WithdrawReason::Expired));
+        let answered = answer_within(None, &abort, async { Ok(42) });
+        assert_eq!(answered.await.unwrap(), 42);
+        assert_eq!(abort.withdrawal(), None);
+    }
+
+    #[test]
+    fn test_strict_schema() {
+        let hints = transit::Hints::new(
+            [DirectHint::new("192.168.1.8", 46295)],
+            [RelayHint::from_urls(None, ["tcp://relay.example:4001".parse().unwrap()]).unwrap()],
+        );
+        for message in [
+            PeerMessage::transit_v1(Abilities::ALL_ABILITIES, hints.clone()),
+            PeerMessage::transit_v2(hints),
+            PeerMessage::offer_message_v1("hello"),
+            PeerMessage::offer_file_v1(
+                "notes.txt",
+                42,
+                Some("text/plain".into()),
+                Some(crate::OfferMetadata::new("ticket", 1, &42).unwrap()),
+                Some(60),
+                false,
+            ),
+            PeerMessage::offer_file_v1("photos.tar", 4096, None, None, None, true),
+            PeerMessage::offer_directory_v1("photos", "zipfile/deflated", 10, 20, 3),
+            PeerMessage::message_ack_v1("ok"),
+            PeerMessage::file_ack_v1("ok"),
+            PeerMessage::error_message("oops"),
+            PeerMessage::OfferWithdrawn(WithdrawReason::Cancelled),
+        ] {
+            PEER_MESSAGE_SCHEMA
+                .validate_json(&message.ser_json())
+                .unwrap();
+        }
+
+        /* As sent by the Python implementation */
+        PEER_MESSAGE_SCHEMA
+            .validate_json(
+                br#"{"transit": {
+                    "abilities-v1": [{"type": "direct-tcp-v1"}, {"type": "relay-v1"}],
+                    "hints-v1": [
+                        {"type": "direct-tcp-v1", "priority": 0.0, "hostname": "10.0.0.2", "port": 4444},
+                        {"type": "tor-tcp-v1", "priority": 0.0, "hostname": "abc.onion", "port": 80},
+                        {"type": "relay-v1", "hints": [
+                            {"type": "direct-tcp-v1", "priority": 2.0, "hostname": "relay.example", "port": 4001}
+                        ]}
+                    ]
+                }}"#,
+            )
+            .unwrap();
+
+        let violation = PEER_MESSAGE_SCHEMA
+            .validate_json(br#"{"offer": {"file": {"filename": "a", "filesize": -1}}}"#)
+            .unwrap_err();
+        assert_eq!(violation.path, "$.offer.file.filesize");
+        PEER_MESSAGE_SCHEMA
+            .validate_json(br#"{"offer": {"file": {"filename": "a", "filesize": 1, "mode": "x"}}}"#)
+            .unwrap_err();
+        PEER_MESSAGE_SCHEMA
+            .validate_json(br#"{"surprise": {}}"#)
+            .unwrap_err();
+    }
 }
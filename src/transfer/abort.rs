@@ -0,0 +1,193 @@
+//! Tell the peer how far a transfer got when aborting it
+//!
+//! Without this, the side that did not abort only learns that the transfer failed. If both sides
+//! support it (negotiated via the `abilities` of the [`AppVersion`](super::AppVersion)), the aborting
+//! side sends an [`AbortSummary`] along with the reason. The other side gets it as part of the
+//! [`TransferError::PeerAborted`](super::TransferError::PeerAborted) error, so that both agree on how
+//! much got through, and where a later attempt may resume.
+
+use super::WithdrawReason;
+use crate::schema::{self, Field, Schema, UnknownFields};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// How far a transfer got when one side aborted it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct AbortSummary {
+    /// Payload bytes the aborting side sent, or received and wrote into its content handler
+    pub bytes: u64,
+    /// The file that was being transferred, if any. With the v1 protocol, this is the offered name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<Vec<String>>,
+    /// How many bytes of that file got through, counted from its start
+    pub offset: u64,
+}
+
+/// The message sent instead of a plain error, if the peer supports it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AbortMessage {
+    pub reason: String,
+    pub summary: AbortSummary,
+}
+
+impl From<AbortMessage> for super::TransferError {
+    fn from(abort: AbortMessage) -> Self {
+        super::TransferError::PeerAborted {
+            reason: abort.reason,
+            summary: abort.summary,
+        }
+    }
+}
+
+pub(super) static ABORT_SCHEMA: Schema = Schema::Object {
+    fields: &[
+        Field::required("reason", &schema::STRING),
+        Field::required(
+            "summary",
+            &Schema::Object {
+                fields: &[
+                    Field::required("bytes", &schema::U64),
+                    Field::optional("file", &schema::PATH),
+                    Field::required("offset", &schema::U64),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            },
+        ),
+    ],
+    unknown_fields: UnknownFields::Reject,
+};
+
+/**
+ * Keeps the [`AbortSummary`] up to date while transferring
+ *
+ * Also tracks whether our offer is still waiting for an answer, so that stopping withdraws it, see
+ * [`WithdrawReason`]. Each part is `None` if the peer does not support it,
+ * then all updates are no-ops.
+ */
+#[derive(Clone, Default)]
+pub(super) struct AbortTracker {
+    summary: Option<Arc<Mutex<AbortSummary>>>,
+    withdrawal: Option<Arc<Mutex<Option<WithdrawReason>>>>,
+}
+
+impl AbortTracker {
+    pub fn new(summary: bool, withdrawal: bool) -> Self {
+        Self {
+            summary: summary.then(Default::default),
+            withdrawal: withdrawal.then(Default::default),
+        }
+    }
+
+    /** Our offer is waiting for an answer, stopping now withdraws it */
+    pub fn offer_pending(&self) {
+        self.set_withdrawal(Some(WithdrawReason::Cancelled));
+    }
+
+    /** Our offer ran out of time */
+    pub fn offer_expired(&self) {
+        self.set_withdrawal(Some(WithdrawReason::Expired));
+    }
+
+    /** The peer answered our offer, there is nothing to withdraw anymore */
+    pub fn offer_answered(&self) {
+        self.set_withdrawal(None);
+    }
+
+    fn set_withdrawal(&self, reason: Option<WithdrawReason>) {
+        if let Some(withdrawal) = &self.withdrawal {
+            *withdrawal.lock().unwrap() = reason;
+        }
+    }
+
+    /** Why we withdraw our offer when stopping now, if it is pending and the peer supports it */
+    pub fn withdrawal(&self) -> Option<WithdrawReason> {
+        *self.withdrawal.as_ref()?.lock().unwrap()
+    }
+
+    /** A new file starts, at `offset` if it is resumed */
+    pub fn start_file(&self, file: Vec<String>, offset: u64) {
+        if let Some(summary) = &self.summary {
+            let mut summary = summary.lock().unwrap();
+            summary.file = Some(file);
+            summary.offset = offset;
+        }
+    }
+
+    /** `n` more bytes of the current file got through */
+    pub fn advance(&self, n: u64) {
+        if let Some(summary) = &self.summary {
+            let mut summary = summary.lock().unwrap();
+            summary.bytes += n;
+            summary.offset += n;
+        }
+    }
+
+    /** Wrap a progress handler of a single file transfer, which reports the bytes of that file */
+    pub fn progress_handler(
+        &self,
+        mut inner: impl FnMut(u64, u64) + 'static,
+    ) -> impl FnMut(u64, u64) + 'static {
+        let tracker = self.clone();
+        move |done, total| {
+            if let Some(summary) = &tracker.summary {
+                let mut summary = summary.lock().unwrap();
+                summary.bytes = done;
+                summary.offset = done;
+            }
+            inner(done, total)
+        }
+    }
+
+    /** The message telling the peer about the abort, if it supports it */
+    pub fn message(&self, reason: &dyn std::fmt::Display) -> Option<AbortMessage> {
+        let summary = self.summary.as_ref()?.lock().unwrap().clone();
+        Some(AbortMessage {
+            reason: reason.to_string(),
+            summary,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_abort_tracker() {
+        let tracker = AbortTracker::new(true, false);
+        tracker.start_file(vec!["a".into()], 0);
+        tracker.advance(10);
+        tracker.start_file(vec!["dir".into(), "b".into()], 100);
+        tracker.advance(5);
+        let message = tracker.message(&"Cancelled").unwrap();
+        assert_eq!(
+            message.summary,
+            AbortSummary {
+                bytes: 15,
+                file: Some(vec!["dir".into(), "b".into()]),
+                offset: 105,
+            }
+        );
+        let json = serde_json::to_vec(&message).unwrap();
+        assert!(ABORT_SCHEMA.validate_json(&json).is_ok());
+
+        let tracker = AbortTracker::new(false, true);
+        tracker.advance(10);
+        assert_eq!(tracker.message(&"Cancelled"), None);
+
+        assert_eq!(tracker.withdrawal(), None);
+        tracker.offer_pending();
+        assert_eq!(tracker.withdrawal(), Some(WithdrawReason::Cancelled));
+        tracker.offer_expired();
+        assert_eq!(tracker.withdrawal(), Some(WithdrawReason::Expired));
+        tracker.offer_answered();
+        assert_eq!(tracker.withdrawal(), None);
+        /* The peer does not support withdrawals */
+        let tracker = AbortTracker::new(true, false);
+        tracker.offer_pending();
+        assert_eq!(tracker.withdrawal(), None);
+    }
+}
@@ -1,6 +1,7 @@
 /// Various helpers to deal with closing connections and cancellation
 use super::*;
 use crate::util;
+use abort::AbortTracker;
 
 /// A weird mixture of [`futures::future::Abortable`], [`async_std::sync::Condvar`] and [`futures::future::Select`] tailored to our Ctrl+C handling.
 ///
@@ -49,7 +50,7 @@ macro_rules! with_cancel_wormhole {
         let run = Box::pin($run);
         let result = cancel::cancellable_2(run, $cancel).await;
         let Some((transit, wormhole, cancel)) =
-            cancel::handle_run_result_noclose($wormhole, result).await?
+            cancel::handle_run_result_noclose($wormhole, result, &Default::default()).await?
         else {
             return Ok($ret_cancel);
         };
@@ -101,8 +102,9 @@ fn debug_err(result: Result<(), impl std::fmt::Display>, operation: &str) {
 pub async fn handle_run_result(
     wormhole: Wormhole,
     result: Result<(Result<(), TransferError>, impl Future<Output = ()>), Cancelled>,
+    abort: &AbortTracker,
 ) -> Result<(), TransferError> {
-    match handle_run_result_noclose(wormhole, result).await {
+    match handle_run_result_noclose(wormhole, result, abort).await {
         Ok(Some(((), wormhole, cancel))) => {
             /* Happy case: everything went okay. Now close the wormholhe */
             log::debug!("Transfer done, doing cleanup logic");
@@ -120,16 +122,28 @@ pub async fn handle_run_result(
     }
 }
 
+/* The message telling the peer why we stop, with an [`AbortSummary`] if it supports it. Withdraws a pending offer instead. */
+fn error_message(abort: &AbortTracker, error: &dyn std::fmt::Display) -> PeerMessage {
+    if let Some(reason) = abort.withdrawal() {
+        return PeerMessage::OfferWithdrawn(reason);
+    }
+    abort
+        .message(error)
+        .map(PeerMessage::Abort)
+        .unwrap_or_else(|| PeerMessage::Error(error.to_string()))
+}
+
 /** Handle the post-{transfer, failure, cancellation} logic */
 pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
     mut wormhole: Wormhole,
     result: Result<(Result<T, TransferError>, C), Cancelled>,
+    abort: &AbortTracker,
 ) -> Result<Option<(T, Wormhole, C)>, TransferError> {
     match result {
         /* Happy case: everything went okay */
         Ok((Ok(val), cancel)) => Ok(Some((val, wormhole, cancel))),
         /* Got peer error: stop everything immediately */
-        Ok((Err(error @ TransferError::PeerError(_)), cancel)) => {
+        Ok((Err(error), cancel)) if error.is_from_peer() => {
             log::debug!(
                 "Transfer encountered an error ({}), doing cleanup logic",
                 error
@@ -144,7 +158,7 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
             Err(error)
         },
         /* Got transit error: try to receive peer error for better error message */
-        Ok((Err(mut error @ TransferError::Transit(_)), cancel)) => {
+        Ok((Err(mut error), cancel)) if matches!(error.root(), TransferError::Transit(_)) => {
             log::debug!(
                 "Transfer encountered an error ({}), doing cleanup logic",
                 error
@@ -155,10 +169,11 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
                 // and we should not only look for the next one but all have been received
                 // and we should not interrupt a receive operation without making sure it leaves the connection
                 // in a consistent state, otherwise the shutdown may cause protocol errors
-                if let Ok(Ok(Ok(PeerMessage::Error(e)))) = util::timeout(SHUTDOWN_TIME / 3, wormhole.receive_json()).await {
-                    error = TransferError::PeerError(e);
-                } else {
-                    log::debug!("Failed to retrieve more specific error message from peer. Maybe it crashed?");
+                match util::timeout(SHUTDOWN_TIME / 3, wormhole.receive_json()).await {
+                    Ok(Ok(Ok(PeerMessage::Error(e)))) => error = TransferError::PeerError(e),
+                    Ok(Ok(Ok(PeerMessage::Abort(message)))) => error = message.into(),
+                    Ok(Ok(Ok(PeerMessage::OfferWithdrawn(reason)))) => error = TransferError::OfferWithdrawn(reason),
+                    _ => log::debug!("Failed to retrieve more specific error message from peer. Maybe it crashed?"),
                 }
                 debug_err(wormhole.close().await, "close Wormhole");
             }, cancel).await;
@@ -174,7 +189,7 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
                 async {
                     debug_err(
                         wormhole
-                            .send_json(&PeerMessage::Error(format!("{}", error)))
+                            .send_json(&error_message(abort, error.root()))
                             .await,
                         "notify peer about the error",
                     );
@@ -192,9 +207,7 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
             wrap_timeout(
                 async {
                     debug_err(
-                        wormhole
-                            .send_json(&PeerMessage::Error(format!("{}", cancelled)))
-                            .await,
+                        wormhole.send_json(&error_message(abort, &cancelled)).await,
                         "notify peer about our cancellation",
                     );
                     debug_err(wormhole.close().await, "close Wormhole");
@@ -213,14 +226,14 @@ pub async fn handle_run_result_noclose<T, C: Future<Output = ()>>(
 pub async fn handle_run_result_transit<T>(
     mut transit: transit::Transit,
     result: Result<(Result<T, TransferError>, impl Future<Output = ()>), Cancelled>,
-    make_error_message: impl FnOnce(&(dyn std::string::ToString + Sync)) -> Vec<u8>,
-    parse_message: impl Fn(&[u8]) -> Result<Option<String>, TransferError>,
+    make_error_message: impl FnOnce(&(dyn std::fmt::Display + Sync)) -> Vec<u8>,
+    parse_message: impl Fn(&[u8]) -> Result<Option<TransferError>, TransferError>,
 ) -> Result<Option<(T, transit::Transit)>, TransferError> {
     match result {
         /* Happy case: everything went okay */
         Ok((Ok(val), _cancel)) => Ok(Some((val, transit))),
         /* Got peer error: stop everything immediately */
-        Ok((Err(error @ TransferError::PeerError(_)), _cancel)) => {
+        Ok((Err(error), _cancel)) if error.is_from_peer() => {
             log::debug!(
                 "Transfer encountered an error ({}), doing cleanup logic",
                 error
@@ -228,7 +241,7 @@ pub async fn handle_run_result_transit<T>(
             Err(error)
         },
         /* Got transit error: try to receive peer error for better error message */
-        Ok((Err(mut error @ TransferError::Transit(_)), cancel)) => {
+        Ok((Err(mut error), cancel)) if matches!(error.root(), TransferError::Transit(_)) => {
             log::debug!(
                 "Transfer encountered an error ({}), doing cleanup logic",
                 error
@@ -246,7 +259,7 @@ pub async fn handle_run_result_transit<T>(
                         match parse_message(&msg) {
                             Ok(None) => continue,
                             Ok(Some(err)) => {
-                                error = TransferError::PeerError(err);
+                                error = err;
                                 break;
                             },
                             Err(_) => break,
@@ -267,7 +280,7 @@ pub async fn handle_run_result_transit<T>(
             wrap_timeout(
                 async {
                     debug_err(
-                        transit.send_record(&make_error_message(&error)).await,
+                        transit.send_record(&make_error_message(error.root())).await,
                         "notify peer about the error",
                     );
                 },
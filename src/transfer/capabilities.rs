@@ -0,0 +1,55 @@
+//! What the peer supports, to adapt the user interface before offering anything
+
+use super::{AppVersion, HashAlgorithm, ProtocolVersion};
+use crate::Wormhole;
+
+/**
+ * A summary of what the peer can do
+ *
+ * Get it with [`PeerCapabilities::of`] right after connecting, before sending an offer. This allows
+ * to disable features in the user interface that the peer would not understand anyway.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerCapabilities {
+    /// The version of the file transfer protocol that is going to be used
+    pub protocol: ProtocolVersion,
+    /// Whether directories can be sent. With the v1 protocol, they get packed into an archive.
+    pub directories: bool,
+    /// Whether several files can be offered on their own, instead of within a directory archive
+    pub multiple_files: bool,
+    /// Whether the peer can resume interrupted transfers, see [`Checkpoint`](super::Checkpoint)
+    pub resume: bool,
+    /// The formats the peer can receive, like `"tar.zst"`, see [`AppVersion::supports_format`]
+    pub formats: Vec<String>,
+    /// The hash algorithm to verify transfers with
+    pub hash_algorithm: HashAlgorithm,
+    /// Whether the peer confirms written data, see [`AppVersion::supports_written_ack`]
+    pub written_ack: bool,
+    /// Whether the peer supports dilation, see [`Wormhole::peer_dilation_versions`]
+    pub dilation: bool,
+}
+
+impl PeerCapabilities {
+    /** Find out what the peer of a file transfer wormhole supports */
+    pub fn of(wormhole: &Wormhole) -> Result<Self, serde_json::Error> {
+        let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
+        let protocol = peer_version.protocol_version();
+        let formats = peer_version.formats().map(str::to_owned).collect();
+        Ok(Self {
+            protocol,
+            directories: true,
+            multiple_files: protocol == ProtocolVersion::V2,
+            resume: protocol == ProtocolVersion::V2,
+            formats,
+            hash_algorithm: super::hash_algorithm(wormhole),
+            written_ack: super::written_ack(wormhole),
+            dilation: !wormhole.peer_dilation_versions().is_empty(),
+        })
+    }
+
+    /** Whether the peer can receive files in the given format, like `"tar.zst"` */
+    pub fn supports_format(&self, format: &str) -> bool {
+        self.formats.iter().any(|supported| supported == format)
+    }
+}
@@ -0,0 +1,342 @@
+//! Remember how far a transfer got, so that it can be resumed later
+
+use super::{AcceptInner, Offer, OfferAccept};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The format version written by this release
+const CURRENT_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CheckpointError {
+    #[error(
+        "Checkpoint format version {} is not supported, it was probably written by a newer release",
+        _0
+    )]
+    UnsupportedVersion(u32),
+    #[error("Corrupt checkpoint")]
+    Corrupt(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("IO error")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+}
+
+/// How far a single file got
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileProgress {
+    /// Number of bytes received
+    pub offset: u64,
+    /// SHA-256 of the received bytes, so that the sender can check that its file did not change
+    #[serde(default, with = "hex_option")]
+    pub sha256: Option<[u8; 32]>,
+}
+
+/**
+ * The progress of a transfer, to resume it later
+ *
+ * Receivers record the progress of each file while receiving. When the same offer comes in again,
+ * the stored offsets and hashes go into the [`AcceptInner`](super::AcceptInner) of the answer, and
+ * the sender continues where the last attempt stopped.
+ *
+ * Senders that fail tend to simply try again, with a new code. The checkpoint of the first attempt
+ * is named after the [`transfer_id`](Offer::transfer_id), which only depends on the offered files.
+ * [`Checkpoint::find`] thus picks it up again for the retry, and [`Checkpoint::resume`] continues
+ * where the first attempt stopped instead of starting from zero.
+ *
+ * Checkpoints outlive the application that wrote them, so the on-disk format is versioned: every file
+ * carries a `version` tag, older versions get migrated when loading, and newer ones are rejected instead
+ * of being misread.
+ *
+ * A checkpoint belongs to one offer. Use [`matches`](Self::matches) to check that an incoming offer
+ * is the same one before resuming it.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    offer: Offer,
+    files: Vec<(Vec<String>, FileProgress)>,
+}
+
+/** Version 1 of the on-disk format. Never change it, add a new version instead. */
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CheckpointV1 {
+    offer: Offer,
+    files: Vec<FileEntryV1>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FileEntryV1 {
+    path: Vec<String>,
+    #[serde(flatten)]
+    progress: FileProgress,
+}
+
+/* Only the version tag, to pick the format for the rest */
+#[derive(Deserialize)]
+struct VersionTag {
+    version: u32,
+}
+
+impl Checkpoint {
+    /** An empty checkpoint for an offer, without any progress yet */
+    pub fn new<T>(offer: &Offer<T>) -> Self {
+        Self {
+            offer: offer.set_content(|_| ()),
+            files: Vec::new(),
+        }
+    }
+
+    /** Whether the offer has the same files with the same sizes as the one of this checkpoint */
+    pub fn matches<T>(&self, offer: &Offer<T>) -> bool {
+        self.offer
+            .iter_files()
+            .map(|(path, _, size)| (path, size))
+            .eq(offer.iter_files().map(|(path, _, size)| (path, size)))
+    }
+
+    /** The progress of a file, if any got recorded */
+    pub fn get(&self, path: &[String]) -> Option<&FileProgress> {
+        self.files
+            .iter()
+            .find(|(file, _)| file == path)
+            .map(|(_, progress)| progress)
+    }
+
+    /** Record the progress of a file, replacing the previous one */
+    pub fn record(&mut self, path: &[String], progress: FileProgress) {
+        match self.files.iter_mut().find(|(file, _)| file == path) {
+            Some((_, old)) => *old = progress,
+            None => self.files.push((path.to_vec(), progress)),
+        }
+    }
+
+    /** Serialize in the current format version */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut value = serde_json::to_value(CheckpointV1 {
+            offer: self.offer.clone(),
+            files: self
+                .files
+                .iter()
+                .map(|(path, progress)| FileEntryV1 {
+                    path: path.clone(),
+                    progress: progress.clone(),
+                })
+                .collect(),
+        })
+        .expect("Checkpoints always serialize");
+        value["version"] = CURRENT_VERSION.into();
+        serde_json::to_vec_pretty(&value).expect("Checkpoints always serialize")
+    }
+
+    /** Parse a checkpoint written by this or any older release */
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CheckpointError> {
+        let VersionTag { version } = serde_json::from_slice(data)?;
+        match version {
+            1 => Ok(serde_json::from_slice::<CheckpointV1>(data)?.into()),
+            /* Older versions get migrated here, one step at a time */
+            unsupported => Err(CheckpointError::UnsupportedVersion(unsupported)),
+        }
+    }
+
+    /** The [`transfer_id`](Offer::transfer_id) of the offer this checkpoint belongs to */
+    pub fn transfer_id(&self) -> String {
+        self.offer.transfer_id()
+    }
+
+    /** The file name for the checkpoint of `offer`, see [`find`](Self::find) */
+    pub fn file_name<T>(offer: &Offer<T>) -> String {
+        format!("{}.checkpoint", offer.transfer_id())
+    }
+
+    /**
+     * Set the offsets and hashes of `answer` to the recorded progress
+     *
+     * Files without progress are received from the start. The sender checks the hashes, and sends
+     * files that changed in the meantime from the start too.
+     */
+    pub fn resume(&self, answer: OfferAccept) -> OfferAccept {
+        answer.map_content(|path, _size, accept| match self.get(path) {
+            Some(progress) => AcceptInner {
+                offset: progress.offset,
+                sha256: progress.sha256,
+                ..accept
+            },
+            None => accept,
+        })
+    }
+
+    /**
+     * Look for the checkpoint of an earlier attempt at `offer` in `dir`
+     *
+     * The checkpoint must have been [saved](Self::save) under its [`file_name`](Self::file_name).
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn find<T>(
+        dir: impl AsRef<std::path::Path>,
+        offer: &Offer<T>,
+    ) -> Result<Option<Self>, CheckpointError> {
+        Ok(Self::load(dir.as_ref().join(Self::file_name(offer)))
+            .await?
+            .filter(|checkpoint| checkpoint.matches(offer)))
+    }
+
+    /** Load a checkpoint from a file. A missing file yields `None`. */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn load(path: impl AsRef<std::path::Path>) -> Result<Option<Self>, CheckpointError> {
+        match async_std::fs::read(path.as_ref()).await {
+            Ok(data) => Self::from_bytes(&data).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /**
+     * Write the checkpoint to a file
+     *
+     * The data goes to a temporary file next to it first, so that a crash can't leave a half
+     * written checkpoint behind.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        async_std::fs::write(&temp, self.to_bytes()).await?;
+        async_std::fs::rename(&temp, path).await?;
+        Ok(())
+    }
+}
+
+impl From<CheckpointV1> for Checkpoint {
+    fn from(checkpoint: CheckpointV1) -> Self {
+        Self {
+            offer: checkpoint.offer,
+            files: checkpoint
+                .files
+                .into_iter()
+                .map(|entry| (entry.path, entry.progress))
+                .collect(),
+        }
+    }
+}
+
+impl<T> Offer<T> {
+    /**
+     * Identify the transfer by the offered files and their sizes
+     *
+     * Unlike the code, this stays the same when a sender offers the same files again.
+     */
+    pub fn transfer_id(&self) -> String {
+        let offer: Offer = self.into();
+        let hash = Sha256::digest(serde_json::to_vec(&offer).expect("Offers always serialize"));
+        hex::encode(&hash[..16])
+    }
+}
+
+/* `hex::serde` does not handle options */
+mod hex_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<[u8; 32]>, ser: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => ser.serialize_some(&hex::encode(value)),
+            None => ser.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<[u8; 32]>, D::Error> {
+        Option::<String>::deserialize(de)?
+            .map(|value| {
+                let mut bytes = [0; 32];
+                hex::decode_to_slice(value, &mut bytes).map_err(serde::de::Error::custom)?;
+                Ok(bytes)
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_offer(size: u64) -> Offer {
+        serde_json::from_value(serde_json::json!({
+            "content": {"file.txt": {"type": "regular-file", "size": size}}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let offer = test_offer(42);
+        let mut checkpoint = Checkpoint::new(&offer);
+        let path = ["file.txt".to_owned()];
+        checkpoint.record(
+            &path,
+            FileProgress {
+                offset: 10,
+                sha256: Some([0xab; 32]),
+            },
+        );
+
+        let loaded = Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.matches(&offer));
+        assert_eq!(loaded.get(&path).unwrap().offset, 10);
+        assert!(!loaded.matches(&test_offer(43)));
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let mut checkpoint = Checkpoint::new(&test_offer(42));
+        let path = ["file.txt".to_owned()];
+        checkpoint.record(
+            &path,
+            FileProgress {
+                offset: 10,
+                sha256: Some([0xab; 32]),
+            },
+        );
+
+        /* A retry offers the same files, under a new code */
+        let retry = test_offer(42);
+        assert_eq!(checkpoint.transfer_id(), retry.transfer_id());
+        assert_ne!(checkpoint.transfer_id(), test_offer(43).transfer_id());
+
+        let answer = checkpoint.resume(retry.set_content(|_| AcceptInner {
+            offset: 0,
+            sha256: None,
+            content: super::super::new_accept_content(|_| async { Ok(futures::io::sink()) }),
+        }));
+        let (_, accept, _) = answer.iter_files().next().unwrap();
+        assert_eq!(accept.offset, 10);
+        assert_eq!(accept.sha256, Some([0xab; 32]));
+    }
+
+    #[test]
+    fn test_checkpoint_versions() {
+        /* The format of version 1 must stay readable forever */
+        let v1 = br#"{
+            "version": 1,
+            "offer": {"content": {"file.txt": {"type": "regular-file", "size": 42}}},
+            "files": [{"path": ["file.txt"], "offset": 10, "sha256": null}]
+        }"#;
+        let checkpoint = Checkpoint::from_bytes(v1).unwrap();
+        assert_eq!(checkpoint.get(&["file.txt".to_owned()]).unwrap().offset, 10);
+
+        assert!(matches!(
+            Checkpoint::from_bytes(br#"{"version": 999}"#),
+            Err(CheckpointError::UnsupportedVersion(999))
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+//! Decompress received files while they are being received
+//!
+//! Senders may compress files (or directory archives) before offering them. The receiver
+//! recognizes such offers by their file name and decides whether to store them as they are,
+//! or to decompress them on the fly. Receivers that do the latter advertise the formats with
+//! [`AppVersion::with_codec`](super::AppVersion::with_codec), see [`AppVersion::supports_format`](super::AppVersion::supports_format).
+//! This only exists in the v1 protocol.
+//!
+//! Zstandard is built in. Other formats can be plugged in by implementing [`Codec`], advertising it
+//! the same way and registering it with [`ReceiveRequestV1::add_codec`](super::ReceiveRequestV1::add_codec).
+
+use futures::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::sync::Arc;
+
+/**
+ * A streaming compression format
+ *
+ * The [`name`](Self::name) identifies the format towards the peer, and is the file extension of
+ * compressed files at the same time.
+ */
+pub trait Codec: Send + Sync {
+    /** The format name and file extension without the dot, like `"zst"` */
+    fn name(&self) -> &str;
+
+    /** A short file extension for compressed tar archives, like `"tzst"`, if there is one */
+    fn tar_extension(&self) -> Option<&str> {
+        None
+    }
+
+    /** Compress the data read from `reader` */
+    fn encoder<'a>(
+        &self,
+        reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+    ) -> Box<dyn AsyncRead + Unpin + Send + 'a>;
+
+    /** Decompress the data read from `reader` */
+    fn decoder<'a>(
+        &self,
+        reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+    ) -> Box<dyn AsyncRead + Unpin + Send + 'a>;
+
+    /** Wrap a writer, so that compressed data written to it arrives decompressed */
+    fn decoding_writer<'a>(
+        &self,
+        writer: Box<dyn AsyncWrite + Unpin + 'a>,
+    ) -> Box<dyn AsyncWrite + Unpin + 'a>;
+}
+
+/* The built-in Zstandard codec */
+struct ZstdCodec;
+
+impl Codec for ZstdCodec {
+    fn name(&self) -> &str {
+        "zst"
+    }
+
+    fn tar_extension(&self) -> Option<&str> {
+        Some("tzst")
+    }
+
+    fn encoder<'a>(
+        &self,
+        reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+    ) -> Box<dyn AsyncRead + Unpin + Send + 'a> {
+        Box::new(async_compression::futures::bufread::ZstdEncoder::new(
+            reader,
+        ))
+    }
+
+    fn decoder<'a>(
+        &self,
+        reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+    ) -> Box<dyn AsyncRead + Unpin + Send + 'a> {
+        Box::new(async_compression::futures::bufread::ZstdDecoder::new(
+            reader,
+        ))
+    }
+
+    fn decoding_writer<'a>(
+        &self,
+        writer: Box<dyn AsyncWrite + Unpin + 'a>,
+    ) -> Box<dyn AsyncWrite + Unpin + 'a> {
+        Box::new(async_compression::futures::write::ZstdDecoder::new(writer))
+    }
+}
+
+/// A compression format that received files can be decompressed from
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum Compression {
+    /// [Zstandard](https://facebook.github.io/zstd/), with the `.zst` file extension
+    Zstd,
+    /// A format provided by the application
+    Custom(Arc<dyn Codec>),
+}
+
+impl std::fmt::Debug for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Zstd => write!(f, "Zstd"),
+            Compression::Custom(codec) => write!(f, "Custom({:?})", codec.name()),
+        }
+    }
+}
+
+/* Codecs are identified by their name */
+impl PartialEq for Compression {
+    fn eq(&self, other: &Self) -> bool {
+        self.codec().name() == other.codec().name()
+    }
+}
+
+impl Eq for Compression {}
+
+impl Compression {
+    /** Guess the compression from the offered file name */
+    pub fn from_filename(name: &str) -> Option<Self> {
+        Self::from_filename_with(name, &[])
+    }
+
+    /** Like [`from_filename`](Self::from_filename), but also consider the given custom codecs */
+    pub fn from_filename_with(name: &str, codecs: &[Arc<dyn Codec>]) -> Option<Self> {
+        std::iter::once(Compression::Zstd)
+            .chain(codecs.iter().cloned().map(Compression::Custom))
+            .find(|compression| compression.strip_extension(name).is_some())
+    }
+
+    /** The codec doing the actual work */
+    pub fn codec(&self) -> &dyn Codec {
+        match self {
+            Compression::Zstd => &ZstdCodec,
+            Compression::Custom(codec) => &**codec,
+        }
+    }
+
+    /* The file name without the compression extension, with `.tar` for the tar shorthand */
+    fn strip_extension(&self, name: &str) -> Option<String> {
+        let codec = self.codec();
+        if let Some(stem) = codec
+            .tar_extension()
+            .and_then(|extension| name.strip_suffix(extension)?.strip_suffix('.'))
+        {
+            return Some(format!("{stem}.tar"));
+        }
+        name.strip_suffix(codec.name())?
+            .strip_suffix('.')
+            .map(str::to_owned)
+    }
+
+    /**
+     * The file name after decompression
+     *
+     * `archive.tar.zst` becomes `archive.tar`, `archive.tzst` becomes `archive.tar`.
+     */
+    pub fn decompressed_filename(&self, name: &str) -> String {
+        self.strip_extension(name)
+            .unwrap_or_else(|| name.to_owned())
+    }
+
+    /** Wrap a reader of compressed data */
+    pub(super) fn decoder<'a>(
+        &self,
+        reader: impl AsyncBufRead + Unpin + Send + 'a,
+    ) -> Box<dyn AsyncRead + Unpin + Send + 'a> {
+        self.codec().decoder(Box::new(reader))
+    }
+
+    /** Wrap a writer, so that compressed data written to it arrives decompressed */
+    pub(super) fn decoding_writer<'a>(
+        &self,
+        writer: impl AsyncWrite + Unpin + 'a,
+    ) -> Box<dyn AsyncWrite + Unpin + 'a> {
+        self.codec().decoding_writer(Box::new(writer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_filename() {
+        assert_eq!(
+            Compression::from_filename("folder.tar.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_filename("folder.tar"), None);
+        assert_eq!(
+            Compression::Zstd.decompressed_filename("folder.tar.zst"),
+            "folder.tar"
+        );
+        assert_eq!(
+            Compression::Zstd.decompressed_filename("folder.tzst"),
+            "folder.tar"
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = b"hello hello hello hello".repeat(100);
+        let compressed = zstd::encode_all(&data[..], 3).unwrap();
+        async_std::task::block_on(async {
+            let mut decoded = Vec::new();
+            Compression::Zstd
+                .decoder(&compressed[..])
+                .read_to_end(&mut decoded)
+                .await
+                .unwrap();
+            assert_eq!(decoded, data);
+
+            let mut decoded = Vec::new();
+            let mut writer = Compression::Zstd.decoding_writer(&mut decoded);
+            writer.write_all(&compressed).await.unwrap();
+            writer.close().await.unwrap();
+            drop(writer);
+            assert_eq!(decoded, data);
+        });
+    }
+
+    /* Does not compress at all */
+    struct Identity;
+
+    impl Codec for Identity {
+        fn name(&self) -> &str {
+            "id"
+        }
+
+        fn encoder<'a>(
+            &self,
+            reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+        ) -> Box<dyn AsyncRead + Unpin + Send + 'a> {
+            Box::new(reader)
+        }
+
+        fn decoder<'a>(
+            &self,
+            reader: Box<dyn AsyncBufRead + Unpin + Send + 'a>,
+        ) -> Box<dyn AsyncRead + Unpin + Send + 'a> {
+            Box::new(reader)
+        }
+
+        fn decoding_writer<'a>(
+            &self,
+            writer: Box<dyn AsyncWrite + Unpin + 'a>,
+        ) -> Box<dyn AsyncWrite + Unpin + 'a> {
+            writer
+        }
+    }
+
+    #[test]
+    fn test_custom_codec() {
+        let codecs: Vec<Arc<dyn Codec>> = vec![Arc::new(Identity)];
+        assert_eq!(Compression::from_filename("folder.tar.id"), None);
+        let compression = Compression::from_filename_with("folder.tar.id", &codecs).unwrap();
+        assert_eq!(compression, Compression::Custom(codecs[0].clone()));
+        assert_eq!(
+            compression.decompressed_filename("folder.tar.id"),
+            "folder.tar"
+        );
+        assert_eq!(
+            Compression::from_filename_with("folder.tzst", &codecs),
+            Some(Compression::Zstd)
+        );
+        /* Only whole extensions */
+        assert_eq!(Compression::from_filename_with("void", &codecs), None);
+
+        let version = super::super::AppVersion::default();
+        assert!(version.supports_format("tar"));
+        assert!(!version.supports_format("zst"));
+        let version = version
+            .with_codec(&Identity)
+            .with_codec(Compression::Zstd.codec());
+        assert!(version.supports_format("id"));
+        assert!(version.supports_format("tar.id"));
+        assert!(version.supports_format("tar.zst"));
+    }
+
+    #[test]
+    fn test_encoder() {
+        let data = b"hello hello hello hello".repeat(100);
+        async_std::task::block_on(async {
+            let mut compressed = Vec::new();
+            ZstdCodec
+                .encoder(Box::new(&data[..]))
+                .read_to_end(&mut compressed)
+                .await
+                .unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data);
+        });
+    }
+}
@@ -0,0 +1,73 @@
+//! Make sure received files actually hit the disk
+
+use async_std::fs::File;
+use futures::{future::BoxFuture, AsyncWrite};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/**
+ * A file that gets synced to disk when it is closed
+ *
+ * Closing an [`async_std::fs::File`] only hands the data to the operating system. Use this as content
+ * handler instead, so that the peer's [written ack](super::AppVersion::supports_written_ack) only goes
+ * out once the data is durable.
+ */
+pub struct SyncOnClose {
+    file: Arc<File>,
+    sync: Option<BoxFuture<'static, io::Result<()>>>,
+}
+
+impl SyncOnClose {
+    /** Wrap a file opened for writing */
+    pub fn new(file: File) -> Self {
+        Self {
+            file: Arc::new(file),
+            sync: None,
+        }
+    }
+}
+
+impl AsyncWrite for SyncOnClose {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self.file).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.sync.is_none() {
+            futures::ready!(Pin::new(&mut &*self.file).poll_flush(cx))?;
+            let file = self.file.clone();
+            self.sync = Some(Box::pin(async move { file.sync_all().await }));
+        }
+        let result = futures::ready!(self.sync.as_mut().unwrap().as_mut().poll(cx));
+        self.sync = None;
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::AsyncWriteExt;
+
+    #[async_std::test]
+    async fn test_sync_on_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let mut file = SyncOnClose::new(File::create(&path).await.unwrap());
+        file.write_all(b"hello").await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    }
+}
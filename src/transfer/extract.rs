@@ -0,0 +1,206 @@
+//! Unpack directory archives while they are being received
+//!
+//! Directories sent over transfer v1 arrive as a single tar file. Instead of leaving that
+//! archive for the user to deal with, we can feed the received bytes through a pipe directly
+//! into a tar reader and write the files out as they come in.
+
+use futures::{channel::mpsc, AsyncBufRead, AsyncRead, AsyncWrite, TryStreamExt};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::{local_filename, FilenamePolicy};
+
+/// Progress events of a receive operation that unpacks an archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExtractProgress {
+    /// Bytes received so far, out of the total archive size
+    Bytes {
+        received: u64,
+        total: u64,
+        /// Estimated time until the transfer is complete, once enough data came in to tell
+        eta: Option<Duration>,
+    },
+    /// Started unpacking a file, with its path relative to the target directory and its size
+    File { path: PathBuf, size: u64 },
+}
+
+/// Estimate the remaining time from the average throughput since the first call
+pub(super) struct Eta {
+    start: Option<instant::Instant>,
+}
+
+impl Eta {
+    pub fn new() -> Self {
+        Self { start: None }
+    }
+
+    pub fn update(&mut self, received: u64, total: u64) -> Option<Duration> {
+        let elapsed = self
+            .start
+            .get_or_insert_with(instant::Instant::now)
+            .elapsed();
+        if received == 0 || elapsed.is_zero() {
+            return None;
+        }
+        let remaining = total.saturating_sub(received) as f64;
+        Some(elapsed.mul_f64(remaining / received as f64))
+    }
+}
+
+/// The writing end of [`pipe`]
+pub(super) struct PipeWriter {
+    tx: mpsc::Sender<io::Result<Vec<u8>>>,
+}
+
+impl AsyncWrite for PipeWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        futures::ready!(self.tx.poll_ready(cx)).map_err(|_| broken_pipe())?;
+        self.tx
+            .start_send(Ok(buf.to_vec()))
+            .map_err(|_| broken_pipe())?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Archive extraction stopped")
+}
+
+/**
+ * An in-memory pipe with backpressure
+ *
+ * The reader sees EOF once the writer got closed or dropped. Writing fails once the reader got dropped.
+ */
+pub(super) fn pipe() -> (PipeWriter, impl AsyncBufRead + Unpin + Send) {
+    let (tx, rx) = mpsc::channel(16);
+    (PipeWriter { tx }, rx.into_async_read())
+}
+
+/**
+ * Unpack a tar archive into `target_dir`, reporting each file before writing it
+ *
+ * Only regular files and directories are unpacked, symlinks and other special files are skipped.
+ * Path components get mapped to local file names using `policy`, which also takes care of `..`.
+ */
+pub(super) async fn unpack_tar(
+    mut reader: impl AsyncRead + Unpin,
+    target_dir: &Path,
+    policy: FilenamePolicy,
+    mut file_handler: impl FnMut(PathBuf, u64),
+) -> io::Result<()> {
+    async_std::fs::create_dir_all(target_dir).await?;
+    let canonical_target = async_std::fs::canonicalize(target_dir).await?;
+    let mut entries = async_tar::Archive::new(&mut reader).entries()?;
+    while let Some(mut entry) = entries.try_next().await? {
+        let entry_type = entry.header().entry_type();
+        let path = local_path(&entry.path()?, policy)?;
+        let destination = target_dir.join(&path);
+        if entry_type.is_dir() {
+            async_std::fs::create_dir_all(&destination).await?;
+        } else if entry_type.is_file() {
+            let parent = destination.parent().unwrap_or(target_dir);
+            async_std::fs::create_dir_all(parent).await?;
+            /* Don't follow symlinks that may already be in the target directory */
+            if !async_std::fs::canonicalize(parent)
+                .await?
+                .starts_with(&canonical_target)
+            {
+                log::warn!("Skipping archive entry '{}'", path.display());
+                continue;
+            }
+            file_handler(path, entry.header().size()?);
+            entry.unpack(&destination).await?;
+        } else {
+            log::warn!("Skipping unsupported archive entry '{}'", path.display());
+        }
+    }
+    drop(entries);
+    /* Drain trailing padding so that the writing side does not get a broken pipe */
+    futures::io::copy(reader, &mut futures::io::sink()).await?;
+    Ok(())
+}
+
+/** Map a path from the archive to a relative local path */
+fn local_path(path: &async_std::path::Path, policy: FilenamePolicy) -> io::Result<PathBuf> {
+    use async_std::path::Component;
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            /* Absolute paths become relative */
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => None,
+        })
+        .map(|name| {
+            local_filename(&name, policy)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::AsyncWriteExt;
+
+    #[test]
+    fn test_unpack_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path();
+        async_std::task::block_on(async {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o755);
+            builder
+                .append_data(&mut header, "folder", io::empty())
+                .unwrap();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, "folder/file.txt", &b"hello"[..])
+                .unwrap();
+            let archive = builder.into_inner().unwrap();
+
+            let (mut writer, reader) = pipe();
+            let mut files = Vec::new();
+            let write = async {
+                writer.write_all(&archive).await.unwrap();
+                writer.close().await.unwrap();
+            };
+            let unpack = unpack_tar(reader, target, FilenamePolicy::Replace, |path, size| {
+                files.push((path, size))
+            });
+            let ((), result) = futures::join!(write, unpack);
+            result.unwrap();
+
+            assert_eq!(files, [(PathBuf::from("folder/file.txt"), 5)]);
+            assert_eq!(
+                async_std::fs::read(target.join("folder/file.txt"))
+                    .await
+                    .unwrap(),
+                b"hello"
+            );
+        });
+    }
+}
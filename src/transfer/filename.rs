@@ -0,0 +1,159 @@
+//! File names that work across platforms
+//!
+//! macOS hands out file names in decomposed Unicode form (NFD), while everybody else uses the
+//! composed form (NFC). Linux accepts nearly any byte sequence, Windows forbids a bunch of
+//! characters and names. Names in offers are normalized to NFC when sending, and checked
+//! against the local platform's rules when receiving.
+
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+/// What to do with a received file name that can't be used on this platform
+#[derive(Clone, Copy, Debug, Default)]
+#[non_exhaustive]
+pub enum FilenamePolicy {
+    /// Replace the offending characters with `_`
+    #[default]
+    Replace,
+    /// Fail the transfer
+    Reject,
+    /// Let the application choose a new name. Returning `None` fails the transfer.
+    Custom(fn(&str) -> Option<String>),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("File name '{}' can't be used on this platform", _0)]
+pub struct InvalidFilename(pub String);
+
+/** Bring a file name into the canonical (NFC) form used on the wire */
+pub fn normalize_filename(name: &str) -> String {
+    if is_nfc(name) {
+        name.to_owned()
+    } else {
+        name.nfc().collect()
+    }
+}
+
+/**
+ * Turn a received path component into a file name for this platform
+ *
+ * The name gets normalized, and if it isn't valid here (like `..`, or `aux` on Windows),
+ * the `policy` decides what happens.
+ */
+pub fn local_filename(name: &str, policy: FilenamePolicy) -> Result<String, InvalidFilename> {
+    let name = normalize_filename(name);
+    if is_valid(&name) {
+        return Ok(name);
+    }
+    let replaced = match policy {
+        FilenamePolicy::Replace => Some(replace_invalid(&name)),
+        FilenamePolicy::Reject => None,
+        FilenamePolicy::Custom(rename) => rename(&name).map(|new| normalize_filename(&new)),
+    };
+    match replaced {
+        Some(replaced) if is_valid(&replaced) => Ok(replaced),
+        _ => Err(InvalidFilename(name)),
+    }
+}
+
+/** Map a received path to a local one below `target_dir`, see [`local_filename`] */
+#[cfg(not(target_family = "wasm"))]
+pub(super) fn local_path(
+    target_dir: &std::path::Path,
+    path: &[String],
+    policy: FilenamePolicy,
+) -> Result<std::path::PathBuf, InvalidFilename> {
+    path.iter().try_fold(target_dir.to_owned(), |dir, name| {
+        Ok(dir.join(local_filename(name, policy)?))
+    })
+}
+
+fn is_forbidden_char(c: char) -> bool {
+    if cfg!(windows) {
+        c.is_ascii_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+    } else {
+        matches!(c, '/' | '\0')
+    }
+}
+
+/** Windows does not allow these as file names, not even with an extension */
+fn is_reserved(name: &str) -> bool {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    cfg!(windows) && RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem))
+}
+
+fn is_valid(name: &str) -> bool {
+    let trailing_dot = cfg!(windows) && name.ends_with(&['.', ' '][..]);
+    !(matches!(name, "" | "." | "..")
+        || name.chars().any(is_forbidden_char)
+        || is_reserved(name)
+        || trailing_dot)
+}
+
+fn replace_invalid(name: &str) -> String {
+    if matches!(name, "" | "." | "..") {
+        return "_".into();
+    }
+    let mut replaced: String = name
+        .chars()
+        .map(|c| if is_forbidden_char(c) { '_' } else { c })
+        .collect();
+    if is_reserved(&replaced) {
+        replaced.insert(0, '_');
+    }
+    if cfg!(windows) {
+        while replaced.ends_with(&['.', ' '][..]) {
+            replaced.pop();
+            replaced.push('_');
+        }
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        /* "é" as 'e' followed by a combining acute accent, like macOS would send it */
+        assert_eq!(normalize_filename("cafe\u{301}.txt"), "caf\u{e9}.txt");
+        assert_eq!(normalize_filename("caf\u{e9}.txt"), "caf\u{e9}.txt");
+    }
+
+    #[test]
+    fn test_local_filename() {
+        assert_eq!(
+            local_filename("cafe\u{301}", FilenamePolicy::Reject).unwrap(),
+            "caf\u{e9}"
+        );
+        assert_eq!(local_filename("..", FilenamePolicy::Replace).unwrap(), "_");
+        assert_eq!(
+            local_filename("a/b", FilenamePolicy::Replace).unwrap(),
+            "a_b"
+        );
+        assert!(local_filename("a/b", FilenamePolicy::Reject).is_err());
+        assert_eq!(
+            local_filename(
+                "a/b",
+                FilenamePolicy::Custom(|name| Some(name.replace('/', "-")))
+            )
+            .unwrap(),
+            "a-b"
+        );
+        assert!(local_filename("a/b", FilenamePolicy::Custom(|_| None)).is_err());
+        if cfg!(windows) {
+            assert_eq!(
+                local_filename("aux.txt", FilenamePolicy::Replace).unwrap(),
+                "_aux.txt"
+            );
+            assert_eq!(
+                local_filename("what?", FilenamePolicy::Replace).unwrap(),
+                "what_"
+            );
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Hash transferred data without holding up the executor
+//!
+//! Hashing a fast local file takes about as long as encrypting it. Doing that inline blocks the task
+//! that also drives the transit connection, which hurts on executors with only a single thread. So
+//! the chunks get hashed on the blocking thread pool instead, one after another, while the next
+//! chunk is already on its way.
+//!
+//! Both sides advertise the algorithms they support in their [`AppVersion`](super::AppVersion), and use
+//! the fastest one they have in common, which is BLAKE3 between two recent versions of this crate.
+//! SHA-256 is what everybody else speaks, and thus the fallback.
+
+use sha2::{digest::DynDigest, Sha256};
+
+/// The algorithm for the checksum over the transferred data
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// BLAKE2b with 256 bit output, considerably faster than SHA-256 on CPUs without SHA extensions
+    Blake2b,
+    /// BLAKE3, faster still thanks to SIMD. Preferred when both sides support it
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// All algorithms, fastest first
+    pub const ALL: &'static [Self] = &[Self::Blake3, Self::Blake2b, Self::Sha256];
+
+    /** The name in the [`AppVersion`](super::AppVersion) and in the transit ack */
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake2b => "blake2b",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.name() == name)
+    }
+
+    fn hasher(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Sha256 => Box::<Sha256>::default(),
+            Self::Blake2b => Box::<blake2::Blake2b<blake2::digest::consts::U32>>::default(),
+            Self::Blake3 => Box::<blake3::Hasher>::default(),
+        }
+    }
+}
+
+/// A hasher that gets fed from async code
+pub(super) struct BackgroundHasher {
+    state: State,
+}
+
+enum State {
+    Idle(Box<dyn DynDigest + Send>),
+    #[cfg(not(target_family = "wasm"))]
+    Hashing(async_std::task::JoinHandle<std::io::Result<Box<dyn DynDigest + Send>>>),
+    /* While switching between the other states, and for good once hashing panicked */
+    Empty,
+}
+
+impl BackgroundHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            state: State::Idle(algorithm.hasher()),
+        }
+    }
+
+    /**
+     * Queue a chunk for hashing. Waits for the previous chunk, but not for this one.
+     *
+     * Fails if hashing the previous chunk panicked. The hasher is unusable afterwards.
+     */
+    pub async fn update(&mut self, chunk: Vec<u8>) -> std::io::Result<()> {
+        let mut hasher = self.wait().await?;
+        #[cfg(not(target_family = "wasm"))]
+        {
+            self.state = State::Hashing(crate::util::spawn_blocking(move || {
+                hasher.update(&chunk);
+                Ok(hasher)
+            }));
+        }
+        /* No threads to offload to */
+        #[cfg(target_family = "wasm")]
+        {
+            hasher.update(&chunk);
+            self.state = State::Idle(hasher);
+        }
+        Ok(())
+    }
+
+    pub async fn finalize(mut self) -> std::io::Result<Vec<u8>> {
+        Ok(self.wait().await?.finalize().into_vec())
+    }
+
+    async fn wait(&mut self) -> std::io::Result<Box<dyn DynDigest + Send>> {
+        match std::mem::replace(&mut self.state, State::Empty) {
+            State::Idle(hasher) => Ok(hasher),
+            #[cfg(not(target_family = "wasm"))]
+            State::Hashing(handle) => handle.await,
+            State::Empty => Err(std::io::Error::other("Hashing failed before")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::Digest;
+
+    #[test]
+    fn test_background_hasher() {
+        let hash = |algorithm| {
+            async_std::task::block_on(async {
+                let mut hasher = BackgroundHasher::new(algorithm);
+                hasher.update(b"hello ".to_vec()).await.unwrap();
+                hasher.update(b"world".to_vec()).await.unwrap();
+                hasher.finalize().await.unwrap()
+            })
+        };
+        assert_eq!(
+            hash(HashAlgorithm::Sha256),
+            Sha256::digest(b"hello world").to_vec()
+        );
+        let blake2b = hash(HashAlgorithm::Blake2b);
+        assert_eq!(blake2b.len(), 32);
+        assert_ne!(blake2b, hash(HashAlgorithm::Sha256));
+        assert_eq!(
+            hash(HashAlgorithm::Blake3),
+            blake3::hash(b"hello world").as_bytes().to_vec()
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! A record of what got transferred, to keep as proof and to verify the files later
+
+use super::{AcceptInner, OfferAccept, OfferSend};
+use futures::{AsyncRead, AsyncSeek, AsyncWrite};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{digest::FixedOutput, Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// A transferred file
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct ManifestEntry {
+    /// Path within the offer
+    pub path: Vec<String>,
+    pub size: u64,
+    #[serde(with = "hex::serde")]
+    pub sha256: [u8; 32],
+}
+
+/// The files of a transfer, sorted by path
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+/**
+ * Collects the manifest while the files of an offer are being transferred
+ *
+ * Wrap an offer with [`OfferSend::with_manifest`] before sending it, or an accepted offer with
+ * [`OfferAccept::with_manifest`] before receiving it. The files get hashed while they pass through,
+ * so no second pass over the data is needed. Once the transfer is done, the recorder
+ * hands out the [`Manifest`] with the path, size and SHA-256 of every file.
+ *
+ * Files only show up once they have been transferred completely, and from the start. Files that did
+ * not get transferred at all, or that got resumed from an offset, are missing from the manifest.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct ManifestRecorder(Arc<Mutex<BTreeMap<Vec<String>, ManifestEntry>>>);
+
+impl ManifestRecorder {
+    /** The files that have been transferred so far */
+    pub fn manifest(&self) -> Manifest {
+        Manifest {
+            files: self.0.lock().unwrap().values().cloned().collect(),
+        }
+    }
+
+    fn record(&self, path: &[String], size: u64, hasher: Sha256) {
+        let entry = ManifestEntry {
+            path: path.to_vec(),
+            size,
+            sha256: hasher.finalize_fixed().into(),
+        };
+        self.0.lock().unwrap().insert(entry.path.clone(), entry);
+    }
+}
+
+impl OfferSend {
+    /** Hash the files while they are being sent, see [`ManifestRecorder`] */
+    pub fn with_manifest(self) -> (Self, ManifestRecorder) {
+        let recorder = ManifestRecorder::default();
+        let offer = self.map_content(|path, size, content| {
+            let path = path.to_vec();
+            let recorder = recorder.clone();
+            super::new_offer_content(move || {
+                let read = content();
+                let path = path.clone();
+                let recorder = recorder.clone();
+                async move {
+                    Ok(HashingReader {
+                        inner: read.await?,
+                        path,
+                        size,
+                        recorder,
+                        hasher: Some(Sha256::new()),
+                        position: 0,
+                    })
+                }
+            })
+        });
+        (offer, recorder)
+    }
+}
+
+impl OfferAccept {
+    /** Hash the files while they are being received, see [`ManifestRecorder`] */
+    pub fn with_manifest(self) -> (Self, ManifestRecorder) {
+        let recorder = ManifestRecorder::default();
+        let offer = self.map_content(|path, _size, accept| {
+            let path = path.to_vec();
+            let recorder = recorder.clone();
+            let content = accept.content;
+            AcceptInner {
+                content: Box::new(move |append: bool| {
+                    Box::pin(async move {
+                        let inner = content(append).await?;
+                        Ok(Box::new(HashingWriter {
+                            inner,
+                            path,
+                            recorder,
+                            /* We won't see the start of the file */
+                            hasher: (!append).then(Sha256::new),
+                            written: 0,
+                        })
+                            as Box<dyn AsyncWrite + Unpin + Send>)
+                    }) as futures::future::BoxFuture<'static, _>
+                }),
+                ..accept
+            }
+        });
+        (offer, recorder)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Manifest {
+    /**
+     * Check the files in `target_dir` against the manifest
+     *
+     * File names get mapped like [`Offer::accept_all`](super::Offer::accept_all) does. Returns the paths of the files that
+     * are missing or differ.
+     */
+    pub async fn verify(&self, target_dir: &std::path::Path) -> io::Result<Vec<Vec<String>>> {
+        let mut mismatches = Vec::new();
+        for entry in &self.files {
+            let path = super::filename::local_path(target_dir, &entry.path, Default::default())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let file = match async_std::fs::File::open(path).await {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    mismatches.push(entry.path.clone());
+                    continue;
+                },
+                Err(err) => return Err(err),
+            };
+            let mut hasher = Sha256::new();
+            let size =
+                futures::io::copy(file, &mut futures::io::AllowStdIo::new(&mut hasher)).await?;
+            if size != entry.size || hasher.finalize_fixed()[..] != entry.sha256 {
+                mismatches.push(entry.path.clone());
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/* Hashes the data while it is read sequentially from the start */
+struct HashingReader<R> {
+    inner: R,
+    path: Vec<String>,
+    size: u64,
+    recorder: ManifestRecorder,
+    /* `None` after seeking somewhere else than the start */
+    hasher: Option<Sha256>,
+    position: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let n = futures::ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.position += n as u64;
+        if n > 0 {
+            if let Some(hasher) = &mut this.hasher {
+                hasher.update(&buf[..n]);
+            }
+        } else if !buf.is_empty() && this.position == this.size {
+            /* End of file */
+            if let Some(hasher) = this.hasher.take() {
+                this.recorder.record(&this.path, this.size, hasher);
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for HashingReader<R> {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: io::SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = &mut *self;
+        let position = futures::ready!(Pin::new(&mut this.inner).poll_seek(cx, pos))?;
+        if position == 0 {
+            this.hasher = Some(Sha256::new());
+        } else if position != this.position {
+            this.hasher = None;
+        }
+        this.position = position;
+        Poll::Ready(Ok(position))
+    }
+}
+
+/* Hashes the written data, and records it once the file gets closed */
+struct HashingWriter<W> {
+    inner: W,
+    path: Vec<String>,
+    recorder: ManifestRecorder,
+    hasher: Option<Sha256>,
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let n = futures::ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(hasher) = &mut this.hasher {
+            hasher.update(&buf[..n]);
+        }
+        this.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        futures::ready!(Pin::new(&mut this.inner).poll_close(cx))?;
+        if let Some(hasher) = this.hasher.take() {
+            this.recorder.record(&this.path, this.written, hasher);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_manifest() {
+        let offer = OfferSend::new_file_custom(
+            "file.txt".into(),
+            5,
+            super::super::new_offer_content(|| async { Ok(futures::io::Cursor::new(b"hello")) }),
+        );
+        let (offer, sent) = offer.with_manifest();
+        let accept: OfferAccept = offer.set_content(|_| AcceptInner {
+            offset: 0,
+            sha256: None,
+            content: super::super::new_accept_content(|_| async { Ok(futures::io::sink()) }),
+        });
+        let (accept, received) = accept.with_manifest();
+
+        async_std::task::block_on(async {
+            let (_, content, _) = offer.into_iter_files().next().unwrap();
+            let (_, answer, _) = accept.into_iter_files().next().unwrap();
+            let mut data = Vec::new();
+            content()
+                .await
+                .unwrap()
+                .read_to_end(&mut data)
+                .await
+                .unwrap();
+            let mut writer = (answer.content)(false).await.unwrap();
+            writer.write_all(&data).await.unwrap();
+            writer.close().await.unwrap();
+        });
+
+        let expected = Manifest {
+            files: vec![ManifestEntry {
+                path: vec!["file.txt".into()],
+                size: 5,
+                sha256: Sha256::digest(b"hello").into(),
+            }],
+        };
+        assert_eq!(sent.manifest(), expected);
+        assert_eq!(received.manifest(), expected);
+    }
+}
@@ -0,0 +1,128 @@
+//! Send the same thing to several receivers, one code each
+//!
+//! [`send_many`] serves one receiver after the other, until a [limit](SendManyLimits) is reached. The rendezvous
+//! server does not hand out a nameplate again before all sides released it, so every receiver gets a fresh code,
+//! which is reported through [`SendManyEvent::Code`]. Like any other code, it is good for a single try, so a wrong
+//! guess only costs that receiver its transfer.
+
+use super::{AppVersion, OfferSend, TransferError};
+use crate::{transit, AppConfig, Code, MailboxConnection, Wormhole, WormholeError};
+use futures::{Future, FutureExt};
+use std::time::Duration;
+
+/// When [`send_many`] stops waiting for more receivers. Without any limit, it goes on until cancelled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SendManyLimits {
+    /// How many receivers to serve at most, including failed transfers
+    pub receivers: Option<u64>,
+    /// How long to wait for new receivers. Transfers that are running by then still get finished.
+    pub time: Option<Duration>,
+}
+
+/// What happened to the receivers of [`send_many`], numbered from zero in order of arrival
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SendManyEvent {
+    /// The code for the next receiver is ready. Show it to them
+    Code { receiver: u64, code: Code },
+    /// A receiver connected, and is being sent the offer
+    Connected { receiver: u64 },
+    /// The transfer to a receiver succeeded
+    Sent { receiver: u64 },
+    /// The transfer to a receiver failed. This does not stop serving the next one.
+    Failed { receiver: u64, error: TransferError },
+}
+
+/**
+ * Send an offer to several receivers, one after the other, each with its own code of `code_length` words
+ *
+ * The codes get allocated on the server of `config` and reported to `event_handler` with [`SendManyEvent::Code`],
+ * once the previous receiver is done. Each receiver gets a fresh offer from `make_offer`, so
+ * that files get read again from the start. Transfers use the default [`Timeouts`](transit::Timeouts); one
+ * that fails gets reported to `event_handler`, and the next receiver is served anyway.
+ *
+ * Stops once the [`SendManyLimits`] are reached or `cancel` resolves, and returns the number of successful
+ * transfers. A running transfer gets cancelled, too. Fails if someone used a wrong code, as somebody may be
+ * guessing them, or if the rendezvous server can't be reached.
+ */
+pub async fn send_many<F>(
+    config: AppConfig<AppVersion>,
+    code_length: usize,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    mut make_offer: impl FnMut() -> F,
+    limits: SendManyLimits,
+    mut event_handler: impl FnMut(SendManyEvent),
+    cancel: impl Future<Output = ()>,
+) -> Result<u64, TransferError>
+where
+    F: Future<Output = std::io::Result<OfferSend>>,
+{
+    let deadline = limits
+        .time
+        .and_then(|time| instant::Instant::now().checked_add(time));
+    let cancel = Box::pin(cancel).shared();
+
+    let mut sent = 0;
+    for receiver in 0.. {
+        if limits
+            .receivers
+            .is_some_and(|receivers| receiver >= receivers)
+        {
+            log::debug!("Served {} receivers, not waiting for more", receiver);
+            break;
+        }
+        let expiry = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(instant::Instant::now()) {
+                Some(expiry) if !expiry.is_zero() => Some(expiry),
+                _ => break,
+            },
+            None => None,
+        };
+
+        let mailbox_connection = MailboxConnection::create(config.clone(), code_length).await?;
+        event_handler(SendManyEvent::Code {
+            receiver,
+            code: mailbox_connection.code.clone(),
+        });
+        let wormhole =
+            match Wormhole::connect_inner(mailbox_connection, expiry, cancel.clone()).await {
+                Ok(wormhole) => wormhole,
+                Err(WormholeError::CodeExpired | WormholeError::Cancelled) => break,
+                Err(error) => return Err(error.into()),
+            };
+        event_handler(SendManyEvent::Connected { receiver });
+
+        let result = async {
+            let offer = make_offer().await?;
+            super::send(
+                wormhole,
+                relay_hints.clone(),
+                transit_abilities,
+                offer,
+                |_| {},
+                |_, _| {},
+                cancel.clone(),
+            )
+            .await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                sent += 1;
+                event_handler(SendManyEvent::Sent { receiver });
+            },
+            Err(error) => {
+                log::debug!("Sending to receiver #{} failed: {}", receiver, error);
+                event_handler(SendManyEvent::Failed { receiver, error });
+            },
+        }
+
+        if cancel.peek().is_some() {
+            break;
+        }
+    }
+
+    Ok(sent)
+}
@@ -0,0 +1,100 @@
+//! Guess the MIME type of a file from its first bytes
+
+use crate::schema::Schema;
+
+/** Read this many bytes of a file for sniffing its type */
+pub(super) const SNIFF_LEN: usize = 512;
+
+/* RFC 6838 limits type and subtype to 127 characters each */
+pub(super) static MIME_TYPE_SCHEMA: Schema = Schema::String { max_len: 255 };
+
+/* Magic numbers at the start of the file */
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"\x7fELF", "application/x-elf"),
+];
+
+/**
+ * Guess the MIME type of a file from its first bytes
+ *
+ * Recognizes common image, audio, video, document and archive formats by their magic numbers.
+ * Other content is reported as `text/plain` if it is valid UTF-8, and not at all otherwise.
+ * About 512 bytes are enough; passing more does not hurt.
+ */
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return Some(mime_type);
+    }
+    /* Container formats with the signature at an offset */
+    match (data.get(..4), data.get(4..8), data.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return Some("image/webp"),
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return Some("audio/wav"),
+        (_, Some(b"ftyp"), _) => return Some("video/mp4"),
+        _ => {},
+    }
+    if data.get(257..262) == Some(&b"ustar"[..]) {
+        return Some("application/x-tar");
+    }
+
+    /* The sniffed data may end within a multi-byte character */
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(error) if error.error_len().is_none() => {
+            std::str::from_utf8(&data[..error.valid_up_to()]).unwrap()
+        },
+        Err(_) => return None,
+    };
+    if text.is_empty() || text.contains('\0') {
+        return None;
+    }
+    Some("text/plain")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime_type() {
+        assert_eq!(
+            sniff_mime_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(
+            sniff_mime_type(b"RIFF\x24\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime_type(b"\0\0\0\x20ftypisom"), Some("video/mp4"));
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff_mime_type(&tar), Some("application/x-tar"));
+
+        assert_eq!(sniff_mime_type("Grüße".as_bytes()), Some("text/plain"));
+        /* Cut off within the 'ü' */
+        assert_eq!(
+            sniff_mime_type(&"Grüße".as_bytes()[..3]),
+            Some("text/plain")
+        );
+        assert_eq!(sniff_mime_type(b"\xff\xfe\0\x01"), None);
+        assert_eq!(sniff_mime_type(b""), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Move received files from their staging location to the final one
+//!
+//! Receivers write into a temporary directory first and move the result into place once the
+//! transfer completed, so that a failed transfer never leaves half written files at the
+//! destination. A plain rename does that atomically, but only within one file system.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/**
+ * Move a received file or directory from `from` to `to`
+ *
+ * If both are on the same file system, this is a rename. Otherwise the data gets copied next
+ * to the destination and synced to disk first, and only then renamed to `to`. Either way, `to`
+ * either does not exist or is complete; it never contains partial data. The source is removed
+ * afterwards.
+ *
+ * Like a rename, this replaces existing files at `to`. Check for them beforehand if that is
+ * not wanted.
+ */
+pub async fn move_into_place(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> Result<(), io::Error> {
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    crate::util::spawn_blocking(move || move_blocking(&from, &to)).await
+}
+
+fn move_blocking(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(err) if is_cross_device(&err) => {
+            log::debug!(
+                "{} and {} are on different file systems, copying instead",
+                from.display(),
+                to.display()
+            );
+        },
+        result => return result,
+    }
+
+    let mut staging = to.as_os_str().to_owned();
+    staging.push(".wormhole-partial");
+    let staging = PathBuf::from(staging);
+
+    let result = copy_synced(from, &staging).and_then(|()| {
+        std::fs::rename(&staging, to)?;
+        if let Some(parent) = to.parent() {
+            sync_dir(parent)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        /* Don't leave a partial copy behind, the source is still intact */
+        let _ = remove(&staging);
+        return Err(err);
+    }
+    remove(from)
+}
+
+fn is_cross_device(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return err.raw_os_error() == Some(libc::EXDEV);
+    /* ERROR_NOT_SAME_DEVICE */
+    #[cfg(windows)]
+    return err.raw_os_error() == Some(17);
+    #[cfg(not(any(unix, windows)))]
+    return false;
+}
+
+/* Copy recursively and make sure everything hit the disk before returning */
+fn copy_synced(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_dir() {
+        std::fs::create_dir(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_synced(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())?;
+        sync_dir(to)
+    } else if metadata.file_type().is_symlink() {
+        copy_symlink(from, to)
+    } else {
+        std::fs::copy(from, to)?;
+        std::fs::File::open(to)?.sync_all()
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Cannot copy symlink {}", from.display()),
+    ))
+}
+
+/* Persist the directory entries. Not possible on all platforms, so this is best effort. */
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_copy_synced() {
+        /* A rename can't be forced to fail, so test the fallback directly */
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        let source = base.join("source");
+        std::fs::create_dir_all(source.join("inner")).unwrap();
+        std::fs::write(source.join("a.txt"), b"hello").unwrap();
+        std::fs::write(source.join("inner/b.txt"), b"world").unwrap();
+
+        let target = base.join("target");
+        copy_synced(&source, &target).unwrap();
+        assert_eq!(std::fs::read(target.join("a.txt")).unwrap(), b"hello");
+        assert_eq!(std::fs::read(target.join("inner/b.txt")).unwrap(), b"world");
+
+        /* The regular path, within one file system */
+        let moved = base.join("moved");
+        async_std::task::block_on(move_into_place(&target, &moved)).unwrap();
+        assert!(!target.exists());
+        assert_eq!(std::fs::read(moved.join("a.txt")).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,301 @@
+//! Receive offers without asking anybody, for scripts and services
+//!
+//! A [`ReceivePolicy`] describes up front which offers to take and where to put them.
+//! [`ReceiveRequest::accept_with_policy`] then either receives the offer or rejects it, without
+//! calling back into the application for decisions.
+
+use super::{
+    local_filename, move_into_place, ExtractProgress, FilenamePolicy, ReceiveRequest, SyncOnClose,
+    TransferError,
+};
+use crate::transit;
+use futures::Future;
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+};
+
+/// What to do if a received file or directory already exists at the destination
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverwritePolicy {
+    /// Reject the offer
+    #[default]
+    Reject,
+    /// Replace the existing file or directory
+    Replace,
+    /// Pick a new name, like `file (1).txt`
+    Rename,
+}
+
+/**
+ * Everything an unattended receiver needs to decide about an offer
+ *
+ * Offers get received into a staging directory within [`target_dir`](Self::target_dir) first, and
+ * moved into place once complete. Nothing gets left behind if the transfer fails.
+ */
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ReceivePolicy {
+    /// Where to put the received files and directories
+    pub target_dir: PathBuf,
+    /// Reject offers with more bytes than this
+    pub max_size: Option<u64>,
+    /// What to do about existing files
+    pub overwrite: OverwritePolicy,
+    /// What to do about file names that can't be used on this platform
+    pub filenames: FilenamePolicy,
+    /// Unpack tar archives offered with the v1 protocol, which is how other implementations send directories
+    pub extract_archives: bool,
+    /// Bound the transfer, see [`ReceiveRequest::set_timeouts`]
+    pub timeouts: Option<transit::Timeouts>,
+}
+
+impl ReceivePolicy {
+    /** Accept everything into `target_dir`, rejecting offers that would overwrite something */
+    pub fn new(target_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            target_dir: target_dir.into(),
+            max_size: None,
+            overwrite: OverwritePolicy::default(),
+            filenames: FilenamePolicy::default(),
+            extract_archives: true,
+            timeouts: None,
+        }
+    }
+}
+
+/// The outcome of [`ReceiveRequest::accept_with_policy`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PolicyDecision {
+    /// The offer was received, these are the top level files and directories that were created
+    Accepted(Vec<PathBuf>),
+    /// The offer was rejected, and the peer has been told so
+    Rejected(PolicyRejection),
+    /// The transfer got cancelled, nothing was kept
+    Cancelled,
+}
+
+/// Why a [`ReceivePolicy`] rejected an offer
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PolicyRejection {
+    #[error("The offer has {size} bytes, but at most {max_size} are allowed")]
+    TooLarge { size: u64, max_size: u64 },
+    #[error("{} already exists", _0.display())]
+    Exists(PathBuf),
+    #[error("File name '{}' can't be used on this platform", _0)]
+    InvalidFilename(String),
+}
+
+impl ReceiveRequest {
+    /**
+     * Accept or reject the offer as `policy` says
+     *
+     * Offers that are too large, that contain invalid file names (depending on the [`FilenamePolicy`]), or
+     * that would overwrite existing files (depending on the [`OverwritePolicy`]) get rejected.
+     */
+    pub async fn accept_with_policy(
+        mut self,
+        policy: &ReceivePolicy,
+        transit_handler: impl FnOnce(transit::TransitInfo),
+        mut progress_handler: impl FnMut(u64, u64) + 'static,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<PolicyDecision, TransferError> {
+        if let Some(timeouts) = policy.timeouts {
+            self.set_timeouts(timeouts);
+        }
+
+        /* Check the offer and find out which top level names it will create */
+        let extract = matches!(&self, ReceiveRequest::V1(request) if policy.extract_archives && request.is_tar_archive());
+        let (size, names) = match &self {
+            ReceiveRequest::V1(request) => {
+                let name = if extract {
+                    let name = match request.compression() {
+                        Some(compression) => compression.decompressed_filename(&request.filename),
+                        None => request.filename.clone(),
+                    };
+                    name.trim_end_matches(".tar").to_owned()
+                } else {
+                    request.filename.clone()
+                };
+                (request.filesize, vec![name])
+            },
+            ReceiveRequest::V2(request) => {
+                let offer = request.offer();
+                let names = offer.top_level_paths().cloned().collect();
+                (offer.total_size(), names)
+            },
+        };
+        let rejection = match policy.max_size {
+            Some(max_size) if size > max_size => Some(PolicyRejection::TooLarge { size, max_size }),
+            _ => check_names(policy, &names),
+        };
+        if let Some(rejection) = rejection {
+            log::debug!("Rejecting the offer: {}", rejection);
+            match self {
+                ReceiveRequest::V1(request) => request.reject().await?,
+                ReceiveRequest::V2(request) => request.reject().await?,
+            }
+            return Ok(PolicyDecision::Rejected(rejection));
+        }
+
+        /* Receive into a staging directory */
+        let staging = policy
+            .target_dir
+            .join(format!(".wormhole-{:08x}", rand::random::<u32>()));
+        async_std::fs::create_dir_all(&staging).await?;
+        let cancelled = Cell::new(false);
+        let cancel = async {
+            cancel.await;
+            cancelled.set(true);
+        };
+        let result = async {
+            match self {
+                ReceiveRequest::V1(request) if extract => {
+                    request
+                        .accept_extract(
+                            transit_handler,
+                            &staging,
+                            policy.filenames,
+                            move |progress| {
+                                if let ExtractProgress::Bytes {
+                                    received, total, ..
+                                } = progress
+                                {
+                                    progress_handler(received, total)
+                                }
+                            },
+                            cancel,
+                        )
+                        .await
+                },
+                ReceiveRequest::V1(request) => {
+                    let path = staging.join(local_filename(&names[0], policy.filenames)?);
+                    let file = async_std::fs::File::create(&path).await?;
+                    super::preallocate(&path, request.filesize).await?;
+                    let mut file = SyncOnClose::new(file);
+                    request
+                        .accept(transit_handler, &mut file, progress_handler, cancel)
+                        .await
+                },
+                ReceiveRequest::V2(request) => {
+                    let offer = request.offer();
+                    offer
+                        .create_directories_with_policy(&staging, policy.filenames)
+                        .await?;
+                    let answer = offer.accept_all_with_policy(&staging, policy.filenames)?;
+                    request
+                        .accept(transit_handler, answer, progress_handler, cancel)
+                        .await
+                },
+            }
+        }
+        .await;
+
+        let result = match result {
+            Ok(()) if cancelled.get() => Ok(PolicyDecision::Cancelled),
+            Ok(()) => move_all_into_place(policy, &staging)
+                .await
+                .map(PolicyDecision::Accepted),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = async_std::fs::remove_dir_all(&staging).await {
+            log::warn!("Failed to remove {}: {}", staging.display(), error);
+        }
+        result
+    }
+}
+
+/** Reject invalid or (depending on the policy) existing names */
+fn check_names(policy: &ReceivePolicy, names: &[String]) -> Option<PolicyRejection> {
+    for name in names {
+        let Ok(name) = local_filename(name, policy.filenames) else {
+            return Some(PolicyRejection::InvalidFilename(name.clone()));
+        };
+        let path = policy.target_dir.join(name);
+        if policy.overwrite == OverwritePolicy::Reject && path.exists() {
+            return Some(PolicyRejection::Exists(path));
+        }
+    }
+    None
+}
+
+/** Move everything received into the target directory, returning the new paths */
+async fn move_all_into_place(
+    policy: &ReceivePolicy,
+    staging: &Path,
+) -> Result<Vec<PathBuf>, TransferError> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(staging)? {
+        let entry = entry?;
+        let mut target = policy.target_dir.join(entry.file_name());
+        if target.exists() {
+            match policy.overwrite {
+                OverwritePolicy::Reject => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        PolicyRejection::Exists(target),
+                    )
+                    .into())
+                },
+                OverwritePolicy::Replace if target.is_dir() => {
+                    async_std::fs::remove_dir_all(&target).await?
+                },
+                OverwritePolicy::Replace => async_std::fs::remove_file(&target).await?,
+                OverwritePolicy::Rename => target = unique_path(&target),
+            }
+        }
+        move_into_place(entry.path(), &target).await?;
+        paths.push(target);
+    }
+    Ok(paths)
+}
+
+/** Add a number to the name, like `file (1).txt`, until it does not exist yet */
+fn unique_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{stem} ({i}){extension}")))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_names() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+        std::fs::write(dir.join("a (1).txt"), b"hello").unwrap();
+
+        let mut policy = ReceivePolicy::new(dir);
+        policy.filenames = FilenamePolicy::Reject;
+        assert_eq!(
+            check_names(&policy, &["a.txt".into()]),
+            Some(PolicyRejection::Exists(dir.join("a.txt")))
+        );
+        assert_eq!(check_names(&policy, &["b.txt".into()]), None);
+        assert_eq!(
+            check_names(&policy, &["a\0b".into()]),
+            Some(PolicyRejection::InvalidFilename("a\0b".into()))
+        );
+
+        policy.overwrite = OverwritePolicy::Rename;
+        assert_eq!(check_names(&policy, &["a.txt".into()]), None);
+        assert_eq!(unique_path(&dir.join("a.txt")), dir.join("a (2).txt"));
+        assert_eq!(unique_path(&dir.join("b")), dir.join("b (1)"));
+    }
+}
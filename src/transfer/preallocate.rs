@@ -0,0 +1,65 @@
+//! Reserve disk space for received files before the data arrives
+
+use std::{io, path::Path};
+
+/**
+ * Reserve `size` bytes of disk space for the file at `path`, which is about to be received
+ *
+ * This reduces fragmentation and makes a transfer fail right away if the disk or the quota is too
+ * small, instead of after most of the data went over the wire. The file size and content stay the
+ * same, so this works for files that are being appended to, too.
+ *
+ * Returns `Ok(false)` if the platform or the file system does not support it, in which case
+ * nothing happened and receiving works as usual. Only errors about missing space are returned.
+ * Currently only supported on Linux.
+ */
+pub async fn preallocate(path: impl AsRef<Path>, size: u64) -> Result<bool, io::Error> {
+    if size == 0 {
+        return Ok(true);
+    }
+    let path = path.as_ref().to_owned();
+    crate::util::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new().write(true).open(path)?;
+        allocate(&file, size)
+    })
+    .await
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn allocate(file: &std::fs::File, size: u64) -> io::Result<bool> {
+    use rustix::{fs::FallocateFlags, io::Errno};
+
+    match rustix::io::retry_on_intr(|| {
+        rustix::fs::fallocate(file, FallocateFlags::KEEP_SIZE, 0, size)
+    }) {
+        Ok(()) => Ok(true),
+        Err(Errno::OPNOTSUPP | Errno::NOSYS | Errno::INVAL | Errno::NODEV) => {
+            log::debug!("Pre-allocating is not supported by the file system");
+            Ok(false)
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn allocate(_file: &std::fs::File, _size: u64) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preallocate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        async_std::task::block_on(async {
+            let file = async_std::fs::File::create(&path).await.unwrap();
+            /* Whether it is supported depends on the file system, but it must never fail here */
+            preallocate(&path, 1024 * 1024).await.unwrap();
+            /* The size does not change */
+            assert_eq!(file.metadata().await.unwrap().len(), 0);
+        });
+    }
+}
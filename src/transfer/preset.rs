@@ -0,0 +1,100 @@
+//! Tuned defaults for common use cases
+
+use super::FilenamePolicy;
+use crate::transit;
+use std::time::Duration;
+
+/**
+ * A bundle of settings for a typical kind of transfer
+ *
+ * Pass it to [`send_with_preset`](super::send_with_preset) or [`request_with_preset`](super::request_with_preset),
+ * or use the individual values with the lower level functions. For port forwarding, there are the
+ * [`serve_options`](Self::serve_options), and the [`timeouts`](Self::timeouts) for
+ * [`ConnectOffer::set_timeouts`](crate::forwarding::ConnectOffer::set_timeouts).
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Preset {
+    /// Small files with a human waiting for them: low latency, keep the progress smooth
+    #[default]
+    Interactive,
+    /// Large amounts of data: bigger records for less overhead
+    Bulk,
+    /// Don't reveal our IP address to the peer and reject anything suspicious
+    Paranoid,
+}
+
+impl Preset {
+    /** Which connection types to use. [`Preset::Paranoid`] only goes through the relay. */
+    pub fn transit_abilities(self) -> transit::Abilities {
+        match self {
+            Preset::Interactive | Preset::Bulk => transit::Abilities::ALL_ABILITIES,
+            Preset::Paranoid => transit::Abilities::FORCE_RELAY,
+        }
+    }
+
+    /** Size of the records a file gets split into when sending */
+    pub fn record_size(self) -> usize {
+        match self {
+            Preset::Interactive | Preset::Paranoid => 16 * 1024,
+            Preset::Bulk => 256 * 1024,
+        }
+    }
+
+    /** How often to ping while waiting for the user, see [`transit::DEFAULT_KEEPALIVE_INTERVAL`] */
+    pub fn keepalive_interval(self) -> Duration {
+        match self {
+            Preset::Interactive | Preset::Paranoid => transit::DEFAULT_KEEPALIVE_INTERVAL,
+            /* Bulk transfers tend to run unattended on servers without aggressive NATs */
+            Preset::Bulk => Duration::from_secs(60),
+        }
+    }
+
+    /** When to give up on the peer */
+    pub fn timeouts(self) -> transit::Timeouts {
+        match self {
+            Preset::Interactive => transit::Timeouts::default(),
+            /* Nobody is watching, so a hung peer must not block forever */
+            Preset::Bulk => transit::Timeouts {
+                idle_peer: Some(Duration::from_secs(5 * 60)),
+                ..Default::default()
+            },
+            /* Don't leave an offer or a connection open for longer than necessary */
+            Preset::Paranoid => transit::Timeouts {
+                offer: Some(Duration::from_secs(10 * 60)),
+                transit: transit::ConnectTimeouts {
+                    handshake: Some(Duration::from_secs(30)),
+                    ..Default::default()
+                },
+                idle_peer: Some(Duration::from_secs(60)),
+            },
+        }
+    }
+
+    /** Whether to check every message from the peer against the protocol, see [`Wormhole::set_strict`](crate::Wormhole::set_strict) */
+    pub fn strict(self) -> bool {
+        matches!(self, Preset::Paranoid)
+    }
+
+    /**
+     * The settings of a forwarding server
+     *
+     * Only the timeouts are taken from the preset. The transit abilities are part of the
+     * [`AppVersion`](crate::forwarding::AppVersion), and strictness is set on the [`Wormhole`](crate::Wormhole).
+     */
+    #[cfg(feature = "forwarding")]
+    pub fn serve_options(self) -> crate::forwarding::ServeOptions {
+        crate::forwarding::ServeOptions {
+            timeouts: self.timeouts(),
+            ..Default::default()
+        }
+    }
+
+    /** What to do with received file names that can't be used on this platform */
+    pub fn filename_policy(self) -> FilenamePolicy {
+        match self {
+            Preset::Interactive | Preset::Bulk => FilenamePolicy::Replace,
+            Preset::Paranoid => FilenamePolicy::Reject,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Read the next chunks of a file while the previous ones are still being sent
+//!
+//! Without this, every read waits for the previous send and vice versa. On spinning disks and network
+//! file systems, where reads regularly stall, the two then add up instead of overlapping.
+
+use futures::{channel::mpsc, AsyncRead, AsyncReadExt, Future, SinkExt, Stream};
+use std::io;
+
+/// Number of chunks that may be read ahead of the sender
+pub(super) const READ_AHEAD_DEPTH: usize = 4;
+
+/**
+ * Read chunks of up to `chunk_size` bytes from `reader`, ahead of their consumption
+ *
+ * Returns a future doing the reading and the stream of chunks. Both must be polled concurrently,
+ * for example with [`futures::join!`]. At most [`READ_AHEAD_DEPTH`] chunks get buffered. A read error
+ * ends the stream, and dropping the stream stops the reading.
+ */
+pub(super) fn read_ahead<'a>(
+    mut reader: impl AsyncRead + Unpin + 'a,
+    chunk_size: usize,
+) -> (
+    impl Future<Output = ()> + 'a,
+    impl Stream<Item = io::Result<Vec<u8>>> + Unpin,
+) {
+    /* The channel has one slot per sender on top of its buffer */
+    let (mut tx, rx) = mpsc::channel(READ_AHEAD_DEPTH - 1);
+    let reading = async move {
+        loop {
+            let mut chunk = vec![0; chunk_size];
+            let chunk = match reader.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    Ok(chunk)
+                },
+                Err(err) => Err(err),
+            };
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    };
+    (reading, rx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_read_ahead() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let (reading, chunks) = read_ahead(&data[..], 30);
+        let ((), chunks) = async_std::task::block_on(async {
+            futures::join!(reading, chunks.collect::<Vec<_>>())
+        });
+        let chunks = chunks.into_iter().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![30, 30, 30, 10]
+        );
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn test_read_ahead_stops() {
+        /* An endless reader stops once the consumer is gone */
+        let (reading, mut chunks) = read_ahead(futures::io::repeat(0), 10);
+        async_std::task::block_on(async {
+            futures::join!(reading, async move {
+                chunks.next().await.unwrap().unwrap();
+                drop(chunks);
+            })
+        });
+    }
+}
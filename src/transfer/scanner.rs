@@ -0,0 +1,34 @@
+//! Inspect received content before the transfer gets acknowledged
+
+use async_trait::async_trait;
+
+/**
+ * A hook to check received content, like a virus scanner
+ *
+ * Install it with [`ReceiveRequest::set_scanner`](super::ReceiveRequest::set_scanner). It sees all
+ * received data while it is being written to the content handler, and gets asked for a verdict once
+ * everything arrived, before the transfer is acknowledged to the sender.
+ *
+ * Rejecting the content ends the transfer with [`TransferError::ContentRejected`](super::TransferError::ContentRejected)
+ * on both sides. The data already written to the content handler is not touched by this crate: whoever
+ * provided the content handler must delete it upon that error.
+ */
+#[async_trait]
+pub trait ContentScanner: Send {
+    /**
+     * A new file starts
+     *
+     * With the v1 protocol, this is called once with the offered file name. The path is untrusted input.
+     */
+    async fn begin_file(&mut self, _path: &[String], _size: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    /** Inspect the next chunk of received data. Returning an error rejects the content right away. */
+    async fn scan(&mut self, _chunk: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /** All content has been received. Return an error with the reason to reject it. */
+    async fn finish(&mut self) -> Result<(), String>;
+}
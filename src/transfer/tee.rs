@@ -0,0 +1,137 @@
+//! Feed received data into several sinks at once
+
+use futures::AsyncWrite;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/**
+ * A writer that copies everything into multiple sinks
+ *
+ * Pass it as content handler of a receive to write the file to disk and, at the same time,
+ * stream it somewhere else (like an upload that verifies a hash), without reading the file back
+ * afterwards.
+ *
+ * Each write is buffered once and handed to all sinks, which may accept it at their own pace.
+ * The next write only goes through once all sinks got the previous one, so the slowest sink
+ * determines the speed. A failing sink fails the whole write.
+ */
+#[derive(Default)]
+pub struct Tee<'a> {
+    sinks: Vec<Box<dyn AsyncWrite + Unpin + Send + 'a>>,
+    /* The last write, and how much of it each sink has consumed */
+    buffer: Vec<u8>,
+    written: Vec<usize>,
+}
+
+impl<'a> Tee<'a> {
+    /** A tee without any sinks yet. Writing to it discards the data. */
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /** Add another sink. Only data written afterwards will reach it. */
+    pub fn add_sink(&mut self, sink: impl AsyncWrite + Unpin + Send + 'a) {
+        self.sinks.push(Box::new(sink));
+        self.written.push(self.buffer.len());
+    }
+
+    /** Builder style version of [`add_sink`](Self::add_sink) */
+    pub fn with_sink(mut self, sink: impl AsyncWrite + Unpin + Send + 'a) -> Self {
+        self.add_sink(sink);
+        self
+    }
+
+    /* Hand the buffered data to all sinks that did not get it yet */
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut pending = false;
+        for (sink, written) in self.sinks.iter_mut().zip(self.written.iter_mut()) {
+            while *written < self.buffer.len() {
+                match Pin::new(&mut *sink).poll_write(cx, &self.buffer[*written..]) {
+                    Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    Poll::Ready(Ok(n)) => *written += n,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        pending = true;
+                        break;
+                    },
+                }
+            }
+        }
+        if pending {
+            return Poll::Pending;
+        }
+        self.buffer.clear();
+        self.written.iter_mut().for_each(|written| *written = 0);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Tee<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        futures::ready!(self.poll_drain(cx))?;
+        self.buffer.extend_from_slice(buf);
+        /* Try right away, but the data is ours now even if the sinks are busy */
+        if let Poll::Ready(Err(err)) = self.poll_drain(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.poll_drain(cx))?;
+        poll_all(&mut self.sinks, |sink| Pin::new(sink).poll_flush(cx))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(self.poll_drain(cx))?;
+        poll_all(&mut self.sinks, |sink| Pin::new(sink).poll_close(cx))
+    }
+}
+
+/* Poll every sink, even if some of them are still pending */
+fn poll_all<S>(
+    sinks: &mut [S],
+    mut poll: impl FnMut(&mut S) -> Poll<io::Result<()>>,
+) -> Poll<io::Result<()>> {
+    let mut pending = false;
+    for sink in sinks {
+        match poll(sink) {
+            Poll::Ready(Ok(())) => {},
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => pending = true,
+        }
+    }
+    if pending {
+        Poll::Pending
+    } else {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::AsyncWriteExt;
+
+    #[test]
+    fn test_tee() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        async_std::task::block_on(async {
+            let mut tee = Tee::new().with_sink(&mut first);
+            tee.write_all(b"hello ").await.unwrap();
+            tee.add_sink(&mut second);
+            tee.write_all(b"world").await.unwrap();
+            tee.close().await.unwrap();
+        });
+        assert_eq!(first, b"hello world");
+        assert_eq!(second, b"world");
+    }
+}
@@ -0,0 +1,110 @@
+//! Short text messages instead of files, like `wormhole send --text`
+//!
+//! The text goes in the offer itself, over the wormhole: there is no transit connection. The receiver
+//! confirms it with a `message_ack` answer. This only exists in [version 1](super::v1) of the protocol, which
+//! every implementation speaks.
+
+use super::*;
+use crate::transfer::v1::{AnswerMessage, OfferMessage};
+
+/**
+ * Send a text message to the peer, and wait until it confirmed receiving it
+ *
+ * The wormhole gets closed afterwards. If `cancel` resolves first, the peer gets told so and this returns
+ * successfully.
+ */
+pub async fn send_text(
+    mut wormhole: Wormhole,
+    text: impl Into<String>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
+    let text = text.into();
+    let run = Box::pin(async {
+        debug!("Sending text message");
+        wormhole
+            .send_json(&PeerMessage::offer_message_v1(text))
+            .await?;
+
+        match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+            PeerMessage::Answer(AnswerMessage::MessageAck(ack)) => {
+                ensure!(ack == "ok", TransferError::AckError);
+                Ok(())
+            },
+            other => bail!(TransferError::unexpected_message(
+                "answer/message_ack",
+                other
+            )),
+        }
+    });
+
+    futures::pin_mut!(cancel);
+    let result = cancel::cancellable_2(run, cancel).await;
+    cancel::handle_run_result(wormhole, result, &Default::default()).await
+}
+
+/**
+ * Wait for a text message from the peer, confirm it and close the wormhole
+ *
+ * Offers of files or directories get rejected with [`TransferError::UnsupportedOffer`], use
+ * [`request`](super::request) for these. Returns `None` if `cancel` resolved first.
+ *
+ * **Security warning:** the text is untrusted input. Don't show it on a terminal without escaping control
+ * characters.
+ */
+pub async fn receive_text(
+    mut wormhole: Wormhole,
+    cancel: impl Future<Output = ()>,
+) -> Result<Option<String>, TransferError> {
+    wormhole.set_validator(|message| PEER_MESSAGE_SCHEMA.validate_json(message));
+    let run = Box::pin(async {
+        loop {
+            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+                PeerMessage::Offer(OfferMessage::Message(text)) => break Ok(text),
+                /* Senders of files start with their transit hints, the offer follows */
+                PeerMessage::Transit(_) => continue,
+                PeerMessage::Offer(_) | PeerMessage::TransitV2(_) => {
+                    bail!(TransferError::UnsupportedOffer)
+                },
+                other => bail!(TransferError::unexpected_message("offer", other)),
+            }
+        }
+    });
+
+    futures::pin_mut!(cancel);
+    let result = cancel::cancellable_2(run, cancel).await;
+    let Some((text, mut wormhole, _cancel)) =
+        cancel::handle_run_result_noclose(wormhole, result, &Default::default()).await?
+    else {
+        return Ok(None);
+    };
+    wormhole
+        .send_json(&PeerMessage::message_ack_v1("ok"))
+        .await?;
+    wormhole.close().await?;
+    Ok(Some(text))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_messages() {
+        /* As sent by the Python implementation */
+        assert_eq!(
+            serde_json::to_value(PeerMessage::offer_message_v1("hello")).unwrap(),
+            json!({"offer": {"message": "hello"}})
+        );
+        assert_eq!(
+            serde_json::to_value(PeerMessage::message_ack_v1("ok")).unwrap(),
+            json!({"answer": {"message_ack": "ok"}})
+        );
+        PEER_MESSAGE_SCHEMA
+            .validate_json(br#"{"offer": {"message": "hello"}}"#)
+            .unwrap();
+        PEER_MESSAGE_SCHEMA
+            .validate_json(br#"{"answer": {"message_ack": "ok"}}"#)
+            .unwrap();
+    }
+}
@@ -1,10 +1,13 @@
-use futures::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    StreamExt, TryFutureExt,
-};
-use sha2::{digest::FixedOutput, Digest, Sha256};
+//! Version 1 of the file transfer protocol
+//!
+//! This is the protocol of the original Python implementation. A single file, or a directory
+//! packed into a zip (Python) or tar (this crate) archive, is sent over the transit connection.
+//! Use the functions in the [parent module](super) to negotiate the version with the peer.
+
+use futures::{io::AsyncWriteExt, StreamExt, TryFutureExt};
 
 use super::*;
+use crate::schema::{self, Field, Schema, UnknownFields, UnknownVariants};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "kebab-case")]
@@ -13,6 +16,18 @@ pub enum OfferMessage {
     File {
         filename: String,
         filesize: u64,
+        /** Only sent by this implementation, and only if known. See [`Offer::mime_type`](super::Offer::mime_type) */
+        #[serde(default, rename = "mimetype", skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+        /** Only sent by this implementation. See [`Offer::metadata`](super::Offer::metadata) */
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<crate::OfferMetadata>,
+        /** Only sent by this implementation: for how many seconds the sender waits for an answer, if limited */
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        valid_for: Option<u64>,
+        /** Only sent by this implementation: the file is a tar archive of a directory. See [`ReceiveRequest::is_directory`] */
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        directory: bool,
     },
     Directory {
         dirname: String,
@@ -42,21 +57,159 @@ pub struct TransitV1 {
     pub hints_v1: transit::Hints,
 }
 
+pub(super) static OFFER_SCHEMA: Schema = Schema::Enum {
+    variants: &[
+        ("message", Some(&schema::STRING)),
+        (
+            "file",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("filename", &schema::STRING),
+                    Field::required("filesize", &schema::U64),
+                    Field::optional("mimetype", &super::mime::MIME_TYPE_SCHEMA),
+                    Field::optional("metadata", &crate::metadata::OFFER_METADATA_SCHEMA),
+                    Field::optional("valid_for", &schema::U64),
+                    Field::optional("directory", &schema::BOOL),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "directory",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("dirname", &schema::STRING),
+                    Field::required("mode", &schema::STRING),
+                    Field::required("zipsize", &schema::U64),
+                    Field::required("numbytes", &schema::U64),
+                    Field::required("numfiles", &schema::U64),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+    ],
+    unknown_variants: UnknownVariants::Reject,
+};
+
+pub(super) static ANSWER_SCHEMA: Schema = Schema::Enum {
+    variants: &[
+        ("message_ack", Some(&schema::STRING)),
+        ("file_ack", Some(&schema::STRING)),
+    ],
+    unknown_variants: UnknownVariants::Reject,
+};
+
+pub(super) static TRANSIT_SCHEMA: Schema = Schema::Object {
+    fields: &[
+        Field::required("abilities-v1", &transit::ABILITIES_SCHEMA),
+        Field::required("hints-v1", &transit::HINTS_SCHEMA),
+    ],
+    unknown_fields: UnknownFields::Reject,
+};
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 struct TransitAck {
     pub ack: String,
-    pub sha256: String,
+    /* Why the content was rejected or not written, if the ack is "rejected" or "failed". Only sent by this implementation */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /* Only one of the checksums is set, depending on the negotiated algorithm */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake2b: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
 }
 
+static TRANSIT_ACK_SCHEMA: Schema = Schema::Object {
+    fields: &[
+        Field::required("ack", &schema::STRING),
+        Field::optional("reason", &schema::STRING),
+        Field::optional("sha256", &schema::STRING),
+        Field::optional("blake2b", &schema::STRING),
+        Field::optional("blake3", &schema::STRING),
+    ],
+    unknown_fields: UnknownFields::Reject,
+};
+
 impl TransitAck {
-    pub fn new(msg: impl Into<String>, sha256: impl Into<String>) -> Self {
-        TransitAck {
+    pub fn new(
+        msg: impl Into<String>,
+        algorithm: HashAlgorithm,
+        checksum: impl Into<String>,
+    ) -> Self {
+        let mut ack = TransitAck {
             ack: msg.into(),
-            sha256: sha256.into(),
+            reason: None,
+            sha256: None,
+            blake2b: None,
+            blake3: None,
+        };
+        *ack.checksum_mut(algorithm) = Some(checksum.into());
+        ack
+    }
+
+    /** The receiver's [`ContentScanner`](super::ContentScanner) rejected the content */
+    pub fn rejected(reason: impl Into<String>) -> Self {
+        TransitAck {
+            ack: "rejected".into(),
+            reason: Some(reason.into()),
+            sha256: None,
+            blake2b: None,
+            blake3: None,
+        }
+    }
+
+    /* The reason why the receiver rejected the content, if it did */
+    pub fn rejection(&self) -> Option<String> {
+        (self.ack == "rejected").then(|| self.reason.clone().unwrap_or_default())
+    }
+
+    /** The second ack: the receiver closed its content handler, see [`AppVersion::supports_written_ack`] */
+    pub fn written() -> Self {
+        TransitAck {
+            ack: "written".into(),
+            reason: None,
+            sha256: None,
+            blake2b: None,
+            blake3: None,
+        }
+    }
+
+    /** The second ack, if closing the content handler failed */
+    pub fn not_written(reason: impl Into<String>) -> Self {
+        TransitAck {
+            ack: "failed".into(),
+            reason: Some(reason.into()),
+            sha256: None,
+            blake2b: None,
+            blake3: None,
+        }
+    }
+
+    /* Whether the second ack confirms that the data got written */
+    pub fn check_written(&self) -> Result<(), String> {
+        match self.ack.as_str() {
+            "written" => Ok(()),
+            "failed" => Err(self.reason.clone().unwrap_or_default()),
+            other => Err(format!("Expected the 'written' ack, got '{other}'")),
+        }
+    }
+
+    fn checksum_mut(&mut self, algorithm: HashAlgorithm) -> &mut Option<String> {
+        match algorithm {
+            HashAlgorithm::Sha256 => &mut self.sha256,
+            HashAlgorithm::Blake2b => &mut self.blake2b,
+            HashAlgorithm::Blake3 => &mut self.blake3,
         }
     }
 
+    pub fn matches(mut self, algorithm: HashAlgorithm, checksum: &[u8]) -> bool {
+        self.checksum_mut(algorithm).take() == Some(hex::encode(checksum))
+    }
+
     #[cfg(test)]
     pub fn serialize(&self) -> String {
         json!(self).to_string()
@@ -67,16 +220,20 @@ impl TransitAck {
     }
 }
 
-pub async fn send(
+pub(super) async fn send(
     wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
+    record_size: usize,
+    timeouts: transit::Timeouts,
     offer: OfferSend,
     progress_handler: impl FnMut(u64, u64) + 'static,
     transit_handler: impl FnOnce(transit::TransitInfo),
     _peer_version: AppVersion,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
+    let hash_algorithm = super::hash_algorithm(&wormhole);
+    let metadata = offer.metadata.clone();
     if offer.is_multiple() {
         let folder = OfferSendEntry::Directory {
             content: offer.content,
@@ -86,7 +243,11 @@ pub async fn send(
             relay_hints,
             "<unnamed folder>".into(),
             folder,
+            metadata,
             transit_abilities,
+            record_size,
+            timeouts,
+            hash_algorithm,
             transit_handler,
             progress_handler,
             cancel,
@@ -99,7 +260,11 @@ pub async fn send(
             relay_hints,
             folder_name,
             folder,
+            metadata,
             transit_abilities,
+            record_size,
+            timeouts,
+            hash_algorithm,
             transit_handler,
             progress_handler,
             cancel,
@@ -107,6 +272,7 @@ pub async fn send(
         .await
     } else {
         let (file_name, file) = offer.content.into_iter().next().unwrap();
+        let mime_type = offer.mime_types.get(&file_name).cloned();
         let (mut file, file_size) = match file {
             OfferSendEntry::RegularFile { content, size } => {
                 /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
@@ -122,7 +288,12 @@ pub async fn send(
             &mut file,
             file_name,
             file_size,
+            mime_type,
+            metadata,
             transit_abilities,
+            record_size,
+            timeouts,
+            hash_algorithm,
             transit_handler,
             progress_handler,
             cancel,
@@ -131,13 +302,18 @@ pub async fn send(
     }
 }
 
-pub async fn send_file<F, G, H>(
+pub(super) async fn send_file<F, G, H>(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     file: &mut F,
     file_name: impl Into<String>,
     file_size: u64,
+    mime_type: Option<String>,
+    metadata: Option<crate::OfferMetadata>,
     transit_abilities: transit::Abilities,
+    record_size: usize,
+    timeouts: transit::Timeouts,
+    hash_algorithm: HashAlgorithm,
     transit_handler: G,
     progress_handler: H,
     cancel: impl Future<Output = ()>,
@@ -147,8 +323,13 @@ where
     G: FnOnce(transit::TransitInfo),
     H: FnMut(u64, u64) + 'static,
 {
+    let file_name = file_name.into();
+    let abort = super::abort_tracker(&wormhole);
+    abort.start_file(vec![file_name.clone()], 0);
+    let progress_handler = abort.progress_handler(progress_handler);
     let run = Box::pin(async {
-        let connector = transit::init(transit_abilities, None, relay_hints).await?;
+        let mut connector = transit::init(transit_abilities, None, relay_hints).await?;
+        connector.set_timeouts(timeouts.transit);
 
         // We want to do some transit
         debug!("Sending transit message '{:?}", connector.our_hints());
@@ -162,7 +343,14 @@ where
         // Send file offer message.
         debug!("Sending file offer");
         wormhole
-            .send_json(&PeerMessage::offer_file_v1(file_name, file_size))
+            .send_json(&PeerMessage::offer_file_v1(
+                file_name.clone(),
+                file_size,
+                mime_type,
+                metadata,
+                timeouts.offer.map(|offer| offer.as_secs()),
+                false,
+            ))
             .await?;
 
         // Wait for their transit response
@@ -179,7 +367,10 @@ where
 
         {
             // Wait for file_ack
-            let fileack_msg = wormhole.receive_json::<PeerMessage>().await??;
+            let fileack_msg = super::answer_within(timeouts.offer, &abort, async {
+                Ok(wormhole.receive_json::<PeerMessage>().await??)
+            })
+            .await?;
             debug!("Received file ack message: {:?}", fileack_msg);
 
             match fileack_msg.check_err()? {
@@ -201,7 +392,13 @@ where
                 their_abilities,
                 Arc::new(their_hints),
             )
-            .await?;
+            .await
+            .map_err(|error| TransferError::from(error).context("Connecting to the receiver"))?;
+        transit.set_idle_timeout(timeouts.idle_peer);
+        if wormhole.is_strict() {
+            /* The ack is the only record we receive */
+            transit.set_validator(Some(|record| TRANSIT_ACK_SCHEMA.validate_json(record)));
+        }
         transit_handler(info);
 
         debug!("Beginning file transfer");
@@ -210,16 +407,35 @@ where
         let file = futures::stream::once(futures::future::ready(std::io::Result::Ok(
             Box::new(file) as Box<dyn AsyncRead + Unpin + Send>,
         )));
-        let checksum = v1::send_records(&mut transit, file, file_size, progress_handler).await?;
+        let checksum = v1::send_records(
+            &mut transit,
+            file,
+            file_size,
+            record_size,
+            hash_algorithm,
+            progress_handler,
+        )
+        .await
+        .map_err(|error| error.context(format!("Sending '{}'", file_name)))?;
 
         // 13. wait for the transit ack with sha256 sum from the peer.
         debug!("sent file. Waiting for ack");
         let transit_ack = transit.receive_record().await?;
         let transit_ack_msg = serde_json::from_slice::<TransitAck>(&transit_ack)?;
+        if let Some(reason) = transit_ack_msg.rejection() {
+            bail!(TransferError::ContentRejected(reason));
+        }
         ensure!(
-            transit_ack_msg.sha256 == hex::encode(checksum),
+            transit_ack_msg.matches(hash_algorithm, &checksum),
             TransferError::Checksum
         );
+        if super::written_ack(&wormhole) {
+            debug!("Waiting for the data to be written");
+            let transit_ack = transit.receive_record().await?;
+            serde_json::from_slice::<TransitAck>(&transit_ack)?
+                .check_written()
+                .map_err(TransferError::NotWritten)?;
+        }
         debug!("Transfer complete!");
 
         Ok(())
@@ -227,21 +443,28 @@ where
 
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
-    cancel::handle_run_result(wormhole, result).await
+    cancel::handle_run_result(wormhole, result, &abort).await
 }
 
-pub async fn send_folder(
+pub(super) async fn send_folder(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     mut folder_name: String,
     folder: OfferSendEntry,
+    metadata: Option<crate::OfferMetadata>,
     transit_abilities: transit::Abilities,
+    record_size: usize,
+    timeouts: transit::Timeouts,
+    hash_algorithm: HashAlgorithm,
     transit_handler: impl FnOnce(transit::TransitInfo),
     progress_handler: impl FnMut(u64, u64) + 'static,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
+    let abort = super::abort_tracker(&wormhole);
+    let progress_handler = abort.progress_handler(progress_handler);
     let run = Box::pin(async {
-        let connector = transit::init(transit_abilities, None, relay_hints).await?;
+        let mut connector = transit::init(transit_abilities, None, relay_hints).await?;
+        connector.set_timeouts(timeouts.transit);
 
         // We want to do some transit
         debug!("Sending transit message '{:?}", connector.our_hints());
@@ -252,90 +475,26 @@ pub async fn send_folder(
             ))
             .await?;
 
-        /* We need to know the length of what we are going to send in advance. So we already build
-         * all the headers of our file now but without the contents. We know that a file is
-         * header + contents + padding
+        /* We need to know the length of what we are going to send in advance. The archive itself
+         * only gets built while sending, so that memory use does not grow with the directory.
          */
         log::debug!("Estimating the file size");
-
-        // TODO try again but without pinning
-        use futures::{
-            future::{ready, BoxFuture},
-            io::Cursor,
-        };
-        use std::io::Result as IoResult;
-
-        type WrappedDataFut = BoxFuture<'static, IoResult<Box<dyn AsyncRead + Unpin + Send>>>;
-
-        /* Type tetris :) */
-        fn wrap(buffer: impl AsRef<[u8]> + Unpin + Send + 'static) -> WrappedDataFut {
-            Box::pin(ready(IoResult::Ok(
-                Box::new(Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>
-            ))) as _
-        }
-
-        /* Walk our offer recursively, concatenate all our readers into a stream that will build the tar file */
-        fn create_offer(
-            mut total_content: Vec<WrappedDataFut>,
-            total_size: &mut u64,
-            offer: OfferSendEntry,
-            path: &mut Vec<String>,
-        ) -> IoResult<Vec<WrappedDataFut>> {
-            match offer {
-                OfferSendEntry::Directory { content } => {
-                    log::debug!("Adding directory {path:?}");
-                    let header = tar_helper::create_header_directory(path)?;
-                    *total_size += header.len() as u64;
-                    total_content.push(wrap(header));
-
-                    for (name, file) in content {
-                        path.push(name);
-                        total_content = create_offer(total_content, total_size, file, path)?;
-                        path.pop();
-                    }
-                },
-                OfferSendEntry::RegularFile { size, content } => {
-                    log::debug!("Adding file {path:?}; {size} bytes");
-                    let header = tar_helper::create_header_file(path, size)?;
-                    let padding = tar_helper::padding(size);
-                    *total_size += header.len() as u64;
-                    *total_size += padding.len() as u64;
-                    *total_size += size;
-
-                    total_content.push(wrap(header));
-                    let content = content().map_ok(
-                        /* Re-box because we can't upcast trait objects */
-                        |read| Box::new(read) as Box<dyn AsyncRead + Unpin + Send>,
-                    );
-                    total_content.push(Box::pin(content) as _);
-                    total_content.push(wrap(padding));
-                },
-                // OfferSendEntry::Symlink { .. } => todo!(),
-            }
-            Ok(total_content)
-        }
-
-        let mut total_size = 0;
-        let mut content = create_offer(
-            Vec::new(),
-            &mut total_size,
-            folder,
-            &mut vec![folder_name.clone()],
-        )?;
-
-        /* Finish tar file */
-        total_size += 1024;
-        content.push(wrap([0; 1024]));
-
-        let content = futures::stream::iter(content).then(|content| content);
-
-        /* Convert to stream */
+        let total_size = tar_size(&folder, &mut vec![folder_name.clone()])? + 1024;
+        let content = tar_stream(folder_name.clone(), folder);
 
         // Send file offer message.
         log::debug!("Sending file offer ({total_size} bytes)");
         folder_name.push_str(".tar");
+        abort.start_file(vec![folder_name.clone()], 0);
         wormhole
-            .send_json(&PeerMessage::offer_file_v1(folder_name, total_size))
+            .send_json(&PeerMessage::offer_file_v1(
+                folder_name.clone(),
+                total_size,
+                None,
+                metadata,
+                timeouts.offer.map(|offer| offer.as_secs()),
+                true,
+            ))
             .await?;
 
         // Wait for their transit response
@@ -351,7 +510,11 @@ pub async fn send_folder(
             };
 
         // Wait for file_ack
-        match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+        let fileack_msg = super::answer_within(timeouts.offer, &abort, async {
+            Ok(wormhole.receive_json::<PeerMessage>().await??)
+        })
+        .await?;
+        match fileack_msg.check_err()? {
             PeerMessage::Answer(AnswerMessage::FileAck(msg)) => {
                 ensure!(msg == "ok", TransferError::AckError);
             },
@@ -366,23 +529,47 @@ pub async fn send_folder(
                 their_abilities,
                 Arc::new(their_hints),
             )
-            .await?;
+            .await
+            .map_err(|error| TransferError::from(error).context("Connecting to the receiver"))?;
+        transit.set_idle_timeout(timeouts.idle_peer);
+        if wormhole.is_strict() {
+            /* The ack is the only record we receive */
+            transit.set_validator(Some(|record| TRANSIT_ACK_SCHEMA.validate_json(record)));
+        }
         transit_handler(info);
 
         debug!("Beginning file transfer");
 
         // 11. send the file as encrypted records.
-        let checksum =
-            v1::send_records(&mut transit, content, total_size, progress_handler).await?;
+        let checksum = v1::send_records(
+            &mut transit,
+            content,
+            total_size,
+            record_size,
+            hash_algorithm,
+            progress_handler,
+        )
+        .await
+        .map_err(|error| error.context(format!("Sending '{}'", folder_name)))?;
 
         // 13. wait for the transit ack with sha256 sum from the peer.
         debug!("sent file. Waiting for ack");
         let transit_ack = transit.receive_record().await?;
         let transit_ack_msg = serde_json::from_slice::<TransitAck>(&transit_ack)?;
+        if let Some(reason) = transit_ack_msg.rejection() {
+            bail!(TransferError::ContentRejected(reason));
+        }
         ensure!(
-            transit_ack_msg.sha256 == hex::encode(checksum),
+            transit_ack_msg.matches(hash_algorithm, &checksum),
             TransferError::Checksum
         );
+        if super::written_ack(&wormhole) {
+            debug!("Waiting for the data to be written");
+            let transit_ack = transit.receive_record().await?;
+            serde_json::from_slice::<TransitAck>(&transit_ack)?
+                .check_written()
+                .map_err(TransferError::NotWritten)?;
+        }
         debug!("Transfer complete!");
 
         Ok(())
@@ -390,7 +577,91 @@ pub async fn send_folder(
 
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
-    cancel::handle_run_result(wormhole, result).await
+    cancel::handle_run_result(wormhole, result, &abort).await
+}
+
+type TarChunk =
+    futures::future::BoxFuture<'static, std::io::Result<Box<dyn AsyncRead + Unpin + Send>>>;
+
+/* Type tetris :) */
+fn tar_chunk(buffer: impl AsRef<[u8]> + Unpin + Send + 'static) -> TarChunk {
+    Box::pin(futures::future::ready(Ok(
+        Box::new(futures::io::Cursor::new(buffer)) as Box<dyn AsyncRead + Unpin + Send>,
+    )))
+}
+
+/** The size of the tar archive of `entry` at `path`, without the end marker. Only builds one header at a time. */
+fn tar_size(entry: &OfferSendEntry, path: &mut Vec<String>) -> std::io::Result<u64> {
+    match entry {
+        OfferSendEntry::Directory { content } => {
+            let mut size = tar_helper::create_header_directory(path)?.len() as u64;
+            for (name, entry) in content {
+                path.push(name.clone());
+                size += tar_size(entry, path)?;
+                path.pop();
+            }
+            Ok(size)
+        },
+        OfferSendEntry::RegularFile { size, .. } => {
+            let header = tar_helper::create_header_file(path, *size)?;
+            Ok(header.len() as u64 + *size + tar_helper::padding(*size).len() as u64)
+        },
+    }
+}
+
+/**
+ * Walk the directory depth-first, yielding the pieces of its tar archive along the way
+ *
+ * Headers get built lazily, and file contents are only opened once it is their turn. Together with
+ * the end marker, this adds up to [`tar_size`] plus 1024 bytes.
+ */
+fn tar_stream(
+    name: String,
+    entry: OfferSendEntry,
+) -> impl futures::Stream<Item = std::io::Result<Box<dyn AsyncRead + Unpin + Send>>> {
+    /* The path to the current directory, and the remaining entries of it and all its parents */
+    let path: Vec<String> = Vec::new();
+    let stack = vec![BTreeMap::from([(name, entry)]).into_iter()];
+    futures::stream::unfold(Some((path, stack)), |state| async move {
+        let (mut path, mut stack) = state?;
+        loop {
+            let Some(entries) = stack.last_mut() else {
+                /* Finish tar file */
+                return Some((vec![tar_chunk([0; 1024])], None));
+            };
+            let Some((name, entry)) = entries.next() else {
+                stack.pop();
+                path.pop();
+                continue;
+            };
+            path.push(name);
+            let chunks = match entry {
+                OfferSendEntry::Directory { content } => {
+                    log::debug!("Adding directory {path:?}");
+                    stack.push(content.into_iter());
+                    let header = tar_helper::create_header_directory(&path);
+                    vec![header.map_or_else(|e| Box::pin(futures::future::err(e)), tar_chunk)]
+                },
+                OfferSendEntry::RegularFile { size, content } => {
+                    log::debug!("Adding file {path:?}; {size} bytes");
+                    let header = tar_helper::create_header_file(&path, size);
+                    path.pop();
+                    let content = content().map_ok(
+                        /* Re-box because we can't upcast trait objects */
+                        |read| Box::new(read) as Box<dyn AsyncRead + Unpin + Send>,
+                    );
+                    vec![
+                        header.map_or_else(|e| Box::pin(futures::future::err(e)), tar_chunk),
+                        Box::pin(content) as _,
+                        tar_chunk(tar_helper::padding(size)),
+                    ]
+                },
+            };
+            return Some((chunks, Some((path, stack))));
+        }
+    })
+    .flat_map(futures::stream::iter)
+    .then(|chunk| chunk)
 }
 
 /**
@@ -401,7 +672,7 @@ pub async fn send_folder(
  *
  * Returns `None` if the task got cancelled.
  */
-pub async fn request(
+pub(super) async fn request(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
@@ -433,17 +704,33 @@ pub async fn request(
             };
 
         // 3. receive file offer message from peer
-        let (filename, filesize) =
+        let (filename, filesize, mime_type, metadata, valid_for, directory) =
             match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
                 PeerMessage::Offer(offer_type) => match offer_type {
-                    v1::OfferMessage::File { filename, filesize } => (filename, filesize),
+                    v1::OfferMessage::File {
+                        filename,
+                        filesize,
+                        mime_type,
+                        metadata,
+                        valid_for,
+                        directory,
+                    } => (
+                        filename, filesize, mime_type, metadata, valid_for, directory,
+                    ),
                     v1::OfferMessage::Directory {
                         mut dirname,
                         zipsize,
                         ..
                     } => {
                         dirname.push_str(".zip");
-                        (dirname, zipsize)
+                        (
+                            dirname,
+                            zipsize,
+                            Some("application/zip".into()),
+                            None,
+                            None,
+                            false,
+                        )
                     },
                     _ => bail!(TransferError::UnsupportedOffer),
                 },
@@ -452,29 +739,88 @@ pub async fn request(
                 },
             };
 
-        Ok((filename, filesize, connector, their_abilities, their_hints))
+        Ok((
+            filename,
+            filesize,
+            mime_type,
+            metadata,
+            super::withdraw::expires_at(valid_for),
+            directory,
+            connector,
+            their_abilities,
+            their_hints,
+        ))
     });
 
     futures::pin_mut!(cancel);
     let result = cancel::cancellable_2(run, cancel).await;
-    cancel::handle_run_result_noclose(wormhole, result)
+    cancel::handle_run_result_noclose(wormhole, result, &Default::default())
         .await
         .map(|inner: Option<_>| {
             inner.map(
-                |((filename, filesize, connector, their_abilities, their_hints), wormhole, _)| {
+                |(
+                    (
+                        filename,
+                        filesize,
+                        mime_type,
+                        metadata,
+                        expires_at,
+                        directory,
+                        connector,
+                        their_abilities,
+                        their_hints,
+                    ),
+                    wormhole,
+                    _,
+                )| {
                     ReceiveRequest {
                         wormhole,
                         filename,
                         filesize,
+                        mime_type,
+                        metadata,
+                        expires_at,
+                        directory,
                         connector,
                         their_abilities,
                         their_hints: Arc::new(their_hints),
+                        idle_timeout: None,
+                        scanner: None,
+                        #[cfg(not(target_family = "wasm"))]
+                        codecs: Vec::new(),
                     }
                 },
             )
         })
 }
 
+/**
+ * Resolves once the pending offer is gone, because it expired or the sender withdrew it
+ *
+ * While waiting for the answer, the sender does not send anything else.
+ */
+async fn watch_offer(
+    wormhole: &mut Wormhole,
+    expires_at: Option<instant::Instant>,
+) -> TransferError {
+    let withdrawn = async {
+        let message = async { wormhole.receive_json::<PeerMessage>().await??.check_err() };
+        match message.await {
+            Ok(other) => TransferError::unexpected_message("offer-withdrawn", other),
+            Err(error) => error,
+        }
+    };
+    futures::pin_mut!(withdrawn);
+    let expired = super::withdraw::expiry(expires_at);
+    futures::pin_mut!(expired);
+    match futures::future::select(withdrawn, expired).await {
+        futures::future::Either::Left((error, _)) => error,
+        futures::future::Either::Right(((), _)) => {
+            TransferError::OfferWithdrawn(super::WithdrawReason::Expired)
+        },
+    }
+}
+
 /**
  * A pending files send offer from the other side
  *
@@ -487,11 +833,74 @@ pub struct ReceiveRequest {
     /// **Security warning:** this is untrusted and unverified input
     pub filename: String,
     pub filesize: u64,
+    /**
+     * The MIME type of the file, if the sender provided one
+     *
+     * **Security warning:** this is untrusted input as well, and may not match the actual content
+     */
+    pub mime_type: Option<String>,
+    /**
+     * The application specific data the sender attached, if any
+     *
+     * **Security warning:** this is untrusted input as well
+     */
+    pub metadata: Option<crate::OfferMetadata>,
+    /// When the sender stops waiting for an answer, see [`ReceiveRequest::expires_at`](super::ReceiveRequest::expires_at)
+    pub expires_at: Option<instant::Instant>,
+    directory: bool,
     their_abilities: transit::Abilities,
     their_hints: Arc<transit::Hints>,
+    idle_timeout: Option<std::time::Duration>,
+    scanner: Option<Box<dyn super::ContentScanner>>,
+    #[cfg(not(target_family = "wasm"))]
+    codecs: Vec<Arc<dyn super::Codec>>,
 }
 
 impl ReceiveRequest {
+    /**
+     * Keep the connection alive while waiting for `future`
+     *
+     * The transit connection does not exist yet at this point, so this pings the rendezvous server instead.
+     * See [`ReceiveRequest::keep_alive_while`](super::ReceiveRequest::keep_alive_while).
+     */
+    pub async fn keep_alive_while<T>(
+        &mut self,
+        interval: std::time::Duration,
+        future: impl Future<Output = T>,
+    ) -> Result<T, TransferError> {
+        let expires_at = self.expires_at;
+        crate::util::keep_alive_watching(
+            &mut self.wormhole,
+            interval,
+            future,
+            |wormhole| Box::pin(wormhole.ping().map_err(Into::into)),
+            move |wormhole| Box::pin(watch_offer(wormhole, expires_at)),
+        )
+        .await
+    }
+
+    /** Limit the transit connection and the transfer, see [`ReceiveRequest::set_timeouts`](super::ReceiveRequest::set_timeouts) */
+    pub fn set_timeouts(&mut self, timeouts: transit::Timeouts) {
+        self.connector.set_timeouts(timeouts.transit);
+        self.idle_timeout = timeouts.idle_peer;
+    }
+
+    /** Check the received content before acknowledging it, see [`ReceiveRequest::set_scanner`](super::ReceiveRequest::set_scanner) */
+    pub fn set_scanner(&mut self, scanner: impl super::ContentScanner + 'static) {
+        self.scanner = Some(Box::new(scanner));
+    }
+
+    /**
+     * Recognize and decompress offers in a custom format as well
+     *
+     * See [`compression`](Self::compression). The codec should also be advertised to the peer with
+     * [`AppVersion::with_codec`](super::AppVersion::with_codec).
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn add_codec(&mut self, codec: Arc<dyn super::Codec>) {
+        self.codecs.push(codec);
+    }
+
     /**
      * Accept the file offer
      *
@@ -509,6 +918,9 @@ impl ReceiveRequest {
         G: FnOnce(transit::TransitInfo),
         W: AsyncWrite + Unpin,
     {
+        let abort = super::abort_tracker(&self.wormhole);
+        abort.start_file(vec![self.filename.clone()], 0);
+        let progress_handler = abort.progress_handler(progress_handler);
         let run = Box::pin(async {
             // send file ack.
             debug!("Sending ack");
@@ -525,23 +937,176 @@ impl ReceiveRequest {
                     self.their_abilities,
                     self.their_hints.clone(),
                 )
-                .await?;
+                .await
+                .map_err(|error| TransferError::from(error).context("Connecting to the sender"))?;
+            transit.set_idle_timeout(self.idle_timeout);
             transit_handler(info);
 
             debug!("Beginning file transfer");
+            let mut scanner = self.scanner.take();
+            if let Some(scanner) = &mut scanner {
+                scanner
+                    .begin_file(&[self.filename.clone()], self.filesize)
+                    .await
+                    .map_err(TransferError::ContentRejected)?;
+            }
             tcp_file_receive(
                 &mut transit,
                 self.filesize,
+                super::hash_algorithm(&self.wormhole),
+                super::written_ack(&self.wormhole),
                 progress_handler,
                 content_handler,
+                &mut scanner,
             )
-            .await?;
+            .await
+            .map_err(|error| error.context(format!("Receiving '{}'", self.filename)))?;
             Ok(())
         });
 
         futures::pin_mut!(cancel);
         let result = cancel::cancellable_2(run, cancel).await;
-        cancel::handle_run_result(self.wormhole, result).await
+        cancel::handle_run_result(self.wormhole, result, &abort).await
+    }
+
+    /**
+     * Whether the sender offered a directory, as tar archive
+     *
+     * Only this implementation marks its directory offers like this. Such offers may be received with
+     * [`accept_extract`](Self::accept_extract) without asking.
+     */
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    /**
+     * Whether the offered file is a tar archive, possibly compressed
+     *
+     * This only goes by the file name. Unless the offer [is a directory](Self::is_directory), only
+     * unpack it with [`accept_extract`](Self::accept_extract) if the user asked for it.
+     */
+    pub fn is_tar_archive(&self) -> bool {
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(compression) = self.compression() {
+            return compression
+                .decompressed_filename(&self.filename)
+                .ends_with(".tar");
+        }
+        self.filename.ends_with(".tar")
+    }
+
+    /**
+     * The compression of the offered file, guessed from its name
+     *
+     * Use [`accept_decompress`](Self::accept_decompress) to decompress it while receiving.
+     * [`accept_extract`](Self::accept_extract) takes care of compressed archives automatically.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn compression(&self) -> Option<super::Compression> {
+        super::Compression::from_filename_with(&self.filename, &self.codecs)
+    }
+
+    /**
+     * Accept the file offer, decompressing it while receiving
+     *
+     * Like [`accept`](Self::accept), but the data written into `content_handler` is decompressed,
+     * see [`compression`](Self::compression). The decompressed size is unknown up front, so the
+     * progress refers to the compressed data. `content_handler` gets closed at the end.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn accept_decompress<F, G, W>(
+        self,
+        transit_handler: G,
+        content_handler: &mut W,
+        progress_handler: F,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(), TransferError>
+    where
+        F: FnMut(u64, u64) + 'static,
+        G: FnOnce(transit::TransitInfo),
+        W: AsyncWrite + Unpin,
+    {
+        let Some(compression) = self.compression() else {
+            return self
+                .accept(transit_handler, content_handler, progress_handler, cancel)
+                .await;
+        };
+        let mut writer = compression.decoding_writer(content_handler);
+        self.accept(transit_handler, &mut writer, progress_handler, cancel)
+            .await?;
+        writer.close().await?;
+        Ok(())
+    }
+
+    /**
+     * Accept the file offer and unpack it into `target_dir` while receiving
+     *
+     * The offered file must be a tar archive, see [`is_tar_archive`](Self::is_tar_archive).
+     * Compressed archives get decompressed on the fly. Only regular files and directories are unpacked, and `policy` decides about file names
+     * that can't be used on this platform. Existing files get overwritten.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn accept_extract<G, P>(
+        self,
+        transit_handler: G,
+        target_dir: &Path,
+        policy: super::FilenamePolicy,
+        progress_handler: P,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<(), TransferError>
+    where
+        G: FnOnce(transit::TransitInfo),
+        P: FnMut(super::ExtractProgress) + 'static,
+    {
+        use super::{extract, ExtractProgress};
+        use std::{cell::RefCell, rc::Rc};
+
+        let compression = self.compression();
+        let progress_handler = Rc::new(RefCell::new(progress_handler));
+        let complete = Rc::new(std::cell::Cell::new(false));
+        let (mut writer, reader) = extract::pipe();
+
+        let receive = {
+            let progress_handler = progress_handler.clone();
+            let complete = complete.clone();
+            let mut eta = extract::Eta::new();
+            async move {
+                self.accept(
+                    transit_handler,
+                    &mut writer,
+                    move |received, total| {
+                        complete.set(received == total);
+                        (progress_handler.borrow_mut())(ExtractProgress::Bytes {
+                            received,
+                            total,
+                            eta: eta.update(received, total),
+                        });
+                    },
+                    cancel,
+                )
+                .await?;
+                writer.close().await?;
+                Ok::<_, TransferError>(())
+            }
+        };
+        let reader: Box<dyn futures::AsyncRead + Unpin + Send> = match compression {
+            Some(compression) => compression.decoder(reader),
+            None => Box::new(reader),
+        };
+        let unpack = extract::unpack_tar(reader, target_dir, policy, |path, size| {
+            (progress_handler.borrow_mut())(ExtractProgress::File { path, size })
+        });
+
+        match futures::join!(receive, unpack) {
+            /* Cancelled, the archive is incomplete */
+            (Ok(()), _) if !complete.get() => Ok(()),
+            (Ok(()), unpacked) => unpacked.map_err(Into::into),
+            /* Writing to the pipe fails once unpacking stopped, report the actual cause */
+            (Err(error), Err(unpacked)) if matches!(error.root(), TransferError::IO(_)) => {
+                Err(unpacked.into())
+            },
+            (Err(error), _) => Err(error),
+        }
     }
 
     /**
@@ -561,15 +1126,17 @@ impl ReceiveRequest {
 
 // encrypt and send the file to tcp stream and return the sha256 sum
 // of the file before encryption.
-pub async fn send_records<'a>(
+pub(super) async fn send_records<'a>(
     transit: &mut Transit,
     files: impl futures::Stream<Item = std::io::Result<Box<dyn AsyncRead + Unpin + Send + 'a>>>,
     file_size: u64,
+    record_size: usize,
+    hash_algorithm: HashAlgorithm,
     mut progress_handler: impl FnMut(u64, u64) + 'static,
 ) -> Result<Vec<u8>, TransferError> {
     // rough plan:
     // 1. Open the file
-    // 2. read a block of N bytes
+    // 2. read a block of N bytes (some blocks ahead of sending them)
     // 3. calculate a rolling sha256sum.
     // 4. AEAD with skey and with nonce as a counter from 0.
     // 5. send the encrypted buffer to the socket.
@@ -579,34 +1146,31 @@ pub async fn send_records<'a>(
     // Report at 0 to allow clients to configure as necessary.
     progress_handler(0, file_size);
 
-    let mut hasher = Sha256::default();
+    let mut hasher = super::hash::BackgroundHasher::new(hash_algorithm);
 
-    let mut plaintext = vec![0u8; 16 * 1024].into_boxed_slice();
     let mut sent_size = 0;
     futures::pin_mut!(files);
-    while let Some(mut file) = files.next().await.transpose()? {
-        loop {
-            // read a block of up to 4096 bytes
-            let n = file.read(&mut plaintext[..]).await?;
-
-            if n == 0 {
-                // EOF
-                break;
+    while let Some(file) = files.next().await.transpose()? {
+        // read blocks of up to `record_size` bytes, ahead of sending them
+        let (reading, chunks) = super::read_ahead::read_ahead(file, record_size);
+        let sending = async {
+            /* Move the stream in here, so that it gets dropped (which stops the reading) on errors */
+            let mut chunks = chunks;
+            while let Some(plaintext) = chunks.next().await {
+                let plaintext = plaintext?;
+
+                // send the encrypted record
+                transit.send_record(&plaintext).await?;
+                sent_size += plaintext.len() as u64;
+                progress_handler(sent_size, file_size);
+
+                // sha256 of the input
+                hasher.update(plaintext).await?;
             }
-
-            // send the encrypted record
-            transit.send_record(&plaintext[0..n]).await?;
-            sent_size += n as u64;
-            progress_handler(sent_size, file_size);
-
-            // sha256 of the input
-            hasher.update(&plaintext[..n]);
-
-            /* Don't do this. The EOF check above is sufficient */
-            // if n < 4096 {
-            //     break;
-            // }
-        }
+            Ok::<_, TransferError>(())
+        };
+        let ((), result) = futures::join!(reading, sending);
+        result?;
     }
     transit.flush().await?;
 
@@ -618,20 +1182,22 @@ pub async fn send_records<'a>(
         }
     );
 
-    Ok(hasher.finalize_fixed().to_vec())
+    Ok(hasher.finalize().await?)
 }
 
-pub async fn receive_records<F, W>(
+pub(super) async fn receive_records<F, W>(
     filesize: u64,
     transit: &mut Transit,
+    hash_algorithm: HashAlgorithm,
     mut progress_handler: F,
-    mut content_handler: W,
+    content_handler: &mut W,
+    scanner: &mut Option<Box<dyn super::ContentScanner>>,
 ) -> Result<Vec<u8>, TransferError>
 where
     F: FnMut(u64, u64) + 'static,
     W: AsyncWrite + Unpin,
 {
-    let mut hasher = Sha256::default();
+    let mut hasher = super::hash::BackgroundHasher::new(hash_algorithm);
     let total = filesize;
 
     let mut remaining_size = filesize as usize;
@@ -645,27 +1211,35 @@ where
         let plaintext = transit.receive_record().await?;
 
         content_handler.write_all(&plaintext).await?;
-
-        // 4. calculate a rolling sha256 sum of the decrypted output.
-        hasher.update(&plaintext);
+        if let Some(scanner) = scanner.as_mut() {
+            scanner
+                .scan(&plaintext)
+                .await
+                .map_err(TransferError::ContentRejected)?;
+        }
 
         remaining_size -= plaintext.len();
 
+        // 4. calculate a rolling sha256 sum of the decrypted output.
+        hasher.update(plaintext.into_vec()).await?;
+
         let remaining = remaining_size as u64;
         progress_handler(total - remaining, total);
     }
-    content_handler.close().await?;
 
     debug!("done");
     // TODO: 5. write the buffer into a file.
-    Ok(hasher.finalize_fixed().to_vec())
+    Ok(hasher.finalize().await?)
 }
 
-pub async fn tcp_file_receive<F, W>(
+pub(super) async fn tcp_file_receive<F, W>(
     transit: &mut Transit,
     filesize: u64,
+    hash_algorithm: HashAlgorithm,
+    written_ack: bool,
     progress_handler: F,
     content_handler: &mut W,
+    scanner: &mut Option<Box<dyn super::ContentScanner>>,
 ) -> Result<(), TransferError>
 where
     F: FnMut(u64, u64) + 'static,
@@ -674,15 +1248,57 @@ where
     // 5. receive encrypted records
     // now skey and rkey can be used. skey is used by the tx side, rkey is used
     // by the rx side for symmetric encryption.
-    let checksum = receive_records(filesize, transit, progress_handler, content_handler).await?;
-
-    let sha256sum = hex::encode(checksum.as_slice());
-    debug!("sha256 sum: {:?}", sha256sum);
+    let received = receive_records(
+        filesize,
+        transit,
+        hash_algorithm,
+        progress_handler,
+        content_handler,
+        scanner,
+    )
+    .await;
+    let verdict = match (&received, scanner) {
+        (Ok(_), Some(scanner)) => scanner.finish().await,
+        (Err(TransferError::ContentRejected(reason)), _) => Err(reason.clone()),
+        _ => Ok(()),
+    };
+    if let Err(reason) = verdict {
+        /* Tell the sender, instead of letting it wait for an ack */
+        debug!("Rejecting the received content: {}", reason);
+        transit
+            .send_record(&TransitAck::rejected(reason.clone()).serialize_vec())
+            .await?;
+        transit.flush().await?;
+        bail!(TransferError::ContentRejected(reason));
+    }
+    let checksum = received?;
+
+    let checksum = hex::encode(checksum.as_slice());
+    debug!("{} sum: {:?}", hash_algorithm.name(), checksum);
+    let ack = TransitAck::new("ok", hash_algorithm, checksum).serialize_vec();
+
+    if !written_ack {
+        content_handler.close().await?;
+        // 6. verify the checksum by sending an ack message to peer along with checksum.
+        transit.send_record(&ack).await?;
+        debug!("Transfer complete");
+        return Ok(());
+    }
 
-    // 6. verify sha256 sum by sending an ack message to peer along with checksum.
+    /* Acknowledge the received bytes right away, and their writing once the content handler is closed */
+    transit.send_record(&ack).await?;
+    transit.flush().await?;
+    if let Err(error) = content_handler.close().await {
+        transit
+            .send_record(&TransitAck::not_written(error.to_string()).serialize_vec())
+            .await?;
+        transit.flush().await?;
+        bail!(error);
+    }
     transit
-        .send_record(&TransitAck::new("ok", &sha256sum).serialize_vec())
+        .send_record(&TransitAck::written().serialize_vec())
         .await?;
+    transit.flush().await?;
 
     // 7. close socket.
     // well, no need, it gets dropped when it goes out of scope.
@@ -715,6 +1331,7 @@ mod tar_helper {
     pub fn create_header_directory(path: &[String]) -> std::io::Result<Vec<u8>> {
         let mut header = tar::Header::new_gnu();
         header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
         let mut data = Vec::with_capacity(1024);
         prepare_header_path(&mut data, &mut header, path.join("/").as_ref())?;
         header.set_mode(0o755);
@@ -822,9 +1439,104 @@ mod tar_helper {
 mod test {
     use super::*;
 
+    #[async_std::test]
+    async fn test_tar_stream() {
+        let file = |data: &'static [u8]| OfferSendEntry::RegularFile {
+            size: data.len() as u64,
+            content: new_offer_content(move || {
+                futures::future::ready(Ok(futures::io::Cursor::new(data)))
+            }),
+        };
+        let long_name = "a".repeat(200);
+        let folder = OfferSendEntry::Directory {
+            content: BTreeMap::from([
+                ("hello.txt".to_owned(), file(b"Hello world")),
+                (
+                    "nested".to_owned(),
+                    OfferSendEntry::Directory {
+                        content: BTreeMap::from([
+                            (long_name.clone(), file(&[7; 1000])),
+                            ("empty".to_owned(), file(b"")),
+                        ]),
+                    },
+                ),
+            ]),
+        };
+
+        let size = tar_size(&folder, &mut vec!["folder".into()]).unwrap() + 1024;
+        let mut archive = Vec::new();
+        let chunks = tar_stream("folder".into(), folder);
+        futures::pin_mut!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            futures::io::copy(chunk.unwrap(), &mut archive)
+                .await
+                .unwrap();
+        }
+        assert_eq!(archive.len() as u64, size);
+
+        let mut archive = tar::Archive::new(archive.as_slice());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "folder".to_owned(),
+                "folder/hello.txt".to_owned(),
+                "folder/nested".to_owned(),
+                "folder/nested/".to_owned() + &long_name,
+                "folder/nested/empty".to_owned(),
+            ]
+        );
+    }
+
     #[test]
     fn test_transit_ack() {
-        let f1 = TransitAck::new("ok", "deadbeaf");
+        let f1 = TransitAck::new("ok", HashAlgorithm::Sha256, "deadbeaf");
         assert_eq!(f1.serialize(), "{\"ack\":\"ok\",\"sha256\":\"deadbeaf\"}");
+        let f2 = TransitAck::new("ok", HashAlgorithm::Blake2b, "deadbeaf");
+        assert_eq!(f2.serialize(), "{\"ack\":\"ok\",\"blake2b\":\"deadbeaf\"}");
+        assert!(f2.matches(HashAlgorithm::Blake2b, &[0xde, 0xad, 0xbe, 0xaf]));
+        let f3 = TransitAck::new("ok", HashAlgorithm::Blake3, "deadbeaf");
+        assert_eq!(f3.serialize(), "{\"ack\":\"ok\",\"blake3\":\"deadbeaf\"}");
+        assert!(!f3.matches(HashAlgorithm::Blake2b, &[0xde, 0xad, 0xbe, 0xaf]));
+    }
+
+    #[test]
+    fn test_transit_ack_rejected() {
+        let ack = TransitAck::rejected("EICAR test signature");
+        assert_eq!(
+            ack.serialize(),
+            "{\"ack\":\"rejected\",\"reason\":\"EICAR test signature\"}"
+        );
+        assert!(TRANSIT_ACK_SCHEMA
+            .validate_json(&ack.serialize_vec())
+            .is_ok());
+        assert_eq!(ack.rejection().as_deref(), Some("EICAR test signature"));
+        assert_eq!(
+            TransitAck::new("ok", HashAlgorithm::Sha256, "deadbeaf").rejection(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_transit_ack_written() {
+        let ack = TransitAck::written();
+        assert_eq!(ack.serialize(), "{\"ack\":\"written\"}");
+        assert_eq!(ack.check_written(), Ok(()));
+        let ack = TransitAck::not_written("No space left on device");
+        assert!(TRANSIT_ACK_SCHEMA
+            .validate_json(&ack.serialize_vec())
+            .is_ok());
+        assert_eq!(
+            ack.check_written(),
+            Err("No space left on device".to_string())
+        );
+        assert!(TransitAck::new("ok", HashAlgorithm::Sha256, "deadbeaf")
+            .check_written()
+            .is_err());
+        assert!(AppVersion::default().supports_written_ack());
     }
 }
@@ -1,8 +1,18 @@
-use futures::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+//! Version 2 of the file transfer protocol
+//!
+//! The offer lists every file, and the receiver may pick which ones it wants and at which offset
+//! to resume them. Everything after the transit hints is sent as msgpack over the transit connection.
+//! Use the functions in the [parent module](super) to negotiate the version with the peer.
+
+use futures::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, StreamExt};
 use serde_derive::{Deserialize, Serialize};
 use sha2::{digest::FixedOutput, Sha256};
 
-use super::*;
+use super::{
+    abort::{AbortMessage, AbortTracker},
+    *,
+};
+use crate::schema::{self, Field, Schema, UnknownFields, UnknownVariants};
 
 /**
  * A set of hints for both sides to find each other
@@ -19,9 +29,11 @@ pub struct TransitV2 {
 #[derive(Deserialize, Serialize, derive_more::Display, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 #[non_exhaustive]
-pub enum PeerMessageV2 {
+pub(super) enum PeerMessageV2 {
     #[display(fmt = "offer")]
     Offer(Offer),
+    #[display(fmt = "offer-manifest")]
+    OfferManifest(OfferManifest),
     #[display(fmt = "answer")]
     Answer(AnswerMessage),
     #[display(fmt = "file-start")]
@@ -32,13 +44,180 @@ pub enum PeerMessageV2 {
     FileEnd(FileEnd),
     #[display(fmt = "transfer-ack")]
     TransferAck(TransferAck),
+    /** Sent back by the receiver once all files are closed, see [`AppVersion::supports_written_ack`] */
+    #[display(fmt = "transfer-written")]
+    TransferWritten(TransferWritten),
     #[display(fmt = "error")]
     Error(String),
+    /** Like `Error`, but with an [`AbortSummary`](super::AbortSummary). Only sent to peers that support it */
+    #[display(fmt = "abort")]
+    Abort(AbortMessage),
+    /** No-op, sent by the receiver while waiting for the user to accept the offer */
+    #[display(fmt = "keep-alive")]
+    KeepAlive,
+    /** Instead of `Error`, if the offer is not answered yet. Only sent to peers that support it */
+    #[display(fmt = "offer-withdrawn")]
+    OfferWithdrawn(super::WithdrawReason),
     #[display(fmt = "unknown")]
     #[serde(other)]
     Unknown,
 }
 
+static PEER_MESSAGE_V2_SCHEMA: Schema = Schema::Enum {
+    variants: &[
+        (
+            "offer",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("content", &OFFER_CONTENT_SCHEMA),
+                    Field::optional(
+                        "mime-types",
+                        &Schema::Map {
+                            keys: &schema::STRING,
+                            values: &super::mime::MIME_TYPE_SCHEMA,
+                        },
+                    ),
+                    Field::optional("metadata", &crate::metadata::OFFER_METADATA_SCHEMA),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "offer-manifest",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required(
+                        "entries",
+                        &Schema::Array {
+                            items: &MANIFEST_ENTRY_SCHEMA,
+                            min_len: 0,
+                            max_len: MANIFEST_CHUNK_LEN,
+                        },
+                    ),
+                    Field::required("last", &schema::BOOL),
+                    Field::optional("metadata", &crate::metadata::OFFER_METADATA_SCHEMA),
+                    Field::optional("valid-for", &schema::U64),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "answer",
+            Some(&Schema::Object {
+                fields: &[Field::required(
+                    "files",
+                    &Schema::Array {
+                        items: &Schema::Object {
+                            fields: &[
+                                Field::required("file", &schema::PATH),
+                                Field::required("offset", &schema::U64),
+                                Field::optional(
+                                    "sha256",
+                                    &Schema::Nullable(&Schema::Array {
+                                        items: &Schema::Integer {
+                                            min: 0,
+                                            max: u8::MAX as i128,
+                                        },
+                                        min_len: 32,
+                                        max_len: 32,
+                                    }),
+                                ),
+                            ],
+                            unknown_fields: UnknownFields::Reject,
+                        },
+                        min_len: 0,
+                        max_len: usize::MAX,
+                    },
+                )],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "file-start",
+            Some(&Schema::Object {
+                fields: &[
+                    Field::required("file", &schema::PATH),
+                    Field::required("start-at-offset", &schema::BOOL),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        (
+            "payload",
+            Some(&Schema::Object {
+                fields: &[Field::required("payload", &schema::BYTES)],
+                unknown_fields: UnknownFields::Reject,
+            }),
+        ),
+        ("file-end", Some(&schema::EMPTY)),
+        ("transfer-ack", Some(&schema::EMPTY)),
+        ("transfer-written", Some(&schema::EMPTY)),
+        ("error", Some(&schema::STRING)),
+        ("abort", Some(&super::abort::ABORT_SCHEMA)),
+        ("keep-alive", None),
+        (
+            "offer-withdrawn",
+            Some(&super::withdraw::WITHDRAW_REASON_SCHEMA),
+        ),
+    ],
+    unknown_variants: UnknownVariants::Reject,
+};
+
+/* The content of a directory, or of the whole offer */
+static OFFER_CONTENT_SCHEMA: Schema = Schema::Map {
+    keys: &schema::STRING,
+    values: &Schema::Tagged {
+        tag: "type",
+        variants: &[
+            (
+                "regular-file",
+                &Schema::Object {
+                    fields: &[Field::required("size", &schema::U64)],
+                    unknown_fields: UnknownFields::Reject,
+                },
+            ),
+            (
+                "directory",
+                &Schema::Object {
+                    fields: &[Field::required("content", &OFFER_CONTENT_SCHEMA)],
+                    unknown_fields: UnknownFields::Reject,
+                },
+            ),
+        ],
+        unknown_variants: UnknownVariants::Reject,
+    },
+};
+
+static MANIFEST_ENTRY_SCHEMA: Schema = Schema::Tagged {
+    tag: "type",
+    variants: &[
+        (
+            "regular-file",
+            &Schema::Object {
+                fields: &[
+                    Field::required("path", &schema::PATH),
+                    Field::required("size", &schema::U64),
+                    Field::optional("mime-type", &super::mime::MIME_TYPE_SCHEMA),
+                ],
+                unknown_fields: UnknownFields::Reject,
+            },
+        ),
+        (
+            "directory",
+            &Schema::Object {
+                fields: &[Field::required("path", &schema::PATH)],
+                unknown_fields: UnknownFields::Reject,
+            },
+        ),
+    ],
+    unknown_variants: UnknownVariants::Reject,
+};
+
+pub(super) static TRANSIT_SCHEMA: Schema = Schema::Object {
+    fields: &[Field::required("hints-v2", &transit::HINTS_SCHEMA)],
+    unknown_fields: UnknownFields::Reject,
+};
+
 impl PeerMessageV2 {
     pub fn ser_msgpack(&self) -> Vec<u8> {
         let mut writer = Vec::with_capacity(128);
@@ -56,14 +235,188 @@ impl PeerMessageV2 {
     pub fn check_err(self) -> Result<Self, TransferError> {
         match self {
             Self::Error(err) => Err(TransferError::PeerError(err)),
+            Self::Abort(abort) => Err(abort.into()),
+            Self::OfferWithdrawn(reason) => Err(TransferError::OfferWithdrawn(reason)),
             other => Ok(other),
         }
     }
 }
 
+/* The message telling the peer why we stop, with an [`AbortSummary`](super::AbortSummary) if it supports it. Withdraws a pending offer instead. */
+fn error_message(abort: &AbortTracker, error: &dyn std::fmt::Display) -> Vec<u8> {
+    if let Some(reason) = abort.withdrawal() {
+        return PeerMessageV2::OfferWithdrawn(reason).ser_msgpack();
+    }
+    abort
+        .message(error)
+        .map(PeerMessageV2::Abort)
+        .unwrap_or_else(|| PeerMessageV2::Error(error.to_string()))
+        .ser_msgpack()
+}
+
+/* Why the peer stopped, if the message tells */
+fn peer_error(message: &[u8]) -> Result<Option<TransferError>, rmp_serde::decode::Error> {
+    Ok(match PeerMessageV2::de_msgpack(message)? {
+        PeerMessageV2::Error(err) => Some(TransferError::PeerError(err)),
+        PeerMessageV2::Abort(abort) => Some(abort.into()),
+        PeerMessageV2::OfferWithdrawn(reason) => Some(TransferError::OfferWithdrawn(reason)),
+        _ => None,
+    })
+}
+
+/** Maximum number of entries per [`OfferManifest`] message */
+const MANIFEST_CHUNK_LEN: usize = 1024;
+/** Maximum number of entries of all [`OfferManifest`] messages together, so that the receiver's memory is bounded */
+const MAX_MANIFEST_ENTRIES: usize = 1024 * 1024;
+
+/**
+ * A part of the offer, as flat list of entries
+ *
+ * Large offers are sent as multiple of these instead of a single [`Offer`] message.
+ * Every directory is listed before its contents.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct OfferManifest {
+    entries: Vec<ManifestEntry>,
+    /// Whether this is the final part of the offer
+    last: bool,
+    /// Only in the first part, see [`Offer::metadata`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<crate::OfferMetadata>,
+    /// Only in the first part: for how many seconds the sender waits for an answer, if limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    valid_for: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-pub struct AnswerMessage {
+#[serde(tag = "type")]
+enum ManifestEntry {
+    RegularFile {
+        path: Vec<String>,
+        size: u64,
+        #[serde(default, rename = "mime-type", skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
+    Directory {
+        path: Vec<String>,
+    },
+}
+
+/**
+ * Walk the offer depth-first, one entry after the other, without collecting all entries first
+ *
+ * Only the path of the last entry is kept in between, and not a borrow of the offer. The file contents of an
+ * [`OfferSend`] are not `Sync`, so the walk may be held across an `.await` this way.
+ */
+#[derive(Clone, Debug, Default)]
+struct ManifestWalk {
+    last: Option<Vec<String>>,
+}
+
+impl ManifestWalk {
+    fn next<T>(&mut self, offer: &Offer<T>) -> Option<ManifestEntry> {
+        /* The path of the entry after `last` below `content`, or of the first one if there is no `last` */
+        fn next_path<T>(
+            content: &BTreeMap<String, OfferEntry<T>>,
+            last: Option<&[String]>,
+        ) -> Option<Vec<String>> {
+            let Some((name, rest)) = last.and_then(<[_]>::split_first) else {
+                return content.keys().next().map(|name| vec![name.clone()]);
+            };
+            /* Descend into the directory first, then go on with its siblings */
+            if let Some(OfferEntry::Directory { content }) = content.get(name) {
+                let below = next_path(content, (!rest.is_empty()).then_some(rest));
+                if let Some(mut below) = below {
+                    below.insert(0, name.clone());
+                    return Some(below);
+                }
+            }
+            content
+                .range::<str, _>((
+                    std::ops::Bound::Excluded(name.as_str()),
+                    std::ops::Bound::Unbounded,
+                ))
+                .next()
+                .map(|(name, _)| vec![name.clone()])
+        }
+
+        let path = next_path(&offer.content, self.last.as_deref())?;
+        let (name, parents) = path.split_last()?;
+        let mut content = &offer.content;
+        for parent in parents {
+            match content.get(parent)? {
+                OfferEntry::Directory { content: below } => content = below,
+                OfferEntry::RegularFile { .. } => return None,
+            }
+        }
+        let entry = match content.get(name)? {
+            OfferEntry::RegularFile { size, .. } => ManifestEntry::RegularFile {
+                size: *size,
+                mime_type: offer.mime_type(&path).map(Into::into),
+                path: path.clone(),
+            },
+            OfferEntry::Directory { .. } => ManifestEntry::Directory { path: path.clone() },
+        };
+        self.last = Some(path);
+        Some(entry)
+    }
+
+    /** The next `len` entries at most */
+    fn take<T>(&mut self, offer: &Offer<T>, len: usize) -> Vec<ManifestEntry> {
+        std::iter::from_fn(|| self.next(offer)).take(len).collect()
+    }
+
+    fn is_done<T>(&self, offer: &Offer<T>) -> bool {
+        self.clone().next(offer).is_none()
+    }
+}
+
+/** Add a manifest entry to a partially received offer. Errors are protocol violations. */
+fn insert_manifest_entry(offer: &mut Offer, entry: ManifestEntry) -> Result<(), String> {
+    let (path, new) = match entry {
+        ManifestEntry::RegularFile {
+            path,
+            size,
+            mime_type,
+        } => {
+            if let Some(mime_type) = mime_type {
+                offer.set_mime_type(&path, mime_type);
+            }
+            (path, OfferEntry::RegularFile { size, content: () })
+        },
+        ManifestEntry::Directory { path } => (
+            path,
+            OfferEntry::Directory {
+                content: BTreeMap::new(),
+            },
+        ),
+    };
+    let Some((name, parents)) = path.split_last() else {
+        return Err("Offer manifest contains an empty path".into());
+    };
+    let mut content = &mut offer.content;
+    for parent in parents {
+        content = match content.get_mut(parent) {
+            Some(OfferEntry::Directory { content }) => content,
+            _ => {
+                return Err(format!(
+                    "Offer manifest lists '{}' before its directory",
+                    path.join("/")
+                ))
+            },
+        };
+    }
+    if content.insert(name.clone(), new).is_some() {
+        return Err(format!("Offer manifest lists '{}' twice", path.join("/")));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct AnswerMessage {
     pub(self) files: Vec<AnswerMessageInner>,
 }
 
@@ -77,24 +430,28 @@ struct AnswerMessageInner {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct FileStart {
+pub(super) struct FileStart {
     pub file: Vec<String>,
     pub start_at_offset: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct Payload {
+pub(super) struct Payload {
     payload: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct FileEnd {}
+pub(super) struct FileEnd {}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct TransferAck {}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct TransferAck {}
+pub(super) struct TransferWritten {}
 
 /** The code to establish a transit connection is essentially the same on both sides. */
 async fn make_transit(
@@ -103,8 +460,10 @@ async fn make_transit(
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
     peer_abilities: transit::Abilities,
+    timeouts: transit::Timeouts,
 ) -> Result<(transit::Transit, transit::TransitInfo), TransferError> {
-    let connector = transit::init(transit_abilities, Some(peer_abilities), relay_hints).await?;
+    let mut connector = transit::init(transit_abilities, Some(peer_abilities), relay_hints).await?;
+    connector.set_timeouts(timeouts.transit);
 
     /* Send our transit hints */
     wormhole
@@ -128,7 +487,7 @@ async fn make_transit(
         };
 
     /* Get a transit connection */
-    let (transit, info) = match connector
+    let (mut transit, info) = match connector
         .connect(
             is_leader,
             wormhole.key().derive_transit_key(wormhole.appid()),
@@ -139,27 +498,38 @@ async fn make_transit(
     {
         Ok(transit) => transit,
         Err(error) => {
-            let error = TransferError::TransitConnect(error);
+            let error = TransferError::from(error);
             let _ = wormhole
                 .send_json(&PeerMessage::Error(format!("{}", error)))
                 .await;
             return Err(error);
         },
     };
+    transit.set_idle_timeout(timeouts.idle_peer);
+    if wormhole.is_strict() {
+        transit.set_validator(Some(|record| {
+            PEER_MESSAGE_V2_SCHEMA.validate_msgpack(record)
+        }));
+    }
 
     Ok((transit, info))
 }
 
-pub async fn send(
+pub(super) async fn send(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
+    record_size: usize,
     offer: OfferSend,
+    timeouts: transit::Timeouts,
     progress_handler: impl FnMut(u64, u64) + 'static,
     peer_version: AppVersion,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
     let peer_abilities = peer_version.transfer_v2.unwrap();
+    let written_ack = super::written_ack(&wormhole);
+    let manifest = super::offer_manifest(&wormhole);
+    let abort = super::abort_tracker(&wormhole);
     futures::pin_mut!(cancel);
 
     /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
@@ -172,6 +542,7 @@ pub async fn send(
                 relay_hints,
                 transit_abilities,
                 peer_abilities.transit_abilities,
+                timeouts,
             )
             .await?
             .0)
@@ -184,16 +555,27 @@ pub async fn send(
         transit,
         run = async {
             /* Close the wormhole only here so that the operation may be cancelled */
-            wormhole.close().await?;
+            let wormhole = wormhole.close_for_transit().await?;
 
-            send_inner(&mut transit, offer, progress_handler).await
+            send_inner(
+                &mut transit,
+                offer,
+                record_size,
+                timeouts,
+                written_ack,
+                manifest,
+                &abort,
+                progress_handler,
+            )
+            .await?;
+            if let Some(wormhole) = wormhole {
+                wormhole.close().await?;
+            }
+            Ok(())
         },
         cancel,
-        |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
-        |msg| match PeerMessageV2::de_msgpack(msg)? {
-            PeerMessageV2::Error(err) => Ok(Some(err)),
-            _ => Ok(None),
-        },
+        |err| error_message(&abort, err),
+        |msg| Ok(peer_error(msg)?),
         ret_cancel = (),
     );
 
@@ -204,19 +586,57 @@ pub async fn send(
 async fn send_inner(
     transit: &mut transit::Transit,
     offer: OfferSend,
+    record_size: usize,
+    timeouts: transit::Timeouts,
+    written_ack: bool,
+    manifest: bool,
+    abort: &AbortTracker,
     mut progress_handler: impl FnMut(u64, u64) + 'static,
 ) -> Result<(), TransferError> {
-    transit.send_record(&{
-        /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
-        PeerMessageV2::Offer((&offer).into()).ser_msgpack()
-    }).await?;
+    /* Send the offer in chunks, so that neither side needs to hold all of it in serialized form.
+     * The offer only gets borrowed in between the `.await`s, as its contents are not `Sync`. */
+    if manifest {
+        let mut walk = ManifestWalk::default();
+        let mut metadata = offer.metadata.clone();
+        let mut valid_for = timeouts.offer.map(|offer| offer.as_secs());
+        loop {
+            let entries = walk.take(&offer, MANIFEST_CHUNK_LEN);
+            let last = walk.is_done(&offer);
+            let message = PeerMessageV2::OfferManifest(OfferManifest {
+                entries,
+                last,
+                metadata: metadata.take(),
+                valid_for: valid_for.take(),
+            });
+            transit.send_record(&message.ser_msgpack()).await?;
+            if last {
+                break;
+            }
+        }
+    } else {
+        /* Peers that don't know about manifests get the offer as a whole */
+        let listing: Offer = (&offer).into();
+        transit
+            .send_record(&PeerMessageV2::Offer(listing).ser_msgpack())
+            .await?;
+    }
 
-    let files = match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
-        PeerMessageV2::Answer(answer) => answer.files,
-        other => {
-            bail!(TransferError::unexpected_message("answer", other))
-        },
-    };
+    /* The user on the other side may take their time to decide, so the idle timeout
+     * does not apply here. Only the offer timeout does. */
+    transit.set_idle_timeout(None);
+    let files = super::answer_within(timeouts.offer, abort, async {
+        loop {
+            match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
+                PeerMessageV2::Answer(answer) => break Ok(answer.files),
+                PeerMessageV2::KeepAlive => continue,
+                other => {
+                    bail!(TransferError::unexpected_message("answer", other))
+                },
+            }
+        }
+    })
+    .await?;
+    transit.set_idle_timeout(timeouts.idle_peer);
 
     let mut total_size = 0;
     for file in &files {
@@ -232,9 +652,6 @@ async fn send_inner(
 
     // use zstd::stream::raw::Encoder;
     // let zstd = Encoder::new(zstd::DEFAULT_COMPRESSION_LEVEL);
-    const BUFFER_LEN: usize = 16 * 1024;
-    let mut buffer = Box::new([0u8; BUFFER_LEN]);
-
     for AnswerMessageInner {
         file,
         offset,
@@ -246,6 +663,7 @@ async fn send_inner(
         let content = (offer.get_file(file).unwrap().0)();
         let mut content = content.await?;
         let file = file.clone();
+        abort.start_file(file.clone(), offset);
 
         /* If they specified a hash, check our local file's contents */
         if let Some(sha256) = sha256 {
@@ -270,6 +688,7 @@ async fn send_inner(
                     )
                     .await?;
             } else {
+                abort.start_file(file.clone(), 0);
                 transit
                     .send_record(
                         &PeerMessageV2::FileStart(FileStart {
@@ -296,30 +715,24 @@ async fn send_inner(
         }
 
         progress_handler(total_sent, total_size);
-        loop {
-            let n = content.read(&mut buffer[..]).await?;
-            let buffer = &buffer[..n];
-
-            if n == 0 {
-                // EOF
-                break;
-            }
-
-            transit
-                .send_record(
-                    &PeerMessageV2::Payload(Payload {
-                        payload: buffer.into(),
-                    })
-                    .ser_msgpack(),
-                )
-                .await?;
-            total_sent += n as u64;
-            progress_handler(total_sent, total_size);
-
-            if n < BUFFER_LEN {
-                break;
+        let (reading, chunks) = super::read_ahead::read_ahead(content, record_size);
+        let sending = async {
+            /* Move the stream in here, so that it gets dropped (which stops the reading) on errors */
+            let mut chunks = chunks;
+            while let Some(buffer) = chunks.next().await {
+                let buffer = buffer?;
+                let n = buffer.len();
+                transit
+                    .send_record(&PeerMessageV2::Payload(Payload { payload: buffer }).ser_msgpack())
+                    .await?;
+                total_sent += n as u64;
+                abort.advance(n as u64);
+                progress_handler(total_sent, total_size);
             }
-        }
+            Ok::<_, TransferError>(())
+        };
+        let ((), result) = futures::join!(reading, sending);
+        result?;
 
         transit
             .send_record(&PeerMessageV2::FileEnd(FileEnd {}).ser_msgpack())
@@ -329,17 +742,31 @@ async fn send_inner(
         .send_record(&PeerMessageV2::TransferAck(TransferAck {}).ser_msgpack())
         .await?;
 
+    if written_ack {
+        debug!("Waiting for the data to be written");
+        match PeerMessageV2::de_msgpack(&transit.receive_record().await?)? {
+            PeerMessageV2::TransferWritten(_) => {},
+            PeerMessageV2::Error(err) => bail!(TransferError::NotWritten(err)),
+            other => bail!(TransferError::unexpected_message("transfer-written", other)),
+        }
+    }
+
     Ok(())
 }
 
-pub async fn request(
+pub(super) async fn request(
     mut wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     peer_version: AppVersion,
     transit_abilities: transit::Abilities,
+    mut offer_progress: impl FnMut(u64, u64),
     cancel: impl Future<Output = ()>,
 ) -> Result<Option<ReceiveRequest>, TransferError> {
     let peer_abilities = peer_version.transfer_v2.unwrap();
+    let written_ack = super::written_ack(&wormhole);
+    let keep_alive = super::keep_alive(&wormhole);
+    let manifest = super::offer_manifest(&wormhole);
+    let abort = super::abort_tracker(&wormhole);
     futures::pin_mut!(cancel);
 
     /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
@@ -352,6 +779,7 @@ pub async fn request(
                 relay_hints,
                 transit_abilities,
                 peer_abilities.transit_abilities,
+                transit::Timeouts::default(),
             )
             .await
         },
@@ -359,32 +787,119 @@ pub async fn request(
         ret_cancel = None,
     );
 
-    let (offer, transit) = cancel::with_cancel_transit!(
+    let ((offer, valid_for, wormhole), transit) = cancel::with_cancel_transit!(
         transit,
         run = async {
             /* Close the wormhole only here so that the `.await` is scoped within cancellation */
-            wormhole.close().await?;
-
-            let offer =
-                match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
-                    PeerMessageV2::Offer(offer) => offer,
-                    other => {
-                        bail!(TransferError::unexpected_message("offer", other))
-                    },
-                };
+            let wormhole = wormhole.close_for_transit().await?;
 
-            Ok(offer)
+            let (offer, valid_for) =
+                receive_offer(&mut transit, manifest, &mut offer_progress).await?;
+            Ok((offer, valid_for, wormhole))
         },
         cancel,
-        |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
-        |msg| match PeerMessageV2::de_msgpack(msg)? {
-            PeerMessageV2::Error(err) => Ok(Some(err)),
-            _ => Ok(None),
-        },
+        |err| error_message(&Default::default(), err),
+        |msg| Ok(peer_error(msg)?),
         ret_cancel = None,
     );
 
-    Ok(Some(ReceiveRequest::new(transit, offer, info)))
+    Ok(Some(ReceiveRequest::new(
+        transit,
+        wormhole,
+        offer,
+        info,
+        written_ack,
+        keep_alive,
+        abort,
+        super::withdraw::expires_at(valid_for),
+    )))
+}
+
+/**
+ * Receive the offer, either as a whole or, if `manifest` got negotiated, as a series of manifest messages
+ *
+ * `offer_progress` gets the number of files and their total size received so far.
+ * Also returns for how many seconds the offer is valid, if the sender told.
+ */
+async fn receive_offer(
+    transit: &mut transit::Transit,
+    manifest: bool,
+    offer_progress: &mut impl FnMut(u64, u64),
+) -> Result<(Offer, Option<u64>), TransferError> {
+    let mut offer = Offer {
+        content: BTreeMap::new(),
+        mime_types: BTreeMap::new(),
+        metadata: None,
+    };
+    let (mut entries, mut files, mut total_size) = (0, 0, 0u64);
+    let mut valid_for = None;
+    loop {
+        match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
+            PeerMessageV2::Offer(whole) if offer.content.is_empty() => break Ok((whole, None)),
+            PeerMessageV2::OfferManifest(manifest_part) if manifest => {
+                if manifest_part.metadata.is_some() {
+                    offer.metadata = manifest_part.metadata;
+                }
+                valid_for = valid_for.or(manifest_part.valid_for);
+                entries += manifest_part.entries.len();
+                if entries > MAX_MANIFEST_ENTRIES {
+                    bail!(TransferError::Protocol(
+                        format!("Offer manifest lists more than {MAX_MANIFEST_ENTRIES} entries")
+                            .into()
+                    ));
+                }
+                for entry in manifest_part.entries {
+                    if let ManifestEntry::RegularFile { size, .. } = entry {
+                        files += 1;
+                        total_size = total_size.checked_add(size).ok_or_else(|| {
+                            TransferError::Protocol("Offer manifest is too large in total".into())
+                        })?;
+                    }
+                    insert_manifest_entry(&mut offer, entry)
+                        .map_err(|e| TransferError::Protocol(e.into()))?;
+                }
+                offer_progress(files, total_size);
+                if manifest_part.last {
+                    break Ok((offer, valid_for));
+                }
+            },
+            other => {
+                bail!(TransferError::unexpected_message("offer-manifest", other))
+            },
+        }
+    }
+}
+
+/**
+ * Resolves once the pending offer is gone, because it expired or the sender withdrew it
+ *
+ * Gets dropped in between keep alive messages, which may lose a partially received record. This is fine:
+ * while waiting for the answer, the sender only ever sends the one message that ends the session anyway.
+ */
+async fn watch_offer(
+    transit: &mut transit::Transit,
+    expires_at: Option<instant::Instant>,
+) -> TransferError {
+    let withdrawn = async {
+        loop {
+            let message =
+                async { PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err() };
+            match message.await {
+                Ok(PeerMessageV2::KeepAlive) => continue,
+                Ok(other) => break TransferError::unexpected_message("offer-withdrawn", other),
+                Err(error) => break error,
+            }
+        }
+    };
+    futures::pin_mut!(withdrawn);
+    let expired = super::withdraw::expiry(expires_at);
+    futures::pin_mut!(expired);
+    match futures::future::select(withdrawn, expired).await {
+        futures::future::Either::Left((error, _)) => error,
+        futures::future::Either::Right(((), _)) => {
+            TransferError::OfferWithdrawn(super::WithdrawReason::Expired)
+        },
+    }
 }
 
 /**
@@ -395,24 +910,100 @@ pub async fn request(
 #[must_use]
 pub struct ReceiveRequest {
     transit: Transit,
+    /* Only if it is kept open during transit, see [`Wormhole::set_keep_open_during_transit`] */
+    wormhole: Option<Wormhole>,
     offer: Arc<Offer>,
     info: transit::TransitInfo,
+    scanner: Option<Box<dyn super::ContentScanner>>,
+    written_ack: bool,
+    /* Whether the peer understands [`PeerMessageV2::KeepAlive`] */
+    keep_alive: bool,
+    abort: AbortTracker,
+    expires_at: Option<instant::Instant>,
 }
 
 impl ReceiveRequest {
-    pub fn new(transit: Transit, offer: Offer, info: transit::TransitInfo) -> Self {
+    pub(super) fn new(
+        transit: Transit,
+        wormhole: Option<Wormhole>,
+        offer: Offer,
+        info: transit::TransitInfo,
+        written_ack: bool,
+        keep_alive: bool,
+        abort: AbortTracker,
+        expires_at: Option<instant::Instant>,
+    ) -> Self {
         Self {
             transit,
+            wormhole,
             offer: Arc::new(offer),
             info,
+            scanner: None,
+            written_ack,
+            keep_alive,
+            abort,
+            expires_at,
         }
     }
 
+    /** When the sender stops waiting for an answer, see [`ReceiveRequest::expires_at`](super::ReceiveRequest::expires_at) */
+    pub fn expires_at(&self) -> Option<instant::Instant> {
+        self.expires_at
+    }
+
     /** The offer we got */
     pub fn offer(&self) -> Arc<Offer> {
         self.offer.clone()
     }
 
+    /** The application specific data the sender attached, see [`Offer::metadata`] */
+    pub fn metadata(&self) -> Option<&crate::OfferMetadata> {
+        self.offer.metadata()
+    }
+
+    /** Limit the transfer, see [`ReceiveRequest::set_timeouts`](super::ReceiveRequest::set_timeouts) */
+    pub fn set_timeouts(&mut self, timeouts: transit::Timeouts) {
+        self.transit.set_idle_timeout(timeouts.idle_peer);
+    }
+
+    /** Check the received content, see [`ReceiveRequest::set_scanner`](super::ReceiveRequest::set_scanner) */
+    pub fn set_scanner(&mut self, scanner: impl super::ContentScanner + 'static) {
+        self.scanner = Some(Box::new(scanner));
+    }
+
+    /**
+     * Keep the transit connection alive while waiting for `future`
+     *
+     * See [`ReceiveRequest::keep_alive_while`](super::ReceiveRequest::keep_alive_while).
+     */
+    pub async fn keep_alive_while<T>(
+        &mut self,
+        interval: std::time::Duration,
+        future: impl Future<Output = T>,
+    ) -> Result<T, TransferError> {
+        let expires_at = self.expires_at;
+        crate::util::keep_alive_watching(
+            self,
+            interval,
+            future,
+            |this| Box::pin(this.keep_alive()),
+            move |this| Box::pin(watch_offer(&mut this.transit, expires_at)),
+        )
+        .await
+    }
+
+    /* Ping the peer if it understands that, and else at least the rendezvous server if it is still connected */
+    async fn keep_alive(&mut self) -> Result<(), TransferError> {
+        if self.keep_alive {
+            self.transit
+                .send_record(&PeerMessageV2::KeepAlive.ser_msgpack())
+                .await?;
+        } else if let Some(wormhole) = &mut self.wormhole {
+            wormhole.ping().await?;
+        }
+        Ok(())
+    }
+
     /**
      * Accept the file offer
      *
@@ -429,6 +1020,7 @@ impl ReceiveRequest {
         futures::pin_mut!(cancel);
 
         let mut transit = self.transit;
+        let mut scanner = self.scanner;
         cancel::with_cancel_transit!(
             transit,
             run = async {
@@ -446,14 +1038,33 @@ impl ReceiveRequest {
                     msg
                 }).await?;
 
-                receive_inner(&mut transit, &self.offer, answer, progress_handler).await
+                receive_inner(
+                    &mut transit,
+                    &self.offer,
+                    answer,
+                    progress_handler,
+                    &mut scanner,
+                    &self.abort,
+                )
+                .await?;
+
+                /* All files got closed while receiving */
+                if self.written_ack {
+                    transit
+                        .send_record(
+                            &PeerMessageV2::TransferWritten(TransferWritten {}).ser_msgpack(),
+                        )
+                        .await?;
+                    transit.flush().await?;
+                }
+                if let Some(wormhole) = self.wormhole {
+                    wormhole.close().await?;
+                }
+                Ok(())
             },
             cancel,
-            |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
-            |msg| match PeerMessageV2::de_msgpack(msg)? {
-                PeerMessageV2::Error(err) => Ok(Some(err)),
-                _ => Ok(None),
-            },
+            |err| error_message(&self.abort, err),
+            |msg| Ok(peer_error(msg)?),
             ret_cancel = (),
         );
         Ok(())
@@ -469,6 +1080,9 @@ impl ReceiveRequest {
             .send_record(&PeerMessageV2::Error("transfer rejected".into()).ser_msgpack())
             .await?;
         self.transit.flush().await?;
+        if let Some(wormhole) = self.wormhole {
+            wormhole.close().await?;
+        }
 
         Ok(())
     }
@@ -480,6 +1094,8 @@ async fn receive_inner(
     offer: &Arc<Offer>,
     our_answer: OfferAccept,
     mut progress_handler: impl FnMut(u64, u64) + 'static,
+    scanner: &mut Option<Box<dyn super::ContentScanner>>,
+    abort: &AbortTracker,
 ) -> Result<(), TransferError> {
     /* This does not check for file sizes, but should be good enough
      * (failures will eventually lead to protocol errors later on anyways)
@@ -522,6 +1138,13 @@ async fn receive_inner(
             )
         );
 
+        if let Some(scanner) = scanner.as_mut() {
+            scanner
+                .begin_file(&file, size)
+                .await
+                .map_err(TransferError::ContentRejected)?;
+        }
+
         let mut content;
         let mut received_size = 0;
         if file_start.start_at_offset {
@@ -531,6 +1154,7 @@ async fn receive_inner(
         } else {
             content = (answer.content)(false).await?;
         }
+        abort.start_file(file.clone(), received_size);
 
         progress_handler(total_received, total_size);
         loop {
@@ -552,6 +1176,13 @@ async fn receive_inner(
                 };
 
             content.write_all(&payload).await?;
+            abort.advance(payload.len() as u64);
+            if let Some(scanner) = scanner.as_mut() {
+                scanner
+                    .scan(&payload)
+                    .await
+                    .map_err(TransferError::ContentRejected)?;
+            }
             received_size += payload.len() as u64;
             total_received += payload.len() as u64;
             progress_handler(total_received, total_size);
@@ -597,5 +1228,139 @@ async fn receive_inner(
             },
         };
 
+    if let Some(scanner) = scanner {
+        scanner
+            .finish()
+            .await
+            .map_err(TransferError::ContentRejected)?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* Directories come before their content, and the walk resumes where the last chunk ended */
+    #[test]
+    fn test_manifest_walk() {
+        let file = |size| OfferEntry::RegularFile { size, content: () };
+        let dir = |content: Vec<(&str, OfferEntry)>| OfferEntry::Directory {
+            content: content
+                .into_iter()
+                .map(|(name, entry)| (name.to_owned(), entry))
+                .collect(),
+        };
+        let offer = Offer {
+            content: [
+                (
+                    "a".to_owned(),
+                    dir(vec![
+                        ("b", dir(vec![("c.txt", file(1))])),
+                        ("d.txt", file(2)),
+                    ]),
+                ),
+                ("e".to_owned(), dir(vec![])),
+                ("z.txt".to_owned(), file(3)),
+            ]
+            .into(),
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        };
+
+        let mut walk = ManifestWalk::default();
+        let mut paths = Vec::new();
+        while !walk.is_done(&offer) {
+            for entry in walk.take(&offer, 2) {
+                let (ManifestEntry::RegularFile { path, .. } | ManifestEntry::Directory { path }) =
+                    entry;
+                paths.push(path.join("/"));
+            }
+        }
+        assert_eq!(paths, ["a", "a/b", "a/b/c.txt", "a/d.txt", "e", "z.txt"]);
+    }
+
+    #[test]
+    fn test_strict_schema() {
+        let mut content = BTreeMap::new();
+        content.insert(
+            "notes.txt".to_owned(),
+            OfferEntry::RegularFile {
+                size: 42,
+                content: (),
+            },
+        );
+        let mut offer = Offer {
+            content: [("photos".to_owned(), OfferEntry::Directory { content })].into(),
+            mime_types: BTreeMap::new(),
+            metadata: None,
+        };
+        let path = ["photos".to_owned(), "notes.txt".to_owned()];
+        offer.set_mime_type(&path, "text/plain");
+        offer.set_metadata(crate::OfferMetadata::new("ticket", 1, &42).unwrap());
+        let manifest = OfferManifest {
+            entries: ManifestWalk::default().take(&offer, usize::MAX),
+            last: true,
+            metadata: offer.metadata.clone(),
+            valid_for: Some(60),
+        };
+
+        /* The MIME types survive the manifest */
+        let mut received = Offer {
+            content: BTreeMap::new(),
+            mime_types: BTreeMap::new(),
+            metadata: manifest.metadata.clone(),
+        };
+        for entry in manifest.entries.clone() {
+            insert_manifest_entry(&mut received, entry).unwrap();
+        }
+        assert_eq!(received, offer);
+        assert_eq!(received.mime_type(&path), Some("text/plain"));
+
+        for message in [
+            PeerMessageV2::Offer(offer),
+            PeerMessageV2::OfferManifest(manifest),
+            PeerMessageV2::Answer(AnswerMessage {
+                files: vec![AnswerMessageInner {
+                    file: vec!["photos".into(), "notes.txt".into()],
+                    offset: 0,
+                    sha256: Some([7; 32]),
+                }],
+            }),
+            PeerMessageV2::FileStart(FileStart {
+                file: vec!["photos".into(), "notes.txt".into()],
+                start_at_offset: false,
+            }),
+            PeerMessageV2::Payload(Payload {
+                payload: vec![0, 1, 255],
+            }),
+            PeerMessageV2::FileEnd(FileEnd {}),
+            PeerMessageV2::TransferAck(TransferAck {}),
+            PeerMessageV2::TransferWritten(TransferWritten {}),
+            PeerMessageV2::Error("oops".into()),
+            PeerMessageV2::Abort(AbortMessage {
+                reason: "Cancelled".into(),
+                summary: super::AbortSummary {
+                    bytes: 100,
+                    file: Some(vec!["a".into()]),
+                    offset: 100,
+                },
+            }),
+            PeerMessageV2::KeepAlive,
+            PeerMessageV2::OfferWithdrawn(super::WithdrawReason::Expired),
+        ] {
+            PEER_MESSAGE_V2_SCHEMA
+                .validate_msgpack(&message.ser_msgpack())
+                .unwrap_or_else(|violation| panic!("{}: {}", message, violation));
+        }
+
+        let forged =
+            rmp_serde::to_vec(&serde_json::json!({"payload": {"payload": [1, 256]}})).unwrap();
+        let violation = PEER_MESSAGE_V2_SCHEMA
+            .validate_msgpack(&forged)
+            .unwrap_err();
+        assert_eq!(violation.path, "$.payload.payload[1]");
+        assert_eq!(violation.message, forged);
+    }
+}
@@ -0,0 +1,44 @@
+//! Take back an offer the peer has not answered yet
+//!
+//! The sender tells how long its offer is valid, which is its [offer timeout](crate::transit::Timeouts::offer).
+//! If both sides support it (negotiated via the `abilities` of the [`AppVersion`](super::AppVersion)), a sender
+//! that gets cancelled or runs out of time while waiting for the answer sends an `offer-withdrawn` message
+//! instead of a plain error. On the receiving side, [`ReceiveRequest::keep_alive_while`](super::ReceiveRequest::keep_alive_while)
+//! watches for both, so that a pending prompt can be dismissed instead of accepting into a dead session.
+
+use crate::schema::{Schema, UnknownVariants};
+use serde_derive::{Deserialize, Serialize};
+
+/// Why the sender took back its offer, see [`TransferError::OfferWithdrawn`](super::TransferError::OfferWithdrawn)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, derive_more::Display)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum WithdrawReason {
+    /// The sender cancelled the transfer
+    #[display(fmt = "cancelled")]
+    Cancelled,
+    /// The offer was not answered while it was valid
+    #[display(fmt = "expired")]
+    Expired,
+}
+
+pub(super) static WITHDRAW_REASON_SCHEMA: Schema = Schema::Enum {
+    variants: &[("cancelled", None), ("expired", None)],
+    unknown_variants: UnknownVariants::Reject,
+};
+
+/** When an offer that is valid for `valid_for` seconds, received just now, expires */
+pub(super) fn expires_at(valid_for: Option<u64>) -> Option<instant::Instant> {
+    valid_for
+        .and_then(|secs| instant::Instant::now().checked_add(std::time::Duration::from_secs(secs)))
+}
+
+/** Resolves once the offer expired, never if it does not */
+pub(super) async fn expiry(expires_at: Option<instant::Instant>) {
+    match expires_at {
+        Some(expires_at) => {
+            crate::util::sleep(expires_at.saturating_duration_since(instant::Instant::now())).await
+        },
+        None => futures::future::pending().await,
+    }
+}
@@ -13,6 +13,8 @@
 //! **Notice:** while the resulting TCP connection is naturally bi-directional, the handshake is not symmetric. There *must* be one
 //! "leader" side and one "follower" side (formerly called "sender" and "receiver").
 
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+use crate::schema::{self, Field, Schema, UnknownFields, UnknownVariants};
 use crate::{util, Key, KeyPurpose};
 use serde_derive::{Deserialize, Serialize};
 
@@ -33,17 +35,34 @@ use std::{
 };
 
 mod crypto;
+mod health;
+mod latency;
+mod probe;
+#[cfg(all(feature = "quic", not(target_family = "wasm")))]
+mod quic;
+mod report;
+mod stats;
 mod transport;
 use crypto::TransitHandshakeError;
+pub use health::{HealthEntry, RelayHealthCache};
+#[cfg(not(target_family = "wasm"))]
+pub use latency::measure_relay_rtts;
+pub use latency::{prefer_lowest_rtt, RelayRtt};
+pub use probe::ProbeResult;
+pub use report::{SessionReport, SessionReporter};
+pub use stats::{RelaySwitchPolicy, TransitStats, TransitStatsHandle};
 use transport::{TransitTransport, TransitTransportRx, TransitTransportTx};
 
 /// ULR to a default hosted relay server. Please don't abuse or DOS.
 pub const DEFAULT_RELAY_SERVER: &str = "tcp://transit.magic-wormhole.io:4001";
+/// How often to send keep-alive messages while waiting for the user. Many NATs drop
+/// idle mappings after 30 seconds.
+pub const DEFAULT_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
 // No need to make public, it's hard-coded anyways (:
 // Open an issue if you want an API for this
 // Use <stun.stunprotocol.org:3478> for non-production testing
 #[cfg(not(target_family = "wasm"))]
-const PUBLIC_STUN_SERVER: &str = "stun.piegames.de:3478";
+pub(crate) const PUBLIC_STUN_SERVER: &str = "stun.piegames.de:3478";
 
 #[derive(Debug)]
 pub struct TransitKey;
@@ -63,6 +82,9 @@ pub enum TransitConnectError {
     Protocol(Box<str>),
     #[error("All (relay) handshakes failed or timed out; could not establish a connection with the peer")]
     Handshake,
+    /** The time budget of the [`ConnectTimeouts`] ran out */
+    #[error("Timed out while establishing a connection with the peer")]
+    Timeout,
     #[error("IO error")]
     IO(
         #[from]
@@ -85,6 +107,22 @@ pub enum TransitError {
     Crypto,
     #[error("Wrong nonce received, got {:x?} but expected {:x?}. This is probably an implementation bug, but may also be caused by an attack.", _0, _1)]
     Nonce(Box<[u8]>, Box<[u8]>),
+    #[error("Records got lost on the way: expected record {expected}, but got {received}")]
+    RecordGap { expected: u64, received: u64 },
+    #[error("Record {received} was received a second time (expected record {expected}). This may be caused by a faulty relay, but also by an attack.")]
+    RecordReplay { expected: u64, received: u64 },
+    #[error("The peer agreed on different transit abilities than we did. Someone may have tampered with the negotiation.")]
+    AbilityMismatch,
+    /** Nothing arrived within the time set with [`Transit::set_idle_timeout`] */
+    #[error("The peer did not send anything for too long")]
+    IdleTimeout,
+    /** The peer sent a record that does not match the protocol, see [`Wormhole::set_strict`](crate::Wormhole::set_strict) */
+    #[error("Malformed record received from peer")]
+    Malformed(
+        #[from]
+        #[source]
+        crate::SchemaViolation,
+    ),
     #[error("IO error")]
     IO(
         #[from]
@@ -106,28 +144,56 @@ impl From<()> for TransitError {
     }
 }
 
+/* QUIC needs native UDP sockets */
+const QUIC_SUPPORTED: bool = cfg!(all(feature = "quic", not(target_family = "wasm")));
+
 /**
  * Defines a way to find the other side.
  *
  * Each ability comes with a set of [`Hints`] to encode how to meet up.
  */
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Abilities {
     /** Direct connection to the peer */
     pub direct_tcp_v1: bool,
+    /**
+     * Direct connection to the peer over QUIC, with UDP hole punching
+     *
+     * This gets through more NATs than direct TCP connections. Requires the `quic` feature.
+     */
+    pub direct_quic_v1: bool,
     /** Connection over a relay */
     pub relay_v1: bool,
-    #[cfg(any())]
-    /** **Experimental** Use the [noise protocol](https://noiseprotocol.org) for the encryption. */
+    /**
+     * Connection over a relay, also via WebSocket
+     *
+     * This reaches relays through firewalls that only let web traffic through, using the `ws://` and
+     * `wss://` URLs of the relay hints.
+     */
+    pub relay_v2: bool,
+    /**
+     * Bind each encrypted record to the session and direction it belongs to
+     *
+     * Only used if both sides support it, otherwise the records fall back to the classic format.
+     */
+    pub record_v2: bool,
+    /**
+     * Use the [noise protocol](https://noiseprotocol.org) for the encryption, instead of secretbox records
+     *
+     * This is the handshake of Dilation, with the ciphers getting rekeyed regularly. Only used if both
+     * sides support it.
+     */
     pub noise_v1: bool,
 }
 
 impl Abilities {
     pub const ALL_ABILITIES: Self = Self {
         direct_tcp_v1: true,
+        direct_quic_v1: QUIC_SUPPORTED,
         relay_v1: true,
-        #[cfg(any())]
-        noise_v1: false,
+        relay_v2: true,
+        record_v2: true,
+        noise_v1: true,
     };
 
     /**
@@ -138,9 +204,11 @@ impl Abilities {
      */
     pub const FORCE_DIRECT: Self = Self {
         direct_tcp_v1: true,
+        direct_quic_v1: QUIC_SUPPORTED,
         relay_v1: false,
-        #[cfg(any())]
-        noise_v1: false,
+        relay_v2: false,
+        record_v2: true,
+        noise_v1: true,
     };
 
     /**
@@ -153,36 +221,47 @@ impl Abilities {
      */
     pub const FORCE_RELAY: Self = Self {
         direct_tcp_v1: false,
+        direct_quic_v1: false,
         relay_v1: true,
-        #[cfg(any())]
-        noise_v1: false,
+        relay_v2: true,
+        record_v2: true,
+        noise_v1: true,
     };
 
     pub fn can_direct(&self) -> bool {
         self.direct_tcp_v1
     }
 
+    pub fn can_direct_quic(&self) -> bool {
+        self.direct_quic_v1
+    }
+
+    /** Whether relays can be used at all, with either ability */
     pub fn can_relay(&self) -> bool {
-        self.relay_v1
+        self.relay_v1 || self.relay_v2
     }
 
-    #[cfg(any())]
-    pub fn can_noise_crypto(&self) -> bool {
-        self.noise_v1
+    /** Whether relays can be reached via WebSocket */
+    pub fn can_relay_ws(&self) -> bool {
+        self.relay_v2
+    }
+
+    pub fn can_bind_records(&self) -> bool {
+        self.record_v2
     }
 
     pub fn can_noise_crypto(&self) -> bool {
-        false
+        self.noise_v1
     }
 
     /** Keep only abilities that both sides support */
     pub fn intersect(mut self, other: &Self) -> Self {
         self.direct_tcp_v1 &= other.direct_tcp_v1;
+        self.direct_quic_v1 &= other.direct_quic_v1;
         self.relay_v1 &= other.relay_v1;
-        #[cfg(any())]
-        {
-            self.noise_v1 &= other.noise_v1;
-        }
+        self.relay_v2 &= other.relay_v2;
+        self.record_v2 &= other.record_v2;
+        self.noise_v1 &= other.noise_v1;
         self
     }
 }
@@ -198,12 +277,26 @@ impl serde::Serialize for Abilities {
                 "type": "direct-tcp-v1",
             }));
         }
+        if self.direct_quic_v1 {
+            hints.push(serde_json::json!({
+                "type": "direct-quic-v1",
+            }));
+        }
         if self.relay_v1 {
             hints.push(serde_json::json!({
                 "type": "relay-v1",
             }));
         }
-        #[cfg(any())]
+        if self.relay_v2 {
+            hints.push(serde_json::json!({
+                "type": "relay-v2",
+            }));
+        }
+        if self.record_v2 {
+            hints.push(serde_json::json!({
+                "type": "record-v2",
+            }));
+        }
         if self.noise_v1 {
             hints.push(serde_json::json!({
                 "type": "noise-crypto-v1",
@@ -222,9 +315,10 @@ impl<'de> serde::Deserialize<'de> for Abilities {
         #[serde(rename_all = "kebab-case", tag = "type")]
         enum Ability {
             DirectTcpV1,
+            DirectQuicV1,
             RelayV1,
             RelayV2,
-            #[cfg(all())]
+            RecordV2,
             NoiseCryptoV1,
             #[serde(other)]
             Other,
@@ -237,10 +331,18 @@ impl<'de> serde::Deserialize<'de> for Abilities {
                 Ability::DirectTcpV1 => {
                     abilities.direct_tcp_v1 = true;
                 },
+                Ability::DirectQuicV1 => {
+                    abilities.direct_quic_v1 = true;
+                },
                 Ability::RelayV1 => {
                     abilities.relay_v1 = true;
                 },
-                #[cfg(any())]
+                Ability::RelayV2 => {
+                    abilities.relay_v2 = true;
+                },
+                Ability::RecordV2 => {
+                    abilities.record_v2 = true;
+                },
                 Ability::NoiseCryptoV1 => {
                     abilities.noise_v1 = true;
                 },
@@ -257,16 +359,100 @@ impl<'de> serde::Deserialize<'de> for Abilities {
 #[non_exhaustive]
 enum HintSerde {
     DirectTcpV1(DirectHint),
+    DirectQuicV1(DirectHint),
     RelayV1(RelayHint),
     #[serde(other)]
     Unknown,
 }
 
+/* Wire format of `Abilities`, for strict mode. Unknown abilities are to be skipped. */
+#[cfg(feature = "transfer")]
+pub(crate) static ABILITIES_SCHEMA: Schema = Schema::Array {
+    items: &Schema::Tagged {
+        tag: "type",
+        variants: &[
+            ("direct-tcp-v1", &schema::EMPTY),
+            ("direct-quic-v1", &schema::EMPTY),
+            ("relay-v1", &schema::EMPTY),
+            ("relay-v2", &schema::EMPTY),
+            ("record-v2", &schema::EMPTY),
+            ("noise-crypto-v1", &schema::EMPTY),
+        ],
+        unknown_variants: UnknownVariants::Ignore,
+    },
+    min_len: 0,
+    max_len: 64,
+};
+
+/* The Python implementation sends a `priority`, and the hints are meant to be extended */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+static DIRECT_HINT_SCHEMA: Schema = Schema::Object {
+    fields: &[
+        Field::required("hostname", &schema::STRING),
+        Field::required("port", &schema::U16),
+        Field::optional("priority", &schema::FLOAT),
+    ],
+    unknown_fields: UnknownFields::Ignore,
+};
+
+/* Relay load in percent */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+static RELAY_LOAD_SCHEMA: Schema = Schema::Integer { min: 0, max: 100 };
+
+/* Wire format of `Hints`, for strict mode. Unknown hints are to be skipped. */
+#[cfg(any(feature = "transfer", feature = "forwarding"))]
+pub(crate) static HINTS_SCHEMA: Schema = Schema::Array {
+    items: &Schema::Tagged {
+        tag: "type",
+        variants: &[
+            ("direct-tcp-v1", &DIRECT_HINT_SCHEMA),
+            ("direct-quic-v1", &DIRECT_HINT_SCHEMA),
+            (
+                "relay-v1",
+                &Schema::Object {
+                    fields: &[
+                        Field::optional("name", &schema::NULLABLE_STRING),
+                        Field::optional("region", &schema::STRING),
+                        Field::optional("load", &RELAY_LOAD_SCHEMA),
+                        Field::required(
+                            "hints",
+                            &Schema::Array {
+                                items: &Schema::Tagged {
+                                    tag: "type",
+                                    variants: &[
+                                        ("direct-tcp-v1", &DIRECT_HINT_SCHEMA),
+                                        (
+                                            "websocket",
+                                            &Schema::Object {
+                                                fields: &[Field::required("url", &schema::STRING)],
+                                                unknown_fields: UnknownFields::Reject,
+                                            },
+                                        ),
+                                    ],
+                                    unknown_variants: UnknownVariants::Ignore,
+                                },
+                                min_len: 0,
+                                max_len: 1024,
+                            },
+                        ),
+                    ],
+                    unknown_fields: UnknownFields::Reject,
+                },
+            ),
+        ],
+        unknown_variants: UnknownVariants::Ignore,
+    },
+    min_len: 0,
+    max_len: 1024,
+};
+
 /** Information about how to find a peer */
 #[derive(Clone, Debug, Default)]
 pub struct Hints {
     /** Hints for direct connection */
     pub direct_tcp: HashSet<DirectHint>,
+    /** Hints for direct connection over QUIC, which need a UDP port */
+    pub direct_quic: HashSet<DirectHint>,
     /** List of relay servers */
     pub relay: Vec<RelayHint>,
 }
@@ -278,6 +464,7 @@ impl Hints {
     ) -> Self {
         Self {
             direct_tcp: direct_tcp.into_iter().collect(),
+            direct_quic: HashSet::new(),
             relay: relay.into_iter().collect(),
         }
     }
@@ -290,6 +477,7 @@ impl<'de> serde::Deserialize<'de> for Hints {
     {
         let hints: Vec<HintSerde> = serde::Deserialize::deserialize(de)?;
         let mut direct_tcp = HashSet::new();
+        let mut direct_quic = HashSet::new();
         let mut relay = Vec::<RelayHint>::new();
         let mut relay_v2 = Vec::<RelayHint>::new();
 
@@ -298,6 +486,9 @@ impl<'de> serde::Deserialize<'de> for Hints {
                 HintSerde::DirectTcpV1(hint) => {
                     direct_tcp.insert(hint);
                 },
+                HintSerde::DirectQuicV1(hint) => {
+                    direct_quic.insert(hint);
+                },
                 HintSerde::RelayV1(hint) => {
                     relay_v2.push(hint);
                 },
@@ -312,7 +503,11 @@ impl<'de> serde::Deserialize<'de> for Hints {
         }
         relay.extend(relay_v2.into_iter().map(Into::into));
 
-        Ok(Hints { direct_tcp, relay })
+        Ok(Hints {
+            direct_tcp,
+            direct_quic,
+            relay,
+        })
     }
 }
 
@@ -322,8 +517,13 @@ impl serde::Serialize for Hints {
         S: serde::Serializer,
     {
         let direct = self.direct_tcp.iter().cloned().map(HintSerde::DirectTcpV1);
+        let direct_quic = self
+            .direct_quic
+            .iter()
+            .cloned()
+            .map(HintSerde::DirectQuicV1);
         let relay = self.relay.iter().cloned().map(HintSerde::RelayV1);
-        ser.collect_seq(direct.chain(relay))
+        ser.collect_seq(direct.chain(direct_quic).chain(relay))
     }
 }
 
@@ -353,6 +553,10 @@ impl DirectHint {
 #[non_exhaustive]
 struct RelayHintSerde {
     name: Option<String>,
+    #[serde(default)]
+    region: Option<String>,
+    #[serde(default)]
+    load: Option<u8>,
     #[serde(rename = "hints")]
     endpoints: Vec<RelayHintSerdeInner>,
 }
@@ -408,6 +612,10 @@ pub struct RelayHint {
     pub tcp: HashSet<DirectHint>,
     /** WebSockets endpoints of that relay */
     pub ws: HashSet<url::Url>,
+    /** Where the relay is located, as advertised by the server, like `"eu-central"` */
+    pub region: Option<String>,
+    /** How busy the relay currently is, in percent */
+    pub load: Option<u8>,
 }
 
 impl RelayHint {
@@ -420,6 +628,8 @@ impl RelayHint {
             name,
             tcp: tcp.into_iter().collect(),
             ws: ws.into_iter().collect(),
+            region: None,
+            load: None,
         }
     }
 
@@ -491,6 +701,8 @@ impl RelayHint {
     pub fn merge_mut(&mut self, other: Self) {
         self.tcp.extend(other.tcp);
         self.ws.extend(other.ws);
+        self.region = self.region.take().or(other.region);
+        self.load = self.load.or(other.load);
     }
 
     pub fn merge_into(self, collection: &mut Vec<RelayHint>) {
@@ -518,11 +730,18 @@ impl serde::Serialize for RelayHint {
                 .map(|h| RelayHintSerdeInner::Websocket { url: h }),
         );
 
-        serde_json::json!({
+        let mut hint = serde_json::json!({
             "name": self.name,
             "hints": hints,
-        })
-        .serialize(ser)
+        });
+        /* Only the relay server knows these, so don't send them if they are unset */
+        if let Some(region) = &self.region {
+            hint["region"] = region.as_str().into();
+        }
+        if let Some(load) = self.load {
+            hint["load"] = load.into();
+        }
+        hint.serialize(ser)
     }
 }
 
@@ -536,6 +755,8 @@ impl<'de> serde::Deserialize<'de> for RelayHint {
             name: raw.name,
             tcp: HashSet::new(),
             ws: HashSet::new(),
+            region: raw.region,
+            load: raw.load.map(|load| load.min(100)),
         };
 
         for e in raw.endpoints {
@@ -595,13 +816,67 @@ pub struct TransitInfo {
     /// This says nothing about the actual transport protocol used.
     #[cfg(not(target_family = "wasm"))]
     pub peer_addr: SocketAddr,
+    /// The abilities we used for connecting, after removing those the peer doesn't support
+    pub our_abilities: Abilities,
+    /// The abilities the peer advertised
+    pub peer_abilities: Abilities,
+    /// Live counters of the connection, see [`Transit::stats_handle`]
+    pub stats: TransitStatsHandle,
+    /// The relays measured before connecting, see [`TransitConnector::set_relay_rtt_budget`]
+    pub relay_rtts: Vec<RelayRtt>,
+    /* Which relay hint the connection went through, keyed like in the `RelayHealthCache` */
+    pub(crate) relay_key: Option<String>,
+}
+
+/// How much traffic went over a relay server, see [`TransitInfo::relay_usage`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RelayUsage {
+    /// The name of the relay, if it has one
+    pub relay: Option<String>,
+    /// Bytes sent to the relay, including the encryption and framing overhead
+    pub bytes_sent: u64,
+    /// Bytes received from the relay, including the encryption and framing overhead
+    pub bytes_received: u64,
+}
+
+impl TransitInfo {
+    /**
+     * Whether a direct connection was impossible because one side disabled it
+     *
+     * Useful to tell the user that it's their (or their peer's) configuration that forces
+     * the traffic through a relay.
+     */
+    pub fn is_relay_forced(&self) -> bool {
+        matches!(self.conn_type, ConnectionType::Relay { .. })
+            && !(self.our_abilities.can_direct() && self.peer_abilities.can_direct())
+    }
+
+    /**
+     * The traffic that went over the relay so far, or `None` for direct connections
+     *
+     * Meant for attributing relay bandwidth: call it once the connection is done.
+     */
+    pub fn relay_usage(&self) -> Option<RelayUsage> {
+        match &self.conn_type {
+            ConnectionType::Relay { name } => {
+                let stats = self.stats.get();
+                Some(RelayUsage {
+                    relay: name.clone(),
+                    bytes_sent: stats.wire_bytes_sent,
+                    bytes_received: stats.wire_bytes_received,
+                })
+            },
+            ConnectionType::Direct => None,
+        }
+    }
 }
 
 type TransitConnection = (Box<dyn TransitTransport>, TransitInfo);
 
 #[cfg(not(target_family = "wasm"))]
 #[derive(Debug, thiserror::Error)]
-enum StunError {
+pub(crate) enum StunError {
     #[error("No IPv4 addresses were found for the selected STUN server")]
     ServerIsV6Only,
     #[error("Server did not tell us our IP address")]
@@ -622,8 +897,29 @@ enum StunError {
     ),
 }
 
+/**
+ * Ask a STUN server for our external address
+ *
+ * Returns the local address the query went out from and the external address the server saw.
+ * Binding multiple queries to the same local address tells us how our NAT maps ports.
+ */
+#[cfg(not(target_family = "wasm"))]
+pub(crate) async fn stun_query(
+    server: &str,
+    local_addr: SocketAddr,
+    timeout: std::time::Duration,
+) -> Result<(SocketAddr, SocketAddr), StunError> {
+    let (external, socket) = util::timeout(timeout, transport::stun_query(server, local_addr))
+        .await
+        .map_err(|_| StunError::Timeout)??;
+    Ok((socket.local_addr()?, external))
+}
+
 /// Utility method that logs information of the transit result
 ///
+/// Meant as a transit handler for applications that show the `info` level of their log to
+/// users, like the CLI. The library itself never logs anything at that level.
+///
 /// Example usage:
 ///
 /// ```no_run
@@ -642,7 +938,7 @@ enum StunError {
 /// ```
 #[cfg(not(target_family = "wasm"))]
 pub fn log_transit_connection(info: TransitInfo) {
-    match info.conn_type {
+    match &info.conn_type {
         ConnectionType::Direct => {
             log::info!(
                 "Established direct transit connection to '{}'",
@@ -663,6 +959,16 @@ pub fn log_transit_connection(info: TransitInfo) {
             );
         },
     }
+    if info.is_relay_forced() {
+        log::info!(
+            "Direct connections are disabled on {} side",
+            if info.our_abilities.can_direct() {
+                "the other"
+            } else {
+                "our"
+            }
+        );
+    }
 }
 
 /**
@@ -683,6 +989,17 @@ pub async fn init(
         abilities = abilities.intersect(&peer_abilities);
     }
 
+    /* Browsers can't open raw TCP connections, this leaves relaying over WebSockets. Don't make the peer
+     * wait for direct connections that will never come.
+     */
+    #[cfg(target_family = "wasm")]
+    {
+        abilities.direct_tcp_v1 = false;
+    }
+    if !QUIC_SUPPORTED {
+        abilities.direct_quic_v1 = false;
+    }
+
     /* Detect our IP addresses if the ability is enabled */
     #[cfg(not(target_family = "wasm"))]
     if abilities.can_direct() {
@@ -773,6 +1090,21 @@ pub async fn init(
             .ok();
     }
 
+    #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+    let quic = if abilities.can_direct_quic() {
+        /* Boxed, since the STUN query makes this future rather large */
+        Box::pin(quic::bind())
+            .await
+            .map(|(endpoint, hints)| {
+                our_hints.direct_quic.extend(hints);
+                endpoint
+            })
+            .map_err(|err| log::error!("Failed to create QUIC hints for our side: {}", err))
+            .ok()
+    } else {
+        None
+    };
+
     if abilities.can_relay() {
         our_hints.relay.extend(relay_hints);
     }
@@ -780,8 +1112,14 @@ pub async fn init(
     Ok(TransitConnector {
         #[cfg(not(target_family = "wasm"))]
         sockets,
+        #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+        quic,
         our_abilities: abilities,
         our_hints: Arc::new(our_hints),
+        timeouts: ConnectTimeouts::default(),
+        relay_preference: None,
+        #[cfg(not(target_family = "wasm"))]
+        relay_rtt_budget: None,
     })
 }
 
@@ -805,6 +1143,183 @@ impl MaybeConnectedSocket {
     }
 }
 
+/**
+ * How long to try establishing a transit connection, see [`TransitConnector::set_timeouts`]
+ *
+ * Direct connections and connections via a relay get separate budgets. On networks where
+ * only the relay works, lowering the direct timeout avoids waiting for direct attempts
+ * that will never succeed.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectTimeouts {
+    /// Time budget for direct connections, including the handshake
+    pub direct: std::time::Duration,
+    /// Time budget for connections via a relay, including waiting for the peer at the relay
+    pub relay: std::time::Duration,
+    /// Time limit for the handshake of each single connection once it is established, within the budgets
+    /// above. This frees up hung relays early. It includes waiting for the peer at a relay, too, so don't
+    /// set it too low. No limit by default.
+    pub handshake: Option<std::time::Duration>,
+}
+
+impl Default for ConnectTimeouts {
+    fn default() -> Self {
+        Self {
+            direct: std::time::Duration::from_secs(60),
+            relay: std::time::Duration::from_secs(60),
+            handshake: None,
+        }
+    }
+}
+
+impl ConnectTimeouts {
+    fn for_connection(&self, conn_type: &ConnectionType) -> std::time::Duration {
+        match conn_type {
+            ConnectionType::Direct => self.direct,
+            ConnectionType::Relay { .. } => self.relay,
+        }
+    }
+
+    /** What is left for the handshake of a connection that got established after `elapsed` */
+    fn for_handshake(
+        &self,
+        conn_type: &ConnectionType,
+        elapsed: std::time::Duration,
+    ) -> std::time::Duration {
+        let remaining = self.for_connection(conn_type).saturating_sub(elapsed);
+        self.handshake
+            .map_or(remaining, |handshake| handshake.min(remaining))
+    }
+
+    fn total(&self) -> std::time::Duration {
+        self.direct.max(self.relay)
+    }
+}
+
+/**
+ * Time limits for the phases of a session, used by the file transfer and the port forwarding
+ *
+ * Each one ends the session with an error of its own, so that automation can tell apart a human who
+ * never accepted from a network that died.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Timeouts {
+    /// Waiting for the peer to accept or reject an offer, which usually involves a human. No limit by default.
+    pub offer: Option<std::time::Duration>,
+    /// Establishing the transit connection
+    pub transit: ConnectTimeouts,
+    /// Waiting for the next record once the transit connection is up, see [`Transit::set_idle_timeout`]. No limit by default.
+    pub idle_peer: Option<std::time::Duration>,
+}
+
+/**
+ * Ranks relay servers for [`TransitConnector::set_relay_preference`], lower is better
+ *
+ * Relays with the same rank keep their order.
+ */
+pub type RelayPreference = Arc<dyn Fn(&RelayHint) -> u32 + Send + Sync>;
+
+/** How much later each less preferred relay gets tried */
+const RELAY_HEADSTART: std::time::Duration = std::time::Duration::from_secs(2);
+
+/**
+ * Prefer relays in the given region, then the ones that did not tell their region
+ *
+ * Within each group, the least loaded relays come first. Relays that did not tell their load are
+ * assumed to be half busy.
+ */
+pub fn prefer_region(region: impl Into<String>) -> RelayPreference {
+    let region = region.into();
+    Arc::new(move |hint| {
+        let distance = match &hint.region {
+            Some(other) if other.eq_ignore_ascii_case(&region) => 0,
+            None => 1,
+            Some(_) => 2,
+        };
+        distance * 1000 + u32::from(hint.load.unwrap_or(50))
+    })
+}
+
+/**
+ * The relays to connect to, and how long to wait before starting each of them
+ *
+ * Takes up to two relays from each side, deduplicated.
+ */
+fn relay_candidates(
+    our_hints: &[RelayHint],
+    their_hints: &[RelayHint],
+    preference: Option<&RelayPreference>,
+) -> Vec<(std::time::Duration, RelayHint)> {
+    let ranked = |hints: &[RelayHint]| {
+        let mut hints = hints.to_vec();
+        if let Some(preference) = preference {
+            hints.sort_by_cached_key(|hint| preference(hint));
+        }
+        hints
+    };
+    let mut relay_hints = Vec::<RelayHint>::new();
+    relay_hints.extend(ranked(our_hints).into_iter().take(2));
+    for hint in ranked(their_hints).into_iter().take(2) {
+        hint.merge_into(&mut relay_hints);
+    }
+
+    match preference {
+        Some(preference) => {
+            relay_hints.sort_by_cached_key(|hint| preference(hint));
+            relay_hints
+                .into_iter()
+                .enumerate()
+                .map(|(i, hint)| (RELAY_HEADSTART * i as u32, hint))
+                .collect()
+        },
+        None => relay_hints
+            .into_iter()
+            .map(|hint| (std::time::Duration::ZERO, hint))
+            .collect(),
+    }
+}
+
+/** How long to spend on closing the connections that lost */
+const NEVERMIND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/**
+ * Whether the leader should switch to a connection that completed its handshake later
+ *
+ * These are the winner selection rules for when several connections succeed: a direct connection
+ * beats a relayed one, otherwise the one that completed first wins. The leader alone decides, the
+ * follower takes the connection on which it gets the go.
+ */
+fn replaces_connection(current: &ConnectionType, new: &ConnectionType) -> bool {
+    *current != ConnectionType::Direct && *new == ConnectionType::Direct
+}
+
+/** Like [`relay_candidates`], but measure the relays first if there is a budget for it */
+async fn ranked_relay_candidates(
+    our_hints: &[RelayHint],
+    their_hints: &[RelayHint],
+    #[allow(unused_mut)] mut preference: Option<RelayPreference>,
+    #[cfg(not(target_family = "wasm"))] rtt_budget: Option<std::time::Duration>,
+) -> (Vec<(std::time::Duration, RelayHint)>, Vec<RelayRtt>) {
+    #[allow(unused_mut)]
+    let mut relay_rtts = Vec::new();
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(budget) = rtt_budget {
+        let relays = relay_candidates(our_hints, their_hints, preference.as_ref())
+            .into_iter()
+            .map(|(_, hint)| hint)
+            .collect::<Vec<_>>();
+        relay_rtts = measure_relay_rtts(&relays, budget).await;
+        log::debug!("Measured relays: {:?}", relay_rtts);
+        preference = Some(prefer_lowest_rtt(relay_rtts.clone()));
+    }
+    (
+        relay_candidates(our_hints, their_hints, preference.as_ref()),
+        relay_rtts,
+    )
+}
+
 /**
  * A partially set up [`Transit`] connection.
  *
@@ -819,8 +1334,15 @@ pub struct TransitConnector {
      */
     #[cfg(not(target_family = "wasm"))]
     sockets: Option<(MaybeConnectedSocket, TcpListener)>,
+    /* Only `Some` if direct-quic-v1 ability has been enabled. Both connects and accepts. */
+    #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+    quic: Option<quinn::Endpoint>,
     our_abilities: Abilities,
     our_hints: Arc<Hints>,
+    timeouts: ConnectTimeouts,
+    relay_preference: Option<RelayPreference>,
+    #[cfg(not(target_family = "wasm"))]
+    relay_rtt_budget: Option<std::time::Duration>,
 }
 
 impl TransitConnector {
@@ -828,6 +1350,32 @@ impl TransitConnector {
         &self.our_abilities
     }
 
+    /** Use different time budgets for the connection attempts than the [defaults](ConnectTimeouts::default) */
+    pub fn set_timeouts(&mut self, timeouts: ConnectTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /**
+     * Rank the relay servers of both sides, for example with [`prefer_region`]
+     *
+     * The preferred relays get tried first, and each following one a bit later. Without a
+     * preference, all relays are tried at the same time.
+     */
+    pub fn set_relay_preference(&mut self, preference: RelayPreference) {
+        self.relay_preference = Some(preference);
+    }
+
+    /**
+     * Measure the round trip time to all relays first, and prefer the fastest ones
+     *
+     * This takes up to `budget` before the actual connection attempts start, and replaces any
+     * [relay preference](Self::set_relay_preference). The measurements end up in [`TransitInfo::relay_rtts`].
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn set_relay_rtt_budget(&mut self, budget: std::time::Duration) {
+        self.relay_rtt_budget = Some(budget);
+    }
+
     /** Send this one to the other side */
     pub fn our_hints(&self) -> &Arc<Hints> {
         &self.our_hints
@@ -868,10 +1416,30 @@ impl TransitConnector {
         let Self {
             #[cfg(not(target_family = "wasm"))]
             sockets,
+            #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+            quic,
             our_abilities,
             our_hints,
+            timeouts,
+            relay_preference,
+            #[cfg(not(target_family = "wasm"))]
+            relay_rtt_budget,
         } = self;
         let transit_key = Arc::new(transit_key);
+        #[cfg(not(target_family = "wasm"))]
+        let relay_rtt_budget =
+            relay_rtt_budget.filter(|_| our_abilities.can_relay() && their_abilities.can_relay());
+        let (relay_hints, relay_rtts) = ranked_relay_candidates(
+            &our_hints.relay,
+            &their_hints.relay,
+            relay_preference,
+            #[cfg(not(target_family = "wasm"))]
+            relay_rtt_budget,
+        )
+        .await;
+
+        #[cfg(target_family = "wasm")]
+        ensure_ws_relay(&relay_hints)?;
 
         let start = instant::Instant::now();
         let mut connection_stream = Box::pin(
@@ -879,11 +1447,14 @@ impl TransitConnector {
                 true,
                 transit_key,
                 our_abilities,
-                our_hints,
+                relay_hints,
                 their_abilities,
                 their_hints,
+                timeouts,
                 #[cfg(not(target_family = "wasm"))]
                 sockets,
+                #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+                quic,
             )
             .filter_map(|result| async {
                 match result {
@@ -897,13 +1468,15 @@ impl TransitConnector {
         );
 
         let (mut transit, mut finalizer, mut conn_info) =
-            util::timeout(std::time::Duration::from_secs(60), connection_stream.next())
+            util::timeout(timeouts.total(), connection_stream.next())
                 .await
                 .map_err(|_| {
                     log::debug!("`leader_connect` timed out");
-                    TransitConnectError::Handshake
+                    TransitConnectError::Timeout
                 })?
                 .ok_or(TransitConnectError::Handshake)?;
+        /* Connections that completed the handshake, but that we don't use */
+        let mut losers = Vec::new();
 
         if conn_info.conn_type != ConnectionType::Direct && our_abilities.can_direct() {
             log::debug!(
@@ -918,19 +1491,24 @@ impl TransitConnector {
                 std::time::Duration::from_secs(1)
             } else {
                 elapsed.mul_f32(0.3)
-            };
+            }
+            /* Don't wait beyond the budget for direct connections */
+            .min(timeouts.direct.saturating_sub(elapsed));
             let _ = util::timeout(to_wait, async {
                 while let Some((new_transit, new_finalizer, new_conn_info)) =
                     connection_stream.next().await
                 {
                     /* We already got a connection, so we're only interested in direct ones */
-                    if new_conn_info.conn_type == ConnectionType::Direct {
-                        transit = new_transit;
-                        finalizer = new_finalizer;
+                    if replaces_connection(&conn_info.conn_type, &new_conn_info.conn_type) {
+                        losers.push((
+                            std::mem::replace(&mut transit, new_transit),
+                            std::mem::replace(&mut finalizer, new_finalizer),
+                        ));
                         conn_info = new_conn_info;
                         log::debug!("Found direct connection; using that instead.");
                         break;
                     }
+                    losers.push((new_transit, new_finalizer));
                 }
             })
             .await;
@@ -939,8 +1517,8 @@ impl TransitConnector {
             log::debug!("Established direct transit connection");
         }
 
-        /* Cancel all remaining non-finished handshakes. We could send "nevermind" to explicitly tell
-         * the other side (probably, this is mostly for relay server statistics), but eeh, nevermind :)
+        /* Cancel all remaining non-finished handshakes. The finished ones that lost get an explicit
+         * "nevermind", so that the follower does not wait for them.
          */
         std::mem::drop(connection_stream);
 
@@ -951,12 +1529,30 @@ impl TransitConnector {
                 log::debug!("`handshake_finalize` failed: {e}");
                 TransitConnectError::Handshake
             })?;
+        /* Tell the follower to close the connections that lost */
+        let _ = util::timeout(
+            NEVERMIND_TIMEOUT,
+            futures::future::join_all(losers.into_iter().map(
+                |(mut socket, finalizer)| async move {
+                    if let Err(err) = finalizer.handshake_nevermind(&mut socket).await {
+                        log::debug!("Failed to close an unused connection: {}", err);
+                    }
+                },
+            )),
+        )
+        .await;
+        conn_info.our_abilities = our_abilities;
+        conn_info.peer_abilities = their_abilities;
+        conn_info.relay_rtts = relay_rtts;
 
         Ok((
             Transit {
                 socket: transit,
                 tx,
                 rx,
+                stats: conn_info.stats.clone(),
+                idle_timeout: None,
+                validator: None,
             },
             conn_info,
         ))
@@ -974,21 +1570,44 @@ impl TransitConnector {
         let Self {
             #[cfg(not(target_family = "wasm"))]
             sockets,
+            #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+            quic,
             our_abilities,
             our_hints,
+            timeouts,
+            relay_preference,
+            #[cfg(not(target_family = "wasm"))]
+            relay_rtt_budget,
         } = self;
         let transit_key = Arc::new(transit_key);
+        #[cfg(not(target_family = "wasm"))]
+        let relay_rtt_budget =
+            relay_rtt_budget.filter(|_| our_abilities.can_relay() && their_abilities.can_relay());
+        let (relay_hints, relay_rtts) = ranked_relay_candidates(
+            &our_hints.relay,
+            &their_hints.relay,
+            relay_preference,
+            #[cfg(not(target_family = "wasm"))]
+            relay_rtt_budget,
+        )
+        .await;
+
+        #[cfg(target_family = "wasm")]
+        ensure_ws_relay(&relay_hints)?;
 
         let mut connection_stream = Box::pin(
             Self::connect_inner(
                 false,
                 transit_key,
                 our_abilities,
-                our_hints,
+                relay_hints,
                 their_abilities,
                 their_hints,
+                timeouts,
                 #[cfg(not(target_family = "wasm"))]
                 sockets,
+                #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+                quic,
             )
             .filter_map(|result| async {
                 match result {
@@ -1001,13 +1620,8 @@ impl TransitConnector {
             }),
         );
 
-        let transit = match util::timeout(
-            std::time::Duration::from_secs(60),
-            &mut connection_stream.next(),
-        )
-        .await
-        {
-            Ok(Some((mut socket, finalizer, conn_info))) => {
+        let transit = match util::timeout(timeouts.total(), &mut connection_stream.next()).await {
+            Ok(Some((mut socket, finalizer, mut conn_info))) => {
                 let (tx, rx) = finalizer
                     .handshake_finalize(&mut socket)
                     .await
@@ -1015,12 +1629,26 @@ impl TransitConnector {
                         log::debug!("`handshake_finalize` failed: {e}");
                         TransitConnectError::Handshake
                     })?;
-
-                Ok((Transit { socket, tx, rx }, conn_info))
+                conn_info.our_abilities = our_abilities;
+                conn_info.peer_abilities = their_abilities;
+                conn_info.relay_rtts = relay_rtts;
+
+                Ok((
+                    Transit {
+                        socket,
+                        tx,
+                        rx,
+                        stats: conn_info.stats.clone(),
+                        idle_timeout: None,
+                        validator: None,
+                    },
+                    conn_info,
+                ))
             },
-            Ok(None) | Err(_) => {
+            Ok(None) => Err(TransitConnectError::Handshake),
+            Err(_) => {
                 log::debug!("`follower_connect` timed out");
-                Err(TransitConnectError::Handshake)
+                Err(TransitConnectError::Timeout)
             },
         };
 
@@ -1045,26 +1673,18 @@ impl TransitConnector {
         is_leader: bool,
         transit_key: Arc<Key<TransitKey>>,
         our_abilities: Abilities,
-        our_hints: Arc<Hints>,
+        relay_hints: Vec<(std::time::Duration, RelayHint)>,
         their_abilities: Abilities,
         their_hints: Arc<Hints>,
+        timeouts: ConnectTimeouts,
         #[cfg(not(target_family = "wasm"))] sockets: Option<(MaybeConnectedSocket, TcpListener)>,
+        #[cfg(all(feature = "quic", not(target_family = "wasm")))] quic: Option<quinn::Endpoint>,
     ) -> impl Stream<Item = Result<HandshakeResult, TransitHandshakeError>> + 'static {
         /* Have Some(sockets) → Can direct */
         #[cfg(not(target_family = "wasm"))]
         assert!(sockets.is_none() || our_abilities.can_direct());
 
-        let cryptor = if our_abilities.can_noise_crypto() && their_abilities.can_noise_crypto() {
-            log::debug!("Using noise protocol for encryption");
-            Arc::new(crypto::NoiseInit {
-                key: transit_key.clone(),
-            }) as Arc<dyn crypto::TransitCryptoInit>
-        } else {
-            log::debug!("Using secretbox for encryption");
-            Arc::new(crypto::SecretboxInit {
-                key: transit_key.clone(),
-            }) as Arc<dyn crypto::TransitCryptoInit>
-        };
+        let cryptor = negotiate_cryptor(&transit_key, &our_abilities, &their_abilities);
 
         // 8. listen for connections on the port and simultaneously try connecting to the peer port.
         let tside = Arc::new(hex::encode(rand::random::<[u8; 8]>()));
@@ -1100,23 +1720,57 @@ impl TransitConnector {
                         .into_iter()
                         /* Nobody should have that many IP addresses, even with NATing */
                         .take(50)
-                        .map(move |hint| transport::connect_tcp_direct(local_addr.clone(), hint))
+                        .map(move |hint| {
+                            let connect = transport::connect_tcp_direct(local_addr.clone(), hint);
+                            async move {
+                                util::timeout(timeouts.direct, connect)
+                                    .await
+                                    .map_err(|_| TransitHandshakeError::Timeout)?
+                            }
+                        })
                         .map(|fut| Box::pin(fut) as ConnectorFuture),
                 ),
             ) as BoxIterator<ConnectorFuture>;
         }
 
-        /* Relay hints. Make sure that both sides advertise it, since it is fine to support it without providing own hints. */
-        if our_abilities.can_relay() && their_abilities.can_relay() {
-            /* Collect intermediate into HashSet for deduplication */
-            let mut relay_hints = Vec::<RelayHint>::new();
-            relay_hints.extend(our_hints.relay.iter().take(2).cloned());
-            for hint in their_hints.relay.iter().take(2).cloned() {
-                hint.merge_into(&mut relay_hints);
+        /* Direct connections over QUIC. Only the follower accepts them, see the `quic` module. */
+        #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+        let mut quic_accept = None;
+        #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+        if let Some(endpoint) = quic.filter(|_| their_abilities.can_direct_quic()) {
+            if is_leader {
+                endpoint.set_server_config(None);
+                connectors = Box::new(
+                    connectors.chain(
+                        their_hints
+                            .direct_quic
+                            .clone()
+                            .into_iter()
+                            .take(50)
+                            .map(move |hint| {
+                                let connect = quic::connect(endpoint.clone(), hint);
+                                async move {
+                                    util::timeout(timeouts.direct, connect)
+                                        .await
+                                        .map_err(|_| TransitHandshakeError::Timeout)?
+                                }
+                            })
+                            .map(|fut| Box::pin(fut) as ConnectorFuture),
+                    ),
+                ) as BoxIterator<ConnectorFuture>;
+            } else {
+                let their_hints = their_hints.direct_quic.iter().take(50).cloned().collect();
+                quic_accept = Some((endpoint, their_hints));
             }
+        }
 
+        /* Relay hints. Make sure that both sides advertise it, since it is fine to support it without providing own hints. */
+        if our_abilities.can_relay() && their_abilities.can_relay() {
             #[cfg(not(target_family = "wasm"))]
             {
+                /* How we reach the relay is up to us, the peer only has to be able to use relays at all */
+                let use_tcp = our_abilities.relay_v1;
+                let use_ws = our_abilities.can_relay_ws();
                 connectors = Box::new(
                     connectors.chain(
                     relay_hints
@@ -1129,7 +1783,8 @@ impl TransitConnector {
                          * start them in a 5 seconds interval spread. If one of them succeeds, the remaining ones
                          * will be cancelled anyways. Note that a hint might not necessarily be reachable via TCP.
                          */
-                        .flat_map(|hint| {
+                        .flat_map(move |(delay, hint)| {
+                            let relay_key = health::relay_key(&hint);
                             /* If the hint has no name, take the first domain name as fallback */
                             let name = hint.name
                             .or_else(|| {
@@ -1141,20 +1796,42 @@ impl TransitConnector {
                                         })
                                         .next()
                                     });
-                            hint.tcp
+                            let tcp_name = name.clone();
+                            let tcp_relay_key = relay_key.clone();
+                            let tcp = hint.tcp
                                 .into_iter()
+                                .filter(move |_| use_tcp)
                                 .take(3)
                                 .enumerate()
-                                .map(move |(i, h)| (i, h, name.clone()))
-                            })
-                            .map(|(index, host, name)| async move {
-                                util::sleep(std::time::Duration::from_secs(
-                                    index as u64 * 5,
-                                ))
-                                .await;
-                                transport::connect_tcp_relay(host, name).await
-                            })
-                            .map(|fut| Box::pin(fut) as ConnectorFuture),
+                                .map(move |(i, host)| {
+                                    let delay = delay + std::time::Duration::from_secs(i as u64 * 5);
+                                    let name = tcp_name.clone();
+                                    let relay_key = tcp_relay_key.clone();
+                                    Box::pin(async move {
+                                        util::sleep(delay).await;
+                                        let (socket, info) = transport::connect_tcp_relay(host, name).await?;
+                                        Ok((socket, TransitInfo { relay_key, ..info }))
+                                    }) as ConnectorFuture
+                                });
+                            /* WebSocket is for when TCP is blocked, so give the latter a head start */
+                            let ws_delay = delay + if use_tcp { std::time::Duration::from_secs(2) } else { std::time::Duration::ZERO };
+                            let ws = hint.ws
+                                .into_iter()
+                                .filter(move |_| use_ws)
+                                .take(3)
+                                .enumerate()
+                                .map(move |(i, url)| {
+                                    let delay = ws_delay + std::time::Duration::from_secs(i as u64 * 5);
+                                    let name = name.clone();
+                                    let relay_key = relay_key.clone();
+                                    Box::pin(async move {
+                                        util::sleep(delay).await;
+                                        let (socket, info) = transport::connect_ws_relay(url, name).await?;
+                                        Ok((socket, TransitInfo { relay_key, ..info }))
+                                    }) as ConnectorFuture
+                                });
+                            tcp.chain(ws)
+                        }),
                     ),
                 ) as BoxIterator<ConnectorFuture>;
             }
@@ -1173,7 +1850,8 @@ impl TransitConnector {
                             * start them in a 5 seconds interval spread. If one of them succeeds, the remaining ones
                             * will be cancelled anyways. Note that a hint might not necessarily be reachable via TCP.
                             */
-                            .flat_map(|hint| {
+                            .flat_map(|(delay, hint)| {
+                                let relay_key = health::relay_key(&hint);
                                 /* If the hint has no name, take the first domain name as fallback */
                                 let name = hint.name
                                     .or_else(|| {
@@ -1189,14 +1867,15 @@ impl TransitConnector {
                                     .into_iter()
                                     .take(3)
                                     .enumerate()
-                                    .map(move |(i, u)| (i, u, name.clone()))
+                                    .map(move |(i, u)| {
+                                        let delay = delay + std::time::Duration::from_secs(i as u64 * 5);
+                                        (delay, u, name.clone(), relay_key.clone())
+                                    })
                             })
-                            .map(|(index, url, name)| async move {
-                                util::sleep(std::time::Duration::from_secs(
-                                    index as u64 * 5,
-                                ))
-                                .await;
-                                transport::connect_ws_relay(url, name).await
+                            .map(|(delay, url, name, relay_key)| async move {
+                                util::sleep(delay).await;
+                                let (socket, info) = transport::connect_ws_relay(url, name).await?;
+                                Ok((socket, TransitInfo { relay_key, ..info }))
                             })
                             .map(|fut| Box::pin(fut) as ConnectorFuture),
                     ),
@@ -1208,6 +1887,7 @@ impl TransitConnector {
         let transit_key2 = transit_key.clone();
         let tside2 = tside.clone();
         let cryptor2 = cryptor.clone();
+        let start = instant::Instant::now();
         #[allow(unused_mut)] // For WASM targets
         let mut connectors = Box::new(
             connectors
@@ -1217,15 +1897,22 @@ impl TransitConnector {
                     let cryptor = cryptor2.clone();
                     async move {
                         let (socket, conn_info) = fut.await?;
-                        let (transit, finalizer) = handshake_exchange(
-                            is_leader,
-                            tside,
-                            socket,
-                            &conn_info.conn_type,
-                            &*cryptor,
-                            transit_key,
+                        /* Each connection type only gets its own time budget */
+                        let remaining =
+                            timeouts.for_handshake(&conn_info.conn_type, start.elapsed());
+                        let (transit, finalizer) = util::timeout(
+                            remaining,
+                            handshake_exchange(
+                                is_leader,
+                                tside,
+                                socket,
+                                &conn_info.conn_type,
+                                &*cryptor,
+                                transit_key,
+                            ),
                         )
-                        .await?;
+                        .await
+                        .map_err(|_| TransitHandshakeError::Timeout)??;
                         Ok((transit, finalizer, conn_info))
                     }
                 })
@@ -1235,6 +1922,49 @@ impl TransitConnector {
         )
             as BoxIterator<BoxFuture<Result<HandshakeResult, TransitHandshakeError>>>;
 
+        /* Accept QUIC connections until one passes the handshake, within the time budget for direct connections */
+        #[cfg(all(feature = "quic", not(target_family = "wasm")))]
+        if let Some((endpoint, their_hints)) = quic_accept {
+            let transit_key = transit_key.clone();
+            let tside = tside.clone();
+            let cryptor = cryptor.clone();
+            let accept = async move {
+                let accept = async {
+                    loop {
+                        let (socket, info) = quic::accept(&endpoint).await?;
+                        match handshake_exchange(
+                            is_leader,
+                            tside.clone(),
+                            socket,
+                            &ConnectionType::Direct,
+                            &*cryptor,
+                            transit_key.clone(),
+                        )
+                        .await
+                        {
+                            Ok((transit, finalizer)) => break Ok((transit, finalizer, info)),
+                            Err(err) => {
+                                log::debug!("Some handshake failed on incoming QUIC: {:?}", err)
+                            },
+                        }
+                    }
+                };
+                let punch = quic::punch(endpoint.clone(), their_hints);
+                futures::pin_mut!(accept, punch);
+                let accept = futures::future::select(accept, punch).map(|either| match either {
+                    futures::future::Either::Left((result, _)) => result,
+                    futures::future::Either::Right(((), _)) => unreachable!(),
+                });
+                util::timeout(timeouts.direct, accept)
+                    .await
+                    .map_err(|_| TransitHandshakeError::Timeout)?
+            };
+            connectors = Box::new(connectors.chain(std::iter::once(
+                Box::pin(accept) as BoxFuture<Result<HandshakeResult, TransitHandshakeError>>
+            )))
+                as BoxIterator<BoxFuture<Result<HandshakeResult, TransitHandshakeError>>>;
+        }
+
         /* Also listen on some port just in case. */
         #[cfg(not(target_family = "wasm"))]
         if let Some(listener) = listener {
@@ -1284,6 +2014,118 @@ impl TransitConnector {
     }
 }
 
+/**
+ * In the browser, the only way to the peer is a relay that speaks WebSocket
+ *
+ * Fail right away if there is none, instead of running into the timeout.
+ */
+#[cfg(target_family = "wasm")]
+fn ensure_ws_relay(
+    relay_hints: &[(std::time::Duration, RelayHint)],
+) -> Result<(), TransitConnectError> {
+    if relay_hints.iter().any(|(_, hint)| !hint.ws.is_empty()) {
+        Ok(())
+    } else {
+        Err(TransitConnectError::Protocol(
+            "None of the relay hints has a WebSocket URL, which is the only way to connect from a browser"
+                .into(),
+        ))
+    }
+}
+
+/** Pick the encryption both sides support */
+fn negotiate_cryptor(
+    transit_key: &Arc<Key<TransitKey>>,
+    our_abilities: &Abilities,
+    their_abilities: &Abilities,
+) -> Arc<dyn crypto::TransitCryptoInit> {
+    let negotiated = our_abilities.intersect(their_abilities);
+    let record_v2 = negotiated.can_bind_records().then_some(negotiated);
+    if record_v2.is_none() {
+        log::debug!("Peer does not support record-v2, falling back to classic records");
+    }
+    if our_abilities.can_noise_crypto() && their_abilities.can_noise_crypto() {
+        log::debug!("Using noise protocol for encryption");
+        Arc::new(crypto::NoiseInit {
+            key: transit_key.clone(),
+            record_v2,
+        })
+    } else {
+        log::debug!("Using secretbox for encryption");
+        Arc::new(crypto::SecretboxInit {
+            key: transit_key.clone(),
+            record_v2,
+        })
+    }
+}
+
+/**
+ * Establish a transit connection over a stream that got connected by other means
+ *
+ * This is for embedders that bring their own IO stack or executor instead of the async-std sockets
+ * the [`TransitConnector`] uses. `stream` must lead to the peer, or to a relay server in case of
+ * [`ConnectionType::Relay`], and the peer must use it as well. Only the transit handshake happens
+ * here, there is no time limit on it.
+ */
+pub async fn connect_over<S>(
+    stream: S,
+    conn_type: ConnectionType,
+    #[cfg(not(target_family = "wasm"))] peer_addr: SocketAddr,
+    is_leader: bool,
+    transit_key: Key<TransitKey>,
+    our_abilities: Abilities,
+    their_abilities: Abilities,
+) -> Result<(Transit, TransitInfo), TransitConnectError>
+where
+    S: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let transit_key = Arc::new(transit_key);
+    let cryptor = negotiate_cryptor(&transit_key, &our_abilities, &their_abilities);
+    let tside = Arc::new(hex::encode(rand::random::<[u8; 8]>()));
+    let (mut socket, finalizer) = handshake_exchange(
+        is_leader,
+        tside,
+        Box::new(stream),
+        &conn_type,
+        &*cryptor,
+        transit_key,
+    )
+    .await
+    .map_err(|e| {
+        log::debug!("Handshake over a custom stream failed: {:?}", e);
+        TransitConnectError::Handshake
+    })?;
+    let (tx, rx) = finalizer
+        .handshake_finalize(&mut socket)
+        .await
+        .map_err(|e| {
+            log::debug!("`handshake_finalize` failed: {e}");
+            TransitConnectError::Handshake
+        })?;
+
+    let info = TransitInfo {
+        conn_type,
+        #[cfg(not(target_family = "wasm"))]
+        peer_addr,
+        our_abilities,
+        peer_abilities: their_abilities,
+        stats: Default::default(),
+        relay_rtts: Vec::new(),
+        relay_key: None,
+    };
+    Ok((
+        Transit {
+            socket,
+            tx,
+            rx,
+            stats: info.stats.clone(),
+            idle_timeout: None,
+            validator: None,
+        },
+        info,
+    ))
+}
+
 /**
  * An established Transit connection.
  *
@@ -1295,18 +2137,57 @@ pub struct Transit {
     socket: Box<dyn TransitTransport>,
     tx: Box<dyn crypto::TransitCryptoEncrypt>,
     rx: Box<dyn crypto::TransitCryptoDecrypt>,
+    stats: TransitStatsHandle,
+    idle_timeout: Option<std::time::Duration>,
+    validator: Option<crate::schema::Validator>,
 }
 
 impl Transit {
     /** Receive and decrypt one message from the other side. */
     pub async fn receive_record(&mut self) -> Result<Box<[u8]>, TransitError> {
-        self.rx.decrypt(&mut self.socket).await
+        let result = with_idle_timeout(self.idle_timeout, self.rx.decrypt(&mut self.socket)).await;
+        self.stats.count_received(&result, self.rx.overhead());
+        validate(self.validator, result)
+    }
+
+    /**
+     * Fail receiving with [`TransitError::IdleTimeout`] if nothing arrives for this long
+     *
+     * Applies to [`receive_record`](Self::receive_record) and to the stream of [`split`](Self::split).
+     * A record that was only partially received when the time ran out is lost, so the connection is
+     * unusable afterwards.
+     */
+    pub fn set_idle_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /* Check every received record, for strict mode. Applies to `split` as well */
+    #[allow(dead_code)]
+    pub(crate) fn set_validator(&mut self, validator: Option<crate::schema::Validator>) {
+        self.validator = validator;
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn validator(&self) -> Option<crate::schema::Validator> {
+        self.validator
     }
 
     /** Send an encrypted message to the other side */
     pub async fn send_record(&mut self, plaintext: &[u8]) -> Result<(), TransitError> {
         assert!(!plaintext.is_empty());
-        self.tx.encrypt(&mut self.socket, plaintext).await
+        self.tx.encrypt(&mut self.socket, plaintext).await?;
+        self.stats.count_sent(plaintext, self.tx.overhead());
+        Ok(())
+    }
+
+    /** Counters about the records sent and received so far */
+    pub fn stats(&self) -> TransitStats {
+        self.stats.get()
+    }
+
+    /** Like [`stats`](Self::stats), but keeps counting after [`split`](Self::split)ting the connection */
+    pub fn stats_handle(&self) -> TransitStatsHandle {
+        self.stats.clone()
     }
 
     pub async fn flush(&mut self) -> Result<(), TransitError> {
@@ -1323,24 +2204,53 @@ impl Transit {
         impl futures::stream::Stream<Item = Result<Box<[u8]>, TransitError>>,
     ) {
         let (reader, writer) = self.socket.split();
+        let stats = self.stats;
+        let idle_timeout = self.idle_timeout;
+        let validator = self.validator;
         (
             futures::sink::unfold(
-                (writer, self.tx),
-                |(mut writer, mut tx), plaintext: Box<[u8]>| async move {
-                    tx.encrypt(&mut writer, &plaintext)
-                        .await
-                        .map(|()| (writer, tx))
+                (writer, self.tx, stats.clone()),
+                |(mut writer, mut tx, stats), plaintext: Box<[u8]>| async move {
+                    tx.encrypt(&mut writer, &plaintext).await?;
+                    stats.count_sent(&plaintext, tx.overhead());
+                    Ok((writer, tx, stats))
+                },
+            ),
+            futures::stream::try_unfold(
+                (reader, self.rx, stats),
+                move |(mut reader, mut rx, stats)| async move {
+                    let result = with_idle_timeout(idle_timeout, rx.decrypt(&mut reader)).await;
+                    stats.count_received(&result, rx.overhead());
+                    validate(validator, result).map(|record| Some((record, (reader, rx, stats))))
                 },
             ),
-            futures::stream::try_unfold((reader, self.rx), |(mut reader, mut rx)| async move {
-                rx.decrypt(&mut reader)
-                    .await
-                    .map(|record| Some((record, (reader, rx))))
-            }),
         )
     }
 }
 
+async fn with_idle_timeout<T>(
+    timeout: Option<std::time::Duration>,
+    receive: impl futures::Future<Output = Result<T, TransitError>>,
+) -> Result<T, TransitError> {
+    match timeout {
+        Some(timeout) => util::timeout(timeout, receive)
+            .await
+            .unwrap_or(Err(TransitError::IdleTimeout)),
+        None => receive.await,
+    }
+}
+
+fn validate(
+    validator: Option<crate::schema::Validator>,
+    record: Result<Box<[u8]>, TransitError>,
+) -> Result<Box<[u8]>, TransitError> {
+    let record = record?;
+    if let Some(validate) = validator {
+        validate(&record)?;
+    }
+    Ok(record)
+}
+
 type HandshakeResult = (
     Box<dyn TransitTransport>,
     Box<dyn crypto::TransitCryptoInitFinalizer>,
@@ -1397,16 +2307,98 @@ mod test {
     use super::*;
     use serde_json::json;
 
+    /** Both ends of an established transit connection over the loopback interface */
+    #[cfg(not(target_family = "wasm"))]
+    pub(super) async fn transit_pair() -> (Transit, Transit) {
+        use crypto::TransitCryptoInit;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (leader_socket, follower_socket) =
+            futures::join!(TcpStream::connect(address), listener.accept());
+        let mut leader_socket: Box<dyn TransitTransport> = Box::new(leader_socket.unwrap());
+        let mut follower_socket: Box<dyn TransitTransport> = Box::new(follower_socket.unwrap().0);
+
+        let init = crypto::SecretboxInit {
+            key: Arc::new(Key::new(Box::new(*crypto_secretbox::Key::from_slice(
+                &[0x42; 32],
+            )))),
+            record_v2: Some(Abilities::ALL_ABILITIES),
+        };
+        let leader = async {
+            let finalizer = init.handshake_leader(&mut leader_socket).await.unwrap();
+            finalizer
+                .handshake_finalize(&mut leader_socket)
+                .await
+                .unwrap()
+        };
+        let follower = async {
+            let finalizer = init.handshake_follower(&mut follower_socket).await.unwrap();
+            finalizer
+                .handshake_finalize(&mut follower_socket)
+                .await
+                .unwrap()
+        };
+        let ((leader_tx, leader_rx), (follower_tx, follower_rx)) = futures::join!(leader, follower);
+        (
+            Transit {
+                socket: leader_socket,
+                tx: leader_tx,
+                rx: leader_rx,
+                stats: Default::default(),
+                idle_timeout: None,
+                validator: None,
+            },
+            Transit {
+                socket: follower_socket,
+                tx: follower_tx,
+                rx: follower_rx,
+                stats: Default::default(),
+                idle_timeout: None,
+                validator: None,
+            },
+        )
+    }
+
     #[test]
     pub fn test_abilities_encoding() {
+        /* Whether QUIC is included depends on the enabled features */
+        let without_quic = |abilities| Abilities {
+            direct_quic_v1: false,
+            ..abilities
+        };
         assert_eq!(
-            serde_json::to_value(Abilities::ALL_ABILITIES).unwrap(),
-            json!([{"type": "direct-tcp-v1"}, {"type": "relay-v1"}])
+            serde_json::to_value(without_quic(Abilities::ALL_ABILITIES)).unwrap(),
+            json!([
+                {"type": "direct-tcp-v1"},
+                {"type": "relay-v1"},
+                {"type": "relay-v2"},
+                {"type": "record-v2"},
+                {"type": "noise-crypto-v1"}
+            ])
         );
         assert_eq!(
-            serde_json::to_value(Abilities::FORCE_DIRECT).unwrap(),
-            json!([{"type": "direct-tcp-v1"}])
+            serde_json::to_value(without_quic(Abilities::FORCE_DIRECT)).unwrap(),
+            json!([
+                {"type": "direct-tcp-v1"},
+                {"type": "record-v2"},
+                {"type": "noise-crypto-v1"}
+            ])
         );
+        let quic: Abilities = serde_json::from_value(json!([{"type": "direct-quic-v1"}])).unwrap();
+        assert!(quic.can_direct_quic());
+        assert!(!quic.can_direct());
+        /* Peers that don't know about record-v2 get the classic record format */
+        let classic: Abilities =
+            serde_json::from_value(json!([{"type": "direct-tcp-v1"}, {"type": "relay-v1"}]))
+                .unwrap();
+        assert!(!Abilities::ALL_ABILITIES
+            .intersect(&classic)
+            .can_bind_records());
+        /* Relaying still works with them, only not via WebSocket */
+        let intersection = Abilities::ALL_ABILITIES.intersect(&classic);
+        assert!(intersection.can_relay());
+        assert!(!intersection.can_relay_ws());
     }
 
     #[test]
@@ -1446,6 +2438,170 @@ mod test {
                     ]
                 }
             ])
-        )
+        );
+
+        /* QUIC hints look like TCP ones, but go to a UDP port */
+        let quic = json!([{"type": "direct-quic-v1", "hostname": "localhost", "port": 1234}]);
+        #[cfg(any(feature = "transfer", feature = "forwarding"))]
+        assert!(HINTS_SCHEMA
+            .validate_json(&serde_json::to_vec(&quic).unwrap())
+            .is_ok());
+        let hints: Hints = serde_json::from_value(quic.clone()).unwrap();
+        assert!(hints.direct_tcp.is_empty());
+        assert!(hints
+            .direct_quic
+            .contains(&DirectHint::new("localhost", 1234)));
+        assert_eq!(serde_json::to_value(&hints).unwrap(), quic);
+    }
+
+    #[test]
+    pub fn test_relay_preference() {
+        let hint: RelayHint = serde_json::from_value(json!({
+            "name": "eu",
+            "region": "eu-central",
+            "load": 80,
+            "hints": [{"type": "direct-tcp-v1", "hostname": "eu.example.com", "port": 4001}],
+        }))
+        .unwrap();
+        assert_eq!(hint.region.as_deref(), Some("eu-central"));
+        assert_eq!(hint.load, Some(80));
+        #[cfg(any(feature = "transfer", feature = "forwarding"))]
+        let json = serde_json::to_vec(&Hints::new([], [hint])).unwrap();
+        #[cfg(any(feature = "transfer", feature = "forwarding"))]
+        assert!(HINTS_SCHEMA.validate_json(&json).is_ok());
+
+        let relay = |name: &str, region: Option<&str>, load: Option<u8>| RelayHint {
+            region: region.map(str::to_owned),
+            load,
+            ..RelayHint::new(Some(name.into()), [DirectHint::new(name, 4001)], [])
+        };
+        let names = |candidates: Vec<(std::time::Duration, RelayHint)>| {
+            candidates
+                .into_iter()
+                .map(|(delay, hint)| (delay.as_secs(), hint.name.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let ours = [
+            relay("us", Some("us-east"), Some(10)),
+            relay("default", None, None),
+        ];
+        let theirs = [
+            relay("busy-eu", Some("eu-central"), Some(90)),
+            relay("eu", Some("EU-Central"), Some(20)),
+        ];
+
+        assert_eq!(
+            names(relay_candidates(&ours, &theirs, None)),
+            [
+                (0, "us".into()),
+                (0, "default".into()),
+                (0, "busy-eu".into()),
+                (0, "eu".into())
+            ]
+        );
+        assert_eq!(
+            names(relay_candidates(
+                &ours,
+                &theirs,
+                Some(&prefer_region("eu-central"))
+            )),
+            [
+                (0, "eu".into()),
+                (2, "busy-eu".into()),
+                (4, "default".into()),
+                (6, "us".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_handshake_timeout() {
+        let second = std::time::Duration::from_secs(1);
+        let relay = ConnectionType::Relay { name: None };
+        let mut timeouts = ConnectTimeouts::default();
+        assert_eq!(timeouts.for_handshake(&relay, 50 * second), 10 * second);
+        timeouts.handshake = Some(5 * second);
+        assert_eq!(timeouts.for_handshake(&relay, 50 * second), 5 * second);
+        assert_eq!(timeouts.for_handshake(&relay, 58 * second), 2 * second);
+        assert_eq!(
+            timeouts.for_handshake(&ConnectionType::Direct, 70 * second),
+            std::time::Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_replaces_connection() {
+        let relay = ConnectionType::Relay { name: None };
+        assert!(replaces_connection(&relay, &ConnectionType::Direct));
+        assert!(!replaces_connection(&ConnectionType::Direct, &relay));
+        /* Ties go to the connection that completed first */
+        assert!(!replaces_connection(
+            &ConnectionType::Direct,
+            &ConnectionType::Direct
+        ));
+        assert!(!replaces_connection(&relay, &relay));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[async_std::test]
+    async fn test_nevermind() {
+        use crypto::{TransitCryptoInit, TransitHandshakeError};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (leader_socket, follower_socket) =
+            futures::join!(TcpStream::connect(address), listener.accept());
+        let mut leader_socket: Box<dyn TransitTransport> = Box::new(leader_socket.unwrap());
+        let mut follower_socket: Box<dyn TransitTransport> = Box::new(follower_socket.unwrap().0);
+
+        let init = crypto::SecretboxInit {
+            key: Arc::new(Key::new(Box::new(*crypto_secretbox::Key::from_slice(
+                &[0x42; 32],
+            )))),
+            record_v2: None,
+        };
+        let leader = async {
+            let finalizer = init.handshake_leader(&mut leader_socket).await.unwrap();
+            finalizer
+                .handshake_nevermind(&mut leader_socket)
+                .await
+                .unwrap()
+        };
+        let (_, follower) = futures::join!(leader, init.handshake_follower(&mut follower_socket));
+        assert!(matches!(follower, Err(TransitHandshakeError::Nevermind)));
+    }
+    #[cfg(not(target_family = "wasm"))]
+    #[async_std::test]
+    async fn test_connect_over() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (leader_socket, follower_socket) =
+            futures::join!(TcpStream::connect(address), listener.accept());
+        let key = || Key::new(Box::new(*crypto_secretbox::Key::from_slice(&[0x42; 32])));
+
+        let (leader, follower) = futures::join!(
+            connect_over(
+                leader_socket.unwrap(),
+                ConnectionType::Direct,
+                address,
+                true,
+                key(),
+                Abilities::ALL_ABILITIES,
+                Abilities::ALL_ABILITIES,
+            ),
+            connect_over(
+                follower_socket.unwrap().0,
+                ConnectionType::Direct,
+                address,
+                false,
+                key(),
+                Abilities::ALL_ABILITIES,
+                Abilities::ALL_ABILITIES,
+            ),
+        );
+        let ((mut leader, info), (mut follower, _)) = (leader.unwrap(), follower.unwrap());
+        assert_eq!(info.conn_type, ConnectionType::Direct);
+        leader.send_record(b"hello").await.unwrap();
+        assert_eq!(&*follower.receive_record().await.unwrap(), b"hello");
     }
 }
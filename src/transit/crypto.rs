@@ -4,14 +4,18 @@
 //! a simple "encrypt/decrypt" abstraction that will be used for all messages.
 
 use super::{
-    TransitError, TransitKey, TransitRxKey, TransitTransport, TransitTransportRx,
+    Abilities, TransitError, TransitKey, TransitRxKey, TransitTransport, TransitTransportRx,
     TransitTransportTx, TransitTxKey,
 };
 use crate::Key;
 use async_trait::async_trait;
 use crypto_secretbox as secretbox;
 use crypto_secretbox::{aead::Aead, KeyInit};
-use futures::{future::BoxFuture, io::AsyncWriteExt};
+use futures::{
+    future::BoxFuture,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use sha2::{digest::FixedOutput, Digest, Sha256};
 use std::sync::Arc;
 
 /// Private, because we try multiple handshakes and only
@@ -23,6 +27,8 @@ pub(super) enum TransitHandshakeError {
     HandshakeFailed,
     #[error("Relay handshake failed")]
     RelayHandshakeFailed,
+    #[error("The peer chose a different connection")]
+    Nevermind,
     #[error("Malformed peer address")]
     BadAddress(
         #[from]
@@ -37,6 +43,8 @@ pub(super) enum TransitHandshakeError {
     ),
     #[error("Decryption error")]
     Decryption,
+    #[error("Connection attempt timed out")]
+    Timeout,
     #[error("IO error")]
     IO(
         #[from]
@@ -58,6 +66,52 @@ impl From<()> for TransitHandshakeError {
     }
 }
 
+/**
+ * Context that binds the records of one direction to their session (the `record-v2` ability)
+ *
+ * The classic record layer only authenticates each record on its own. With `record-v2`, records
+ * are additionally authenticated against a hash over the transit key (which is derived from the
+ * application ID and the session's shared key), the side that sends them and the handshake
+ * transcript. A record recorded in one session or direction will never decrypt in another one.
+ *
+ * The transcript also covers the abilities both sides agreed on. If someone stripped abilities from
+ * the messages of one side, the peers end up with different contexts and the first record fails with
+ * [`TransitError::AbilityMismatch`]. Stripping `record-v2` itself can't be detected this way, so
+ * applications that care should require it.
+ */
+fn record_context(
+    key: &Key<TransitKey>,
+    leader_sends: bool,
+    abilities: &Abilities,
+    transcript_hash: &[u8],
+) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.update(b"wormhole:transit:record-v2\n");
+    hasher.update(
+        key.derive_subkey_from_purpose::<crate::GenericKey>("transit_record_context")
+            .as_slice(),
+    );
+    hasher.update(serde_json::to_vec(abilities).expect("Abilities always serialize"));
+    hasher.update(if leader_sends {
+        &b"leader"[..]
+    } else {
+        &b"follower"[..]
+    });
+    hasher.update(transcript_hash);
+    hasher.finalize_fixed().into()
+}
+
+/**
+ * Secretbox has no associated data, so the record keys get derived from the context instead.
+ * Without `record-v2`, the key is used as it is.
+ */
+fn bind_record_key<P: crate::KeyPurpose>(key: Key<P>, context: Option<[u8; 32]>) -> Key<P> {
+    match context {
+        Some(context) => key.derive_subkey_from_purpose(&hex::encode(context)),
+        None => key,
+    }
+}
+
 /// The Transit protocol has the property that the last message of the handshake is from the leader
 /// and confirms the usage of that specific connection. This trait represents that specific type state.
 pub(super) trait TransitCryptoInitFinalizer: Send {
@@ -65,6 +119,18 @@ pub(super) trait TransitCryptoInitFinalizer: Send {
         self: Box<Self>,
         socket: &mut dyn TransitTransport,
     ) -> BoxFuture<Result<DynTransitCrypto, TransitHandshakeError>>;
+
+    /// The opposite of [`handshake_finalize`](Self::handshake_finalize): tell the peer that another
+    /// connection won, and close this one.
+    fn handshake_nevermind(
+        self: Box<Self>,
+        socket: &mut dyn TransitTransport,
+    ) -> BoxFuture<'_, Result<(), TransitHandshakeError>> {
+        Box::pin(async move {
+            socket.close().await?;
+            Ok(())
+        })
+    }
 }
 
 /// Due to poorly chosen abstractions elsewhere, the [`TransitCryptoInitFinalizer`] trait is also
@@ -92,6 +158,9 @@ pub(super) trait TransitCryptoInit: Send + Sync {
     ) -> Result<Box<dyn TransitCryptoInitFinalizer>, TransitHandshakeError>;
 }
 
+/// What the leader sends on the connections it does not use
+const NEVERMIND: &[u8] = b"nevermind\n";
+
 /// The classic Transit cryptography backend, powered by libsodium's "Secretbox" API.
 ///
 /// The handshake looks like this (leader perspective):
@@ -100,8 +169,50 @@ pub(super) trait TransitCryptoInit: Send + Sync {
 /// <- transit receiver ${transit_key.derive("transit_receiver")} ready\n\n
 /// -> go\n
 /// ```
+///
+/// On the connections that lost against another one, the leader sends `nevermind\n` instead of `go\n`
+/// and closes them.
+///
+/// With `record_v2`, the record keys are bound to the session, see [`record_context`].
 pub struct SecretboxInit {
     pub key: Arc<Key<TransitKey>>,
+    /** The abilities both sides agreed on, if they both support `record-v2` */
+    pub record_v2: Option<Abilities>,
+}
+
+impl SecretboxInit {
+    fn leader_handshake(&self) -> String {
+        format!(
+            "transit sender {} ready\n\n",
+            self.key
+                .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
+                .to_hex()
+        )
+    }
+
+    fn follower_handshake(&self) -> String {
+        format!(
+            "transit receiver {} ready\n\n",
+            self.key
+                .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
+                .to_hex()
+        )
+    }
+
+    /** The contexts of the records sent by the leader and the follower, if enabled */
+    fn record_contexts(&self) -> (Option<[u8; 32]>, Option<[u8; 32]>) {
+        let Some(abilities) = &self.record_v2 else {
+            return (None, None);
+        };
+        let mut transcript = Sha256::default();
+        transcript.update(self.leader_handshake());
+        transcript.update(self.follower_handshake());
+        let transcript = transcript.finalize_fixed();
+        (
+            Some(record_context(&self.key, true, abilities, &transcript)),
+            Some(record_context(&self.key, false, abilities, &transcript)),
+        )
+    }
 }
 
 #[async_trait]
@@ -111,39 +222,30 @@ impl TransitCryptoInit for SecretboxInit {
         socket: &mut dyn TransitTransport,
     ) -> Result<Box<dyn TransitCryptoInitFinalizer>, TransitHandshakeError> {
         // 9. create record keys
-        let rkey = self
-            .key
-            .derive_subkey_from_purpose("transit_record_receiver_key");
-        let skey = self
-            .key
-            .derive_subkey_from_purpose("transit_record_sender_key");
+        let (leader_context, follower_context) = self.record_contexts();
+        let rkey = bind_record_key(
+            self.key
+                .derive_subkey_from_purpose("transit_record_receiver_key"),
+            follower_context,
+        );
+        let skey = bind_record_key(
+            self.key
+                .derive_subkey_from_purpose("transit_record_sender_key"),
+            leader_context,
+        );
 
         // for transmit mode, send send_handshake_msg and compare.
         // the received message with send_handshake_msg
-        socket
-            .write_all(
-                format!(
-                    "transit sender {} ready\n\n",
-                    self.key
-                        .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
-                        .to_hex()
-                )
-                .as_bytes(),
-            )
-            .await?;
+        socket.write_all(self.leader_handshake().as_bytes()).await?;
 
-        let expected_rx_handshake = format!(
-            "transit receiver {} ready\n\n",
-            self.key
-                .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
-                .to_hex()
-        );
+        let expected_rx_handshake = self.follower_handshake();
         assert_eq!(expected_rx_handshake.len(), 89);
         socket.read_expect(expected_rx_handshake.as_bytes()).await?;
 
         struct Finalizer {
             skey: Key<TransitTxKey>,
             rkey: Key<TransitRxKey>,
+            bound: bool,
         }
 
         impl TransitCryptoInitFinalizer for Finalizer {
@@ -162,13 +264,29 @@ impl TransitCryptoInit for SecretboxInit {
                         Box::new(SecretboxCryptoDecrypt {
                             rkey: self.rkey,
                             rnonce: Default::default(),
+                            bound: self.bound,
                         }) as Box<dyn TransitCryptoDecrypt>,
                     ))
                 })
             }
+
+            fn handshake_nevermind(
+                self: Box<Self>,
+                socket: &mut dyn TransitTransport,
+            ) -> BoxFuture<'_, Result<(), TransitHandshakeError>> {
+                Box::pin(async move {
+                    socket.write_all(NEVERMIND).await?;
+                    socket.close().await?;
+                    Ok(())
+                })
+            }
         }
 
-        Ok(Box::new(Finalizer { skey, rkey }))
+        Ok(Box::new(Finalizer {
+            skey,
+            rkey,
+            bound: self.record_v2.is_some(),
+        }))
     }
 
     async fn handshake_follower(
@@ -180,35 +298,35 @@ impl TransitCryptoInit for SecretboxInit {
          * "leader" and "follower" instead. As a follower, we use the leader key for receiving and our
          * key for sending.
          */
-        let rkey = self
-            .key
-            .derive_subkey_from_purpose("transit_record_sender_key");
-        let skey = self
-            .key
-            .derive_subkey_from_purpose("transit_record_receiver_key");
+        let (leader_context, follower_context) = self.record_contexts();
+        let rkey = bind_record_key(
+            self.key
+                .derive_subkey_from_purpose("transit_record_sender_key"),
+            leader_context,
+        );
+        let skey = bind_record_key(
+            self.key
+                .derive_subkey_from_purpose("transit_record_receiver_key"),
+            follower_context,
+        );
 
         // for receive mode, send receive_handshake_msg and compare.
         // the received message with send_handshake_msg
         socket
-            .write_all(
-                format!(
-                    "transit receiver {} ready\n\n",
-                    self.key
-                        .derive_subkey_from_purpose::<crate::GenericKey>("transit_receiver")
-                        .to_hex(),
-                )
-                .as_bytes(),
-            )
+            .write_all(self.follower_handshake().as_bytes())
             .await?;
 
-        let expected_tx_handshake = format!(
-            "transit sender {} ready\n\ngo\n",
-            self.key
-                .derive_subkey_from_purpose::<crate::GenericKey>("transit_sender")
-                .to_hex(),
-        );
-        assert_eq!(expected_tx_handshake.len(), 90);
+        let expected_tx_handshake = self.leader_handshake();
+        assert_eq!(expected_tx_handshake.len(), 87);
         socket.read_expect(expected_tx_handshake.as_bytes()).await?;
+        /* Either `go\n` or `nevermind\n` */
+        let mut decision = [0; 3];
+        socket.read_exact(&mut decision).await?;
+        if decision == NEVERMIND[..3] {
+            socket.read_expect(&NEVERMIND[3..]).await?;
+            bail!(TransitHandshakeError::Nevermind);
+        }
+        ensure!(&decision == b"go\n", TransitHandshakeError::HandshakeFailed);
 
         Ok(Box::new((
             Box::new(SecretboxCryptoEncrypt {
@@ -218,6 +336,7 @@ impl TransitCryptoInit for SecretboxInit {
             Box::new(SecretboxCryptoDecrypt {
                 rkey,
                 rnonce: Default::default(),
+                bound: self.record_v2.is_some(),
             }) as Box<dyn TransitCryptoDecrypt>,
         )) as Box<dyn TransitCryptoInitFinalizer>)
     }
@@ -239,8 +358,30 @@ type NoiseCipherState = noise_protocol::CipherState<noise_rust_crypto::ChaCha20P
 /// → "" // Not in this method, to confirm the connection
 ///
 /// The noise protocol pattern used is "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s"
+///
+/// Both sides [rekey](noise_protocol::CipherState::rekey) their ciphers every
+/// [`NOISE_REKEY_INTERVAL`] records in each direction, counting the empty confirmation messages.
+///
+/// With `record_v2`, all records after the handshake carry the [`record_context`] as associated
+/// data, using the handshake hash as transcript.
 pub struct NoiseInit {
     pub key: Arc<Key<TransitKey>>,
+    /** The abilities both sides agreed on, if they both support `record-v2` */
+    pub record_v2: Option<Abilities>,
+}
+
+impl NoiseInit {
+    /** The associated data of the records sent by the leader and the follower */
+    fn record_ads(&self, handshake: &NoiseHandshakeState) -> (Box<[u8]>, Box<[u8]>) {
+        let Some(abilities) = &self.record_v2 else {
+            return Default::default();
+        };
+        let transcript = handshake.get_hash();
+        (
+            Box::new(record_context(&self.key, true, abilities, transcript)),
+            Box::new(record_context(&self.key, false, abilities, transcript)),
+        )
+    }
 }
 
 #[async_trait]
@@ -274,6 +415,7 @@ impl TransitCryptoInit for NoiseInit {
         handshake.read_message(&socket.read_transit_message().await?, &mut [])?;
 
         assert!(handshake.completed());
+        let (tx_ad, rx_ad) = self.record_ads(&handshake);
         let (tx, mut rx) = handshake.get_ciphers();
 
         // ← ""
@@ -285,7 +427,9 @@ impl TransitCryptoInit for NoiseInit {
 
         struct Finalizer {
             tx: NoiseCipherState,
+            tx_ad: Box<[u8]>,
             rx: NoiseCipherState,
+            rx_ad: Box<[u8]>,
         }
 
         impl TransitCryptoInitFinalizer for Finalizer {
@@ -300,16 +444,26 @@ impl TransitCryptoInit for NoiseInit {
                         .await?;
 
                     Ok::<_, TransitHandshakeError>((
-                        Box::new(NoiseCryptoEncrypt { tx: self.tx })
-                            as Box<dyn TransitCryptoEncrypt>,
-                        Box::new(NoiseCryptoDecrypt { rx: self.rx })
-                            as Box<dyn TransitCryptoDecrypt>,
+                        Box::new(NoiseCryptoEncrypt {
+                            tx: self.tx,
+                            ad: self.tx_ad,
+                        }) as Box<dyn TransitCryptoEncrypt>,
+                        Box::new(NoiseCryptoDecrypt {
+                            rx: self.rx,
+                            ad: self.rx_ad,
+                            first: true,
+                        }) as Box<dyn TransitCryptoDecrypt>,
                     ))
                 })
             }
         }
 
-        Ok(Box::new(Finalizer { tx, rx }))
+        Ok(Box::new(Finalizer {
+            tx,
+            tx_ad,
+            rx,
+            rx_ad,
+        }))
     }
 
     async fn handshake_follower(
@@ -341,6 +495,7 @@ impl TransitCryptoInit for NoiseInit {
             .await?;
 
         assert!(handshake.completed());
+        let (rx_ad, tx_ad) = self.record_ads(&handshake);
         // Warning: rx and tx are swapped here (read the `get_ciphers` doc carefully)
         let (mut rx, mut tx) = handshake.get_ciphers();
 
@@ -355,8 +510,12 @@ impl TransitCryptoInit for NoiseInit {
         );
 
         Ok(Box::new((
-            Box::new(NoiseCryptoEncrypt { tx }) as Box<dyn TransitCryptoEncrypt>,
-            Box::new(NoiseCryptoDecrypt { rx }) as Box<dyn TransitCryptoDecrypt>,
+            Box::new(NoiseCryptoEncrypt { tx, ad: tx_ad }) as Box<dyn TransitCryptoEncrypt>,
+            Box::new(NoiseCryptoDecrypt {
+                rx,
+                ad: rx_ad,
+                first: true,
+            }) as Box<dyn TransitCryptoDecrypt>,
         )) as Box<dyn TransitCryptoInitFinalizer>)
     }
 }
@@ -370,6 +529,9 @@ pub(super) trait TransitCryptoEncrypt: Send {
         socket: &mut dyn TransitTransportTx,
         plaintext: &[u8],
     ) -> Result<(), TransitError>;
+
+    /** Number of bytes each record takes on the wire in addition to its plaintext */
+    fn overhead(&self) -> usize;
 }
 
 #[async_trait]
@@ -378,6 +540,9 @@ pub(super) trait TransitCryptoDecrypt: Send {
         &mut self,
         socket: &mut dyn TransitTransportRx,
     ) -> Result<Box<[u8]>, TransitError>;
+
+    /** Number of bytes each record takes on the wire in addition to its plaintext */
+    fn overhead(&self) -> usize;
 }
 
 struct SecretboxCryptoEncrypt {
@@ -396,6 +561,8 @@ struct SecretboxCryptoDecrypt {
      * We'll count as receiver and track if messages come in in order
      */
     pub rnonce: secretbox::Nonce,
+    /** Whether the keys are bound with `record-v2`, see [`record_context`] */
+    pub bound: bool,
 }
 
 #[async_trait]
@@ -429,6 +596,10 @@ impl TransitCryptoEncrypt for SecretboxCryptoEncrypt {
 
         Ok(())
     }
+
+    fn overhead(&self) -> usize {
+        SECRETBOX_OVERHEAD
+    }
 }
 
 #[async_trait]
@@ -454,11 +625,13 @@ impl TransitCryptoDecrypt for SecretboxCryptoDecrypt {
         let plaintext = {
             let (received_nonce, ciphertext) = enc_packet
                 .split_at(secretbox::SecretBox::<secretbox::XSalsa20Poly1305>::NONCE_SIZE);
+            /* With bound keys, a first record that fails to decrypt means the contexts differ */
+            let first_bound_record = self.bound && nonce.iter().all(|&b| b == 0);
             {
                 // Nonce check
                 ensure!(
                     nonce.as_slice() == received_nonce,
-                    TransitError::Nonce(received_nonce.into(), nonce.as_slice().into()),
+                    nonce_mismatch(received_nonce, nonce)
                 );
 
                 crate::util::sodium_increment_be(nonce);
@@ -468,19 +641,67 @@ impl TransitCryptoDecrypt for SecretboxCryptoDecrypt {
             cipher
                 .decrypt(secretbox::Nonce::from_slice(received_nonce), ciphertext)
                 /* TODO replace with (TransitError::Crypto) after the next xsalsa20poly1305 update */
-                .map_err(|_| TransitError::Crypto)?
+                .map_err(|_| {
+                    if first_bound_record {
+                        TransitError::AbilityMismatch
+                    } else {
+                        TransitError::Crypto
+                    }
+                })?
         };
 
         Ok(plaintext.into_boxed_slice())
     }
+
+    fn overhead(&self) -> usize {
+        SECRETBOX_OVERHEAD
+    }
+}
+
+/** Length prefix, nonce and authentication tag */
+const SECRETBOX_OVERHEAD: usize =
+    4 + secretbox::SecretBox::<secretbox::XSalsa20Poly1305>::NONCE_SIZE + 16;
+/** Length prefix and authentication tag */
+const NOISE_OVERHEAD: usize = 4 + 16;
+/// After how many records of one direction its noise cipher gets rekeyed
+const NOISE_REKEY_INTERVAL: u64 = 1 << 16;
+
+/**
+ * Tell lost records apart from repeated ones
+ *
+ * The nonces are big-endian record counters, so they can be compared as numbers. Only nonces
+ * that don't fit into an `u64` are reported as they are.
+ */
+fn nonce_mismatch(received: &[u8], expected: &[u8]) -> TransitError {
+    fn sequence_number(nonce: &[u8]) -> Option<u64> {
+        let (high, low) = nonce.split_at(nonce.len().checked_sub(8)?);
+        if high.iter().any(|&b| b != 0) {
+            return None;
+        }
+        Some(u64::from_be_bytes(low.try_into().ok()?))
+    }
+
+    match (sequence_number(expected), sequence_number(received)) {
+        (Some(expected), Some(received)) if received > expected => {
+            TransitError::RecordGap { expected, received }
+        },
+        (Some(expected), Some(received)) => TransitError::RecordReplay { expected, received },
+        _ => TransitError::Nonce(received.into(), expected.into()),
+    }
 }
 
 struct NoiseCryptoEncrypt {
     tx: NoiseCipherState,
+    /** Associated data of each record, empty without `record-v2` */
+    ad: Box<[u8]>,
 }
 
 struct NoiseCryptoDecrypt {
     rx: NoiseCipherState,
+    /** Associated data of each record, empty without `record-v2` */
+    ad: Box<[u8]>,
+    /** No record got decrypted yet */
+    first: bool,
 }
 
 #[async_trait]
@@ -490,11 +711,18 @@ impl TransitCryptoEncrypt for NoiseCryptoEncrypt {
         socket: &mut dyn TransitTransportTx,
         plaintext: &[u8],
     ) -> Result<(), TransitError> {
-        socket
-            .write_transit_message(&self.tx.encrypt_vec(plaintext))
-            .await?;
+        let mut ciphertext = vec![0; plaintext.len() + 16];
+        self.tx.encrypt_ad(&self.ad, plaintext, &mut ciphertext);
+        if self.tx.get_next_n() % NOISE_REKEY_INTERVAL == 0 {
+            self.tx.rekey();
+        }
+        socket.write_transit_message(&ciphertext).await?;
         Ok(())
     }
+
+    fn overhead(&self) -> usize {
+        NOISE_OVERHEAD
+    }
 }
 
 #[async_trait]
@@ -503,7 +731,110 @@ impl TransitCryptoDecrypt for NoiseCryptoDecrypt {
         &mut self,
         socket: &mut dyn TransitTransportRx,
     ) -> Result<Box<[u8]>, TransitError> {
-        let plaintext = self.rx.decrypt_vec(&socket.read_transit_message().await?)?;
+        let ciphertext = socket.read_transit_message().await?;
+        ensure!(ciphertext.len() >= 16, TransitError::Crypto);
+        let mut plaintext = vec![0; ciphertext.len() - 16];
+        let first = std::mem::replace(&mut self.first, false);
+        self.rx
+            .decrypt_ad(&self.ad, &ciphertext, &mut plaintext)
+            .map_err(|()| {
+                /* With bound records, a first record that fails to decrypt means the contexts differ */
+                if first && !self.ad.is_empty() {
+                    TransitError::AbilityMismatch
+                } else {
+                    TransitError::Crypto
+                }
+            })?;
+        if self.rx.get_next_n() % NOISE_REKEY_INTERVAL == 0 {
+            self.rx.rekey();
+        }
         Ok(plaintext.into_boxed_slice())
     }
+
+    fn overhead(&self) -> usize {
+        NOISE_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nonce_mismatch() {
+        let nonce = |n: u64| {
+            let mut nonce = [0; 24];
+            nonce[16..].copy_from_slice(&n.to_be_bytes());
+            nonce
+        };
+        assert!(matches!(
+            nonce_mismatch(&nonce(5), &nonce(3)),
+            TransitError::RecordGap {
+                expected: 3,
+                received: 5
+            }
+        ));
+        assert!(matches!(
+            nonce_mismatch(&nonce(2), &nonce(3)),
+            TransitError::RecordReplay {
+                expected: 3,
+                received: 2
+            }
+        ));
+        assert!(matches!(
+            nonce_mismatch(&[0xff; 24], &nonce(3)),
+            TransitError::Nonce(..)
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_noise_rekey() {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (mut leader_socket, (mut follower_socket, _)) =
+            futures::try_join!(async_std::net::TcpStream::connect(addr), listener.accept())
+                .unwrap();
+
+        let init = NoiseInit {
+            key: Arc::new(Key::new(Box::new(*secretbox::Key::from_slice(&[0x42; 32])))),
+            record_v2: Some(Abilities::ALL_ABILITIES),
+        };
+        /* The follower only finishes its handshake once the leader confirmed the connection */
+        let ((mut encrypt, _), (_, mut decrypt)) = futures::try_join!(
+            async {
+                let leader = init.handshake_leader(&mut leader_socket).await?;
+                leader.handshake_finalize(&mut leader_socket).await
+            },
+            async {
+                let follower = init.handshake_follower(&mut follower_socket).await?;
+                follower.handshake_finalize(&mut follower_socket).await
+            }
+        )
+        .unwrap();
+
+        /* Cross the rekeying point, both sides must agree on when it happens */
+        let records = NOISE_REKEY_INTERVAL + 2;
+        futures::try_join!(
+            async {
+                for i in 0..records {
+                    encrypt
+                        .encrypt(&mut leader_socket, &i.to_be_bytes())
+                        .await?;
+                }
+                Ok::<_, TransitError>(())
+            },
+            async {
+                for i in 0..records {
+                    assert_eq!(
+                        *decrypt.decrypt(&mut follower_socket).await?,
+                        i.to_be_bytes()
+                    );
+                }
+                Ok(())
+            }
+        )
+        .unwrap();
+    }
 }
@@ -0,0 +1,274 @@
+//! Remember which relays (and which connection types) worked in previous runs
+//!
+//! Users who regularly transfer things tend to always end up on the same relay or always get a direct
+//! connection. Persisting that knowledge lets us try the known-good relays first next time.
+
+use super::{ConnectionType, RelayHint, TransitInfo};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Smoothing factor for the moving latency average. Higher values favor recent measurements.
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Success statistics for a single relay server or connection type
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthEntry {
+    /// Number of times a transit connection was successfully established
+    pub successes: u64,
+    /// Number of times no connection could be established
+    pub failures: u64,
+    /// Smoothed time it took to establish the connection, in milliseconds
+    pub latency_ms: Option<u64>,
+    /// Total bytes that went over the wire in both directions, see [`RelayHealthCache::record_usage`]
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+impl HealthEntry {
+    fn record_success(&mut self, latency: std::time::Duration) {
+        self.successes += 1;
+        let latency = latency.as_millis() as f64;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(old) => (old as f64 * (1.0 - LATENCY_SMOOTHING) + latency * LATENCY_SMOOTHING)
+                .round() as u64,
+            None => latency as u64,
+        });
+    }
+
+    fn record_failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /** The last attempts went rather bad than good */
+    pub fn is_unhealthy(&self) -> bool {
+        self.failures > self.successes
+    }
+}
+
+/**
+ * On-disk cache of relay and connection health
+ *
+ * Feed it with the results of your transit connections using [`record_success`](Self::record_success) and
+ * [`record_failure`](Self::record_failure), then use [`prioritize_relays`](Self::prioritize_relays) to sort
+ * the relay hints before passing them to [`init`](super::init). Relays are identified by their name, or
+ * by their domain name or address if they have none.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RelayHealthCache {
+    /// Statistics for direct connections
+    #[serde(default)]
+    pub direct: HealthEntry,
+    /// Statistics for each relay server, by name
+    #[serde(default)]
+    pub relays: HashMap<String, HealthEntry>,
+}
+
+impl RelayHealthCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Load the cache from a file
+     *
+     * A missing file yields an empty cache. A corrupt file is treated as an error, it is up to the caller
+     * to discard it.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        match async_std::fs::read(path.as_ref()).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(Into::into),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /** Write the cache to a file, replacing its previous contents */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        async_std::fs::write(path.as_ref(), serde_json::to_vec_pretty(self)?).await
+    }
+
+    /** Statistics for a relay, if we know it */
+    pub fn relay(&self, hint: &RelayHint) -> Option<&HealthEntry> {
+        relay_key(hint).and_then(|key| self.relays.get(&key))
+    }
+
+    /**
+     * Record an established transit connection
+     *
+     * `connect_time` is the time it took from starting the connection attempts until the
+     * connection was established.
+     */
+    pub fn record_success(&mut self, info: &TransitInfo, connect_time: std::time::Duration) {
+        if let Some(entry) = self.entry(info) {
+            entry.record_success(connect_time);
+        }
+    }
+
+    /**
+     * Add the traffic of a finished transit connection to the totals
+     *
+     * Together with [`record_success`](Self::record_success), this keeps track of how much
+     * bandwidth each relay server was used for.
+     */
+    pub fn record_usage(&mut self, info: &TransitInfo) {
+        let stats = info.stats.get();
+        let bytes = stats.wire_bytes_sent + stats.wire_bytes_received;
+        if let Some(entry) = self.entry(info) {
+            entry.bytes += bytes;
+        }
+    }
+
+    /* The relay is keyed by the hint it was reached through, like in `record_failure` */
+    fn entry(&mut self, info: &TransitInfo) -> Option<&mut HealthEntry> {
+        match (&info.conn_type, &info.relay_key) {
+            (ConnectionType::Direct, _) => Some(&mut self.direct),
+            (ConnectionType::Relay { .. }, Some(key)) => {
+                Some(self.relays.entry(key.clone()).or_default())
+            },
+            (ConnectionType::Relay { .. }, None) => None,
+        }
+    }
+
+    /**
+     * Record that no connection could be established at all using the given relays
+     */
+    pub fn record_failure(&mut self, relay_hints: &[RelayHint]) {
+        for key in relay_hints.iter().filter_map(relay_key) {
+            self.relays.entry(key).or_default().record_failure();
+        }
+    }
+
+    /**
+     * Sort relay hints so that the ones most likely to work come first
+     *
+     * Relays that worked before come first (fastest first), followed by unknown ones and
+     * finally the ones that failed more often than not. The order within each group is preserved.
+     */
+    pub fn prioritize_relays(&self, relay_hints: &mut [RelayHint]) {
+        relay_hints.sort_by_key(|hint| match self.relay(hint) {
+            Some(entry) if entry.is_unhealthy() => (2, u64::MAX),
+            Some(entry) => (0, entry.latency_ms.unwrap_or(u64::MAX)),
+            None => (1, u64::MAX),
+        });
+    }
+}
+
+/**
+ * The name of the relay, or else its domain name, or else its address
+ *
+ * Ends up in the [`TransitInfo`] of connections through that relay. The endpoints are sets, so
+ * take the smallest one to get the same key every time.
+ */
+pub(super) fn relay_key(hint: &RelayHint) -> Option<String> {
+    let domain = || {
+        hint.tcp
+            .iter()
+            .filter(|hint| matches!(url::Host::parse(&hint.hostname), Ok(url::Host::Domain(_))))
+            .map(|hint| &hint.hostname)
+            .min()
+    };
+    let address = || {
+        let tcp = hint.tcp.iter().map(|hint| hint.hostname.as_str());
+        let ws = hint.ws.iter().filter_map(url::Url::host_str);
+        tcp.chain(ws).min()
+    };
+    hint.name
+        .clone()
+        .or_else(|| domain().cloned())
+        .or_else(|| address().map(str::to_owned))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transit::{Abilities, DirectHint};
+
+    fn relay(name: &str) -> RelayHint {
+        RelayHint::new(Some(name.into()), [DirectHint::new(name, 4001)], [])
+    }
+
+    fn relay_info(name: &str) -> TransitInfo {
+        relay_info_for(&relay(name))
+    }
+
+    fn relay_info_for(hint: &RelayHint) -> TransitInfo {
+        TransitInfo {
+            conn_type: ConnectionType::Relay {
+                name: hint.name.clone(),
+            },
+            #[cfg(not(target_family = "wasm"))]
+            peer_addr: "[::1]:4001".parse().unwrap(),
+            our_abilities: Abilities::ALL_ABILITIES,
+            peer_abilities: Abilities::ALL_ABILITIES,
+            stats: Default::default(),
+            relay_rtts: Vec::new(),
+            relay_key: relay_key(hint),
+        }
+    }
+
+    #[test]
+    fn test_prioritize_relays() {
+        let mut cache = RelayHealthCache::new();
+        cache.record_success(&relay_info("slow"), std::time::Duration::from_millis(500));
+        cache.record_success(&relay_info("fast"), std::time::Duration::from_millis(50));
+        cache.record_failure(&[relay("broken")]);
+
+        let mut hints = vec![
+            relay("broken"),
+            relay("unknown"),
+            relay("slow"),
+            relay("fast"),
+        ];
+        cache.prioritize_relays(&mut hints);
+        let names: Vec<_> = hints.iter().map(|h| h.name.clone().unwrap()).collect();
+        assert_eq!(names, ["fast", "slow", "unknown", "broken"]);
+    }
+
+    #[test]
+    fn test_unnamed_relay() {
+        let mut cache = RelayHealthCache::new();
+        let hint = RelayHint::new(
+            None,
+            [
+                DirectHint::new("relay.example.org", 4001),
+                DirectHint::new("192.0.2.1", 4001),
+            ],
+            [],
+        );
+        cache.record_failure(std::slice::from_ref(&hint));
+        cache.record_success(&relay_info_for(&hint), std::time::Duration::from_millis(50));
+        assert_eq!(cache.relays.len(), 1);
+        let entry = cache.relay(&hint).unwrap();
+        assert_eq!((entry.successes, entry.failures), (1, 1));
+
+        /* Relays without domain name go by their address */
+        let hint = RelayHint::new(None, [], ["ws://192.0.2.2:4002".parse().unwrap()]);
+        cache.record_failure(std::slice::from_ref(&hint));
+        cache.record_success(&relay_info_for(&hint), std::time::Duration::from_millis(50));
+        assert_eq!(cache.relays["192.0.2.2"].successes, 1);
+        assert_eq!(cache.relays["192.0.2.2"].failures, 1);
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let mut cache = RelayHealthCache::new();
+        cache.record_success(&relay_info("relay"), std::time::Duration::from_millis(100));
+        cache.record_success(&relay_info("relay"), std::time::Duration::from_millis(200));
+        assert_eq!(cache.relays["relay"].latency_ms, Some(130));
+        let info = relay_info("relay");
+        info.stats.count_sent(b"hello", 44);
+        cache.record_usage(&info);
+        assert_eq!(cache.relays["relay"].bytes, 49);
+        assert_eq!(info.relay_usage().unwrap().bytes_sent, 49);
+
+        let serialized = serde_json::to_string(&cache).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RelayHealthCache>(&serialized).unwrap(),
+            cache
+        );
+    }
+}
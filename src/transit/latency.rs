@@ -0,0 +1,134 @@
+//! Pick the relay with the lowest latency before committing to one
+//!
+//! All candidate relays get a TCP connection opened in parallel, and the time until it is
+//! established is taken as round trip time. This happens before the actual connection attempts and
+//! is bounded by a short time budget, see [`TransitConnector::set_relay_rtt_budget`](super::TransitConnector::set_relay_rtt_budget).
+
+use super::{health::relay_key, RelayHint, RelayPreference};
+use serde_derive::Serialize;
+use std::{sync::Arc, time::Duration};
+
+/// Measure at most this many endpoints of each relay
+const ENDPOINTS_PER_RELAY: usize = 3;
+
+/// The measured round trip time to a relay server
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct RelayRtt {
+    /// The relay that got measured, serialized as its name
+    #[serde(serialize_with = "relay_name")]
+    pub relay: RelayHint,
+    /// Fastest connection setup to any of its endpoints, or `None` if none answered within the budget
+    #[serde(serialize_with = "as_millis_option")]
+    pub rtt: Option<Duration>,
+}
+
+/**
+ * Open a connection to each relay in parallel, and time it
+ *
+ * Gives up on the relays that did not answer within `budget`. The results are in the order of `relays`.
+ */
+#[cfg(not(target_family = "wasm"))]
+pub async fn measure_relay_rtts(relays: &[RelayHint], budget: Duration) -> Vec<RelayRtt> {
+    let measurements = relays.iter().map(|relay| async move {
+        let endpoints = relay
+            .tcp
+            .iter()
+            .take(ENDPOINTS_PER_RELAY)
+            .map(|endpoint| async move {
+                let start = instant::Instant::now();
+                let connect =
+                    async_std::net::TcpStream::connect((endpoint.hostname.as_str(), endpoint.port));
+                match crate::util::timeout(budget, connect).await {
+                    Ok(Ok(_stream)) => Some(start.elapsed()),
+                    Ok(Err(err)) => {
+                        log::debug!("Could not reach relay {} for measuring: {}", endpoint, err);
+                        None
+                    },
+                    Err(_) => None,
+                }
+            });
+        let rtt = futures::future::join_all(endpoints)
+            .await
+            .into_iter()
+            .flatten()
+            .min();
+        RelayRtt {
+            relay: relay.clone(),
+            rtt,
+        }
+    });
+    futures::future::join_all(measurements).await
+}
+
+/**
+ * Prefer the relays with the lowest measured round trip time
+ *
+ * Relays that have not been measured or did not answer come last.
+ */
+pub fn prefer_lowest_rtt(measurements: Vec<RelayRtt>) -> RelayPreference {
+    Arc::new(move |hint| {
+        measurements
+            .iter()
+            .find(|measurement| measurement.relay.can_merge(hint))
+            .and_then(|measurement| measurement.rtt)
+            .map_or(u32::MAX, |rtt| {
+                rtt.as_millis().try_into().unwrap_or(u32::MAX - 1)
+            })
+    })
+}
+
+fn relay_name<S: serde::Serializer>(relay: &RelayHint, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_some(&relay_key(relay))
+}
+
+fn as_millis_option<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => ser.serialize_some(&(duration.as_millis() as u64)),
+        None => ser.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transit::DirectHint;
+
+    #[async_std::test]
+    async fn test_measure_relay_rtts() {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reachable = RelayHint::new(
+            Some("reachable".into()),
+            [DirectHint::new("127.0.0.1", port)],
+            [],
+        );
+        /* Nothing listens on the discard port */
+        let unreachable = RelayHint::new(
+            Some("unreachable".into()),
+            [DirectHint::new("127.0.0.1", 9)],
+            [],
+        );
+
+        let measurements = measure_relay_rtts(
+            &[unreachable.clone(), reachable.clone()],
+            Duration::from_secs(2),
+        )
+        .await;
+        assert_eq!(measurements[0].rtt, None);
+        assert!(measurements[1].rtt.unwrap() < Duration::from_secs(2));
+
+        let json = serde_json::to_value(&measurements).unwrap();
+        assert_eq!(json[0]["relay"], "unreachable");
+        assert!(json[1]["rtt"].is_u64());
+
+        let preference = prefer_lowest_rtt(measurements);
+        assert!(preference(&reachable) < preference(&unreachable));
+    }
+}
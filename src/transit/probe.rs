@@ -0,0 +1,123 @@
+//! Measure round trip time and bandwidth of an established transit connection
+//!
+//! Both sides must agree on when to probe, for example right after connecting. One side calls
+//! [`Transit::probe`], the other one [`Transit::answer_probe`]. The probe first sends a few pings to
+//! measure the round trip time, then sends filler records for the requested duration. The bandwidth
+//! is estimated from how long it takes until a final ping behind that filler data gets answered.
+
+use super::{Transit, TransitError};
+use std::time::Duration;
+
+/// Send this many pings for measuring the round trip time, and take the fastest one
+const PINGS: usize = 5;
+/// Size of the filler records for measuring the bandwidth
+const FILLER_SIZE: usize = 64 * 1024;
+
+const PING: u8 = b'p';
+const PONG: u8 = b'a';
+const FILLER: u8 = b'd';
+const END: u8 = b'e';
+
+/// The result of a [`Transit::probe`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProbeResult {
+    /// Fastest measured round trip time
+    pub rtt: Duration,
+    /// Approximate bandwidth towards the peer, in bytes per second
+    pub bandwidth: u64,
+}
+
+impl Transit {
+    /**
+     * Measure round trip time and bandwidth for about `duration`
+     *
+     * The peer must call [`answer_probe`](Self::answer_probe) at the same time. Larger durations
+     * give more accurate bandwidth estimates; one or two seconds should be enough.
+     */
+    pub async fn probe(&mut self, duration: Duration) -> Result<ProbeResult, TransitError> {
+        let mut rtt = Duration::MAX;
+        for _ in 0..PINGS {
+            rtt = rtt.min(self.ping().await?);
+        }
+
+        let filler = vec![FILLER; FILLER_SIZE];
+        let start = instant::Instant::now();
+        let mut sent = 0u64;
+        while start.elapsed() < duration {
+            self.send_record(&filler).await?;
+            sent += filler.len() as u64;
+        }
+        self.ping().await?;
+        let elapsed = start.elapsed().saturating_sub(rtt / 2);
+
+        self.send_record(&[END]).await?;
+        self.flush().await?;
+
+        Ok(ProbeResult {
+            rtt,
+            bandwidth: (sent as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+        })
+    }
+
+    /** Answer a [`probe`](Self::probe) of the peer, until it is done */
+    pub async fn answer_probe(&mut self) -> Result<(), TransitError> {
+        loop {
+            let record = self.receive_record().await?;
+            match record.first() {
+                Some(&PING) => {
+                    self.send_record(&[PONG]).await?;
+                    self.flush().await?;
+                },
+                Some(&FILLER) => {},
+                Some(&END) => return Ok(()),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Unexpected record while answering a probe",
+                    )
+                    .into())
+                },
+            }
+        }
+    }
+
+    async fn ping(&mut self) -> Result<Duration, TransitError> {
+        let start = instant::Instant::now();
+        self.send_record(&[PING]).await?;
+        self.flush().await?;
+        match self.receive_record().await?.first() {
+            Some(&PONG) => Ok(start.elapsed()),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Expected an answer to the probe",
+            )
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::test::transit_pair, *};
+
+    #[test]
+    fn test_probe() {
+        async_std::task::block_on(async {
+            let (mut leader, mut follower) = transit_pair().await;
+            let (result, answer) = futures::join!(
+                leader.probe(Duration::from_millis(100)),
+                follower.answer_probe()
+            );
+            answer.unwrap();
+            let result = result.unwrap();
+            assert!(result.bandwidth > 0);
+            assert!(result.rtt < Duration::from_secs(1));
+
+            /* The connection is still usable afterwards */
+            leader.send_record(b"hello").await.unwrap();
+            leader.flush().await.unwrap();
+            assert_eq!(&*follower.receive_record().await.unwrap(), b"hello");
+        });
+    }
+}
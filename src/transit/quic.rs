@@ -0,0 +1,346 @@
+//! Direct connections over QUIC, with UDP hole punching
+//!
+//! Direct TCP connections rarely make it through two NATs, so the session falls back to a relay. With UDP,
+//! both sides can open their NAT for each other by sending packets at the same time: the leader connects
+//! to the hints of the follower, while the follower accepts connections and sends QUIC handshakes towards
+//! the hints of the leader. Those get refused, but they open the NAT of the follower for the leader's
+//! handshakes. Our hints include the external address of the very same UDP socket, found out via STUN.
+//!
+//! QUIC requires TLS, but here it only wraps the connection: any certificate is accepted, as
+//! authentication and encryption come from the transit handshake and records on top, like with TCP.
+
+use super::{
+    transport, util, ConnectionType, DirectHint, TransitConnection, TransitHandshakeError,
+    TransitInfo,
+};
+use futures::io::{AsyncRead, AsyncWrite};
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/* Negotiated during the TLS handshake, so that stray QUIC connections get refused early */
+const ALPN: &[u8] = b"magic-wormhole-transit";
+/* Certificates don't get verified, but TLS wants a name anyways */
+const SERVER_NAME: &str = "magic-wormhole";
+
+/** Bind a UDP socket for QUIC, returning its endpoint and the hints to reach it */
+pub(super) async fn bind() -> std::io::Result<(quinn::Endpoint, Vec<DirectHint>)> {
+    /* Hosts without IPv6 can't bind the dual-stack address */
+    let socket =
+        match async_io::Async::<std::net::UdpSocket>::bind("[::]:0".parse::<SocketAddr>().unwrap())
+        {
+            Ok(socket) => socket,
+            Err(err) => {
+                log::debug!("Failed to bind QUIC to IPv6, falling back to IPv4: {}", err);
+                async_io::Async::<std::net::UdpSocket>::bind(
+                    "0.0.0.0:0".parse::<SocketAddr>().unwrap(),
+                )?
+            },
+        };
+    let port = socket.get_ref().local_addr()?.port();
+    let mut hints = Vec::new();
+
+    match util::timeout(
+        std::time::Duration::from_secs(4),
+        transport::udp_stun_query(&socket, super::PUBLIC_STUN_SERVER),
+    )
+    .await
+    {
+        Ok(Ok(external_addr)) => {
+            log::debug!("Our external UDP address is {}", external_addr);
+            hints.push(DirectHint::new(
+                external_addr.ip().to_string(),
+                external_addr.port(),
+            ));
+        },
+        Ok(Err(err)) => log::warn!("Failed to get external UDP address via STUN, {}", err),
+        Err(_) => log::warn!("Failed to get external UDP address via STUN, timed out"),
+    }
+    hints.extend(
+        if_addrs::get_if_addrs()?
+            .iter()
+            .filter(|iface| !iface.is_loopback())
+            .map(|iface| DirectHint::new(iface.ip().to_string(), port)),
+    );
+
+    let endpoint = new_endpoint(socket.into_inner()?)?;
+    log::debug!("Our socket for QUIC is bound to {}", endpoint.local_addr()?);
+    Ok((endpoint, hints))
+}
+
+fn new_endpoint(socket: std::net::UdpSocket) -> std::io::Result<quinn::Endpoint> {
+    let mut endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(server_config()?),
+        socket,
+        Arc::new(quinn::AsyncStdRuntime),
+    )?;
+    endpoint.set_default_client_config(client_config());
+    Ok(endpoint)
+}
+
+/** As leader: connect to one of the follower's hints */
+pub(super) async fn connect(
+    endpoint: quinn::Endpoint,
+    hint: DirectHint,
+) -> Result<TransitConnection, TransitHandshakeError> {
+    let addr = resolve(&hint).await?;
+    log::debug!("Connecting via QUIC to {}", addr);
+    let connection = endpoint
+        .connect(addr, SERVER_NAME)
+        .map_err(std::io::Error::other)?
+        .await
+        .map_err(std::io::Error::from)?;
+    let (send, recv) = connection.open_bi().await.map_err(std::io::Error::from)?;
+    log::debug!("Connected via QUIC to {}!", addr);
+
+    Ok(wrap_quic_connection(connection, send, recv))
+}
+
+/**
+ * As follower: send QUIC handshakes towards `their_hints`, so that the leader's ones get through our NAT
+ *
+ * The leader must not accept connections itself, so that these get refused. Never resolves, so that
+ * accepting goes on once all of them failed.
+ */
+pub(super) async fn punch(endpoint: quinn::Endpoint, their_hints: Vec<DirectHint>) {
+    futures::future::join_all(their_hints.into_iter().map(|hint| {
+        let endpoint = endpoint.clone();
+        async move {
+            let Ok(addr) = resolve(&hint).await else {
+                return;
+            };
+            log::trace!("Punching a hole towards {}", addr);
+            if let Ok(connecting) = endpoint.connect(addr, SERVER_NAME) {
+                let _ = connecting.await;
+            }
+        }
+    }))
+    .await;
+    futures::future::pending().await
+}
+
+/**
+ * As follower: accept the next connection of the leader, while [`punch`]ing holes
+ *
+ * Incoming connections that fail before the stream is open get skipped. Fails only once the endpoint is closed.
+ */
+pub(super) async fn accept(
+    endpoint: &quinn::Endpoint,
+) -> Result<TransitConnection, TransitHandshakeError> {
+    loop {
+        let connecting = endpoint
+            .accept()
+            .await
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::ConnectionAborted))?;
+        let connection = match connecting.await {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::debug!("Some incoming QUIC connection failed: {}", err);
+                continue;
+            },
+        };
+        /* The leader opens the stream, it becomes visible to us with the first data on it */
+        match connection.accept_bi().await {
+            Ok((send, recv)) => {
+                log::debug!("Got QUIC connection from {}!", connection.remote_address());
+                break Ok(wrap_quic_connection(connection, send, recv));
+            },
+            Err(err) => log::debug!("Some incoming QUIC connection failed: {}", err),
+        }
+    }
+}
+
+async fn resolve(hint: &DirectHint) -> std::io::Result<SocketAddr> {
+    async_std::net::ToSocketAddrs::to_socket_addrs(&(hint.hostname.as_str(), hint.port))
+        .await?
+        .next()
+        .ok_or_else(|| std::io::ErrorKind::AddrNotAvailable.into())
+}
+
+fn wrap_quic_connection(
+    connection: quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+) -> TransitConnection {
+    let info = TransitInfo {
+        conn_type: ConnectionType::Direct,
+        peer_addr: connection.remote_address(),
+        /* Filled in once the connection is established */
+        our_abilities: Default::default(),
+        peer_abilities: Default::default(),
+        stats: Default::default(),
+        relay_rtts: Vec::new(),
+        relay_key: None,
+    };
+    (
+        Box::new(QuicStream {
+            send,
+            recv,
+            _connection: connection,
+        }),
+        info,
+    )
+}
+
+/* One bidirectional stream of a QUIC connection, as byte stream for the transit protocol */
+struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    /* The connection gets closed once all handles to it are dropped */
+    _connection: quinn::Connection,
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_close(cx)
+    }
+}
+
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    /* Keep the holes in the NATs open */
+    config.keep_alive_interval(Some(std::time::Duration::from_secs(10)));
+    Arc::new(config)
+}
+
+fn server_config() -> std::io::Result<quinn::ServerConfig> {
+    let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()])
+        .map_err(std::io::Error::other)?;
+    let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+    let certificate =
+        rustls::Certificate(certificate.serialize_der().map_err(std::io::Error::other)?);
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key)
+        .map_err(std::io::Error::other)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn client_config() -> quinn::ClientConfig {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+    config
+}
+
+/* See the module documentation for why this is fine */
+struct AnyCertificate;
+
+impl rustls::client::ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[async_std::test]
+    async fn test_quic_loopback() {
+        let bind = || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            let hint = DirectHint::new("127.0.0.1", socket.local_addr().unwrap().port());
+            (new_endpoint(socket).unwrap(), hint)
+        };
+        let (leader, leader_hint) = bind();
+        let (follower, follower_hint) = bind();
+        /* The leader refuses the punching attempts */
+        leader.set_server_config(None);
+
+        /* The follower only sees the stream once the leader wrote to it, like in the transit handshake */
+        let leader = async {
+            let (mut stream, info) = connect(leader, follower_hint).await?;
+            stream.write_all(b"hello").await?;
+            Ok::<_, TransitHandshakeError>((stream, info))
+        };
+        let follower = async {
+            let punch = punch(follower.clone(), vec![leader_hint]);
+            let accept = accept(&follower);
+            futures::pin_mut!(punch, accept);
+            match futures::future::select(accept, punch).await {
+                futures::future::Either::Left((result, _)) => result,
+                futures::future::Either::Right(((), _)) => unreachable!(),
+            }
+        };
+        let ((mut leader_stream, leader_info), (mut follower_stream, follower_info)) =
+            futures::try_join!(leader, follower).unwrap();
+        assert_eq!(leader_info.conn_type, ConnectionType::Direct);
+        assert_eq!(follower_info.conn_type, ConnectionType::Direct);
+
+        let mut buf = [0u8; 5];
+        follower_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        follower_stream.write_all(b"world").await.unwrap();
+        leader_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world");
+    }
+
+    /* A connection that goes away before opening its stream must not stop accepting */
+    #[async_std::test]
+    async fn test_quic_accept_skips_failed() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let hint = DirectHint::new("127.0.0.1", socket.local_addr().unwrap().port());
+        let follower = new_endpoint(socket).unwrap();
+        let leader = new_endpoint(std::net::UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+        leader.set_server_config(None);
+
+        let leader = async {
+            let (stream, _) = connect(leader.clone(), hint.clone()).await?;
+            drop(stream);
+            let (mut stream, _) = connect(leader, hint).await?;
+            stream.write_all(b"hello").await?;
+            Ok::<_, TransitHandshakeError>(stream)
+        };
+        let (_leader_stream, (mut follower_stream, _)) =
+            futures::try_join!(leader, accept(&follower)).unwrap();
+        let mut buf = [0u8; 5];
+        follower_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
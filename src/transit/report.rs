@@ -0,0 +1,240 @@
+//! A summary of a finished session, for logging and aggregating the transfer quality
+
+use super::{ConnectionType, RelayRtt, TransitInfo, TransitStats};
+use crate::rendezvous::{MailboxStats, MailboxStatsHandle};
+use serde_derive::Serialize;
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The summary of a session, see [`SessionReporter`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct SessionReport {
+    /// From creating the reporter until the end, in seconds when serialized
+    #[serde(serialize_with = "as_secs")]
+    pub total: Duration,
+    /// Until the transit connection was established
+    #[serde(serialize_with = "as_secs_option")]
+    pub connect: Option<Duration>,
+    /// From the transit connection being established until the end
+    #[serde(serialize_with = "as_secs_option")]
+    pub transfer: Option<Duration>,
+    /// Payload bytes as reported by the progress handler
+    pub bytes: u64,
+    /// Payload bytes as reported by the peer when the transfer got aborted, see [`SessionReporter::record_peer_bytes`]
+    pub peer_bytes: Option<u64>,
+    /// `"direct"` or `"relay"` when serialized
+    #[serde(serialize_with = "connection_type")]
+    pub connection: Option<ConnectionType>,
+    /// Counters of the transit connection
+    pub stats: Option<TransitStats>,
+    /// Timing of the rendezvous server, see [`SessionReporter::watch_mailbox`]
+    pub mailbox: Option<MailboxStats>,
+    /// The relays measured before connecting, with round trip times in milliseconds when serialized
+    pub relay_rtts: Vec<RelayRtt>,
+    /// Number of times something had to be tried again, see [`SessionReporter::record_retry`]
+    pub retries: u32,
+    /// Errors that did not end the session, see [`SessionReporter::record_error`]
+    pub recovered_errors: Vec<String>,
+    /// The error that ended the session, if any
+    pub error: Option<String>,
+}
+
+struct State {
+    start: instant::Instant,
+    connected: Option<instant::Instant>,
+    info: Option<TransitInfo>,
+    bytes: u64,
+    peer_bytes: Option<u64>,
+    retries: u32,
+    recovered_errors: Vec<String>,
+    mailbox: Option<MailboxStatsHandle>,
+}
+
+/// Collects a [`SessionReport`]
+///
+/// The transfer and forwarding APIs report what happens through their handler callbacks. A
+/// reporter wraps those handlers, listens in, and sums everything up into a
+/// [`SessionReport`] once the session is over:
+///
+/// ```no_run
+/// # async fn doc(wormhole: magic_wormhole::Wormhole, offer: magic_wormhole::transfer::OfferSend) {
+/// use magic_wormhole::{transfer, transit};
+///
+/// let reporter = transit::SessionReporter::new();
+/// let result = transfer::send(
+///     wormhole,
+///     vec![],
+///     transit::Abilities::ALL_ABILITIES,
+///     offer,
+///     reporter.transit_handler(transit::log_transit_connection),
+///     reporter.progress_handler(|_sent, _total| {}),
+///     futures::future::pending(),
+/// )
+/// .await;
+/// let report = reporter.finish(&result);
+/// println!("{}", serde_json::to_string(&report).unwrap());
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SessionReporter(Arc<Mutex<State>>);
+
+impl Default for SessionReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionReporter {
+    /** Start the clock. Create it right before the session starts, it counts towards [`SessionReport::total`]. */
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            start: instant::Instant::now(),
+            connected: None,
+            info: None,
+            bytes: 0,
+            peer_bytes: None,
+            retries: 0,
+            recovered_errors: Vec::new(),
+            mailbox: None,
+        })))
+    }
+
+    /** Wrap a transit handler to learn when and how the connection got established */
+    pub fn transit_handler(&self, inner: impl FnOnce(TransitInfo)) -> impl FnOnce(TransitInfo) {
+        let state = self.0.clone();
+        move |info| {
+            {
+                let mut state = state.lock().unwrap();
+                state.connected = Some(instant::Instant::now());
+                state.info = Some(info.clone());
+            }
+            inner(info)
+        }
+    }
+
+    /** Wrap a progress handler to count the transferred bytes */
+    pub fn progress_handler(
+        &self,
+        mut inner: impl FnMut(u64, u64) + 'static,
+    ) -> impl FnMut(u64, u64) + 'static {
+        let state = self.0.clone();
+        move |done, total| {
+            state.lock().unwrap().bytes = done;
+            inner(done, total)
+        }
+    }
+
+    /**
+     * Note how many bytes the peer got through, as it told when aborting
+     *
+     * Take them from the summary of a `TransferError::PeerAborted`. They may differ from our own count,
+     * for example if data was still in flight.
+     */
+    pub fn record_peer_bytes(&self, bytes: u64) {
+        self.0.lock().unwrap().peer_bytes = Some(bytes);
+    }
+
+    /** Include the timing of the rendezvous server, from [`Wormhole::mailbox_stats_handle`](crate::Wormhole::mailbox_stats_handle) */
+    pub fn watch_mailbox(&self, stats: MailboxStatsHandle) {
+        self.0.lock().unwrap().mailbox = Some(stats);
+    }
+
+    /** Count an attempt that had to be repeated, like a new code after a failed key exchange */
+    pub fn record_retry(&self) {
+        self.0.lock().unwrap().retries += 1;
+    }
+
+    /** Note an error that the session recovered from */
+    pub fn record_error(&self, error: &dyn Display) {
+        self.0
+            .lock()
+            .unwrap()
+            .recovered_errors
+            .push(error.to_string());
+    }
+
+    /** Sum up the session, given its outcome */
+    pub fn finish<T, E: Display>(&self, result: &Result<T, E>) -> SessionReport {
+        let state = self.0.lock().unwrap();
+        let now = instant::Instant::now();
+        SessionReport {
+            total: now - state.start,
+            connect: state.connected.map(|connected| connected - state.start),
+            transfer: state.connected.map(|connected| now - connected),
+            bytes: state.bytes,
+            peer_bytes: state.peer_bytes,
+            connection: state.info.as_ref().map(|info| info.conn_type.clone()),
+            stats: state.info.as_ref().map(|info| info.stats.get()),
+            mailbox: state.mailbox.as_ref().map(MailboxStatsHandle::get),
+            relay_rtts: state
+                .info
+                .as_ref()
+                .map(|info| info.relay_rtts.clone())
+                .unwrap_or_default(),
+            retries: state.retries,
+            recovered_errors: state.recovered_errors.clone(),
+            error: result.as_ref().err().map(ToString::to_string),
+        }
+    }
+}
+
+fn as_secs<S: serde::Serializer>(duration: &Duration, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_f64(duration.as_secs_f64())
+}
+
+fn as_secs_option<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => ser.serialize_some(&duration.as_secs_f64()),
+        None => ser.serialize_none(),
+    }
+}
+
+fn connection_type<S: serde::Serializer>(
+    conn_type: &Option<ConnectionType>,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    match conn_type {
+        Some(ConnectionType::Direct) => ser.serialize_some("direct"),
+        Some(ConnectionType::Relay { .. }) => ser.serialize_some("relay"),
+        None => ser.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_report() {
+        let reporter = SessionReporter::new();
+        let mut progress = reporter.progress_handler(|_, _| {});
+        progress(0, 100);
+        progress(100, 100);
+        reporter.record_retry();
+        reporter.record_error(&"Relay went away");
+        reporter.record_peer_bytes(80);
+        reporter.watch_mailbox(MailboxStatsHandle::default());
+
+        let report = reporter.finish(&Err::<(), _>("Peer gave up"));
+        assert_eq!(report.bytes, 100);
+        assert_eq!(report.peer_bytes, Some(80));
+        assert_eq!(report.retries, 1);
+        assert_eq!(report.connect, None);
+        assert_eq!(report.error.as_deref(), Some("Peer gave up"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["connection"], serde_json::Value::Null);
+        assert_eq!(json["recovered-errors"][0], "Relay went away");
+        assert_eq!(json["mailbox"]["acks"], 0);
+        assert_eq!(json["mailbox"]["mean-ack-latency"], serde_json::Value::Null);
+        assert!(json["total"].is_f64());
+    }
+}
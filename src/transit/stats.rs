@@ -0,0 +1,225 @@
+//! Counters about the records that went over a transit connection
+//!
+//! Lost or repeated records abort the connection with [`TransitError::RecordGap`] or
+//! [`TransitError::RecordReplay`]. When a relay keeps dropping connections, the counters tell
+//! how far a transfer got and whether the records got mangled on the way. The throughput also tells
+//! when a slow relay is worth replacing with a direct connection, see [`RelaySwitchPolicy`].
+
+use super::{ConnectionType, TransitError, TransitInfo};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A snapshot of the counters of a [`Transit`](super::Transit) connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde_derive::Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct TransitStats {
+    /// Number of records sent
+    pub records_sent: u64,
+    /// Number of records received and successfully decrypted
+    pub records_received: u64,
+    /// Payload bytes sent, without the encryption overhead
+    pub bytes_sent: u64,
+    /// Payload bytes received, without the encryption overhead
+    pub bytes_received: u64,
+    /// Bytes sent over the wire, including the encryption and framing overhead
+    pub wire_bytes_sent: u64,
+    /// Bytes received over the wire, including the encryption and framing overhead
+    pub wire_bytes_received: u64,
+    /// Number of times records were missing on receive
+    pub gaps: u64,
+    /// Number of times records were received a second time
+    pub replays: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    records_sent: AtomicU64,
+    records_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    wire_bytes_sent: AtomicU64,
+    wire_bytes_received: AtomicU64,
+    gaps: AtomicU64,
+    replays: AtomicU64,
+}
+
+/**
+ * Live view on the counters of a [`Transit`](super::Transit) connection
+ *
+ * Unlike [`Transit::stats`](super::Transit::stats), it keeps counting after the connection
+ * has been [split](super::Transit::split).
+ */
+#[derive(Clone, Debug, Default)]
+pub struct TransitStatsHandle(Arc<Counters>);
+
+/** Handles are equal if they count for the same connection */
+impl PartialEq for TransitStatsHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TransitStatsHandle {}
+
+impl TransitStatsHandle {
+    pub fn get(&self) -> TransitStats {
+        let counters = &self.0;
+        TransitStats {
+            records_sent: counters.records_sent.load(Ordering::Relaxed),
+            records_received: counters.records_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            wire_bytes_sent: counters.wire_bytes_sent.load(Ordering::Relaxed),
+            wire_bytes_received: counters.wire_bytes_received.load(Ordering::Relaxed),
+            gaps: counters.gaps.load(Ordering::Relaxed),
+            replays: counters.replays.load(Ordering::Relaxed),
+        }
+    }
+
+    /** Count a sent record, `overhead` is the number of bytes the record layer adds to it */
+    pub(super) fn count_sent(&self, plaintext: &[u8], overhead: usize) {
+        let counters = &self.0;
+        counters.records_sent.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes_sent
+            .fetch_add(plaintext.len() as u64, Ordering::Relaxed);
+        counters
+            .wire_bytes_sent
+            .fetch_add((plaintext.len() + overhead) as u64, Ordering::Relaxed);
+    }
+
+    /** Count a received record, `overhead` is the number of bytes the record layer adds to it */
+    pub(super) fn count_received(&self, result: &Result<Box<[u8]>, TransitError>, overhead: usize) {
+        let counters = &self.0;
+        match result {
+            Ok(plaintext) => {
+                counters.records_received.fetch_add(1, Ordering::Relaxed);
+                counters
+                    .bytes_received
+                    .fetch_add(plaintext.len() as u64, Ordering::Relaxed);
+                counters
+                    .wire_bytes_received
+                    .fetch_add((plaintext.len() + overhead) as u64, Ordering::Relaxed);
+            },
+            Err(TransitError::RecordGap { .. }) => {
+                counters.gaps.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(TransitError::RecordReplay { .. }) => {
+                counters.replays.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(_) => {},
+        }
+    }
+}
+
+/**
+ * When to look for a faster path than a slow relay
+ *
+ * A transit connection can't move over to another path on its own. That needs help from the application
+ * protocol, which has to connect again and resume where it stopped (like the file offsets of transfer v2).
+ * This only tells when it is worth trying: if the relay is slower than `min_throughput` (in bytes per second)
+ * after the `grace_period`, and both sides could connect directly in the first place.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelaySwitchPolicy {
+    pub min_throughput: u64,
+    pub grace_period: Duration,
+}
+
+impl Default for RelaySwitchPolicy {
+    fn default() -> Self {
+        Self {
+            min_throughput: 256 * 1024,
+            grace_period: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RelaySwitchPolicy {
+    /** Decide from the counters of a connection that has been running for `elapsed` */
+    pub fn should_try_direct(
+        &self,
+        info: &TransitInfo,
+        stats: &TransitStats,
+        elapsed: Duration,
+    ) -> bool {
+        if !matches!(info.conn_type, ConnectionType::Relay { .. })
+            || info.is_relay_forced()
+            || elapsed < self.grace_period
+        {
+            return false;
+        }
+        let bytes = stats.bytes_sent + stats.bytes_received;
+        (bytes as f64 / elapsed.as_secs_f64()) < self.min_throughput as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counting() {
+        let handle = TransitStatsHandle::default();
+        handle.count_sent(b"hello", 44);
+        handle.count_received(&Ok(Box::new(*b"hi")), 44);
+        handle.count_received(
+            &Err(TransitError::RecordGap {
+                expected: 1,
+                received: 3,
+            }),
+            44,
+        );
+        handle.count_received(&Err(TransitError::Crypto), 44);
+        assert_eq!(
+            handle.get(),
+            TransitStats {
+                records_sent: 1,
+                records_received: 1,
+                bytes_sent: 5,
+                bytes_received: 2,
+                wire_bytes_sent: 49,
+                wire_bytes_received: 46,
+                gaps: 1,
+                replays: 0,
+            }
+        );
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn test_relay_switch_policy() {
+        use super::super::Abilities;
+
+        let mut info = TransitInfo {
+            conn_type: ConnectionType::Relay { name: None },
+            peer_addr: "127.0.0.1:4001".parse().unwrap(),
+            our_abilities: Abilities::ALL_ABILITIES,
+            peer_abilities: Abilities::ALL_ABILITIES,
+            stats: Default::default(),
+            relay_rtts: Vec::new(),
+            relay_key: None,
+        };
+        let slow = TransitStats {
+            bytes_received: 1024 * 1024,
+            ..Default::default()
+        };
+        let policy = RelaySwitchPolicy::default();
+        assert!(!policy.should_try_direct(&info, &slow, Duration::from_secs(1)));
+        assert!(policy.should_try_direct(&info, &slow, Duration::from_secs(60)));
+        let fast = TransitStats {
+            bytes_received: 100 * 1024 * 1024,
+            ..Default::default()
+        };
+        assert!(!policy.should_try_direct(&info, &fast, Duration::from_secs(60)));
+
+        info.peer_abilities = Abilities::FORCE_RELAY;
+        assert!(!policy.should_try_direct(&info, &slow, Duration::from_secs(60)));
+    }
+}
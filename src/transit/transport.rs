@@ -5,16 +5,20 @@ use super::{ConnectionType, TransitConnection, TransitHandshakeError, TransitInf
 use super::{DirectHint, StunError};
 
 #[cfg(not(target_family = "wasm"))]
-use async_std::net::TcpStream;
+use async_std::net::{TcpStream, ToSocketAddrs};
 use async_trait::async_trait;
 use futures::{
     future::TryFutureExt,
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 };
 #[cfg(not(target_family = "wasm"))]
+use futures::{SinkExt, StreamExt};
+#[cfg(not(target_family = "wasm"))]
 use std::{
-    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
     sync::Arc,
+    task::{Context, Poll},
 };
 
 #[async_trait]
@@ -97,73 +101,21 @@ pub(super) fn set_socket_opts(socket: &socket2::Socket) -> std::io::Result<()> {
 /** Perform a STUN query to get the external IP address */
 #[cfg(not(target_family = "wasm"))]
 pub(super) async fn tcp_get_external_ip() -> Result<(SocketAddr, TcpStream), StunError> {
-    let mut socket = tcp_connect_custom(
-        &"[::]:0".parse::<SocketAddr>().unwrap().into(),
-        &super::PUBLIC_STUN_SERVER
-            .to_socket_addrs()?
-            /* If you find yourself behind a NAT66, open an issue */
-            .find(|x| x.is_ipv4())
-            /* TODO add a helper method to stdlib for this */
-            .map(|addr| match addr {
-                SocketAddr::V4(v4) => {
-                    SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
-                },
-                SocketAddr::V6(_) => unreachable!(),
-            })
-            .ok_or(StunError::ServerIsV6Only)?
-            .into(),
+    stun_query(
+        super::PUBLIC_STUN_SERVER,
+        "[::]:0".parse::<SocketAddr>().unwrap(),
     )
-    .await?;
-
-    use bytecodec::{DecodeExt, EncodeExt};
-    use stun_codec::{
-        rfc5389::{
-            self,
-            attributes::{MappedAddress, Software, XorMappedAddress},
-            Attribute,
-        },
-        Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId,
-    };
-
-    fn get_binding_request() -> Result<Vec<u8>, bytecodec::Error> {
-        use rand::Rng;
-        let random_bytes = rand::thread_rng().gen::<[u8; 12]>();
-
-        let mut message: Message<Attribute> = Message::new(
-            MessageClass::Request,
-            rfc5389::methods::BINDING,
-            TransactionId::new(random_bytes),
-        );
-
-        message.add_attribute(Attribute::Software(Software::new(
-            "magic-wormhole-rust".to_owned(),
-        )?));
-
-        // Encodes the message
-        let mut encoder = MessageEncoder::new();
-        let bytes = encoder.encode_into_bytes(message.clone())?;
-        Ok(bytes)
-    }
-
-    fn decode_address(buf: &[u8]) -> Result<Option<SocketAddr>, bytecodec::Error> {
-        let mut decoder = MessageDecoder::<Attribute>::new();
-        let decoded = decoder.decode_from_bytes(buf)??;
-
-        let external_addr1 = decoded
-            .get_attribute::<XorMappedAddress>()
-            .map(|x| x.address());
-        //let external_addr2 = decoded.get_attribute::<XorMappedAddress2>().map(|x|x.address());
-        let external_addr3 = decoded
-            .get_attribute::<MappedAddress>()
-            .map(|x| x.address());
-        let external_addr = external_addr1
-            // .or(external_addr2)
-            .or(external_addr3);
-
-        Ok(external_addr)
-    }
+    .await
+}
 
-    /* Connect the plugs */
+/** Ask `server` for our external address, as seen from a connection bound to `local_addr` */
+#[cfg(not(target_family = "wasm"))]
+pub(super) async fn stun_query(
+    server: &str,
+    local_addr: SocketAddr,
+) -> Result<(SocketAddr, TcpStream), StunError> {
+    let mut socket =
+        tcp_connect_custom(&local_addr.into(), &stun_server_addr(server).await?.into()).await?;
 
     socket.write_all(get_binding_request()?.as_ref()).await?;
 
@@ -179,6 +131,95 @@ pub(super) async fn tcp_get_external_ip() -> Result<(SocketAddr, TcpStream), Stu
     Ok((external_addr, socket))
 }
 
+/** Ask `server` for the external address of a UDP socket, for hole punching */
+#[cfg(all(feature = "quic", not(target_family = "wasm")))]
+pub(super) async fn udp_stun_query(
+    socket: &async_io::Async<std::net::UdpSocket>,
+    server: &str,
+) -> Result<SocketAddr, StunError> {
+    let server = stun_server_addr(server).await?;
+    socket.send_to(&get_binding_request()?, server).await?;
+
+    let mut buf = [0u8; 256];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        /* Anybody can send us packets, but only the server knows the answer */
+        if from == server {
+            break decode_address(&buf[..len])?.ok_or(StunError::ServerNoResponse);
+        }
+    }
+}
+
+/* The IPv4 address of a STUN server, mapped to IPv6 since our sockets are dual stack.
+ * Resolve without blocking the executor, so that dropping the connector aborts the lookup. */
+#[cfg(not(target_family = "wasm"))]
+async fn stun_server_addr(server: &str) -> Result<SocketAddr, StunError> {
+    server
+        .to_socket_addrs()
+        .await?
+        /* If you find yourself behind a NAT66, open an issue */
+        .find(|x| x.is_ipv4())
+        /* TODO add a helper method to stdlib for this */
+        .map(|addr| match addr {
+            SocketAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+            SocketAddr::V6(_) => unreachable!(),
+        })
+        .ok_or(StunError::ServerIsV6Only)
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn get_binding_request() -> Result<Vec<u8>, bytecodec::Error> {
+    use bytecodec::EncodeExt;
+    use rand::Rng;
+    use stun_codec::{
+        rfc5389::{self, attributes::Software, Attribute},
+        Message, MessageClass, MessageEncoder, TransactionId,
+    };
+    let random_bytes = rand::thread_rng().gen::<[u8; 12]>();
+
+    let mut message: Message<Attribute> = Message::new(
+        MessageClass::Request,
+        rfc5389::methods::BINDING,
+        TransactionId::new(random_bytes),
+    );
+
+    message.add_attribute(Attribute::Software(Software::new(
+        "magic-wormhole-rust".to_owned(),
+    )?));
+
+    // Encodes the message
+    let mut encoder = MessageEncoder::new();
+    let bytes = encoder.encode_into_bytes(message.clone())?;
+    Ok(bytes)
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn decode_address(buf: &[u8]) -> Result<Option<SocketAddr>, bytecodec::Error> {
+    use bytecodec::DecodeExt;
+    use stun_codec::{
+        rfc5389::{
+            attributes::{MappedAddress, XorMappedAddress},
+            Attribute,
+        },
+        MessageDecoder,
+    };
+    let mut decoder = MessageDecoder::<Attribute>::new();
+    let decoded = decoder.decode_from_bytes(buf)??;
+
+    let external_addr1 = decoded
+        .get_attribute::<XorMappedAddress>()
+        .map(|x| x.address());
+    //let external_addr2 = decoded.get_attribute::<XorMappedAddress2>().map(|x|x.address());
+    let external_addr3 = decoded
+        .get_attribute::<MappedAddress>()
+        .map(|x| x.address());
+    let external_addr = external_addr1
+        // .or(external_addr2)
+        .or(external_addr3);
+
+    Ok(external_addr)
+}
+
 /**
  * Bind to a port with SO_REUSEADDR, connect to the destination and then hide the blood behind a pretty [`async_std::net::TcpStream`]
  *
@@ -256,6 +297,136 @@ pub(super) async fn connect_tcp_relay(
     wrap_tcp_connection(socket, ConnectionType::Relay { name })
 }
 
+/* Take a WebSocket URL of a relay hint and try to connect to it, for when plain TCP is blocked */
+#[cfg(not(target_family = "wasm"))]
+pub(super) async fn connect_ws_relay(
+    url: url::Url,
+    name: Option<String>,
+) -> Result<TransitConnection, TransitHandshakeError> {
+    log::debug!("Connecting to relay {}", url);
+    let host = url.host_str().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "relay URL has no host")
+    })?;
+    let port = url.port_or_known_default().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "relay URL has no port")
+    })?;
+    let socket = TcpStream::connect((host, port))
+        .err_into::<TransitHandshakeError>()
+        .await?;
+    let peer_addr = socket.peer_addr()?;
+    #[cfg(feature = "native-certs")]
+    let connector = Some(crate::tls::connector());
+    #[cfg(not(feature = "native-certs"))]
+    let connector = None;
+    let (stream, _response) = async_tungstenite::async_tls::client_async_tls_with_connector(
+        url.as_str(),
+        socket,
+        connector,
+    )
+    .await
+    .map_err(ws_to_io_error)?;
+    log::debug!("Connected to {}!", url);
+
+    Ok((
+        Box::new(WsByteStream {
+            inner: stream,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        }),
+        TransitInfo {
+            conn_type: ConnectionType::Relay { name },
+            peer_addr,
+            /* Filled in once the connection is established */
+            our_abilities: Default::default(),
+            peer_abilities: Default::default(),
+            stats: Default::default(),
+            relay_rtts: Vec::new(),
+            relay_key: None,
+        },
+    ))
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn ws_to_io_error(error: async_tungstenite::tungstenite::Error) -> std::io::Error {
+    use async_tungstenite::tungstenite::Error;
+    match error {
+        Error::Io(error) => error,
+        Error::ConnectionClosed | Error::AlreadyClosed => std::io::ErrorKind::BrokenPipe.into(),
+        error => std::io::Error::other(error),
+    }
+}
+
+/**
+ * Byte stream over a WebSocket connection
+ *
+ * Each write gets sent as one binary message, and reads return the payloads of the received binary
+ * messages back to back. The relay forwards the bytes as they are, so message boundaries carry no meaning.
+ */
+#[cfg(not(target_family = "wasm"))]
+struct WsByteStream<S> {
+    inner: async_tungstenite::WebSocketStream<S>,
+    /* The last received message, of which `read_buf[read_pos..]` has not been read yet */
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsByteStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        use async_tungstenite::tungstenite::Message;
+        loop {
+            if self.read_pos < self.read_buf.len() {
+                let start = self.read_pos;
+                let len = buf.len().min(self.read_buf.len() - start);
+                buf[..len].copy_from_slice(&self.read_buf[start..start + len]);
+                self.read_pos += len;
+                return Poll::Ready(Ok(len));
+            }
+            match futures::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                },
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(0)),
+                /* Pings get answered by tungstenite itself, and text is not part of the protocol */
+                Some(Ok(_)) => {},
+                Some(Err(error)) => return Poll::Ready(Err(ws_to_io_error(error))),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsByteStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        use async_tungstenite::tungstenite::Message;
+        futures::ready!(self.inner.poll_ready_unpin(cx)).map_err(ws_to_io_error)?;
+        self.inner
+            .start_send_unpin(Message::Binary(buf.to_vec()))
+            .map_err(ws_to_io_error)?;
+        /* Callers of plain sockets do not always flush, so push the message out right away if possible.
+         * Errors will show up again on the next call. */
+        let _ = self.inner.poll_flush_unpin(cx);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.poll_flush_unpin(cx).map_err(ws_to_io_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.poll_close_unpin(cx).map_err(ws_to_io_error)
+    }
+}
+
 #[cfg(target_family = "wasm")]
 pub(super) async fn connect_ws_relay(
     url: url::Url,
@@ -273,6 +444,12 @@ pub(super) async fn connect_ws_relay(
         transit,
         TransitInfo {
             conn_type: ConnectionType::Relay { name },
+            /* Filled in once the connection is established */
+            our_abilities: Default::default(),
+            peer_abilities: Default::default(),
+            stats: Default::default(),
+            relay_rtts: Vec::new(),
+            relay_key: None,
         },
     ))
 }
@@ -296,6 +473,12 @@ pub(super) fn wrap_tcp_connection(
         peer_addr: socket
             .peer_addr()
             .expect("Internal error: socket must be IP"),
+        /* Filled in once the connection is established */
+        our_abilities: Default::default(),
+        peer_abilities: Default::default(),
+        stats: Default::default(),
+        relay_rtts: Vec::new(),
+        relay_key: None,
     };
 
     Ok((Box::new(socket), info))
@@ -5,6 +5,11 @@
 /// This, however, might change in the future.
 use super::*;
 
+#[cfg(feature = "qr")]
+mod qr;
+#[cfg(feature = "qr")]
+pub use qr::{QrCode, QrError};
+
 #[derive(Debug, thiserror::Error, Clone, Eq, PartialEq)]
 #[non_exhaustive]
 pub enum ParseError {
@@ -56,6 +61,20 @@ impl WormholeTransferUri {
             is_leader: false,
         }
     }
+
+    /** Link to `code` on the rendezvous server of `config`, which is only included if it isn't the default one */
+    pub fn with_config<V>(code: Code, config: &AppConfig<V>) -> Result<Self, ParseError> {
+        let rendezvous_server = if config.rendezvous_url == rendezvous::DEFAULT_RENDEZVOUS_SERVER {
+            None
+        } else {
+            Some(url::Url::parse(&config.rendezvous_url)?)
+        };
+        Ok(Self {
+            code,
+            rendezvous_server,
+            is_leader: false,
+        })
+    }
 }
 
 impl TryFrom<&url::Url> for WormholeTransferUri {
@@ -178,6 +197,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_with_config() {
+        let code = Code("4-hurricane-equipment".to_owned());
+        let config = crate::transfer::APP_CONFIG;
+        assert_eq!(
+            WormholeTransferUri::with_config(code.clone(), &config).unwrap(),
+            WormholeTransferUri::new(code.clone())
+        );
+        let config = config.rendezvous_url("ws://localhost:4000".into());
+        assert_eq!(
+            WormholeTransferUri::with_config(code, &config)
+                .unwrap()
+                .rendezvous_server,
+            Some(url::Url::parse("ws://localhost:4000").unwrap())
+        );
+    }
+
     #[test]
     fn test_uri_err() {
         assert_eq!(
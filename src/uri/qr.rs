@@ -0,0 +1,80 @@
+//! Render wormhole URIs as QR codes
+//!
+//! Only available with the `qr` feature.
+
+use super::WormholeTransferUri;
+
+/// The URI does not fit into a QR code
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to generate QR code")]
+pub struct QrError(#[source] qrcode::types::QrError);
+
+/**
+ * A QR code of a [`WormholeTransferUri`]
+ *
+ * The modules ("pixels") can be read directly with [`is_dark`](Self::is_dark) to draw
+ * the code in a GUI, or the code can be rendered as SVG or as text for terminals.
+ */
+#[derive(Clone)]
+pub struct QrCode(qrcode::QrCode);
+
+impl QrCode {
+    /** Number of modules per side, without the quiet zone around the code */
+    pub fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    /** Whether the module at column `x` and row `y` is dark */
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.0[(x, y)] == qrcode::Color::Dark
+    }
+
+    /** Render as SVG image, with a quiet zone and `module_size` pixels per module */
+    pub fn to_svg(&self, module_size: u32) -> String {
+        self.0
+            .render::<qrcode::render::svg::Color>()
+            .module_dimensions(module_size, module_size)
+            .build()
+    }
+
+    /**
+     * Render as text for terminals, using half block characters for two rows per line
+     *
+     * Light modules are drawn as blocks in the text color and dark ones as blank space, which
+     * gives the right colors on terminals with a dark background.
+     */
+    pub fn to_terminal_string(&self) -> String {
+        self.0
+            .render::<qrcode::render::unicode::Dense1x2>()
+            .dark_color(qrcode::render::unicode::Dense1x2::Light)
+            .light_color(qrcode::render::unicode::Dense1x2::Dark)
+            .build()
+    }
+}
+
+impl WormholeTransferUri {
+    /** Encode this URI as QR code */
+    pub fn to_qr_code(&self) -> Result<QrCode, QrError> {
+        qrcode::QrCode::new(self.to_string())
+            .map(QrCode)
+            .map_err(QrError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Code;
+
+    #[test]
+    fn test_qr_code() {
+        let qr = WormholeTransferUri::new(Code("4-hurricane-equipment".to_owned()))
+            .to_qr_code()
+            .unwrap();
+        /* Version 3, the top left finder pattern starts with a dark module */
+        assert_eq!(qr.width(), 29);
+        assert!(qr.is_dark(0, 0));
+        assert!(qr.to_svg(4).starts_with("<?xml"));
+        assert!(!qr.to_terminal_string().is_empty());
+    }
+}
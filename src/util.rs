@@ -188,6 +188,105 @@ pub async fn sleep(duration: std::time::Duration) {
     let _ = wasm_timer::Delay::new(duration).await;
 }
 
+/** Wall clock time in milliseconds since the Unix epoch, for timestamps that get sent to the peer */
+#[cfg(not(target_family = "wasm"))]
+pub fn unix_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+/** Wall clock time in milliseconds since the Unix epoch, for timestamps that get sent to the peer */
+#[cfg(target_family = "wasm")]
+pub fn unix_millis() -> f64 {
+    /* `performance.now()` counts from page load instead */
+    js_sys::Date::now()
+}
+
+/**
+ * Drive `future` to completion, calling `keep_alive` every time `interval` elapses in the meantime
+ *
+ * An error from `keep_alive` aborts the wait.
+ */
+#[cfg(feature = "forwarding")]
+pub async fn keep_alive_while<S: ?Sized, T, E>(
+    state: &mut S,
+    interval: std::time::Duration,
+    future: impl futures::Future<Output = T>,
+    mut keep_alive: impl for<'a> FnMut(&'a mut S) -> futures::future::LocalBoxFuture<'a, Result<(), E>>,
+) -> Result<T, E> {
+    use futures::future::Either;
+    futures::pin_mut!(future);
+    loop {
+        let tick = sleep(interval);
+        futures::pin_mut!(tick);
+        match futures::future::select(future.as_mut(), tick).await {
+            Either::Left((value, _)) => break Ok(value),
+            Either::Right(((), _)) => keep_alive(state).await?,
+        }
+    }
+}
+
+/**
+ * Like [`keep_alive_while`], but also aborts the wait with the error `watch` resolves to
+ *
+ * `watch` gets dropped and restarted around every call to `keep_alive`.
+ */
+#[allow(dead_code)]
+pub async fn keep_alive_watching<S: ?Sized, T, E>(
+    state: &mut S,
+    interval: std::time::Duration,
+    future: impl futures::Future<Output = T>,
+    mut keep_alive: impl for<'a> FnMut(&'a mut S) -> futures::future::LocalBoxFuture<'a, Result<(), E>>,
+    mut watch: impl for<'a> FnMut(&'a mut S) -> futures::future::LocalBoxFuture<'a, E>,
+) -> Result<T, E> {
+    use futures::future::{select, Either};
+    futures::pin_mut!(future);
+    loop {
+        let tick = sleep(interval);
+        futures::pin_mut!(tick);
+        match select(future.as_mut(), select(tick, watch(state))).await {
+            Either::Left((value, _)) => break Ok(value),
+            Either::Right((Either::Left(((), _)), _)) => {},
+            Either::Right((Either::Right((error, _)), _)) => break Err(error),
+        }
+        keep_alive(state).await?;
+    }
+}
+
+/** The message a panic got raised with, as far as there is one */
+#[allow(dead_code)]
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
+}
+
+/**
+ * Run blocking `work` on the thread pool of async-std, starting right away
+ *
+ * A panic in `work` gets returned as error, instead of tearing down whoever waits for it.
+ */
+#[cfg(not(target_family = "wasm"))]
+#[allow(dead_code)]
+pub fn spawn_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> async_std::task::JoinHandle<std::io::Result<T>> {
+    async_std::task::spawn_blocking(move || {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(work)).unwrap_or_else(|panic| {
+            Err(std::io::Error::other(format!(
+                "Background task panicked: {}",
+                panic_message(&*panic)
+            )))
+        })
+    })
+}
+
 #[cfg(not(target_family = "wasm"))]
 #[allow(dead_code)]
 pub async fn timeout<F, T>(
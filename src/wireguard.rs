@@ -0,0 +1,348 @@
+//! Pair two machines into a WireGuard VPN with one code
+//!
+//! Both sides call [`pair`] with their own WireGuard public key, tunnel addresses and the endpoints where
+//! they can be reached. They get back a [`PeerConfig`] for the other side, ready to be put into their
+//! WireGuard configuration. The private keys never leave the machines.
+//!
+//! On top of the public keys, both sides get the same preshared key, derived from the wormhole's key.
+//! Only the two of them know it, so the tunnel is bound to this very code exchange.
+//!
+//! **Security warning:** the other side's addresses become its `AllowedIPs`, so WireGuard routes traffic for
+//! them into the tunnel. A peer claiming `0.0.0.0/0` would get all of it. So by default, only single hosts
+//! (`/32` and `/128`) are accepted. If the other side routes a whole network, pass the ranges it may use as
+//! [`LocalPeer::peer_ranges`]; all of its addresses must lie within them then.
+//!
+//! It is bound to an [`APPID`](APPID) of its own and built upon [`introduce`](crate::introduce).
+
+use super::*;
+use crate::introduce::{self, IntroductionError};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, fmt, net::IpAddr};
+
+const APPID_RAW: &str = "piegames.de/wormhole/wireguard";
+
+/// The App ID associated with this protocol.
+pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
+
+/// An [`crate::AppConfig`] with sane defaults for this protocol.
+///
+/// You **must not** change `id` and `rendezvous_url` to be interoperable.
+pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersion> {
+    id: AppID(Cow::Borrowed(APPID_RAW)),
+    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    app_version: AppVersion {},
+    client_version: crate::ClientVersion::DEFAULT,
+    reconnect_policy: crate::rendezvous::ReconnectPolicy::INTERACTIVE,
+    timeouts: crate::WormholeTimeouts::DEFAULT,
+};
+
+/**
+ * The application specific version information for this protocol.
+ *
+ * There is nothing to negotiate (yet), this is always empty.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct AppVersion {}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum WireguardError {
+    /// What we were given or got sent is not a WireGuard key
+    #[error("Invalid WireGuard key")]
+    InvalidKey,
+    /// What we were given or got sent is not an address with prefix length, like `10.0.0.1/32`
+    #[error("Invalid tunnel address: {}", _0)]
+    InvalidAddress(Box<str>),
+    /// The other side claims addresses outside of [`LocalPeer::peer_ranges`], or a whole network without them
+    #[error("Tunnel address of the peer not allowed: {}", _0)]
+    AddressNotAllowed(Box<str>),
+    /// What we were given or got sent is not an endpoint, like `192.0.2.1:51820`
+    #[error("Invalid endpoint: {}", _0)]
+    InvalidEndpoint(Box<str>),
+    /// Some deserialization went wrong, we probably got some garbage
+    #[error("Corrupt JSON message received")]
+    ProtocolJson(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("Exchanging the configuration failed")]
+    Introduction(
+        #[from]
+        #[source]
+        IntroductionError,
+    ),
+}
+
+/**
+ * A WireGuard key, like a public key from `wg pubkey`
+ *
+ * Parsed from and displayed as base64, like `wg` does.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct WireguardKey([u8; 32]);
+
+impl WireguardKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for WireguardKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use base64::Engine;
+        f.write_str(&base64::engine::general_purpose::STANDARD.encode(self.0))
+    }
+}
+
+/* Don't leak preshared keys into logs */
+impl fmt::Debug for WireguardKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WireguardKey(…)")
+    }
+}
+
+impl std::str::FromStr for WireguardKey {
+    type Err = WireguardError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Self)
+            .ok_or(WireguardError::InvalidKey)
+    }
+}
+
+/** What we tell the other side about us */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalPeer {
+    /** Our public key */
+    pub public_key: WireguardKey,
+    /** Our addresses within the tunnel, like `10.0.0.1/32`. They become the other side's `AllowedIPs` */
+    pub addresses: Vec<String>,
+    /** Where the other side may reach us, like `192.0.2.1:51820`. Empty if we cannot be reached */
+    pub endpoints: Vec<String>,
+    /**
+     * The networks the other side's addresses must lie within, like `10.0.0.0/24`
+     *
+     * If empty, the other side may only claim single hosts. See the [module docs](self) for why.
+     */
+    pub peer_ranges: Vec<String>,
+}
+
+/* The details sent along the introduction */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PeerDetails {
+    public_key: String,
+    addresses: Vec<String>,
+}
+
+/**
+ * The `[Peer]` section for the other side
+ *
+ * [`Display`](std::fmt::Display) gives the section in the format of `wg-quick`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeerConfig {
+    pub public_key: WireguardKey,
+    /** Derived from the wormhole's key, both sides have the same one */
+    pub preshared_key: WireguardKey,
+    /** The other side's tunnel addresses */
+    pub allowed_ips: Vec<String>,
+    /**
+     * All endpoints the other side told us about
+     *
+     * The configuration only has room for one, the first is used. Try the others if it does not work.
+     */
+    pub endpoints: Vec<String>,
+}
+
+impl PeerConfig {
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoints.first().map(String::as_str)
+    }
+}
+
+impl fmt::Display for PeerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Peer]")?;
+        writeln!(f, "PublicKey = {}", self.public_key)?;
+        writeln!(f, "PresharedKey = {}", self.preshared_key)?;
+        writeln!(f, "AllowedIPs = {}", self.allowed_ips.join(", "))?;
+        if let Some(endpoint) = self.endpoint() {
+            writeln!(f, "Endpoint = {}", endpoint)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Exchange the configuration with the other side
+ *
+ * The wormhole must be connected with [`APP_CONFIG`], and gets closed afterwards. Both sides do the same.
+ * Fails with [`WireguardError::AddressNotAllowed`] if the other side's addresses don't fit the
+ * [`peer_ranges`](LocalPeer::peer_ranges).
+ */
+pub async fn pair(wormhole: Wormhole, local: &LocalPeer) -> Result<PeerConfig, WireguardError> {
+    if let Some(err) = find_invalid(&local.addresses, &local.endpoints)
+        .or_else(|| find_invalid(&local.peer_ranges, &[]))
+    {
+        bail!(err);
+    }
+    let details = serde_json::to_value(PeerDetails {
+        public_key: local.public_key.to_string(),
+        addresses: local.addresses.clone(),
+    })?;
+
+    let introduction =
+        introduce::introduce_with_details(wormhole, local.endpoints.clone(), details).await?;
+    let peer: PeerDetails = serde_json::from_value(introduction.peer_details)?;
+    if let Some(err) = find_invalid(&peer.addresses, &introduction.peer_endpoints) {
+        bail!(err);
+    }
+    if let Some(address) = peer
+        .addresses
+        .iter()
+        .find(|address| !is_allowed(address, &local.peer_ranges))
+    {
+        bail!(WireguardError::AddressNotAllowed(address.as_str().into()));
+    }
+
+    Ok(PeerConfig {
+        public_key: peer.public_key.parse()?,
+        preshared_key: preshared_key(&introduction.key),
+        allowed_ips: peer.addresses,
+        endpoints: introduction.peer_endpoints,
+    })
+}
+
+fn preshared_key(key: &Key<introduce::IntroductionKey>) -> WireguardKey {
+    let key = key.derive_subkey_from_purpose::<GenericKey>("wireguard-preshared-key");
+    WireguardKey((*key.0).into())
+}
+
+/* Everything that ends up in the configuration file */
+fn find_invalid(addresses: &[String], endpoints: &[String]) -> Option<WireguardError> {
+    let address = addresses.iter().find(|address| !is_address(address));
+    let endpoint = endpoints.iter().find(|endpoint| !is_endpoint(endpoint));
+    address
+        .map(|address| WireguardError::InvalidAddress(address.as_str().into()))
+        .or_else(|| {
+            endpoint.map(|endpoint| WireguardError::InvalidEndpoint(endpoint.as_str().into()))
+        })
+}
+
+/* An address with prefix length, as `AllowedIPs` wants it */
+fn is_address(address: &str) -> bool {
+    parse_address(address).is_some()
+}
+
+fn parse_address(address: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = address.split_once('/')?;
+    let (ip, prefix) = (ip.parse::<IpAddr>().ok()?, prefix.parse::<u8>().ok()?);
+    (prefix <= host_prefix(ip)).then_some((ip, prefix))
+}
+
+/* The prefix length of a single host */
+fn host_prefix(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/* Whether the other side may claim `address`: a single host if there are no `ranges`, otherwise within one of them */
+fn is_allowed(address: &str, ranges: &[String]) -> bool {
+    /* Left-aligned, so that the prefix lengths of both families mean the same bits */
+    fn bits(ip: IpAddr) -> u128 {
+        match ip {
+            IpAddr::V4(ip) => u128::from(u32::from(ip)) << 96,
+            IpAddr::V6(ip) => u128::from(ip),
+        }
+    }
+    let Some((ip, prefix)) = parse_address(address) else {
+        return false;
+    };
+    if ranges.is_empty() {
+        return prefix == host_prefix(ip);
+    }
+    ranges
+        .iter()
+        .filter_map(|range| parse_address(range))
+        .any(|(network, network_prefix)| {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(network_prefix))
+                .unwrap_or(0);
+            ip.is_ipv4() == network.is_ipv4()
+                && prefix >= network_prefix
+                && bits(ip) & mask == bits(network) & mask
+        })
+}
+
+/* A host and port. Only checked for what would break the configuration file, resolving is up to WireGuard */
+fn is_endpoint(endpoint: &str) -> bool {
+    endpoint
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+        && !endpoint.contains(|c: char| c.is_whitespace() || c.is_control() || c == '#')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    #[test]
+    fn test_peer_config() {
+        let key: WireguardKey = KEY.parse().unwrap();
+        assert_eq!(key.to_string(), KEY);
+        assert!("xTIBA5rboUvnH4ht".parse::<WireguardKey>().is_err());
+        assert!("not base64!".parse::<WireguardKey>().is_err());
+
+        assert!(is_address("10.0.0.1/32"));
+        assert!(is_address("fd00::1/128"));
+        assert!(!is_address("10.0.0.1"));
+        assert!(!is_address("10.0.0.1/33"));
+        assert!(!is_address("10.0.0.1/32\nPostUp = rm -rf /"));
+        assert!(is_endpoint("vpn.example.com:51820"));
+        assert!(is_endpoint("[2001:db8::1]:51820"));
+        assert!(!is_endpoint("192.0.2.1"));
+        assert!(!is_endpoint("192.0.2.1:51820\nPostUp = rm -rf /"));
+
+        assert!(is_allowed("10.0.0.2/32", &[]));
+        assert!(is_allowed("fd00::2/128", &[]));
+        assert!(!is_allowed("0.0.0.0/0", &[]));
+        assert!(!is_allowed("10.0.0.0/24", &[]));
+        let ranges = ["10.0.0.0/16".to_owned(), "fd00::/64".to_owned()];
+        assert!(is_allowed("10.0.1.0/24", &ranges));
+        assert!(is_allowed("fd00::2/128", &ranges));
+        assert!(!is_allowed("10.0.0.0/8", &ranges));
+        assert!(!is_allowed("10.1.0.1/32", &ranges));
+        assert!(!is_allowed("::a00:1/128", &ranges));
+        assert!(is_allowed("192.0.2.1/32", &["0.0.0.0/0".to_owned()]));
+
+        let config = PeerConfig {
+            public_key: key,
+            preshared_key: preshared_key(&Key::new(Box::default())),
+            allowed_ips: vec!["10.0.0.2/32".into(), "fd00::2/128".into()],
+            endpoints: vec!["192.0.2.1:51820".into(), "[2001:db8::1]:51820".into()],
+        };
+        assert_eq!(
+            config.to_string(),
+            format!(
+                "[Peer]\nPublicKey = {}\nPresharedKey = {}\nAllowedIPs = 10.0.0.2/32, fd00::2/128\nEndpoint = 192.0.2.1:51820\n",
+                KEY, config.preshared_key
+            )
+        );
+    }
+}